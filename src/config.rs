@@ -8,11 +8,28 @@ use cln_rpc::{model::requests::ListconfigsRequest, ClnRpc, RpcError};
 use serde_json::json;
 
 use crate::{
-    model::PluginState, Config, OPT_CANDIDATES_MIN_AGE, OPT_DEPLETEUPTOAMOUNT,
-    OPT_DEPLETEUPTOPERCENT, OPT_MAXHOPS, OPT_MAX_HTLC_COUNT, OPT_PARALLELJOBS,
-    OPT_REFRESH_ALIASMAP_INTERVAL, OPT_REFRESH_GOSSMAP_INTERVAL, OPT_REFRESH_PEERS_INTERVAL,
-    OPT_RESET_LIQUIDITY_INTERVAL, OPT_STATS_DELETE_FAILURES_AGE, OPT_STATS_DELETE_FAILURES_SIZE,
-    OPT_STATS_DELETE_SUCCESSES_AGE, OPT_STATS_DELETE_SUCCESSES_SIZE, OPT_TIMEOUTPAY, OPT_UTF8,
+    model::{
+        PluginState, CANDIDATES_CAP_CHEAPEST_HISTORY, CANDIDATES_CAP_MOST_IMBALANCED,
+        CANDIDATES_CAP_ROUND_ROBIN, CANDIDATE_ORDER_CHEAPEST, CANDIDATE_ORDER_LARGEST_FIRST,
+        CANDIDATE_ORDER_MOST_OVERFULL, CANDIDATE_ORDER_MOST_OVER_TARGET, GRAPH_SOURCE_GOSSIP_STORE,
+        GRAPH_SOURCE_LISTCHANNELS, MODE_ACTIVE, MODE_FOLLOWER,
+    },
+    Config, OPT_CANDIDATES_CAP_STRATEGY, OPT_CANDIDATES_MIN_AGE, OPT_CANDIDATE_MAX_FAILURES,
+    OPT_CANDIDATE_ORDER, OPT_CAPACITY_PREFERENCE, OPT_CIRCUIT_BREAKER_THRESHOLD,
+    OPT_DEPLETEUPTOAMOUNT, OPT_DEPLETEUPTOPERCENT, OPT_EXCLUDE_ALIAS, OPT_FLOW_PRIORS,
+    OPT_GRAPH_MAX_CHANNELS, OPT_GRAPH_SOURCE, OPT_GRAPH_WRITE_INTERVAL, OPT_HOP_RESULTS,
+    OPT_LIQUIDITY_CONFIDENCE_WINDOW, OPT_LIQUIDITY_MAX_AGE, OPT_MAXHOPS,
+    OPT_MAX_CANDIDATES_PER_ITERATION, OPT_MAX_HTLC_COUNT, OPT_MAX_PEER_DATA_AGE,
+    OPT_MAX_PEER_HTLC_COUNT, OPT_MAX_PEER_HTLC_VALUE, OPT_MAX_ROUTE_ATTEMPTS,
+    OPT_MIN_FORWARD_VOLUME, OPT_MIN_FUNDED_SATS, OPT_MODE, OPT_NODE_BAN_THRESHOLD,
+    OPT_NODE_BAN_WINDOW, OPT_NODE_PENALTY, OPT_NODE_PENALTY_HALF_LIFE, OPT_PARALLELJOBS,
+    OPT_PAYS_SWEEP_INTERVAL, OPT_POST_SUCCESS_COMMAND, OPT_REFRESH_ALIASMAP_INTERVAL,
+    OPT_REFRESH_GOSSMAP_INTERVAL, OPT_REFRESH_PEERS_INTERVAL, OPT_RELIABILITY_WEIGHT,
+    OPT_RESET_LIQUIDITY_INTERVAL, OPT_ROI_WARN, OPT_ROUTE_CACHE_MAX_AGE, OPT_RPC_CONCURRENCY,
+    OPT_STARTUP_DELAY_SECS, OPT_STATS_CACHE_SIZE, OPT_STATS_DELETE_FAILURES_AGE,
+    OPT_STATS_DELETE_FAILURES_SIZE, OPT_STATS_DELETE_SUCCESSES_AGE,
+    OPT_STATS_DELETE_SUCCESSES_SIZE, OPT_STATS_RECENT_WINDOW_DAYS, OPT_STATS_ROLLUP,
+    OPT_TIMEOUTPAY, OPT_TRACE, OPT_UTF8, OPT_VERIFY_BALANCE, OPT_VERIFY_OWN_PAYMENT,
 };
 
 pub async fn setconfig_callback(
@@ -46,6 +63,13 @@ pub async fn setconfig_callback(
         }))
     })?;
 
+    if name.eq(OPT_RPC_CONCURRENCY) {
+        plugin
+            .state()
+            .rpc_pool
+            .set_capacity(config.rpc_concurrency.value as usize);
+    }
+
     plugin.set_option_str(name, opt_value).map_err(|e| {
         anyhow!(json!(RpcError {
             code: Some(-32602),
@@ -59,14 +83,30 @@ pub async fn setconfig_callback(
 
 fn parse_option(name: &str, value: &serde_json::Value) -> Result<options::Value, Error> {
     match name {
-        n if n.eq(OPT_DEPLETEUPTOPERCENT) => {
+        n if n.eq(OPT_DEPLETEUPTOPERCENT)
+            || n.eq(OPT_RELIABILITY_WEIGHT)
+            || n.eq(OPT_POST_SUCCESS_COMMAND)
+            || n.eq(OPT_GRAPH_SOURCE)
+            || n.eq(OPT_CANDIDATE_ORDER)
+            || n.eq(OPT_CANDIDATES_CAP_STRATEGY)
+            || n.eq(OPT_MODE)
+            || n.eq(OPT_EXCLUDE_ALIAS) =>
+        {
             if value.is_string() {
                 Ok(options::Value::String(value.as_str().unwrap().to_owned()))
             } else {
                 Err(anyhow!("{} is not a valid string!", name))
             }
         }
-        n if n.eq(OPT_UTF8) => {
+        n if n.eq(OPT_UTF8)
+            || n.eq(OPT_FLOW_PRIORS)
+            || n.eq(OPT_HOP_RESULTS)
+            || n.eq(OPT_TRACE)
+            || n.eq(OPT_ROI_WARN)
+            || n.eq(OPT_VERIFY_BALANCE)
+            || n.eq(OPT_VERIFY_OWN_PAYMENT)
+            || n.eq(OPT_STATS_ROLLUP) =>
+        {
             if let Some(n_bool) = value.as_bool() {
                 return Ok(options::Value::Boolean(n_bool));
             } else if let Some(n_str) = value.as_str() {
@@ -174,6 +214,63 @@ pub async fn get_startup_options(
     if let Some(rli) = plugin.option_str(OPT_RESET_LIQUIDITY_INTERVAL)? {
         check_option(&mut config, OPT_RESET_LIQUIDITY_INTERVAL, &rli)?;
     };
+    if let Some(gmc) = plugin.option_str(OPT_GRAPH_MAX_CHANNELS)? {
+        check_option(&mut config, OPT_GRAPH_MAX_CHANNELS, &gmc)?;
+    };
+    if let Some(gs) = plugin.option_str(OPT_GRAPH_SOURCE)? {
+        check_option(&mut config, OPT_GRAPH_SOURCE, &gs)?;
+    };
+    if let Some(co) = plugin.option_str(OPT_CANDIDATE_ORDER)? {
+        check_option(&mut config, OPT_CANDIDATE_ORDER, &co)?;
+    };
+    if let Some(mcpi) = plugin.option_str(OPT_MAX_CANDIDATES_PER_ITERATION)? {
+        check_option(&mut config, OPT_MAX_CANDIDATES_PER_ITERATION, &mcpi)?;
+    };
+    if let Some(ccs) = plugin.option_str(OPT_CANDIDATES_CAP_STRATEGY)? {
+        check_option(&mut config, OPT_CANDIDATES_CAP_STRATEGY, &ccs)?;
+    };
+    if let Some(m) = plugin.option_str(OPT_MODE)? {
+        check_option(&mut config, OPT_MODE, &m)?;
+    };
+    if let Some(ea) = plugin.option_str(OPT_EXCLUDE_ALIAS)? {
+        check_option(&mut config, OPT_EXCLUDE_ALIAS, &ea)?;
+    };
+    if let Some(rcma) = plugin.option_str(OPT_ROUTE_CACHE_MAX_AGE)? {
+        check_option(&mut config, OPT_ROUTE_CACHE_MAX_AGE, &rcma)?;
+    };
+    if let Some(mfv) = plugin.option_str(OPT_MIN_FORWARD_VOLUME)? {
+        check_option(&mut config, OPT_MIN_FORWARD_VOLUME, &mfv)?;
+    };
+    if let Some(mpda) = plugin.option_str(OPT_MAX_PEER_DATA_AGE)? {
+        check_option(&mut config, OPT_MAX_PEER_DATA_AGE, &mpda)?;
+    };
+    if let Some(rc) = plugin.option_str(OPT_RPC_CONCURRENCY)? {
+        check_option(&mut config, OPT_RPC_CONCURRENCY, &rc)?;
+    };
+    if let Some(lma) = plugin.option_str(OPT_LIQUIDITY_MAX_AGE)? {
+        check_option(&mut config, OPT_LIQUIDITY_MAX_AGE, &lma)?;
+    };
+    if let Some(lcw) = plugin.option_str(OPT_LIQUIDITY_CONFIDENCE_WINDOW)? {
+        check_option(&mut config, OPT_LIQUIDITY_CONFIDENCE_WINDOW, &lcw)?;
+    };
+    if let Some(fp) = plugin.option_str(OPT_FLOW_PRIORS)? {
+        check_option(&mut config, OPT_FLOW_PRIORS, &fp)?;
+    };
+    if let Some(hr) = plugin.option_str(OPT_HOP_RESULTS)? {
+        check_option(&mut config, OPT_HOP_RESULTS, &hr)?;
+    };
+    if let Some(tr) = plugin.option_str(OPT_TRACE)? {
+        check_option(&mut config, OPT_TRACE, &tr)?;
+    };
+    if let Some(rw) = plugin.option_str(OPT_ROI_WARN)? {
+        check_option(&mut config, OPT_ROI_WARN, &rw)?;
+    };
+    if let Some(vb) = plugin.option_str(OPT_VERIFY_BALANCE)? {
+        check_option(&mut config, OPT_VERIFY_BALANCE, &vb)?;
+    };
+    if let Some(vop) = plugin.option_str(OPT_VERIFY_OWN_PAYMENT)? {
+        check_option(&mut config, OPT_VERIFY_OWN_PAYMENT, &vop)?;
+    };
     if let Some(dup) = plugin.option_str(OPT_DEPLETEUPTOPERCENT)? {
         check_option(&mut config, OPT_DEPLETEUPTOPERCENT, &dup)?;
     };
@@ -192,9 +289,60 @@ pub async fn get_startup_options(
     if let Some(tp) = plugin.option_str(OPT_TIMEOUTPAY)? {
         check_option(&mut config, OPT_TIMEOUTPAY, &tp)?;
     };
+    if let Some(mra) = plugin.option_str(OPT_MAX_ROUTE_ATTEMPTS)? {
+        check_option(&mut config, OPT_MAX_ROUTE_ATTEMPTS, &mra)?;
+    };
+    if let Some(cmf) = plugin.option_str(OPT_CANDIDATE_MAX_FAILURES)? {
+        check_option(&mut config, OPT_CANDIDATE_MAX_FAILURES, &cmf)?;
+    };
+    if let Some(cbt) = plugin.option_str(OPT_CIRCUIT_BREAKER_THRESHOLD)? {
+        check_option(&mut config, OPT_CIRCUIT_BREAKER_THRESHOLD, &cbt)?;
+    };
+    if let Some(nbt) = plugin.option_str(OPT_NODE_BAN_THRESHOLD)? {
+        check_option(&mut config, OPT_NODE_BAN_THRESHOLD, &nbt)?;
+    };
+    if let Some(nbw) = plugin.option_str(OPT_NODE_BAN_WINDOW)? {
+        check_option(&mut config, OPT_NODE_BAN_WINDOW, &nbw)?;
+    };
+    if let Some(sds) = plugin.option_str(OPT_STARTUP_DELAY_SECS)? {
+        check_option(&mut config, OPT_STARTUP_DELAY_SECS, &sds)?;
+    };
+    if let Some(mfs) = plugin.option_str(OPT_MIN_FUNDED_SATS)? {
+        check_option(&mut config, OPT_MIN_FUNDED_SATS, &mfs)?;
+    };
     if let Some(mhc) = plugin.option_str(OPT_MAX_HTLC_COUNT)? {
         check_option(&mut config, OPT_MAX_HTLC_COUNT, &mhc)?;
     };
+    if let Some(mphc) = plugin.option_str(OPT_MAX_PEER_HTLC_COUNT)? {
+        check_option(&mut config, OPT_MAX_PEER_HTLC_COUNT, &mphc)?;
+    };
+    if let Some(mphv) = plugin.option_str(OPT_MAX_PEER_HTLC_VALUE)? {
+        check_option(&mut config, OPT_MAX_PEER_HTLC_VALUE, &mphv)?;
+    };
+    if let Some(rw) = plugin.option_str(OPT_RELIABILITY_WEIGHT)? {
+        check_option(&mut config, OPT_RELIABILITY_WEIGHT, &rw)?;
+    };
+    if let Some(cp) = plugin.option_str(OPT_CAPACITY_PREFERENCE)? {
+        check_option(&mut config, OPT_CAPACITY_PREFERENCE, &cp)?;
+    };
+    if let Some(np) = plugin.option_str(OPT_NODE_PENALTY)? {
+        check_option(&mut config, OPT_NODE_PENALTY, &np)?;
+    };
+    if let Some(nphl) = plugin.option_str(OPT_NODE_PENALTY_HALF_LIFE)? {
+        check_option(&mut config, OPT_NODE_PENALTY_HALF_LIFE, &nphl)?;
+    };
+    if let Some(gwi) = plugin.option_str(OPT_GRAPH_WRITE_INTERVAL)? {
+        check_option(&mut config, OPT_GRAPH_WRITE_INTERVAL, &gwi)?;
+    };
+    if let Some(psi) = plugin.option_str(OPT_PAYS_SWEEP_INTERVAL)? {
+        check_option(&mut config, OPT_PAYS_SWEEP_INTERVAL, &psi)?;
+    };
+    if let Some(scs) = plugin.option_str(OPT_STATS_CACHE_SIZE)? {
+        check_option(&mut config, OPT_STATS_CACHE_SIZE, &scs)?;
+    };
+    if let Some(psc) = plugin.option_str(OPT_POST_SUCCESS_COMMAND)? {
+        check_option(&mut config, OPT_POST_SUCCESS_COMMAND, &psc)?;
+    };
     if let Some(sdfa) = plugin.option_str(OPT_STATS_DELETE_FAILURES_AGE)? {
         check_option(&mut config, OPT_STATS_DELETE_FAILURES_AGE, &sdfa)?;
     };
@@ -207,6 +355,15 @@ pub async fn get_startup_options(
     if let Some(sdss) = plugin.option_str(OPT_STATS_DELETE_SUCCESSES_SIZE)? {
         check_option(&mut config, OPT_STATS_DELETE_SUCCESSES_SIZE, &sdss)?;
     };
+    if let Some(srwd) = plugin.option_str(OPT_STATS_RECENT_WINDOW_DAYS)? {
+        check_option(&mut config, OPT_STATS_RECENT_WINDOW_DAYS, &srwd)?;
+    };
+    if let Some(sr) = plugin.option_str(OPT_STATS_ROLLUP)? {
+        check_option(&mut config, OPT_STATS_ROLLUP, &sr)?;
+    };
+    state
+        .rpc_pool
+        .set_capacity(config.rpc_concurrency.value as usize);
 
     Ok(())
 }
@@ -214,6 +371,14 @@ pub async fn get_startup_options(
 fn check_option(config: &mut Config, name: &str, value: &options::Value) -> Result<(), Error> {
     match name {
         n if n.eq(OPT_UTF8) => config.utf8.value = value.as_bool().unwrap(),
+        n if n.eq(OPT_FLOW_PRIORS) => config.flow_priors.value = value.as_bool().unwrap(),
+        n if n.eq(OPT_HOP_RESULTS) => config.hop_results.value = value.as_bool().unwrap(),
+        n if n.eq(OPT_TRACE) => config.trace.value = value.as_bool().unwrap(),
+        n if n.eq(OPT_ROI_WARN) => config.roi_warn.value = value.as_bool().unwrap(),
+        n if n.eq(OPT_VERIFY_BALANCE) => config.verify_balance.value = value.as_bool().unwrap(),
+        n if n.eq(OPT_VERIFY_OWN_PAYMENT) => {
+            config.verify_own_payment.value = value.as_bool().unwrap()
+        }
         n if n.eq(OPT_REFRESH_PEERS_INTERVAL) => {
             config.refresh_peers_interval.value =
                 options_value_to_u64(OPT_REFRESH_PEERS_INTERVAL, value.as_i64().unwrap(), 1, None)?
@@ -242,6 +407,111 @@ fn check_option(config: &mut Config, name: &str, value: &options::Value) -> Resu
                 None,
             )?
         }
+        n if n.eq(OPT_GRAPH_MAX_CHANNELS) => {
+            config.graph_max_channels.value =
+                options_value_to_u64(OPT_GRAPH_MAX_CHANNELS, value.as_i64().unwrap(), 0, None)?
+        }
+        n if n.eq(OPT_GRAPH_SOURCE) => {
+            let source = value.as_str().unwrap().trim();
+            if source != GRAPH_SOURCE_GOSSIP_STORE && source != GRAPH_SOURCE_LISTCHANNELS {
+                return Err(anyhow!(
+                    "Error: {} needs to be `{}` or `{}`, not `{}`.",
+                    config.graph_source.name,
+                    GRAPH_SOURCE_GOSSIP_STORE,
+                    GRAPH_SOURCE_LISTCHANNELS,
+                    source
+                ));
+            }
+            config.graph_source.value = source.to_owned()
+        }
+        n if n.eq(OPT_CANDIDATE_ORDER) => {
+            let order = value.as_str().unwrap().trim();
+            if order != CANDIDATE_ORDER_CHEAPEST
+                && order != CANDIDATE_ORDER_LARGEST_FIRST
+                && order != CANDIDATE_ORDER_MOST_OVERFULL
+                && order != CANDIDATE_ORDER_MOST_OVER_TARGET
+            {
+                return Err(anyhow!(
+                    "Error: {} needs to be `{}`, `{}`, `{}` or `{}`, not `{}`.",
+                    config.candidate_order.name,
+                    CANDIDATE_ORDER_CHEAPEST,
+                    CANDIDATE_ORDER_LARGEST_FIRST,
+                    CANDIDATE_ORDER_MOST_OVERFULL,
+                    CANDIDATE_ORDER_MOST_OVER_TARGET,
+                    order
+                ));
+            }
+            config.candidate_order.value = order.to_owned()
+        }
+        n if n.eq(OPT_MAX_CANDIDATES_PER_ITERATION) => {
+            config.max_candidates_per_iteration.value = options_value_to_u64(
+                OPT_MAX_CANDIDATES_PER_ITERATION,
+                value.as_i64().unwrap(),
+                0,
+                None,
+            )?
+        }
+        n if n.eq(OPT_CANDIDATES_CAP_STRATEGY) => {
+            let strategy = value.as_str().unwrap().trim();
+            if strategy != CANDIDATES_CAP_ROUND_ROBIN
+                && strategy != CANDIDATES_CAP_MOST_IMBALANCED
+                && strategy != CANDIDATES_CAP_CHEAPEST_HISTORY
+            {
+                return Err(anyhow!(
+                    "Error: {} needs to be `{}`, `{}` or `{}`, not `{}`.",
+                    config.candidates_cap_strategy.name,
+                    CANDIDATES_CAP_ROUND_ROBIN,
+                    CANDIDATES_CAP_MOST_IMBALANCED,
+                    CANDIDATES_CAP_CHEAPEST_HISTORY,
+                    strategy
+                ));
+            }
+            config.candidates_cap_strategy.value = strategy.to_owned()
+        }
+        n if n.eq(OPT_MODE) => {
+            let mode = value.as_str().unwrap().trim();
+            if mode != MODE_ACTIVE && mode != MODE_FOLLOWER {
+                return Err(anyhow!(
+                    "Error: {} needs to be `{}` or `{}`, not `{}`.",
+                    config.mode.name,
+                    MODE_ACTIVE,
+                    MODE_FOLLOWER,
+                    mode
+                ));
+            }
+            config.mode.value = mode.to_owned()
+        }
+        n if n.eq(OPT_EXCLUDE_ALIAS) => {
+            config.exclude_alias.value = value.as_str().unwrap().trim().to_owned()
+        }
+        n if n.eq(OPT_ROUTE_CACHE_MAX_AGE) => {
+            config.route_cache_max_age.value =
+                options_value_to_u64(OPT_ROUTE_CACHE_MAX_AGE, value.as_i64().unwrap(), 0, None)?
+        }
+        n if n.eq(OPT_MIN_FORWARD_VOLUME) => {
+            config.min_forward_volume.value =
+                options_value_to_u64(OPT_MIN_FORWARD_VOLUME, value.as_i64().unwrap(), 0, None)?
+        }
+        n if n.eq(OPT_RPC_CONCURRENCY) => {
+            config.rpc_concurrency.value =
+                options_value_to_u64(OPT_RPC_CONCURRENCY, value.as_i64().unwrap(), 1, None)?
+        }
+        n if n.eq(OPT_MAX_PEER_DATA_AGE) => {
+            config.max_peer_data_age.value =
+                options_value_to_u64(OPT_MAX_PEER_DATA_AGE, value.as_i64().unwrap(), 0, None)?
+        }
+        n if n.eq(OPT_LIQUIDITY_MAX_AGE) => {
+            config.liquidity_max_age.value =
+                options_value_to_u64(OPT_LIQUIDITY_MAX_AGE, value.as_i64().unwrap(), 0, None)?
+        }
+        n if n.eq(OPT_LIQUIDITY_CONFIDENCE_WINDOW) => {
+            config.liquidity_confidence_window.value = options_value_to_u64(
+                OPT_LIQUIDITY_CONFIDENCE_WINDOW,
+                value.as_i64().unwrap(),
+                0,
+                None,
+            )?
+        }
         n if n.eq(OPT_DEPLETEUPTOPERCENT) => {
             config.depleteuptopercent.value = match value.as_str().unwrap().parse::<f64>() {
                 Ok(f) => {
@@ -264,18 +534,78 @@ fn check_option(config: &mut Config, name: &str, value: &options::Value) -> Resu
                 }
             }
         }
+        n if n.eq(OPT_POST_SUCCESS_COMMAND) => {
+            config.post_success_command.value = value.as_str().unwrap().trim().to_owned()
+        }
+        n if n.eq(OPT_RELIABILITY_WEIGHT) => {
+            config.reliability_weight.value = match value.as_str().unwrap().parse::<f64>() {
+                Ok(f) => {
+                    if (0.0..=1.0).contains(&f) {
+                        f
+                    } else {
+                        return Err(anyhow!(
+                            "Error: {} needs to be between 0 and 1, not `{}`.",
+                            config.reliability_weight.name,
+                            f
+                        ));
+                    }
+                }
+                Err(e) => {
+                    return Err(anyhow!(
+                        "Error: {} could not parse a floating point for `{}`.",
+                        e,
+                        config.reliability_weight.name,
+                    ))
+                }
+            }
+        }
+        n if n.eq(OPT_CAPACITY_PREFERENCE) => {
+            let preference = value.as_i64().unwrap();
+            if preference.unsigned_abs() > 1_000_000 {
+                return Err(anyhow!(
+                    "Error: {} needs to be between -1000000 and 1000000, not `{}`.",
+                    config.capacity_preference.name,
+                    preference
+                ));
+            }
+            config.capacity_preference.value = preference
+        }
+        n if n.eq(OPT_NODE_PENALTY) => {
+            config.node_penalty.value =
+                options_value_to_u64(OPT_NODE_PENALTY, value.as_i64().unwrap(), 0, None)?
+        }
+        n if n.eq(OPT_GRAPH_WRITE_INTERVAL) => {
+            config.graph_write_interval.value =
+                options_value_to_u64(OPT_GRAPH_WRITE_INTERVAL, value.as_i64().unwrap(), 0, None)?
+        }
+        n if n.eq(OPT_PAYS_SWEEP_INTERVAL) => {
+            config.pays_sweep_interval.value =
+                options_value_to_u64(OPT_PAYS_SWEEP_INTERVAL, value.as_i64().unwrap(), 0, None)?
+        }
+        n if n.eq(OPT_STATS_CACHE_SIZE) => {
+            config.stats_cache_size.value =
+                options_value_to_u64(OPT_STATS_CACHE_SIZE, value.as_i64().unwrap(), 0, None)?
+        }
+        n if n.eq(OPT_NODE_PENALTY_HALF_LIFE) => {
+            config.node_penalty_half_life.value =
+                options_value_to_u64(OPT_NODE_PENALTY_HALF_LIFE, value.as_i64().unwrap(), 1, None)?
+        }
         n if n.eq(OPT_DEPLETEUPTOAMOUNT) => {
             config.depleteuptoamount.value =
                 options_value_to_u64(OPT_DEPLETEUPTOAMOUNT, value.as_i64().unwrap(), 0, None)?
                     * 1000
         }
         n if n.eq(OPT_MAXHOPS) => {
-            config.maxhops.value = u8::try_from(options_value_to_u64(
-                OPT_MAXHOPS,
-                value.as_i64().unwrap(),
-                2,
-                None,
-            )?)?
+            config.maxhops.value = if value.as_i64() == Some(0) {
+                0
+            } else {
+                u8::try_from(options_value_to_u64(
+                    OPT_MAXHOPS,
+                    value.as_i64().unwrap(),
+                    2,
+                    None,
+                )?)?
+            }
         }
         n if n.eq(OPT_CANDIDATES_MIN_AGE) => {
             config.candidates_min_age.value = u32::try_from(options_value_to_u64(
@@ -301,10 +631,58 @@ fn check_option(config: &mut Config, name: &str, value: &options::Value) -> Resu
                 None,
             )?)?
         }
+        n if n.eq(OPT_MAX_ROUTE_ATTEMPTS) => {
+            config.max_route_attempts.value =
+                options_value_to_u64(OPT_MAX_ROUTE_ATTEMPTS, value.as_i64().unwrap(), 0, None)?
+        }
+        n if n.eq(OPT_CANDIDATE_MAX_FAILURES) => {
+            config.candidate_max_failures.value =
+                options_value_to_u64(OPT_CANDIDATE_MAX_FAILURES, value.as_i64().unwrap(), 0, None)?
+        }
+        n if n.eq(OPT_CIRCUIT_BREAKER_THRESHOLD) => {
+            config.circuit_breaker_threshold.value = options_value_to_u64(
+                OPT_CIRCUIT_BREAKER_THRESHOLD,
+                value.as_i64().unwrap(),
+                0,
+                None,
+            )?
+        }
+        n if n.eq(OPT_NODE_BAN_THRESHOLD) => {
+            config.node_ban_threshold.value =
+                options_value_to_u64(OPT_NODE_BAN_THRESHOLD, value.as_i64().unwrap(), 0, None)?
+        }
+        n if n.eq(OPT_NODE_BAN_WINDOW) => {
+            config.node_ban_window.value =
+                options_value_to_u64(OPT_NODE_BAN_WINDOW, value.as_i64().unwrap(), 1, None)?
+        }
+        n if n.eq(OPT_STARTUP_DELAY_SECS) => {
+            config.startup_delay_secs.value =
+                options_value_to_u64(OPT_STARTUP_DELAY_SECS, value.as_i64().unwrap(), 0, None)?
+        }
+        n if n.eq(OPT_MIN_FUNDED_SATS) => {
+            config.min_funded_sats.value =
+                options_value_to_u64(OPT_MIN_FUNDED_SATS, value.as_i64().unwrap(), 0, None)?
+        }
         n if n.eq(OPT_MAX_HTLC_COUNT) => {
             config.max_htlc_count.value =
                 options_value_to_u64(OPT_MAX_HTLC_COUNT, value.as_i64().unwrap(), 1, None)?
         }
+        n if n.eq(OPT_MAX_PEER_HTLC_VALUE) => {
+            let percent =
+                options_value_to_u64(OPT_MAX_PEER_HTLC_VALUE, value.as_i64().unwrap(), 0, None)?;
+            if percent > 100 {
+                return Err(anyhow!(
+                    "Error: {} needs to be between 0 and 100, not `{}`.",
+                    config.max_peer_htlc_value.name,
+                    percent
+                ));
+            }
+            config.max_peer_htlc_value.value = percent
+        }
+        n if n.eq(OPT_MAX_PEER_HTLC_COUNT) => {
+            config.max_peer_htlc_count.value =
+                options_value_to_u64(OPT_MAX_PEER_HTLC_COUNT, value.as_i64().unwrap(), 0, None)?
+        }
         n if n.eq(OPT_STATS_DELETE_FAILURES_AGE) => {
             config.stats_delete_failures_age.value = options_value_to_u64(
                 OPT_STATS_DELETE_FAILURES_AGE,
@@ -337,6 +715,15 @@ fn check_option(config: &mut Config, name: &str, value: &options::Value) -> Resu
                 None,
             )?
         }
+        n if n.eq(OPT_STATS_RECENT_WINDOW_DAYS) => {
+            config.stats_recent_window_days.value = options_value_to_u64(
+                OPT_STATS_RECENT_WINDOW_DAYS,
+                value.as_i64().unwrap(),
+                0,
+                Some(24 * 60 * 60),
+            )?
+        }
+        n if n.eq(OPT_STATS_ROLLUP) => config.stats_rollup.value = value.as_bool().unwrap(),
         _ => return Err(anyhow!("Unknown option: {}", name)),
     }
     Ok(())
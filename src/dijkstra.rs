@@ -1,15 +1,20 @@
 use crate::model::{DijkstraNode, ExcludeGraph, LnGraph, PublicKeyPair};
-use crate::util::{edge_cost, fee_total_msat_precise};
+use crate::util::{capacity_tier_penalty, edge_cost, fee_total_msat_precise, reliability_penalty};
 use anyhow::Error;
 use cln_rpc::model::requests::SendpayRoute;
 use cln_rpc::primitives::*;
-use sling::{DirectedChannel, Job, SatDirection};
+use sling::{DirectedChannel, Job, RouteObjective, SatDirection};
 use std::collections::hash_map::Entry::{Occupied, Vacant};
 use std::collections::BinaryHeap;
 use std::{
-    cmp::Ordering,
+    cmp::{min, Ordering},
     collections::{HashMap, HashSet},
 };
+
+/// A node in the search together with whether the path to it already
+/// passed the job's `via_node`. Without a `via_node` this is always true.
+type SearchState = (PublicKey, bool);
+
 #[allow(clippy::too_many_arguments)]
 pub fn dijkstra(
     my_pubkey: &PublicKey,
@@ -24,17 +29,33 @@ pub fn dijkstra(
     last_delay: u32,
     tempbans: &HashMap<ShortChannelId, u64>,
     parallel_bans: &[DirectedChannel],
+    failure_rates: &HashMap<ShortChannelId, f64>,
+    reliability_weight: f64,
+    liquidity_max_age: u64,
+    liquidity_confidence_window: u64,
+    fee_budget_msat: Option<u64>,
+    capacity_preference: i64,
+    node_penalties: &HashMap<PublicKey, u64>,
 ) -> Result<Vec<SendpayRoute>, Error> {
     let mut visited = HashSet::with_capacity(lngraph.graph.len());
     let mut scores = HashMap::new();
     let mut predecessor = HashMap::new();
+    let mut fees = HashMap::new();
     let mut visit_next = BinaryHeap::new();
-    let zero_score = u64::default();
+    // the other of fee and hops breaks ties
+    let priority = |score: u64, hops: u8| match job.objective.unwrap_or_default() {
+        RouteObjective::Cheapest => (score, hops as u64),
+        RouteObjective::Shortest => (hops as u64, score),
+    };
 
-    scores.insert(*start, *slingchan);
-    visit_next.push(MinScored(zero_score, *start));
-    while let Some(MinScored(node_score, node)) = visit_next.pop() {
-        if visited.contains(&node) {
+    let start_state = (*start, job.via_node.is_none_or(|via| &via == start));
+    scores.insert(start_state, *slingchan);
+    fees.insert(start_state, 0);
+    visit_next.push(MinScored(priority(0, 0), start_state));
+    let max_fees_msat = job.maxppm_for(job.sat_direction) as u64 * job.amount_msat / 1_000_000;
+    while let Some(MinScored(_, state)) = visit_next.pop() {
+        let (node, passed_via) = state;
+        if visited.contains(&state) {
             // debug!(
             //     "{}: already visited: {}",
             //     slingchan.channel.short_channel_id.to_string(),
@@ -42,6 +63,10 @@ pub fn dijkstra(
             // );
             continue;
         }
+        if goal == &node && !passed_via {
+            visited.insert(state);
+            continue;
+        }
         if goal == &node {
             // debug!(
             //     "{}: arrived at goal: {}  {}",
@@ -51,7 +76,9 @@ pub fn dijkstra(
             // );
             break;
         }
-        let current_hops = scores.get(&node).unwrap().hops;
+        let node_score = scores.get(&state).unwrap().score;
+        let current_hops = scores.get(&state).unwrap().hops;
+        let node_fees = *fees.get(&state).unwrap();
         if current_hops + 2 > max_hops {
             continue;
         }
@@ -65,9 +92,12 @@ pub fn dijkstra(
             candidatelist,
             tempbans,
             parallel_bans,
+            liquidity_max_age,
+            liquidity_confidence_window,
         ) {
             let next = edge.destination;
-            if visited.contains(&next) {
+            let next_state = (next, passed_via || job.via_node == Some(next));
+            if visited.contains(&next_state) {
                 // debug!(
                 //     "{}: already visited: {}",
                 //     slingchan.channel.short_channel_id.to_string(),
@@ -75,11 +105,48 @@ pub fn dijkstra(
                 // );
                 continue;
             }
-            let next_score = if edge.source == *my_pubkey {
+            // with a via_node a node can be reached before and after passing
+            // it, don't let the route go through the same node twice
+            if job.via_node.is_some() && on_path(&predecessor, state, &next) {
+                continue;
+            }
+            let own_hop = edge.source == *my_pubkey;
+            let next_fees = if own_hop {
+                0
+            } else {
+                node_fees + edge_cost(edge, job.amount_msat)
+            };
+            // the htlc over this hop also carries the fees of the hops after
+            // it, those are only known once the route is built but can't
+            // exceed what is left of the job's fee ceiling
+            if Amount::msat(&edge.htlc_minimum_msat)
+                > job.amount_msat + max_fees_msat.saturating_sub(next_fees)
+            {
+                continue;
+            }
+            if fee_budget_msat.is_some_and(|budget| next_fees > budget) {
+                continue;
+            }
+            let mut next_score = if own_hop {
                 0
             } else {
                 node_score + edge_cost(edge, job.amount_msat)
             };
+            if reliability_weight > 0.0 {
+                if let Some(rate) = failure_rates.get(&scid.short_channel_id) {
+                    next_score += reliability_penalty(*rate, reliability_weight, job.amount_msat);
+                }
+            }
+            if !own_hop {
+                next_score += capacity_tier_penalty(
+                    Amount::msat(&edge.amount_msat),
+                    capacity_preference,
+                    job.amount_msat,
+                );
+                if let Some(ppm) = node_penalties.get(&edge.source) {
+                    next_score += ppm.saturating_mul(job.amount_msat) / 1_000_000;
+                }
+            }
             // debug!(
             //     "{}: next: {} node_score:{} next_score:{}",
             //     slingchan.channel.short_channel_id.to_string(),
@@ -94,17 +161,23 @@ pub fn dijkstra(
                 hops: current_hops + 1,
                 short_channel_id: scid.short_channel_id,
             };
-            match scores.entry(next) {
+            match scores.entry(next_state) {
                 Occupied(ent) => {
-                    if next_score < ent.get().score {
+                    if priority(next_score, current_hops + 1)
+                        < priority(ent.get().score, ent.get().hops)
+                    {
                         // debug!(
                         //     "{}: found better path to: {}",
                         //     slingchan.channel.short_channel_id.to_string(),
                         //     &next
                         // );
                         *ent.into_mut() = dijkstra_node;
-                        visit_next.push(MinScored(next_score, next));
-                        predecessor.insert(next, node);
+                        visit_next.push(MinScored(
+                            priority(next_score, current_hops + 1),
+                            next_state,
+                        ));
+                        predecessor.insert(next_state, state);
+                        fees.insert(next_state, next_fees);
                     }
                 }
                 Vacant(ent) => {
@@ -115,31 +188,51 @@ pub fn dijkstra(
                     //     &edge.channel.short_channel_id.to_string()
                     // );
                     ent.insert(dijkstra_node);
-                    visit_next.push(MinScored(next_score, next));
-                    predecessor.insert(next, node);
+                    visit_next.push(MinScored(
+                        priority(next_score, current_hops + 1),
+                        next_state,
+                    ));
+                    predecessor.insert(next_state, state);
+                    fees.insert(next_state, next_fees);
                 }
             }
         }
-        visited.insert(node);
+        visited.insert(state);
     }
 
     build_route(
         &predecessor,
-        goal,
+        &(*goal, true),
         &scores,
         job,
-        start,
+        &start_state,
         slingchan,
         last_delay,
     )
 }
 
+fn on_path(
+    predecessor: &HashMap<SearchState, SearchState>,
+    mut state: SearchState,
+    node: &PublicKey,
+) -> bool {
+    loop {
+        if &state.0 == node {
+            return true;
+        }
+        match predecessor.get(&state) {
+            Some(prev) => state = *prev,
+            None => return false,
+        }
+    }
+}
+
 fn build_route(
-    predecessor: &HashMap<PublicKey, PublicKey>,
-    goal: &PublicKey,
-    scores: &HashMap<PublicKey, DijkstraNode>,
+    predecessor: &HashMap<SearchState, SearchState>,
+    goal: &SearchState,
+    scores: &HashMap<SearchState, DijkstraNode>,
     job: &Job,
-    start: &PublicKey,
+    start: &SearchState,
     slingchan: &DijkstraNode,
     last_delay: u32,
 ) -> Result<Vec<SendpayRoute>, Error> {
@@ -178,8 +271,37 @@ fn build_route(
     } else {
         return Ok(sendpay_route);
     };
+    let candidate = match job.sat_direction {
+        SatDirection::Pull => dijkstra_path.last().unwrap(),
+        SatDirection::Push => first_hop,
+    };
+    let route_amount_msat = job
+        .route_amount(
+            Amount::msat(&candidate.channel_state.amount_msat),
+            min(
+                Amount::msat(&candidate.channel_state.htlc_maximum_msat),
+                Amount::msat(&slingchan.channel_state.htlc_maximum_msat),
+            ),
+        )
+        .max(
+            dijkstra_path
+                .iter()
+                .map(|hop| Amount::msat(&hop.channel_state.htlc_minimum_msat))
+                .max()
+                .unwrap_or(0)
+                .min(job.amount_msat),
+        );
 
     for hop in &dijkstra_path {
+        // a hop carries the amount plus the fees of all hops after it
+        let hop_amount_msat = if hop == first_hop {
+            route_amount_msat
+        } else {
+            Amount::msat(&amount_msat)
+        };
+        if Amount::msat(&hop.channel_state.htlc_minimum_msat) > hop_amount_msat {
+            return Ok(vec![]);
+        }
         if hop == first_hop {
             let routing_scid = if let Some(rscid) = first_hop.channel_state.scid_alias {
                 rscid
@@ -189,7 +311,7 @@ fn build_route(
             sendpay_route.insert(
                 0,
                 SendpayRoute {
-                    amount_msat: Amount::from_msat(job.amount_msat),
+                    amount_msat: Amount::from_msat(route_amount_msat),
                     id: dijkstra_path.first().unwrap().destination,
                     delay,
                     channel: routing_scid,
@@ -2,7 +2,7 @@ use std::{
     collections::HashMap,
     fs::File,
     io::{BufReader, Read, Seek, SeekFrom},
-    path::Path,
+    path::PathBuf,
     str::FromStr,
     time::{Instant, SystemTime, UNIX_EPOCH},
 };
@@ -10,11 +10,14 @@ use std::{
 use anyhow::{anyhow, Error};
 use bitcoin::secp256k1::PublicKey;
 use cln_plugin::Plugin;
-use cln_rpc::primitives::{Amount, ShortChannelId};
+use cln_rpc::{
+    model::{requests::ListchannelsRequest, responses::ListchannelsChannels},
+    primitives::{Amount, ShortChannelId},
+};
 use log::{debug, warn};
 use sling::DirectedChannel;
 
-use crate::{DirectedChannelState, PluginState};
+use crate::{LnGraph, PluginState};
 
 #[derive(Debug, Clone)]
 pub struct ChannelUpdate {
@@ -37,282 +40,354 @@ pub struct ChannelAnnouncement {
     // pub features: String,
 }
 
-pub async fn read_gossip_store(plugin: Plugin<PluginState>, offset: &mut u64) -> Result<(), Error> {
-    let now = Instant::now();
-    debug!("gossip_reader: offset:{}", offset);
-    let is_start_up = *offset == 0;
+#[derive(Debug, Default)]
+pub struct GraphData {
+    pub announcements: HashMap<ShortChannelId, ChannelAnnouncement>,
+    pub amounts: HashMap<ShortChannelId, u64>,
+    pub updates: HashMap<DirectedChannel, ChannelUpdate>,
+    pub deleted: Vec<ShortChannelId>,
+    // channels without an update in this data are removed from the graph
+    pub full: bool,
+}
 
-    let file = File::open(Path::new(&plugin.configuration().lightning_dir).join("gossip_store"))?;
-    let mut reader = BufReader::new(file);
+pub trait GraphSource {
+    async fn read(&mut self, plugin: &Plugin<PluginState>) -> Result<GraphData, Error>;
+    fn applied(&mut self, _graph: &LnGraph) {}
+}
 
-    if is_start_up {
-        // Read and check the version
-        debug!("gossip_reader: checking gossip_store version...");
-        let mut version = [0u8; 1];
-        reader.read_exact(&mut version)?;
-        if (u8::from_be_bytes(version) & 0b1110_0000) != 0b0000_0000 {
-            warn!("gossip_reader: Unsupported gossip_store version!");
-            return plugin.shutdown();
+pub fn is_supported_store_version(version: u8) -> bool {
+    (version & 0b1110_0000) == 0b0000_0000
+}
+
+pub struct GossipStoreSource {
+    path: PathBuf,
+    offset: u64,
+    announcements: HashMap<ShortChannelId, ChannelAnnouncement>,
+    amounts: HashMap<ShortChannelId, u64>,
+}
+impl GossipStoreSource {
+    pub fn new(path: PathBuf) -> Self {
+        GossipStoreSource {
+            path,
+            offset: 0,
+            announcements: HashMap::new(),
+            amounts: HashMap::new(),
         }
-        debug!("gossip_reader: gossip_store version is good");
     }
+    pub fn store_version(&self) -> Result<u8, Error> {
+        let mut version = [0u8; 1];
+        File::open(&self.path)?.read_exact(&mut version)?;
+        Ok(u8::from_be_bytes(version))
+    }
+    pub fn read_store(&mut self) -> Result<GraphData, Error> {
+        let now = Instant::now();
+        debug!("gossip_reader: offset:{}", self.offset);
+        let is_start_up = self.offset == 0;
 
-    let mut channel_anns = plugin.state().gossip_store_anns.lock();
-    let mut channel_updates: HashMap<DirectedChannel, ChannelUpdate> = HashMap::new();
-    let mut channel_amts = plugin.state().gossip_store_amts.lock();
-    let mut channel_dels = Vec::new();
-    let mut last_scid = None;
-
-    reader.seek(SeekFrom::Current(*offset as i64))?;
-    loop {
-        *offset = reader.stream_position()?;
-        // Read the record header + type
-        let mut header_type = [0u8; 14];
+        let file = File::open(&self.path)?;
+        let mut reader = BufReader::new(file);
 
-        let flags;
-        let len;
-        // let crc;
-        // let timestamp;
-        let msg_type;
-        match reader.read_exact(&mut header_type) {
-            Ok(_) => {
-                flags = u16::from_be_bytes(header_type[0..2].try_into()?);
-                len = u16::from_be_bytes(header_type[2..4].try_into()?);
-                // crc = u32::from_be_bytes(header_type[4..8].try_into()?);
-                // timestamp = u32::from_be_bytes(header_type[8..12].try_into()?);
-                msg_type = u16::from_be_bytes(header_type[12..14].try_into()?);
-            }
-            Err(e) => {
-                // EOF or read error
-                debug!(
-                    "gossip_reader: header error at {}:{} (not an actual error if \
-                        buffer could not be filled)",
-                    offset, e
-                );
-                break;
+        if is_start_up {
+            // Read and check the version
+            debug!("gossip_reader: checking gossip_store version...");
+            let mut version = [0u8; 1];
+            reader.read_exact(&mut version)?;
+            if !is_supported_store_version(u8::from_be_bytes(version)) {
+                return Err(anyhow!("gossip_reader: Unsupported gossip_store version!"));
             }
-        };
-
-        // Check if the record is marked as deleted
-        if flags & 0x8000 != 0 {
-            reader.seek(SeekFrom::Current((len - 2) as i64))?;
-            continue;
-        }
-        // Check if the record is marked as dying
-        if flags & 0x0800 != 0 {
-            reader.seek(SeekFrom::Current((len - 2) as i64))?;
-            continue;
+            debug!("gossip_reader: gossip_store version is good");
         }
 
-        match msg_type {
-            256 => {
-                // public channel_announcement
-                let mut ann = vec![0u8; len as usize - 2];
-                reader.read_exact(&mut ann)?;
-                let (scid, chan_ann) = parse_channel_announcement(&ann)?;
-                last_scid = Some(scid);
-                channel_anns.insert(scid, chan_ann);
+        let offset = &mut self.offset;
+        let channel_anns = &mut self.announcements;
+        let mut channel_updates: HashMap<DirectedChannel, ChannelUpdate> = HashMap::new();
+        let channel_amts = &mut self.amounts;
+        let mut channel_dels = Vec::new();
+        let mut last_scid = None;
+
+        reader.seek(SeekFrom::Current(*offset as i64))?;
+        loop {
+            *offset = reader.stream_position()?;
+            // Read the record header + type
+            let mut header_type = [0u8; 14];
+
+            let flags;
+            let len;
+            // let crc;
+            // let timestamp;
+            let msg_type;
+            match reader.read_exact(&mut header_type) {
+                Ok(_) => {
+                    flags = u16::from_be_bytes(header_type[0..2].try_into()?);
+                    len = u16::from_be_bytes(header_type[2..4].try_into()?);
+                    // crc = u32::from_be_bytes(header_type[4..8].try_into()?);
+                    // timestamp = u32::from_be_bytes(header_type[8..12].try_into()?);
+                    msg_type = u16::from_be_bytes(header_type[12..14].try_into()?);
+                }
+                Err(e) => {
+                    // EOF or read error
+                    debug!(
+                        "gossip_reader: header error at {}:{} (not an actual error if \
+                            buffer could not be filled)",
+                        offset, e
+                    );
+                    break;
+                }
+            };
+
+            // Check if the record is marked as deleted
+            if flags & 0x8000 != 0 {
+                reader.seek(SeekFrom::Current((len - 2) as i64))?;
+                continue;
             }
-            4104 => {
-                // private_channel_announcement
-                //  `gossip_store_private_channel` (4104)
-                //   - `amount_sat`: u64
-                //   - `len`: u16
-                //   - `msg_type + announcement`: u16 + u8[len-2]
-                let mut ann = vec![0u8; len as usize - 2];
-                reader.read_exact(&mut ann)?;
-                let (scid, chan_ann) = parse_channel_announcement(&ann[12..])?;
-                channel_amts.insert(scid, u64::from_be_bytes(ann[0..8].try_into()?));
-                channel_anns.insert(scid, chan_ann);
+            // Check if the record is marked as dying
+            if flags & 0x0800 != 0 {
+                reader.seek(SeekFrom::Current((len - 2) as i64))?;
+                continue;
             }
-            258 => {
-                // channel_update
-                let mut update = vec![0u8; len as usize - 2];
-                reader.read_exact(&mut update)?;
-                let (scid, chan_up) = parse_channel_update(&update)?;
-                channel_updates.insert(
-                    DirectedChannel {
+
+            match msg_type {
+                256 => {
+                    // public channel_announcement
+                    let mut ann = vec![0u8; len as usize - 2];
+                    reader.read_exact(&mut ann)?;
+                    let (scid, chan_ann) = parse_channel_announcement(&ann)?;
+                    last_scid = Some(scid);
+                    channel_anns.insert(scid, chan_ann);
+                }
+                4104 => {
+                    // private_channel_announcement
+                    //  `gossip_store_private_channel` (4104)
+                    //   - `amount_sat`: u64
+                    //   - `len`: u16
+                    //   - `msg_type + announcement`: u16 + u8[len-2]
+                    let mut ann = vec![0u8; len as usize - 2];
+                    reader.read_exact(&mut ann)?;
+                    let (scid, chan_ann) = parse_channel_announcement(&ann[12..])?;
+                    channel_amts.insert(scid, u64::from_be_bytes(ann[0..8].try_into()?));
+                    channel_anns.insert(scid, chan_ann);
+                }
+                258 => {
+                    // channel_update
+                    let mut update = vec![0u8; len as usize - 2];
+                    reader.read_exact(&mut update)?;
+                    let (scid, chan_up) = parse_channel_update(&update)?;
+                    channel_updates.insert(
+                        DirectedChannel {
+                            short_channel_id: scid,
+                            direction: chan_up.direction,
+                        },
+                        chan_up,
+                    );
+                }
+                4102 => {
+                    //   - `gossip_store_private_update` (4102)
+                    //   - `len`: u16
+                    //   - `msg_type + update`: u16 + u8[len-2]
+                    let mut update = vec![0u8; len as usize - 2];
+                    reader.read_exact(&mut update)?;
+                    let (scid, chan_up) = parse_channel_update(&update[4..])?;
+                    channel_updates.insert(
+                        DirectedChannel {
+                            short_channel_id: scid,
+                            direction: chan_up.direction,
+                        },
+                        chan_up,
+                    );
+                }
+                4101 => {
+                    // gossip_store_channel_amount
+                    //  - `satoshis`: u64
+                    let mut satoshis = [0u8; 8];
+                    reader.read_exact(&mut satoshis)?;
+                    if let Some(scid) = last_scid {
+                        channel_amts.insert(scid, u64::from_be_bytes(satoshis));
+                        last_scid = None;
+                    } else {
+                        warn!("gossip_reader: Malformed gossip_store: 4101 without 256")
+                    }
+                }
+                4103 => {
+                    // 4103 gossip_store_delete_chan
+                    //  - `scid`: u64
+                    let mut scid_bytes = vec![0u8; 8];
+                    reader.read_exact(&mut scid_bytes)?;
+                    let scid = extract_scid(&scid_bytes)?;
+                    channel_dels.push(scid);
+                    channel_anns.remove(&scid);
+                    channel_updates.remove(&DirectedChannel {
                         short_channel_id: scid,
-                        direction: chan_up.direction,
-                    },
-                    chan_up,
-                );
-            }
-            4102 => {
-                //   - `gossip_store_private_update` (4102)
-                //   - `len`: u16
-                //   - `msg_type + update`: u16 + u8[len-2]
-                let mut update = vec![0u8; len as usize - 2];
-                reader.read_exact(&mut update)?;
-                let (scid, chan_up) = parse_channel_update(&update[4..])?;
-                channel_updates.insert(
-                    DirectedChannel {
+                        direction: 0,
+                    });
+                    channel_updates.remove(&DirectedChannel {
                         short_channel_id: scid,
-                        direction: chan_up.direction,
-                    },
-                    chan_up,
-                );
-            }
-            4101 => {
-                // gossip_store_channel_amount
-                //  - `satoshis`: u64
-                let mut satoshis = [0u8; 8];
-                reader.read_exact(&mut satoshis)?;
-                if let Some(scid) = last_scid {
-                    channel_amts.insert(scid, u64::from_be_bytes(satoshis));
-                    last_scid = None;
-                } else {
-                    warn!("gossip_reader: Malformed gossip_store: 4101 without 256")
+                        direction: 1,
+                    });
+                    channel_amts.remove(&scid);
+                }
+                4106 => {
+                    // 4106 WIRE_GOSSIP_STORE_CHAN_DYING
+                    //  - `scid`: u64
+                    //  - `blockheight`: u32
+                    let mut scid_bytes = vec![0u8; 12];
+                    reader.read_exact(&mut scid_bytes)?;
+                    let scid = extract_scid(&scid_bytes[0..8])?;
+                    channel_dels.push(scid);
+                    channel_anns.remove(&scid);
+                    channel_updates.remove(&DirectedChannel {
+                        short_channel_id: scid,
+                        direction: 0,
+                    });
+                    channel_updates.remove(&DirectedChannel {
+                        short_channel_id: scid,
+                        direction: 1,
+                    });
+                    channel_amts.remove(&scid);
+                }
+                _e => {
+                    // Unknown message type
+                    // debug!("unknown: {}", e);
+                    reader.seek(SeekFrom::Current(len as i64 - 2))?;
                 }
-            }
-            4103 => {
-                // 4103 gossip_store_delete_chan
-                //  - `scid`: u64
-                let mut scid_bytes = vec![0u8; 8];
-                reader.read_exact(&mut scid_bytes)?;
-                let scid = extract_scid(&scid_bytes)?;
-                channel_dels.push(scid);
-                channel_anns.remove(&scid);
-                channel_updates.remove(&DirectedChannel {
-                    short_channel_id: scid,
-                    direction: 0,
-                });
-                channel_updates.remove(&DirectedChannel {
-                    short_channel_id: scid,
-                    direction: 1,
-                });
-                channel_amts.remove(&scid);
-            }
-            4106 => {
-                // 4106 WIRE_GOSSIP_STORE_CHAN_DYING
-                //  - `scid`: u64
-                //  - `blockheight`: u32
-                let mut scid_bytes = vec![0u8; 12];
-                reader.read_exact(&mut scid_bytes)?;
-                let scid = extract_scid(&scid_bytes[0..8])?;
-                channel_dels.push(scid);
-                channel_anns.remove(&scid);
-                channel_updates.remove(&DirectedChannel {
-                    short_channel_id: scid,
-                    direction: 0,
-                });
-                channel_updates.remove(&DirectedChannel {
-                    short_channel_id: scid,
-                    direction: 1,
-                });
-                channel_amts.remove(&scid);
-            }
-            _e => {
-                // Unknown message type
-                // debug!("unknown: {}", e);
-                reader.seek(SeekFrom::Current(len as i64 - 2))?;
             }
         }
+        debug!(
+            "gossip_reader: gossip_store read in: {}ms",
+            now.elapsed().as_millis()
+        );
+        debug!(
+            "gossip_reader: found updates:{} announcements:{} amounts:{} deletes/dying:{}",
+            channel_updates.len(),
+            channel_anns.len(),
+            channel_amts.len(),
+            channel_dels.len()
+        );
+
+        Ok(GraphData {
+            announcements: channel_anns.clone(),
+            amounts: channel_amts.clone(),
+            updates: channel_updates,
+            deleted: channel_dels,
+            full: is_start_up,
+        })
     }
-    debug!(
-        "gossip_reader: gossip_store read in: {}ms",
-        now.elapsed().as_millis()
-    );
-    debug!(
-        "gossip_reader: found updates:{} announcements:{} amounts:{} deletes/dying:{}",
-        channel_updates.len(),
-        channel_anns.len(),
-        channel_amts.len(),
-        channel_dels.len()
-    );
+}
+impl GraphSource for GossipStoreSource {
+    async fn read(&mut self, plugin: &Plugin<PluginState>) -> Result<GraphData, Error> {
+        if self.offset == 0 && !is_supported_store_version(self.store_version()?) {
+            warn!("gossip_reader: Unsupported gossip_store version!");
+            plugin.shutdown()?;
+            return Ok(GraphData::default());
+        }
+        self.read_store()
+    }
+    fn applied(&mut self, graph: &LnGraph) {
+        self.announcements.retain(|scid, chan_ann| {
+            let has_channel = |node| {
+                graph.graph.get(node).map_or(false, |channels| {
+                    channels.contains_key(&DirectedChannel {
+                        short_channel_id: *scid,
+                        direction: 0,
+                    }) || channels.contains_key(&DirectedChannel {
+                        short_channel_id: *scid,
+                        direction: 1,
+                    })
+                })
+            };
 
-    let post_now = Instant::now();
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
+            !(has_channel(&chan_ann.source) && has_channel(&chan_ann.destination))
+        });
 
-    let mut lngraph = plugin.state().graph.lock();
+        let announcements = &self.announcements;
+        self.amounts
+            .retain(|scid, _| announcements.contains_key(scid));
+    }
+}
 
-    for node_channels in lngraph.graph.values_mut() {
-        for (dir_chan, dir_chan_state) in node_channels {
-            if let Some(update) = channel_updates.get(dir_chan) {
-                dir_chan_state.update(update);
-            }
-        }
+pub struct ListchannelsSource;
+impl GraphSource for ListchannelsSource {
+    async fn read(&mut self, plugin: &Plugin<PluginState>) -> Result<GraphData, Error> {
+        let now = Instant::now();
+        let rpc_path = plugin.state().config.lock().rpc_path.clone();
+        let mut rpc = plugin.state().rpc_pool.get_cln(&rpc_path).await?;
+        let result = rpc
+            .call_typed(&ListchannelsRequest {
+                short_channel_id: None,
+                source: None,
+                destination: None,
+            })
+            .await;
+        rpc.discard_on_transport_error(&result);
+        let channels = result?.channels;
+        debug!(
+            "listchannels: got {} channels in: {}ms",
+            channels.len(),
+            now.elapsed().as_millis()
+        );
+        Ok(listchannels_graph_data(&channels))
     }
+}
 
-    for (ann_scid, chan_ann) in channel_anns.iter() {
-        let dir_chan_0 = DirectedChannel {
-            short_channel_id: *ann_scid,
-            direction: 0,
-        };
-        let dir_chan_1 = DirectedChannel {
-            short_channel_id: *ann_scid,
-            direction: 1,
-        };
-        for dir_chan in [dir_chan_0, dir_chan_1] {
-            if let Some(chan_update) = channel_updates.get(&dir_chan) {
-                if let Some(chan_amt) = channel_amts.get(ann_scid) {
-                    let (source, destination) = get_node_order(
-                        chan_update.direction,
-                        chan_ann.source,
-                        chan_ann.destination,
-                    )?;
-                    let new_dir_chan_state = DirectedChannelState {
-                        source,
-                        destination,
-                        active: chan_update.active,
-                        scid_alias: None,
-                        fee_per_millionth: chan_update.fee_per_millionth,
-                        base_fee_millisatoshi: chan_update.base_fee_millisatoshi,
-                        htlc_maximum_msat: chan_update.htlc_maximum_msat,
-                        htlc_minimum_msat: chan_update.htlc_minimum_msat,
-                        amount_msat: Amount::from_sat(*chan_amt),
-                        delay: chan_update.delay,
-                        liquidity: chan_update.htlc_maximum_msat.msat() / 2,
-                        liquidity_age: timestamp,
-                        last_update: chan_update.last_update,
-                    };
-                    if let Some(graph_node_channels) = lngraph.graph.get_mut(&source) {
-                        if let Some(old_dir_chan_state) = graph_node_channels.get_mut(&dir_chan) {
-                            old_dir_chan_state.update(chan_update);
-                        } else {
-                            graph_node_channels.insert(dir_chan, new_dir_chan_state);
-                        }
-                    } else {
-                        let mut first_chan = HashMap::new();
-                        first_chan.insert(dir_chan, new_dir_chan_state);
-                        lngraph.graph.insert(source, first_chan);
-                    }
-                }
-            }
-        }
+pub fn listchannels_graph_data(channels: &[ListchannelsChannels]) -> GraphData {
+    let mut data = GraphData {
+        full: true,
+        ..Default::default()
+    };
+    for chan in channels {
+        data.announcements.insert(
+            chan.short_channel_id,
+            ChannelAnnouncement {
+                source: chan.source,
+                destination: chan.destination,
+            },
+        );
+        data.amounts
+            .insert(chan.short_channel_id, chan.amount_msat.msat() / 1_000);
+        data.updates.insert(
+            DirectedChannel {
+                short_channel_id: chan.short_channel_id,
+                direction: chan.direction,
+            },
+            ChannelUpdate {
+                direction: chan.direction,
+                active: chan.active,
+                last_update: chan.last_update,
+                base_fee_millisatoshi: chan.base_fee_millisatoshi,
+                fee_per_millionth: chan.fee_per_millionth,
+                delay: chan.delay,
+                htlc_minimum_msat: chan.htlc_minimum_msat,
+                htlc_maximum_msat: chan.htlc_maximum_msat.unwrap_or(chan.amount_msat),
+            },
+        );
     }
+    data
+}
 
-    channel_anns.retain(|scid, chan_ann| {
-        let has_channel = |node| {
-            lngraph.graph.get(node).map_or(false, |channels| {
-                channels.contains_key(&DirectedChannel {
-                    short_channel_id: *scid,
-                    direction: 0,
-                }) || channels.contains_key(&DirectedChannel {
-                    short_channel_id: *scid,
-                    direction: 1,
-                })
-            })
-        };
+pub async fn update_graph<S: GraphSource>(
+    plugin: &Plugin<PluginState>,
+    source: &mut S,
+) -> Result<(), Error> {
+    let data = source.read(plugin).await?;
 
-        !(has_channel(&chan_ann.source) && has_channel(&chan_ann.destination))
-    });
+    let post_now = Instant::now();
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
 
-    channel_amts.retain(|scid, _| channel_anns.contains_key(scid));
+    let (my_pubkey, flow_priors) = {
+        let config = plugin.state().config.lock();
+        (config.pubkey, config.flow_priors.value)
+    };
+    let flows = if flow_priors {
+        plugin.state().historical_flows.lock().clone()
+    } else {
+        HashMap::new()
+    };
 
-    for channels in lngraph.graph.values_mut() {
-        channels.retain(|dir_chan, _| {
-            if is_start_up {
-                !channel_dels.contains(&dir_chan.short_channel_id)
-                    && channel_updates.contains_key(dir_chan)
-            } else {
-                !channel_dels.contains(&dir_chan.short_channel_id)
-            }
-        })
-    }
+    let mut lngraph = plugin.state().graph.lock();
+    lngraph.update(&data, &my_pubkey, &flows, timestamp)?;
+    source.applied(&lngraph);
 
     debug!(
         "gossip_reader: post_processing_time: {}ms",
@@ -321,7 +396,7 @@ pub async fn read_gossip_store(plugin: Plugin<PluginState>, offset: &mut u64) ->
     Ok(())
 }
 
-fn get_node_order(
+pub fn get_node_order(
     direction: u32,
     node_1: PublicKey,
     node_2: PublicKey,
@@ -1,9 +1,15 @@
+use std::{
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
 use anyhow::{anyhow, Error, Ok};
 use cln_plugin::Plugin;
+use cln_rpc::primitives::ShortChannelId;
 use log::debug;
 use serde_json::json;
 
-use crate::model::{PluginState, PLUGIN_NAME};
+use crate::model::{ArrivedPay, PluginState, PLUGIN_NAME};
 
 pub async fn htlc_handler(
     plugin: Plugin<PluginState>,
@@ -14,12 +20,17 @@ pub async fn htlc_handler(
             Some(ph) => {
                 let mut pays = plugin.state().pays.write();
                 let ph_str = ph.as_str().unwrap();
-                if pays.contains_key(ph_str) {
-                    let pi = pays.remove(ph_str).unwrap();
-                    debug!("{}: resolving htlc. payment_hash: {}", PLUGIN_NAME, ph_str);
-                    Ok(json!({"result":"resolve","payment_key":pi}))
-                } else {
-                    Ok(json!({"result": "continue"}))
+                let arrived = htlc_arrival(htlc);
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs();
+                match pays.resolve(ph_str, arrived, now) {
+                    Some(pi) => {
+                        debug!("{}: resolving htlc. payment_hash: {}", PLUGIN_NAME, ph_str);
+                        Ok(json!({"result":"resolve","payment_key":pi}))
+                    }
+                    None => Ok(json!({"result": "continue"})),
                 }
             }
             None => Ok(json!({"result": "continue"})),
@@ -28,6 +39,21 @@ pub async fn htlc_handler(
     }
 }
 
+// older versions send the amount as a string with a msat suffix
+fn htlc_arrival(htlc: &serde_json::Value) -> Option<ArrivedPay> {
+    let short_channel_id =
+        ShortChannelId::from_str(htlc.get("short_channel_id")?.as_str()?).ok()?;
+    let amount = htlc.get("amount_msat")?;
+    let amount_msat = match amount.as_u64() {
+        Some(a) => a,
+        None => amount.as_str()?.trim_end_matches("msat").parse().ok()?,
+    };
+    Some(ArrivedPay {
+        short_channel_id,
+        amount_msat,
+    })
+}
+
 pub async fn block_added(plugin: Plugin<PluginState>, v: serde_json::Value) -> Result<(), Error> {
     let block = if let Some(b) = v.get("block") {
         b
@@ -1,6 +1,13 @@
-use std::{collections::HashMap, fmt, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use anyhow::{anyhow, Error};
+use chrono::{DateTime, FixedOffset, NaiveTime, Utc};
+use chrono_tz::Tz;
 use cln_rpc::{
     model::responses::ListpeerchannelsChannels,
     primitives::{Amount, PublicKey, ShortChannelId},
@@ -36,6 +43,97 @@ impl fmt::Display for SatDirection {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, Copy, PartialEq, Eq, Default)]
+pub enum RouteObjective {
+    #[default]
+    #[serde(alias = "cheapest")]
+    Cheapest,
+    #[serde(alias = "shortest")]
+    Shortest,
+}
+
+impl FromStr for RouteObjective {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cheapest" => Ok(RouteObjective::Cheapest),
+            "shortest" => Ok(RouteObjective::Shortest),
+            _ => Err(anyhow!("could not parse route objective from `{}`", s)),
+        }
+    }
+}
+impl fmt::Display for RouteObjective {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RouteObjective::Cheapest => write!(f, "cheapest"),
+            RouteObjective::Shortest => write!(f, "shortest"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScheduleZone {
+    Offset(FixedOffset),
+    Named(Tz),
+}
+
+// a daily window in the wall clock time of its zone, e.g. `22:00-06:00 America/New_York`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Schedule {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub zone: ScheduleZone,
+}
+
+impl FromStr for Schedule {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split_whitespace();
+        let window = parts
+            .next()
+            .ok_or(anyhow!("could not parse schedule from `{}`", s))?;
+        let zone = match parts.next() {
+            Some(z) if z.starts_with(['+', '-']) => ScheduleZone::Offset(
+                FixedOffset::from_str(z)
+                    .map_err(|_| anyhow!("could not parse schedule offset from `{}`", z))?,
+            ),
+            Some(z) => ScheduleZone::Named(
+                Tz::from_str(z).map_err(|_| anyhow!("unknown schedule timezone `{}`", z))?,
+            ),
+            None => ScheduleZone::Named(Tz::UTC),
+        };
+        if parts.next().is_some() {
+            return Err(anyhow!("could not parse schedule from `{}`", s));
+        }
+        let (start, end) = window
+            .split_once('-')
+            .ok_or(anyhow!("schedule window must look like `HH:MM-HH:MM`"))?;
+        let start = NaiveTime::parse_from_str(start, "%H:%M")
+            .map_err(|_| anyhow!("could not parse schedule start from `{}`", start))?;
+        let end = NaiveTime::parse_from_str(end, "%H:%M")
+            .map_err(|_| anyhow!("could not parse schedule end from `{}`", end))?;
+        if start == end {
+            return Err(anyhow!("schedule start and end must not be the same"));
+        }
+        Ok(Schedule { start, end, zone })
+    }
+}
+
+impl Schedule {
+    // converting the instant to local time makes the window follow DST shifts
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        let local = match self.zone {
+            ScheduleZone::Offset(offset) => now.with_timezone(&offset).time(),
+            ScheduleZone::Named(tz) => now.with_timezone(&tz).time(),
+        };
+        if self.start < self.end {
+            self.start <= local && local < self.end
+        } else {
+            local >= self.start || local < self.end
+        }
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct DirectedChannel {
     pub short_channel_id: ShortChannelId,
@@ -76,11 +174,21 @@ pub struct Job {
     #[serde(alias = "amount")]
     pub amount_msat: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_amount_msat: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount_fraction: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub outppm: Option<u64>,
     pub maxppm: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maxppm_pull: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maxppm_push: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub candidatelist: Option<Vec<ShortChannelId>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub candidatelist_backup: Option<Vec<ShortChannelId>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub target: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub maxhops: Option<u8>,
@@ -90,28 +198,122 @@ pub struct Job {
     pub depleteuptoamount: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub paralleljobs: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paralleljobs_min: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer: Option<PublicKey>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dryrun: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub via_node: Option<PublicKey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub objective: Option<RouteObjective>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_total_sat: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_rebalance_sat: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subdir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<String>,
+}
+
+pub fn has_balance_fields(channel: &ListpeerchannelsChannels) -> bool {
+    channel.total_msat.is_some()
+        && channel.to_us_msat.is_some()
+        && channel.our_reserve_msat.is_some()
+        && channel.their_reserve_msat.is_some()
+        && channel.spendable_msat.is_some()
+        && channel.receivable_msat.is_some()
 }
 
 impl Job {
+    pub fn outside_schedule(&self, now: DateTime<Utc>) -> bool {
+        self.schedule
+            .as_deref()
+            .and_then(|s| Schedule::from_str(s).ok())
+            .is_some_and(|s| !s.contains(now))
+    }
     pub fn is_balanced(
         &self,
-        channel: &ListpeerchannelsChannels,
+        channels: &[ListpeerchannelsChannels],
         chan_id: &ShortChannelId,
     ) -> bool {
-        let target_cap = self.target_cap(channel);
+        let target_cap = self.target_cap(channels);
         debug!("{}: target: {}sats", chan_id, target_cap / 1_000);
 
-        let channel_msat = Amount::msat(&channel.total_msat.unwrap());
-        let to_us_msat = Amount::msat(&channel.to_us_msat.unwrap());
+        let channel_msat: u64 = channels
+            .iter()
+            .filter(|c| has_balance_fields(c))
+            .map(|c| Amount::msat(&c.total_msat.unwrap()))
+            .sum();
+        let to_us_msat: u64 = channels
+            .iter()
+            .filter(|c| has_balance_fields(c))
+            .map(|c| Amount::msat(&c.to_us_msat.unwrap()))
+            .sum();
 
+        let current_msat = match self.sat_direction {
+            SatDirection::Pull => to_us_msat,
+            SatDirection::Push => channel_msat - to_us_msat,
+        };
+        let gap_msat = target_cap.saturating_sub(current_msat);
+        let min_gap_msat = self.min_rebalance_sat.unwrap_or(0).saturating_mul(1_000);
+        if gap_msat > 0 && gap_msat < min_gap_msat {
+            debug!(
+                "{}: {}sats below target is less than min_rebalance_sat",
+                chan_id,
+                gap_msat / 1_000
+            );
+            return true;
+        }
+        gap_msat == 0
+    }
+    // the share of the channel that should be on our side, a push job's
+    // target is the share on their side
+    pub fn local_target(&self) -> f64 {
+        let target = self.target.unwrap_or(0.5);
         match self.sat_direction {
-            SatDirection::Pull => to_us_msat >= target_cap,
-            SatDirection::Push => channel_msat - to_us_msat >= target_cap,
+            SatDirection::Pull => target,
+            SatDirection::Push => 1.0 - target,
         }
     }
-    pub fn target_cap(&self, channel: &ListpeerchannelsChannels) -> u64 {
+    pub fn target_cap(&self, channels: &[ListpeerchannelsChannels]) -> u64 {
+        channels
+            .iter()
+            .filter_map(|c| self.channel_target_cap(c))
+            .sum()
+    }
+    // how far a single channel is below its share of the job's target
+    pub fn channel_shortfall(&self, channel: &ListpeerchannelsChannels) -> u64 {
+        let target_cap = match self.channel_target_cap(channel) {
+            Some(t) => t,
+            None => return 0,
+        };
+        let total_msat = Amount::msat(&channel.total_msat.unwrap());
+        let to_us_msat = Amount::msat(&channel.to_us_msat.unwrap());
+        let current_msat = match self.sat_direction {
+            SatDirection::Pull => to_us_msat,
+            SatDirection::Push => total_msat - to_us_msat,
+        };
+        target_cap.saturating_sub(current_msat)
+    }
+    fn channel_target_cap(&self, channel: &ListpeerchannelsChannels) -> Option<u64> {
         let target = self.target.unwrap_or(0.5);
 
+        if !has_balance_fields(channel) {
+            debug!(
+                "{:?}: missing balance fields, skipping",
+                channel.short_channel_id
+            );
+            return None;
+        }
         let total_msat = Amount::msat(&channel.total_msat.unwrap());
         let their_reserve_msat = Amount::msat(&channel.their_reserve_msat.unwrap());
         let our_reserve_msat = Amount::msat(&channel.our_reserve_msat.unwrap());
@@ -129,13 +331,105 @@ impl Job {
                 }
             }
         }
-        target_cap
+        Some(target_cap)
+    }
+    pub fn dedup_candidatelist(&mut self) -> Vec<ShortChannelId> {
+        let mut duplicates = Vec::new();
+        if let Some(candidates) = &mut self.candidatelist {
+            let mut seen = HashSet::new();
+            candidates.retain(|c| {
+                if seen.insert(*c) {
+                    true
+                } else {
+                    duplicates.push(*c);
+                    false
+                }
+            });
+        }
+        duplicates
+    }
+    pub fn drop_target_candidate(&mut self, target: &ShortChannelId) -> bool {
+        let mut dropped = false;
+        for candidates in [&mut self.candidatelist, &mut self.candidatelist_backup]
+            .into_iter()
+            .flatten()
+        {
+            let len = candidates.len();
+            candidates.retain(|c| c != target);
+            dropped |= candidates.len() != len;
+        }
+        dropped
+    }
+    pub fn backup_job(&self) -> Option<Job> {
+        match &self.candidatelist_backup {
+            Some(backup) if !backup.is_empty() => Some(Job {
+                candidatelist: Some(backup.clone()),
+                candidatelist_backup: None,
+                ..self.clone()
+            }),
+            _ => None,
+        }
+    }
+    pub fn split_amount(&self, amount_msat: u64) -> Option<u64> {
+        match self.min_amount_msat {
+            Some(min) if amount_msat / 2 >= min => Some(amount_msat / 2),
+            Some(min) if amount_msat > min => Some(min),
+            _ => None,
+        }
+    }
+    pub fn route_amount(&self, candidate_capacity_msat: u64, limit_msat: u64) -> u64 {
+        match self.amount_fraction {
+            Some(fraction) => ((candidate_capacity_msat as f64 * fraction) as u64)
+                .min(limit_msat)
+                .min(self.amount_msat),
+            None => self.amount_msat,
+        }
+    }
+    // the fee ceiling for rebalancing in direction, falls back to maxppm
+    pub fn maxppm_for(&self, direction: SatDirection) -> u32 {
+        match direction {
+            SatDirection::Pull => self.maxppm_pull,
+            SatDirection::Push => self.maxppm_push,
+        }
+        .unwrap_or(self.maxppm)
+    }
+    // where the success and failure stats of the job are kept
+    pub fn stats_dir(&self, sling_dir: &Path) -> PathBuf {
+        match &self.subdir {
+            Some(s) => sling_dir.join(s),
+            None => sling_dir.to_path_buf(),
+        }
+    }
+    pub fn total_cap_reached(&self, total_rebalanced_msat: u64) -> bool {
+        self.max_total_sat
+            .is_some_and(|cap| total_rebalanced_msat >= cap.saturating_mul(1_000))
+    }
+    pub fn with_amount(&self, amount_msat: u64) -> Job {
+        Job {
+            amount_msat,
+            ..self.clone()
+        }
     }
     pub fn to_json(&self) -> serde_json::Value {
         let mut result = HashMap::new();
         result.insert("direction", self.sat_direction.to_string());
         result.insert("amount", (self.amount_msat / 1_000).to_string());
+        result.insert("amount_msat", self.amount_msat.to_string());
         result.insert("maxppm", self.maxppm.to_string());
+        if let Some(m) = self.maxppm_pull {
+            result.insert("maxppm_pull", m.to_string());
+        }
+        if let Some(m) = self.maxppm_push {
+            result.insert("maxppm_push", m.to_string());
+        }
+        if let Some(m) = self.min_amount_msat {
+            result.insert("min_amount", (m / 1_000).to_string());
+            result.insert("min_amount_msat", m.to_string());
+        }
+        match self.amount_fraction {
+            Some(f) => result.insert("amount_fraction", f.to_string()),
+            None => None,
+        };
         match self.outppm {
             Some(o) => result.insert("outppm", o.to_string()),
             None => None,
@@ -158,18 +452,64 @@ impl Job {
             ),
             None => None,
         };
-        match self.depleteuptopercent {
-            Some(dp) => result.insert("depleteuptopercent", dp.to_string()),
+        match &self.candidatelist_backup {
+            Some(c) => result.insert(
+                "candidates_backup",
+                c.iter()
+                    .map(|y| y.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", "),
+            ),
             None => None,
         };
-        match self.depleteuptoamount {
-            Some(da) => result.insert("depleteuptoamount", (da / 1_000).to_string()),
+        match self.depleteuptopercent {
+            Some(dp) => result.insert("depleteuptopercent", dp.to_string()),
             None => None,
         };
+        if let Some(da) = self.depleteuptoamount {
+            result.insert("depleteuptoamount", (da / 1_000).to_string());
+            result.insert("depleteuptoamount_msat", da.to_string());
+        }
         match self.paralleljobs {
             Some(pj) => result.insert("paralleljobs", pj.to_string()),
             None => None,
         };
+        if let Some(pm) = self.paralleljobs_min {
+            result.insert("paralleljobs_min", pm.to_string());
+        }
+        if let Some(p) = self.peer {
+            result.insert("peer", p.to_string());
+        }
+        if self.dryrun {
+            result.insert("dryrun", self.dryrun.to_string());
+        }
+        if let Some(v) = self.via_node {
+            result.insert("via_node", v.to_string());
+        }
+        if let Some(o) = self.objective {
+            result.insert("objective", o.to_string());
+        }
+        if let Some(t) = &self.tags {
+            result.insert("tags", t.join(", "));
+        }
+        if let Some(m) = self.max_total_sat {
+            result.insert("max_total_sat", m.to_string());
+        }
+        if let Some(m) = self.min_rebalance_sat {
+            result.insert("min_rebalance_sat", m.to_string());
+        }
+        if let Some(s) = &self.subdir {
+            result.insert("subdir", s.clone());
+        }
+        if let Some(n) = &self.note {
+            result.insert("note", n.clone());
+        }
+        if let Some(g) = &self.group {
+            result.insert("group", g.clone());
+        }
+        if let Some(s) = &self.schedule {
+            result.insert("schedule", s.clone());
+        }
         json!(result)
     }
 }
@@ -198,6 +538,8 @@ pub struct FailureReasonCount {
 pub struct FailuresInTimeWindow {
     pub time_window_days: String,
     pub total_amount_tried_sats: u64,
+    #[serde(default)]
+    pub total_amount_tried_msat: u64,
     pub top_5_failure_reasons: Vec<FailureReasonCount>,
     pub top_5_fail_nodes: Vec<PeerPartnerStats>,
     pub top_5_channel_partners: Vec<ChannelPartnerStats>,
@@ -220,10 +562,131 @@ pub struct SuccessesInTimeWindow {
     pub time_of_last_rebalance: String,
     pub total_rebalances: u64,
     pub total_spent_sats: u64,
+    #[serde(default)]
+    pub total_amount_msat: u64,
+    #[serde(default)]
+    pub total_spent_msat: u64,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct SlingStats {
     pub successes_in_time_window: Option<SuccessesInTimeWindow>,
     pub failures_in_time_window: Option<FailuresInTimeWindow>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fee_comparison: Option<FeeComparison>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub job_states: Vec<JobStateStats>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lifetime: Option<StatsRollup>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+// totals of stats that survive pruning of the raw success and failure logs
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
+pub struct StatsRollup {
+    pub total_rebalances: u64,
+    pub total_amount_msat: u64,
+    pub total_spent_msat: u64,
+    pub feeppm_weighted_avg: u64,
+    pub total_rebalances_failed: u64,
+    pub total_failed_amount_msat: u64,
+}
+impl StatsRollup {
+    pub fn add_success(&mut self, amount_msat: u64, fee_msat: u64) {
+        self.total_rebalances += 1;
+        self.total_amount_msat += amount_msat;
+        self.total_spent_msat += fee_msat;
+        self.feeppm_weighted_avg = self.total_spent_msat * 1_000_000 / self.total_amount_msat;
+    }
+    pub fn add_failure(&mut self, amount_msat: u64) {
+        self.total_rebalances_failed += 1;
+        self.total_failed_amount_msat += amount_msat;
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct RollupStats {
+    pub period: String,
+    pub total_amount_sats: u64,
+    pub total_spent_sats: u64,
+    #[serde(default)]
+    pub total_amount_msat: u64,
+    #[serde(default)]
+    pub total_spent_msat: u64,
+    pub total_rebalances: u64,
+    pub total_rebalances_failed: u64,
+    pub success_rate: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Rollups {
+    pub weekly: Vec<RollupStats>,
+    pub monthly: Vec<RollupStats>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct JobStateStats {
+    pub task_id: u8,
+    pub state: String,
+    pub code: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stuck_htlc: Option<StuckHtlc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub success_ratio: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub too_expensive: Option<TooExpensive>,
+}
+
+// the cheapest route a task in TooExp found, to compare with its maxppm
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub struct TooExpensive {
+    pub cheapest_feeppm: u32,
+    pub maxppm: u32,
+}
+
+// a rebalance that is still unresolved well after sling-timeoutpay
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct StuckHtlc {
+    pub payment_hash: String,
+    pub short_channel_id: ShortChannelId,
+    pub amount_msat: u64,
+    pub sent_at: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ChannelRoi {
+    pub short_channel_id: ShortChannelId,
+    pub forward_fees_msat: u64,
+    pub rebalance_fees_msat: u64,
+    pub net_msat: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ExceptImpact {
+    pub short_channel_id: ShortChannelId,
+    pub direction: SatDirection,
+    pub listed_candidate: bool,
+    pub current_candidate: bool,
+    pub recent_successes: u64,
+    pub recent_success_msat: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct MaxppmSuggestion {
+    pub samples: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history_p75_feeppm: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network_median_feeppm: Option<u32>,
+    pub suggested_maxppm: u32,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct FeeComparison {
+    pub feeppm_weighted_avg: u64,
+    pub hops_avg: f64,
+    pub network_median_hop_feeppm: u32,
+    pub network_expected_feeppm: u64,
+    pub overpaying: bool,
 }
@@ -47,17 +47,55 @@ const OPT_UTF8: &str = "sling-utf8";
 const OPT_REFRESH_PEERS_INTERVAL: &str = "sling-refresh-peers-interval";
 const OPT_REFRESH_ALIASMAP_INTERVAL: &str = "sling-refresh-aliasmap-interval";
 const OPT_REFRESH_GOSSMAP_INTERVAL: &str = "sling-refresh-gossmap-interval";
+const OPT_GRAPH_MAX_CHANNELS: &str = "sling-graph-max-channels";
+const OPT_GRAPH_SOURCE: &str = "sling-graph-source";
 const OPT_RESET_LIQUIDITY_INTERVAL: &str = "sling-reset-liquidity-interval";
+const OPT_LIQUIDITY_MAX_AGE: &str = "sling-liquidity-max-age";
+const OPT_LIQUIDITY_CONFIDENCE_WINDOW: &str = "sling-liquidity-confidence-window";
+const OPT_FLOW_PRIORS: &str = "sling-flow-priors";
+const OPT_HOP_RESULTS: &str = "sling-hop-results";
+const OPT_TRACE: &str = "sling-trace";
+const OPT_ROI_WARN: &str = "sling-roi-warn";
+const OPT_VERIFY_BALANCE: &str = "sling-verify-balance";
+const OPT_VERIFY_OWN_PAYMENT: &str = "sling-verify-own-payment";
 const OPT_DEPLETEUPTOPERCENT: &str = "sling-depleteuptopercent";
 const OPT_DEPLETEUPTOAMOUNT: &str = "sling-depleteuptoamount";
 const OPT_MAXHOPS: &str = "sling-maxhops";
+const OPT_CANDIDATE_ORDER: &str = "sling-candidate-order";
 const OPT_CANDIDATES_MIN_AGE: &str = "sling-candidates-min-age";
+const OPT_MAX_CANDIDATES_PER_ITERATION: &str = "sling-max-candidates-per-iteration";
+const OPT_CANDIDATES_CAP_STRATEGY: &str = "sling-candidates-cap-strategy";
+const OPT_MODE: &str = "sling-mode";
+const OPT_EXCLUDE_ALIAS: &str = "sling-exclude-alias";
+const OPT_ROUTE_CACHE_MAX_AGE: &str = "sling-route-cache-max-age";
+const OPT_MIN_FORWARD_VOLUME: &str = "sling-min-forward-volume";
+const OPT_MAX_PEER_DATA_AGE: &str = "sling-max-peer-data-age";
+const OPT_RPC_CONCURRENCY: &str = "sling-rpc-concurrency";
 const OPT_PARALLELJOBS: &str = "sling-paralleljobs";
 const OPT_TIMEOUTPAY: &str = "sling-timeoutpay";
+const OPT_MAX_ROUTE_ATTEMPTS: &str = "sling-max-route-attempts-per-iteration";
+const OPT_CIRCUIT_BREAKER_THRESHOLD: &str = "sling-circuit-breaker-threshold";
+const OPT_NODE_BAN_THRESHOLD: &str = "sling-node-ban-threshold";
+const OPT_NODE_BAN_WINDOW: &str = "sling-node-ban-window";
+const OPT_STARTUP_DELAY_SECS: &str = "sling-startup-delay-secs";
+const OPT_MIN_FUNDED_SATS: &str = "sling-min-funded-sats";
 const OPT_MAX_HTLC_COUNT: &str = "sling-max-htlc-count";
+const OPT_MAX_PEER_HTLC_COUNT: &str = "sling-max-peer-htlc-count";
+const OPT_MAX_PEER_HTLC_VALUE: &str = "sling-max-peer-htlc-value";
+const OPT_CANDIDATE_MAX_FAILURES: &str = "sling-candidate-max-failures";
+const OPT_RELIABILITY_WEIGHT: &str = "sling-reliability-weight";
+const OPT_CAPACITY_PREFERENCE: &str = "sling-capacity-preference";
+const OPT_NODE_PENALTY: &str = "sling-node-penalty";
+const OPT_NODE_PENALTY_HALF_LIFE: &str = "sling-node-penalty-half-life";
+const OPT_GRAPH_WRITE_INTERVAL: &str = "sling-graph-write-interval";
+const OPT_PAYS_SWEEP_INTERVAL: &str = "sling-pays-sweep-interval";
+const OPT_STATS_CACHE_SIZE: &str = "sling-stats-cache-size";
+const OPT_POST_SUCCESS_COMMAND: &str = "sling-post-success-command";
 const OPT_STATS_DELETE_FAILURES_AGE: &str = "sling-stats-delete-failures-age";
 const OPT_STATS_DELETE_FAILURES_SIZE: &str = "sling-stats-delete-failures-size";
 const OPT_STATS_DELETE_SUCCESSES_AGE: &str = "sling-stats-delete-successes-age";
+const OPT_STATS_RECENT_WINDOW_DAYS: &str = "sling-stats-recent-window-days";
+const OPT_STATS_ROLLUP: &str = "sling-stats-rollup";
 const OPT_STATS_DELETE_SUCCESSES_SIZE: &str = "sling-stats-delete-successes-size";
 
 #[tokio::main]
@@ -86,11 +124,61 @@ async fn main() -> Result<(), anyhow::Error> {
         "Refresh interval for gossmap task. Default is `10`",
     )
     .dynamic();
+    let opt_graph_max_channels: IntegerConfigOption = ConfigOption::new_i64_no_default(
+        OPT_GRAPH_MAX_CHANNELS,
+        "Max number of channels to keep in the graph. Default is `0` (unlimited)",
+    )
+    .dynamic();
+    let opt_graph_source: StringConfigOption = ConfigOption::new_str_no_default(
+        OPT_GRAPH_SOURCE,
+        "Where to read the graph from, `gossip_store` or `listchannels`. Default is `gossip_store`",
+    )
+    .dynamic();
     let opt_reset_liquidity_interval: IntegerConfigOption = ConfigOption::new_i64_no_default(
         OPT_RESET_LIQUIDITY_INTERVAL,
         "Refresh interval for liquidity reset task. Default is `360`",
     )
     .dynamic();
+    let opt_liquidity_max_age: IntegerConfigOption = ConfigOption::new_i64_no_default(
+        OPT_LIQUIDITY_MAX_AGE,
+        "After how many minutes to stop trusting a liquidity belief. Default is `0` (off)",
+    )
+    .dynamic();
+    let opt_liquidity_confidence_window: IntegerConfigOption = ConfigOption::new_i64_no_default(
+        OPT_LIQUIDITY_CONFIDENCE_WINDOW,
+        "Minutes over which the confidence in a liquidity belief widens to the full channel. Default is `0` (off)",
+    )
+    .dynamic();
+    let opt_flow_priors: BooleanConfigOption = ConfigOption::new_bool_no_default(
+        OPT_FLOW_PRIORS,
+        "Use historical rebalance flow of our channels for their initial liquidity belief. Default is `false`",
+    )
+    .dynamic();
+    let opt_hop_results: BooleanConfigOption = ConfigOption::new_bool_no_default(
+        OPT_HOP_RESULTS,
+        "Record the outcome of every hop of every attempt in hop_results.jsonl. Default is `false`",
+    )
+    .dynamic();
+    let opt_trace: BooleanConfigOption = ConfigOption::new_bool_no_default(
+        OPT_TRACE,
+        "Record the candidates, route and outcome of every iteration in trace.jsonl. Default is `false`",
+    )
+    .dynamic();
+    let opt_roi_warn: BooleanConfigOption = ConfigOption::new_bool_no_default(
+        OPT_ROI_WARN,
+        "Log a warning for channels that spent more on rebalancing than they earned forwarding in the recent stats window. Default is `false`",
+    )
+    .dynamic();
+    let opt_verify_balance: BooleanConfigOption = ConfigOption::new_bool_no_default(
+        OPT_VERIFY_BALANCE,
+        "Warn if our balance did not move after a successful rebalance. Default is `false`",
+    )
+    .dynamic();
+    let opt_verify_own_payment: BooleanConfigOption = ConfigOption::new_bool_no_default(
+        OPT_VERIFY_OWN_PAYMENT,
+        "Only record a success if our own payment arrived back over the job's channel. Default is `false`",
+    )
+    .dynamic();
     let opt_depleteuptopercent: StringConfigOption = ConfigOption::new_str_no_default(
         OPT_DEPLETEUPTOPERCENT,
         "Deplete up to percent for candidate search. Default is `0.2`",
@@ -103,7 +191,7 @@ async fn main() -> Result<(), anyhow::Error> {
     .dynamic();
     let opt_maxhops: IntegerConfigOption = ConfigOption::new_i64_no_default(
         OPT_MAXHOPS,
-        "Maximum number of hops in a route. Default is `8`",
+        "Maximum number of hops in a route, `0` for no limit. Default is `8`",
     )
     .dynamic();
     let opt_candidates_min_age: IntegerConfigOption = ConfigOption::new_i64_no_default(
@@ -111,6 +199,51 @@ async fn main() -> Result<(), anyhow::Error> {
         "Minium age of a candidate to rebalance with in days. Default is `0`",
     )
     .dynamic();
+    let opt_candidate_order: StringConfigOption = ConfigOption::new_str_no_default(
+        OPT_CANDIDATE_ORDER,
+        "Order to try candidates in, `cheapest`, `largest-first`, `most-overfull` or `most-over-target`. Default is `cheapest`",
+    )
+    .dynamic();
+    let opt_max_candidates_per_iteration: IntegerConfigOption = ConfigOption::new_i64_no_default(
+        OPT_MAX_CANDIDATES_PER_ITERATION,
+        "Max number of candidates a job considers per iteration, `0` for no limit. Default is `0`",
+    )
+    .dynamic();
+    let opt_candidates_cap_strategy: StringConfigOption = ConfigOption::new_str_no_default(
+        OPT_CANDIDATES_CAP_STRATEGY,
+        "Which candidates to consider if there are more than `sling-max-candidates-per-iteration`, `round-robin`, `most-imbalanced` or `cheapest-history`. Default is `round-robin`",
+    )
+    .dynamic();
+    let opt_mode: StringConfigOption = ConfigOption::new_str_no_default(
+        OPT_MODE,
+        "`active` rebalances, `follower` keeps graph and stats up to date without sending payments. Default is `active`",
+    )
+    .dynamic();
+    let opt_exclude_alias: StringConfigOption = ConfigOption::new_str_no_default(
+        OPT_EXCLUDE_ALIAS,
+        "Comma separated list of alias parts, candidates whose peer alias contains one are skipped. Default is none",
+    )
+    .dynamic();
+    let opt_route_cache_max_age: IntegerConfigOption = ConfigOption::new_i64_no_default(
+        OPT_ROUTE_CACHE_MAX_AGE,
+        "Keep the last successful route of each job across restarts for this many minutes. Default is `0` (off)",
+    )
+    .dynamic();
+    let opt_min_forward_volume: IntegerConfigOption = ConfigOption::new_i64_no_default(
+        OPT_MIN_FORWARD_VOLUME,
+        "Skip jobs on channels that forwarded less than this many sats in the recent stats window. Default is `0` (off)",
+    )
+    .dynamic();
+    let opt_max_peer_data_age: IntegerConfigOption = ConfigOption::new_i64_no_default(
+        OPT_MAX_PEER_DATA_AGE,
+        "Hold all jobs if our channel data wasn't refreshed for this many seconds. Default is `0` (off)",
+    )
+    .dynamic();
+    let opt_rpc_concurrency: IntegerConfigOption = ConfigOption::new_i64_no_default(
+        OPT_RPC_CONCURRENCY,
+        "Max number of pooled rpc connections to lightningd in use at once. Default is `4`",
+    )
+    .dynamic();
     let opt_paralleljobs: IntegerConfigOption = ConfigOption::new_i64_no_default(
         OPT_PARALLELJOBS,
         "Number of parallel tasks for a job. Default is `1`",
@@ -121,11 +254,96 @@ async fn main() -> Result<(), anyhow::Error> {
         "Timeout for rebalances until we give up and continue. Default is `120`",
     )
     .dynamic();
+    let opt_max_route_attempts: IntegerConfigOption = ConfigOption::new_i64_no_default(
+        OPT_MAX_ROUTE_ATTEMPTS,
+        "Max number of failed routes in a row before a job sleeps. Default is `0` (unlimited)",
+    )
+    .dynamic();
+    let opt_candidate_max_failures: IntegerConfigOption = ConfigOption::new_i64_no_default(
+        OPT_CANDIDATE_MAX_FAILURES,
+        "Number of failed routes in a row through a candidate after which a job tries its other candidates first. Default is `0` (off)",
+    )
+    .dynamic();
+    let opt_circuit_breaker_threshold: IntegerConfigOption = ConfigOption::new_i64_no_default(
+        OPT_CIRCUIT_BREAKER_THRESHOLD,
+        "Number of failed payments in a row after which a job is disabled until reset. Default is `0` (disabled)",
+    )
+    .dynamic();
+    let opt_node_ban_threshold: IntegerConfigOption = ConfigOption::new_i64_no_default(
+        OPT_NODE_BAN_THRESHOLD,
+        "Number of failures at a node within the ban window after which it is avoided. Default is `0` (disabled)",
+    )
+    .dynamic();
+    let opt_node_ban_window: IntegerConfigOption = ConfigOption::new_i64_no_default(
+        OPT_NODE_BAN_WINDOW,
+        "Minutes to count node failures in and to avoid a node for. Default is `60`",
+    )
+    .dynamic();
+    let opt_startup_delay_secs: IntegerConfigOption = ConfigOption::new_i64_no_default(
+        OPT_STARTUP_DELAY_SECS,
+        "Seconds to wait after startup and the first graph refresh before jobs start rebalancing. Default is `0`",
+    )
+    .dynamic();
+    let opt_min_funded_sats: IntegerConfigOption = ConfigOption::new_i64_no_default(
+        OPT_MIN_FUNDED_SATS,
+        "Job channels with a total below this many sats are treated as not yet funded. Default is `0` (only empty channels)",
+    )
+    .dynamic();
     let opt_max_htlc_count: IntegerConfigOption = ConfigOption::new_i64_no_default(
         OPT_MAX_HTLC_COUNT,
         "Max number of htlc allowed pending in job and candidate. Default is `5`",
     )
     .dynamic();
+    let opt_max_peer_htlc_count: IntegerConfigOption = ConfigOption::new_i64_no_default(
+        OPT_MAX_PEER_HTLC_COUNT,
+        "Max number of htlc allowed pending across all channels with a candidate's peer. Default is `0` (off)",
+    )
+    .dynamic();
+    let opt_max_peer_htlc_value: IntegerConfigOption = ConfigOption::new_i64_no_default(
+        OPT_MAX_PEER_HTLC_VALUE,
+        "Max percent of a candidate peer's in-flight value limit that may be used by pending htlcs. Default is `0` (off)",
+    )
+    .dynamic();
+    let opt_reliability_weight: StringConfigOption = ConfigOption::new_str_no_default(
+        OPT_RELIABILITY_WEIGHT,
+        "Weight of candidate failure rates in route scoring, 0 is cheapest fee only. Default is `0`",
+    )
+    .dynamic();
+    let opt_capacity_preference: IntegerConfigOption = ConfigOption::new_i64_no_default(
+        OPT_CAPACITY_PREFERENCE,
+        "ppm added to the route score per capacity tier a hop is away from large (positive) or small (negative) channels. Default is `0`",
+    )
+    .dynamic();
+    let opt_node_penalty: IntegerConfigOption = ConfigOption::new_i64_no_default(
+        OPT_NODE_PENALTY,
+        "ppm added to the route score for hops out of nodes that recently failed our payments, decaying over time. Default is `0` (disabled)",
+    )
+    .dynamic();
+    let opt_node_penalty_half_life: IntegerConfigOption = ConfigOption::new_i64_no_default(
+        OPT_NODE_PENALTY_HALF_LIFE,
+        "Minutes after which a node failure penalty has decayed to half. Default is `60`",
+    )
+    .dynamic();
+    let opt_graph_write_interval: IntegerConfigOption = ConfigOption::new_i64_no_default(
+        OPT_GRAPH_WRITE_INTERVAL,
+        "Minimum minutes between writing the graph to disk after a refresh. Default is `0` (only on shutdown)",
+    )
+    .dynamic();
+    let opt_pays_sweep_interval: IntegerConfigOption = ConfigOption::new_i64_no_default(
+        OPT_PAYS_SWEEP_INTERVAL,
+        "Minutes between sweeps of preimages of payments that never resolved. Default is `60`, `0` disables",
+    )
+    .dynamic();
+    let opt_stats_cache_size: IntegerConfigOption = ConfigOption::new_i64_no_default(
+        OPT_STATS_CACHE_SIZE,
+        "Maximum number of channels whose stats totals are kept in memory. Default is `100`, `0` disables",
+    )
+    .dynamic();
+    let opt_post_success_command: StringConfigOption = ConfigOption::new_str_no_default(
+        OPT_POST_SUCCESS_COMMAND,
+        "Executable to run after each successful rebalance with scid, amount_msat and fee_ppm as arguments. Default is none",
+    )
+    .dynamic();
     let opt_stats_delete_failures_age: IntegerConfigOption = ConfigOption::new_i64_no_default(
         OPT_STATS_DELETE_FAILURES_AGE,
         "Max age of failure stats in days. Default is `30`",
@@ -146,6 +364,16 @@ async fn main() -> Result<(), anyhow::Error> {
         "Max number of success stats per channel. Default is `10000`",
     )
     .dynamic();
+    let opt_stats_recent_window_days: IntegerConfigOption = ConfigOption::new_i64_no_default(
+        OPT_STATS_RECENT_WINDOW_DAYS,
+        "Days of stats counted as recent for fee ppm, amounts and success rates. Default is `30`",
+    )
+    .dynamic();
+    let opt_stats_rollup: BooleanConfigOption = ConfigOption::new_bool_no_default(
+        OPT_STATS_ROLLUP,
+        "Add pruned stats to a per channel rollup file. Default is `false`",
+    )
+    .dynamic();
     match Builder::new(tokio::io::stdin(), tokio::io::stdout())
         .hook("htlc_accepted", htlc_handler)
         .subscribe("block_added", block_added)
@@ -153,29 +381,87 @@ async fn main() -> Result<(), anyhow::Error> {
         .option(opt_refresh_peers_interval)
         .option(opt_refresh_aliasmap_interval)
         .option(opt_refresh_gossmap_interval)
+        .option(opt_graph_max_channels)
+        .option(opt_graph_source)
         .option(opt_reset_liquidity_interval)
+        .option(opt_liquidity_max_age)
+        .option(opt_liquidity_confidence_window)
+        .option(opt_flow_priors)
+        .option(opt_hop_results)
+        .option(opt_trace)
+        .option(opt_roi_warn)
+        .option(opt_verify_balance)
+        .option(opt_verify_own_payment)
         .option(opt_depleteuptopercent)
         .option(opt_depleteuptoamount)
         .option(opt_maxhops)
         .option(opt_candidates_min_age)
+        .option(opt_candidate_order)
+        .option(opt_max_candidates_per_iteration)
+        .option(opt_candidates_cap_strategy)
+        .option(opt_mode)
+        .option(opt_exclude_alias)
+        .option(opt_route_cache_max_age)
+        .option(opt_min_forward_volume)
+        .option(opt_max_peer_data_age)
+        .option(opt_rpc_concurrency)
         .option(opt_paralleljobs)
         .option(opt_timeoutpay)
+        .option(opt_max_route_attempts)
+        .option(opt_candidate_max_failures)
+        .option(opt_circuit_breaker_threshold)
+        .option(opt_node_ban_threshold)
+        .option(opt_node_ban_window)
+        .option(opt_startup_delay_secs)
+        .option(opt_min_funded_sats)
         .option(opt_max_htlc_count)
+        .option(opt_max_peer_htlc_count)
+        .option(opt_max_peer_htlc_value)
+        .option(opt_reliability_weight)
+        .option(opt_capacity_preference)
+        .option(opt_node_penalty)
+        .option(opt_node_penalty_half_life)
+        .option(opt_graph_write_interval)
+        .option(opt_pays_sweep_interval)
+        .option(opt_stats_cache_size)
+        .option(opt_post_success_command)
         .option(opt_stats_delete_failures_age)
         .option(opt_stats_delete_failures_size)
         .option(opt_stats_delete_successes_age)
         .option(opt_stats_delete_successes_size)
+        .option(opt_stats_recent_window_days)
+        .option(opt_stats_rollup)
         .setconfig_callback(setconfig_callback)
         .rpcmethod(
             &(PLUGIN_NAME.to_string() + "-job"),
             "add sling job",
             slingjob,
         )
+        .rpcmethod(
+            &(PLUGIN_NAME.to_string() + "-add-from-template"),
+            "add sling job from a template in templates.json",
+            slingaddfromtemplate,
+        )
+        .rpcmethod(
+            &(PLUGIN_NAME.to_string() + "-reload-jobs"),
+            "re-read jobs.json and start, stop or restart changed jobs",
+            slingreloadjobs,
+        )
         .rpcmethod(
             &(PLUGIN_NAME.to_string() + "-jobsettings"),
             "show job settings",
             slingjobsettings,
         )
+        .rpcmethod(
+            &(PLUGIN_NAME.to_string() + "-job-config"),
+            "show effective settings of job(s) after applying config defaults",
+            slingjobconfig,
+        )
+        .rpcmethod(
+            &(PLUGIN_NAME.to_string() + "-last-route"),
+            "show the route of the last attempt of a job",
+            slinglastroute,
+        )
         .rpcmethod(
             &(PLUGIN_NAME.to_string() + "-deletejob"),
             "delete sling job",
@@ -191,11 +477,36 @@ async fn main() -> Result<(), anyhow::Error> {
             "stop sling jobs",
             slingstop,
         )
+        .rpcmethod(
+            &(PLUGIN_NAME.to_string() + "-reset-circuit"),
+            "re-enable sling jobs disabled by the circuit breaker",
+            slingresetcircuit,
+        )
+        .rpcmethod(
+            &(PLUGIN_NAME.to_string() + "-diagnostics"),
+            "show a snapshot of sling's state for bug reports",
+            slingdiagnostics,
+        )
         .rpcmethod(
             &(PLUGIN_NAME.to_string() + "-stats"),
             "show stats on channel(s)",
             slingstats,
         )
+        .rpcmethod(
+            &(PLUGIN_NAME.to_string() + "-roi"),
+            "show forwarding earnings against rebalancing costs per channel",
+            slingroi,
+        )
+        .rpcmethod(
+            &(PLUGIN_NAME.to_string() + "-clear-stats"),
+            "delete stats of a channel or all channels",
+            slingclearstats,
+        )
+        .rpcmethod(
+            &(PLUGIN_NAME.to_string() + "-import-history"),
+            "import past rebalances of another rebalancer into the stats",
+            slingimporthistory,
+        )
         .rpcmethod(
             &(PLUGIN_NAME.to_string() + "-except-chan"),
             "channels to avoid for all jobs",
@@ -206,6 +517,46 @@ async fn main() -> Result<(), anyhow::Error> {
             "peers to avoid for all jobs",
             slingexceptpeer,
         )
+        .rpcmethod(
+            &(PLUGIN_NAME.to_string() + "-tempban-exempt"),
+            "channels that never get tempbanned after failures",
+            slingtempbanexempt,
+        )
+        .rpcmethod(
+            &(PLUGIN_NAME.to_string() + "-maintenance"),
+            "halt all payments while keeping state up to date",
+            slingmaintenance,
+        )
+        .rpcmethod(
+            &(PLUGIN_NAME.to_string() + "-pin-liquidity"),
+            "pin the liquidity belief of a channel direction",
+            slingpinliquidity,
+        )
+        .rpcmethod(
+            &(PLUGIN_NAME.to_string() + "-unpin-liquidity"),
+            "remove pinned liquidity belief of a channel",
+            slingunpinliquidity,
+        )
+        .rpcmethod(
+            &(PLUGIN_NAME.to_string() + "-sourceable"),
+            "estimate the liquidity candidates can source for a channel",
+            slingsourceable,
+        )
+        .rpcmethod(
+            &(PLUGIN_NAME.to_string() + "-suggest-maxppm"),
+            "suggest a maxppm for a channel from its fee history",
+            slingsuggestmaxppm,
+        )
+        .rpcmethod(
+            &(PLUGIN_NAME.to_string() + "-unmanaged"),
+            "list channels that have no sling job",
+            slingunmanaged,
+        )
+        .rpcmethod(
+            &(PLUGIN_NAME.to_string() + "-export-graph"),
+            "export the graph or our neighborhood for visualization tools",
+            slingexportgraph,
+        )
         .rpcmethod(
             &(PLUGIN_NAME.to_string() + "-version"),
             "print version",
@@ -221,6 +572,9 @@ async fn main() -> Result<(), anyhow::Error> {
                 .join(plugin.configuration().rpc_file);
             let mut rpc = ClnRpc::new(&rpc_path).await?;
             let sling_dir = Path::new(&plugin.configuration().lightning_dir).join(PLUGIN_NAME);
+            if let Err(e) = check_sling_dir_writable(&sling_dir).await {
+                return plugin.disable(format!("{}", e).as_str()).await;
+            }
             let mut networkdir = PathBuf::from_str(&plugin.configuration().lightning_dir).unwrap();
             networkdir.pop();
             let getinfo = rpc.call_typed(&GetinfoRequest {}).await?;
@@ -239,6 +593,10 @@ async fn main() -> Result<(), anyhow::Error> {
     };
     if let Ok(plugin) = confplugin.start(state).await {
         debug!("{:?}", plugin.configuration());
+        // jobs are checked against our channels below
+        if let Err(e) = tasks::refresh_listpeerchannels(&plugin).await {
+            warn!("Error refreshing listpeerchannels on startup: {}", e);
+        }
         let peersclone = plugin.clone();
         tokio::spawn(async move {
             match tasks::refresh_listpeerchannels_loop(peersclone).await {
@@ -247,8 +605,16 @@ async fn main() -> Result<(), anyhow::Error> {
             };
         });
         plugin.state().read_excepts().await?;
+        plugin.state().read_route_cache().await?;
         let joblists_clone = plugin.clone();
         refresh_joblists(joblists_clone).await?;
+        let jobs = read_jobs(
+            &Path::new(&plugin.configuration().lightning_dir).join(PLUGIN_NAME),
+            &plugin,
+        )
+        .await?;
+        warn_conflicting_jobs(&jobs, &plugin.state().peer_channels.lock());
+        *plugin.state().loaded_jobs.lock() = jobs;
         let channelsclone = plugin.clone();
         tokio::spawn(async move {
             match tasks::refresh_graph(channelsclone).await {
@@ -277,6 +643,41 @@ async fn main() -> Result<(), anyhow::Error> {
                 Err(e) => warn!("Error in clear_tempbans thread: {:?}", e),
             };
         });
+        let failureratesclone = plugin.clone();
+        tokio::spawn(async move {
+            match tasks::refresh_failure_rates(failureratesclone).await {
+                Ok(()) => (),
+                Err(e) => warn!("Error in refresh_failure_rates thread: {:?}", e),
+            };
+        });
+        let roiclone = plugin.clone();
+        tokio::spawn(async move {
+            match tasks::warn_negative_roi(roiclone).await {
+                Ok(()) => (),
+                Err(e) => warn!("Error in warn_negative_roi thread: {:?}", e),
+            };
+        });
+        let volumesclone = plugin.clone();
+        tokio::spawn(async move {
+            match tasks::refresh_forward_volumes(volumesclone).await {
+                Ok(()) => (),
+                Err(e) => warn!("Error in refresh_forward_volumes thread: {:?}", e),
+            };
+        });
+        let sweeppaysclone = plugin.clone();
+        tokio::spawn(async move {
+            match tasks::sweep_pays(sweeppaysclone).await {
+                Ok(()) => (),
+                Err(e) => warn!("Error in sweep_pays thread: {:?}", e),
+            };
+        });
+        let routecacheclone = plugin.clone();
+        tokio::spawn(async move {
+            match tasks::persist_route_cache(routecacheclone).await {
+                Ok(()) => (),
+                Err(e) => warn!("Error in persist_route_cache thread: {:?}", e),
+            };
+        });
         let clearstatsclone = plugin.clone();
         tokio::spawn(async move {
             match tasks::clear_stats(clearstatsclone).await {
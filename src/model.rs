@@ -1,5 +1,6 @@
 use std::{
-    collections::{HashMap, HashSet},
+    cmp::{max, min},
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet, VecDeque},
     fmt::{self, Display, Formatter},
     path::{Path, PathBuf},
     str::FromStr,
@@ -9,56 +10,439 @@ use std::{
 
 use anyhow::{anyhow, Error};
 use cln_rpc::{
-    model::responses::ListpeerchannelsChannels,
+    model::{requests::SendpayRoute, responses::ListpeerchannelsChannels},
     primitives::{Amount, PublicKey, ShortChannelId},
+    ClnRpc, RpcError,
 };
-use log::{info, warn};
+use log::{debug, error, info, warn};
 use parking_lot::{Mutex, RwLock};
-use serde::{Deserialize, Serialize};
-use sling::{DirectedChannel, Job};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sling::{DirectedChannel, Job, SatDirection, StuckHtlc, TooExpensive};
 use tabled::Tabled;
 use tokio::{
     fs::{self, File, OpenOptions},
     io::{self, AsyncWriteExt},
+    sync::{Semaphore, SemaphorePermit},
+    task::AbortHandle,
 };
 
 use crate::{
     create_sling_dir,
-    gossip::{ChannelAnnouncement, ChannelUpdate},
-    OPT_CANDIDATES_MIN_AGE, OPT_DEPLETEUPTOAMOUNT, OPT_DEPLETEUPTOPERCENT, OPT_MAXHOPS,
-    OPT_MAX_HTLC_COUNT, OPT_PARALLELJOBS, OPT_REFRESH_ALIASMAP_INTERVAL,
-    OPT_REFRESH_GOSSMAP_INTERVAL, OPT_REFRESH_PEERS_INTERVAL, OPT_RESET_LIQUIDITY_INTERVAL,
-    OPT_STATS_DELETE_FAILURES_AGE, OPT_STATS_DELETE_FAILURES_SIZE, OPT_STATS_DELETE_SUCCESSES_AGE,
-    OPT_STATS_DELETE_SUCCESSES_SIZE, OPT_TIMEOUTPAY, OPT_UTF8,
+    gossip::{get_node_order, ChannelUpdate, GraphData},
+    util::{fee_total_msat_precise, feeppm_effective_from_amts, liquidity_prior},
+    OPT_CANDIDATES_CAP_STRATEGY, OPT_CANDIDATES_MIN_AGE, OPT_CANDIDATE_MAX_FAILURES,
+    OPT_CANDIDATE_ORDER, OPT_CAPACITY_PREFERENCE, OPT_CIRCUIT_BREAKER_THRESHOLD,
+    OPT_DEPLETEUPTOAMOUNT, OPT_DEPLETEUPTOPERCENT, OPT_EXCLUDE_ALIAS, OPT_FLOW_PRIORS,
+    OPT_GRAPH_MAX_CHANNELS, OPT_GRAPH_SOURCE, OPT_GRAPH_WRITE_INTERVAL, OPT_HOP_RESULTS,
+    OPT_LIQUIDITY_CONFIDENCE_WINDOW, OPT_LIQUIDITY_MAX_AGE, OPT_MAXHOPS,
+    OPT_MAX_CANDIDATES_PER_ITERATION, OPT_MAX_HTLC_COUNT, OPT_MAX_PEER_DATA_AGE,
+    OPT_MAX_PEER_HTLC_COUNT, OPT_MAX_PEER_HTLC_VALUE, OPT_MAX_ROUTE_ATTEMPTS,
+    OPT_MIN_FORWARD_VOLUME, OPT_MIN_FUNDED_SATS, OPT_MODE, OPT_NODE_BAN_THRESHOLD,
+    OPT_NODE_BAN_WINDOW, OPT_NODE_PENALTY, OPT_NODE_PENALTY_HALF_LIFE, OPT_PARALLELJOBS,
+    OPT_PAYS_SWEEP_INTERVAL, OPT_POST_SUCCESS_COMMAND, OPT_REFRESH_ALIASMAP_INTERVAL,
+    OPT_REFRESH_GOSSMAP_INTERVAL, OPT_REFRESH_PEERS_INTERVAL, OPT_RELIABILITY_WEIGHT,
+    OPT_RESET_LIQUIDITY_INTERVAL, OPT_ROI_WARN, OPT_ROUTE_CACHE_MAX_AGE, OPT_RPC_CONCURRENCY,
+    OPT_STARTUP_DELAY_SECS, OPT_STATS_CACHE_SIZE, OPT_STATS_DELETE_FAILURES_AGE,
+    OPT_STATS_DELETE_FAILURES_SIZE, OPT_STATS_DELETE_SUCCESSES_AGE,
+    OPT_STATS_DELETE_SUCCESSES_SIZE, OPT_STATS_RECENT_WINDOW_DAYS, OPT_STATS_ROLLUP,
+    OPT_TIMEOUTPAY, OPT_TRACE, OPT_UTF8, OPT_VERIFY_BALANCE, OPT_VERIFY_OWN_PAYMENT,
 };
 
 pub const SUCCESSES_SUFFIX: &str = "_successes.json";
 pub const FAILURES_SUFFIX: &str = "_failures.json";
+pub const ROLLUP_SUFFIX: &str = "_rollup.json";
+pub const HOP_RESULTS_FILE_NAME: &str = "hop_results.jsonl";
+pub const TRACE_FILE_NAME: &str = "trace.jsonl";
+pub const ROUTE_CACHE_FILE_NAME: &str = "route_cache.json";
 pub const NO_ALIAS_SET: &str = "NO_ALIAS_SET";
+pub const REDACTED: &str = "REDACTED";
+pub const GRAPH_SOURCE_GOSSIP_STORE: &str = "gossip_store";
+pub const GRAPH_SOURCE_LISTCHANNELS: &str = "listchannels";
+pub const CANDIDATE_ORDER_CHEAPEST: &str = "cheapest";
+pub const CANDIDATE_ORDER_LARGEST_FIRST: &str = "largest-first";
+pub const CANDIDATE_ORDER_MOST_OVERFULL: &str = "most-overfull";
+pub const CANDIDATE_ORDER_MOST_OVER_TARGET: &str = "most-over-target";
+pub const CANDIDATES_CAP_ROUND_ROBIN: &str = "round-robin";
+pub const CANDIDATES_CAP_MOST_IMBALANCED: &str = "most-imbalanced";
+pub const CANDIDATES_CAP_CHEAPEST_HISTORY: &str = "cheapest-history";
+pub const MODE_ACTIVE: &str = "active";
+pub const MODE_FOLLOWER: &str = "follower";
+pub const MAX_HOPS_HARD_CAP: u8 = 20;
+pub const REFRESH_BACKOFF_MAX_SECS: u64 = 3_600;
+pub const GRAPH_EMPTY_RETRY_MIN_SECS: u64 = 10;
+pub const RPC_CONCURRENCY_DEFAULT: u64 = 4;
+pub const CAPACITY_TIER_MEDIUM_MSAT: u64 = 1_000_000_000;
+pub const CAPACITY_TIER_LARGE_MSAT: u64 = 5_000_000_000;
+pub const GRAPH_EMPTY_RETRY_MAX_SECS: u64 = 600;
 
 pub const PLUGIN_NAME: &str = "sling";
 pub const GRAPH_FILE_NAME: &str = "graph.json";
+pub const GRAPH_EXPORT_FILE_NAME: &str = "graph_export";
 pub const JOB_FILE_NAME: &str = "jobs.json";
 pub const EXCEPTS_CHANS_FILE_NAME: &str = "excepts.json";
 pub const EXCEPTS_PEERS_FILE_NAME: &str = "excepts_peers.json";
+pub const TEMPLATES_FILE_NAME: &str = "templates.json";
+pub const TEMPBAN_EXEMPTS_FILE_NAME: &str = "tempban_exempts.json";
+pub const WRITE_PROBE_FILE_NAME: &str = ".write_probe";
+pub const STUCK_HTLC_MARGIN_SECS: u64 = 60;
+pub const PENDING_PAYMENT_POLL_SECS: u64 = 10;
+pub const PARALLEL_TUNE_WINDOW: u64 = 10;
+const PARALLEL_TUNE_UP_RATE: f64 = 0.8;
+const PARALLEL_TUNE_DOWN_RATE: f64 = 0.5;
+pub const LOW_DISK_SPACE_KB: u64 = 102_400;
+pub const WRITE_FAILURE_WARN_COUNT: u64 = 3;
+// an htlc can't stay pending for longer than the 2016 blocks of max cltv
+pub const PAYS_ORPHAN_AGE_SECS: u64 = 14 * 24 * 3_600;
+const NODE_PENALTY_STEP: f64 = 0.5;
+const NODE_PENALTY_MIN_WEIGHT: f64 = 0.01;
+
+// nodes that failed our payments too often recently, kept in memory only
+#[derive(Debug, Default)]
+pub struct NodeBans {
+    failures: HashMap<PublicKey, VecDeque<u64>>,
+    banned: HashMap<PublicKey, u64>,
+}
+impl NodeBans {
+    pub fn record_failure(
+        &mut self,
+        node: PublicKey,
+        now: u64,
+        threshold: u64,
+        window_secs: u64,
+    ) -> bool {
+        if threshold == 0 || self.banned.contains_key(&node) {
+            return false;
+        }
+        let failures = self.failures.entry(node).or_default();
+        failures.push_back(now);
+        while failures.front().is_some_and(|t| *t + window_secs < now) {
+            failures.pop_front();
+        }
+        if failures.len() as u64 >= threshold {
+            self.failures.remove(&node);
+            self.banned.insert(node, now);
+            true
+        } else {
+            false
+        }
+    }
+    pub fn reinstate_expired(&mut self, now: u64, window_secs: u64) -> Vec<PublicKey> {
+        let expired = self
+            .banned
+            .iter()
+            .filter(|(_, banned_at)| **banned_at + window_secs <= now)
+            .map(|(node, _)| *node)
+            .collect::<Vec<_>>();
+        for node in &expired {
+            self.banned.remove(node);
+        }
+        self.failures
+            .retain(|_, f| f.back().is_some_and(|t| *t + window_secs >= now));
+        expired
+    }
+    pub fn banned(&self) -> HashSet<PublicKey> {
+        self.banned.keys().copied().collect()
+    }
+}
+
+// decaying per node failure weight, 1.0 means the full node penalty,
+// kept in memory only
+#[derive(Debug, Default)]
+pub struct NodePenalties {
+    penalties: HashMap<PublicKey, (f64, u64)>,
+}
+impl NodePenalties {
+    fn decayed(weight: f64, since: u64, now: u64, half_life_secs: u64) -> f64 {
+        let elapsed = now.saturating_sub(since) as f64;
+        weight * 0.5_f64.powf(elapsed / half_life_secs.max(1) as f64)
+    }
+    pub fn record_failure(&mut self, node: PublicKey, now: u64, half_life_secs: u64) {
+        let weight = match self.penalties.get(&node) {
+            Some((weight, since)) => Self::decayed(*weight, *since, now, half_life_secs),
+            None => 0.0,
+        };
+        self.penalties
+            .insert(node, ((weight + NODE_PENALTY_STEP).min(1.0), now));
+        self.penalties.retain(|_, (weight, since)| {
+            Self::decayed(*weight, *since, now, half_life_secs) >= NODE_PENALTY_MIN_WEIGHT
+        });
+    }
+    pub fn penalties_ppm(
+        &self,
+        now: u64,
+        max_ppm: u64,
+        half_life_secs: u64,
+    ) -> HashMap<PublicKey, u64> {
+        if max_ppm == 0 {
+            return HashMap::new();
+        }
+        self.penalties
+            .iter()
+            .map(|(node, (weight, since))| {
+                (
+                    *node,
+                    (Self::decayed(*weight, *since, now, half_life_secs) * max_ppm as f64) as u64,
+                )
+            })
+            .filter(|(_, ppm)| *ppm > 0)
+            .collect()
+    }
+}
+
+// preimages of our payments in flight by payment_hash, handed out by the
+// htlc_accepted hook. Entries of payments that never came back, e.g. because
+// the job was stopped mid-payment, are swept after PAYS_ORPHAN_AGE_SECS
+#[derive(Debug, Default)]
+pub struct Pays {
+    pending: HashMap<String, (String, u64)>,
+    arrived: HashMap<String, (ArrivedPay, u64)>,
+}
+impl Pays {
+    pub fn insert(&mut self, payment_hash: String, preimage: String, now: u64) {
+        self.pending.insert(payment_hash, (preimage, now));
+    }
+    pub fn remove(&mut self, payment_hash: &str) -> Option<String> {
+        self.arrived.remove(payment_hash);
+        self.pending
+            .remove(payment_hash)
+            .map(|(preimage, _)| preimage)
+    }
+    // our own htlc came back, remember where it arrived until the sender picks it up
+    pub fn resolve(
+        &mut self,
+        payment_hash: &str,
+        arrived: Option<ArrivedPay>,
+        now: u64,
+    ) -> Option<String> {
+        let (preimage, _) = self.pending.remove(payment_hash)?;
+        if let Some(a) = arrived {
+            self.arrived.insert(payment_hash.to_string(), (a, now));
+        }
+        Some(preimage)
+    }
+    pub fn take_arrived(&mut self, payment_hash: &str) -> Option<ArrivedPay> {
+        self.arrived.remove(payment_hash).map(|(a, _)| a)
+    }
+    pub fn sweep(&mut self, now: u64, max_age_secs: u64) -> usize {
+        let before = self.pending.len();
+        self.pending
+            .retain(|_, (_, created_at)| *created_at + max_age_secs > now);
+        self.arrived
+            .retain(|_, (_, arrived_at)| *arrived_at + max_age_secs > now);
+        before - self.pending.len()
+    }
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArrivedPay {
+    pub short_channel_id: ShortChannelId,
+    pub amount_msat: u64,
+}
+// a balance change is only our rebalance if our htlc came back over the
+// last channel of the route with at least the amount we sent to ourselves
+pub fn is_own_payment(arrived: Option<&ArrivedPay>, route: &[SendpayRoute]) -> bool {
+    match (arrived, route.last()) {
+        (Some(a), Some(last)) => {
+            a.short_channel_id == last.channel && a.amount_msat >= Amount::msat(&last.amount_msat)
+        }
+        _ => false,
+    }
+}
+
+// a failed write of one of our files, keeps the path for logging and
+// counting failures per file
+#[derive(Debug)]
+pub struct WriteError {
+    pub path: PathBuf,
+    pub source: io::Error,
+}
+impl Display for WriteError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "could not write {}: {}",
+            self.path.display(),
+            self.source
+        )
+    }
+}
+impl std::error::Error for WriteError {}
+
+pub async fn write_file(path: PathBuf, contents: &[u8], append: bool) -> Result<(), Error> {
+    let result = async {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .create(true)
+            .open(&path)
+            .await?;
+        file.write_all(contents).await?;
+        file.flush().await
+    }
+    .await;
+    match result {
+        Ok(()) => Ok(()),
+        Err(source) => Err(WriteError { path, source }.into()),
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct WriteFailures {
+    failures: HashMap<PathBuf, u64>,
+}
+impl WriteFailures {
+    pub fn record(&mut self, path: &Path) -> u64 {
+        let count = self.failures.entry(path.to_path_buf()).or_default();
+        *count += 1;
+        *count
+    }
+    pub fn total(&self) -> u64 {
+        self.failures.values().sum()
+    }
+}
+
+// size and modification time of the stats files an entry was computed from
+pub type StatsFingerprint = [Option<(u64, SystemTime)>; 2];
+
+#[derive(Debug)]
+struct StatsCacheEntry {
+    fingerprint: StatsFingerprint,
+    lifetime_msat: u64,
+    last_used: u64,
+}
+
+// per-channel stats totals, only the max_entries most recently used channels are kept
+#[derive(Debug, Default)]
+pub struct StatsCache {
+    entries: HashMap<PathBuf, StatsCacheEntry>,
+    uses: u64,
+}
+impl StatsCache {
+    pub fn get(&mut self, key: &Path, fingerprint: &StatsFingerprint) -> Option<u64> {
+        self.uses += 1;
+        match self.entries.get_mut(key) {
+            Some(entry) if entry.fingerprint == *fingerprint => {
+                entry.last_used = self.uses;
+                Some(entry.lifetime_msat)
+            }
+            _ => None,
+        }
+    }
+    pub fn insert(
+        &mut self,
+        key: PathBuf,
+        fingerprint: StatsFingerprint,
+        lifetime_msat: u64,
+        max_entries: usize,
+    ) {
+        self.uses += 1;
+        self.entries.insert(
+            key,
+            StatsCacheEntry {
+                fingerprint,
+                lifetime_msat,
+                last_used: self.uses,
+            },
+        );
+        while self.entries.len() > max_entries {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+                .unwrap();
+            self.entries.remove(&oldest);
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct JobsDiff {
+    pub added: Vec<ShortChannelId>,
+    pub removed: Vec<ShortChannelId>,
+    pub changed: Vec<ShortChannelId>,
+    pub unchanged: Vec<ShortChannelId>,
+}
+
+// remembers per peer whether its alias matched the sling-exclude-alias
+// patterns, forgotten when the patterns or the aliases change
+#[derive(Debug, Default)]
+pub struct AliasBlocklist {
+    patterns: String,
+    matches: HashMap<PublicKey, bool>,
+}
+impl AliasBlocklist {
+    pub fn is_blocked(
+        &mut self,
+        patterns: &str,
+        peer: &PublicKey,
+        alias_map: &HashMap<PublicKey, String>,
+    ) -> bool {
+        if patterns.is_empty() {
+            return false;
+        }
+        if self.patterns != patterns {
+            self.patterns = patterns.to_string();
+            self.matches.clear();
+        }
+        *self.matches.entry(*peer).or_insert_with(|| {
+            alias_map.get(peer).is_some_and(|alias| {
+                let alias = alias.to_lowercase();
+                patterns
+                    .split(',')
+                    .map(|p| p.trim().to_lowercase())
+                    .any(|p| !p.is_empty() && alias.contains(&p))
+            })
+        })
+    }
+    pub fn clear(&mut self) {
+        self.matches.clear();
+    }
+}
 
 #[derive(Clone)]
 pub struct PluginState {
     pub config: Arc<Mutex<Config>>,
     pub peer_channels: Arc<Mutex<HashMap<ShortChannelId, ListpeerchannelsChannels>>>,
     pub graph: Arc<Mutex<LnGraph>>,
-    pub pays: Arc<RwLock<HashMap<String, String>>>,
+    pub pays: Arc<RwLock<Pays>>,
     pub alias_peer_map: Arc<Mutex<HashMap<PublicKey, String>>>,
     pub pull_jobs: Arc<Mutex<HashSet<ShortChannelId>>>,
     pub push_jobs: Arc<Mutex<HashSet<ShortChannelId>>>,
     pub excepts_chans: Arc<Mutex<HashSet<ShortChannelId>>>,
     pub excepts_peers: Arc<Mutex<HashSet<PublicKey>>>,
     pub tempbans: Arc<Mutex<HashMap<ShortChannelId, u64>>>,
+    pub tempban_exempts: Arc<Mutex<HashSet<ShortChannelId>>>,
     pub parrallel_bans: Arc<Mutex<HashMap<ShortChannelId, HashMap<u8, DirectedChannel>>>>,
     pub job_state: Arc<Mutex<HashMap<ShortChannelId, Vec<JobState>>>>,
+    pub task_handles: Arc<Mutex<HashMap<Task, AbortHandle>>>,
+    pub tuned_parallelism: Arc<Mutex<HashMap<ShortChannelId, u8>>>,
     pub blockheight: Arc<Mutex<u32>>,
-    pub gossip_store_anns: Arc<Mutex<HashMap<ShortChannelId, ChannelAnnouncement>>>,
-    pub gossip_store_amts: Arc<Mutex<HashMap<ShortChannelId, u64>>>,
+    pub failure_rates: Arc<Mutex<HashMap<ShortChannelId, f64>>>,
+    pub candidate_feeppms: Arc<Mutex<HashMap<ShortChannelId, u64>>>,
+    pub candidate_rotation: Arc<Mutex<HashMap<ShortChannelId, usize>>>,
+    pub maintenance: Arc<Mutex<bool>>,
+    pub historical_flows: Arc<Mutex<HashMap<ShortChannelId, (u64, u64)>>>,
+    pub pinned_liquidity: Arc<Mutex<HashMap<DirectedChannel, u64>>>,
+    pub loaded_jobs: Arc<Mutex<BTreeMap<ShortChannelId, Job>>>,
+    pub node_bans: Arc<Mutex<NodeBans>>,
+    pub node_penalties: Arc<Mutex<NodePenalties>>,
+    pub write_failures: Arc<Mutex<WriteFailures>>,
+    pub alias_blocklist: Arc<Mutex<AliasBlocklist>>,
+    pub route_cache: Arc<Mutex<RouteCache>>,
+    pub stats_cache: Arc<Mutex<StatsCache>>,
+    pub forward_volumes: Arc<Mutex<Option<HashMap<ShortChannelId, u64>>>>,
+    pub started_at: u64,
+    pub graph_refreshed: Arc<Mutex<bool>>,
+    pub peers_refreshed_at: Arc<Mutex<u64>>,
+    pub rpc_pool: Arc<RpcPool<ClnRpc>>,
 }
 impl PluginState {
     pub fn new(
@@ -78,26 +462,197 @@ impl PluginState {
             ))),
             peer_channels: Arc::new(Mutex::new(HashMap::new())),
             graph: Arc::new(Mutex::new(LnGraph::new())),
-            pays: Arc::new(RwLock::new(HashMap::new())),
+            pays: Arc::new(RwLock::new(Pays::default())),
             alias_peer_map: Arc::new(Mutex::new(HashMap::new())),
             pull_jobs: Arc::new(Mutex::new(HashSet::new())),
             push_jobs: Arc::new(Mutex::new(HashSet::new())),
             excepts_chans: Arc::new(Mutex::new(HashSet::new())),
             excepts_peers: Arc::new(Mutex::new(HashSet::new())),
             tempbans: Arc::new(Mutex::new(HashMap::new())),
+            tempban_exempts: Arc::new(Mutex::new(HashSet::new())),
             parrallel_bans: Arc::new(Mutex::new(HashMap::new())),
             job_state: Arc::new(Mutex::new(HashMap::new())),
+            task_handles: Arc::new(Mutex::new(HashMap::new())),
+            tuned_parallelism: Arc::new(Mutex::new(HashMap::new())),
             blockheight: Arc::new(Mutex::new(0)),
-            gossip_store_anns: Arc::new(Mutex::new(HashMap::new())),
-            gossip_store_amts: Arc::new(Mutex::new(HashMap::new())),
+            failure_rates: Arc::new(Mutex::new(HashMap::new())),
+            candidate_feeppms: Arc::new(Mutex::new(HashMap::new())),
+            candidate_rotation: Arc::new(Mutex::new(HashMap::new())),
+            maintenance: Arc::new(Mutex::new(false)),
+            historical_flows: Arc::new(Mutex::new(HashMap::new())),
+            pinned_liquidity: Arc::new(Mutex::new(HashMap::new())),
+            loaded_jobs: Arc::new(Mutex::new(BTreeMap::new())),
+            node_bans: Arc::new(Mutex::new(NodeBans::default())),
+            node_penalties: Arc::new(Mutex::new(NodePenalties::default())),
+            write_failures: Arc::new(Mutex::new(WriteFailures::default())),
+            alias_blocklist: Arc::new(Mutex::new(AliasBlocklist::default())),
+            route_cache: Arc::new(Mutex::new(RouteCache::default())),
+            stats_cache: Arc::new(Mutex::new(StatsCache::default())),
+            forward_volumes: Arc::new(Mutex::new(None)),
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            graph_refreshed: Arc::new(Mutex::new(false)),
+            peers_refreshed_at: Arc::new(Mutex::new(0)),
+            rpc_pool: Arc::new(RpcPool::new(RPC_CONCURRENCY_DEFAULT as usize)),
+        }
+    }
+    // counts failed writes per file and escalates once a file keeps failing,
+    // the error itself is left to the caller
+    pub fn track_write<T>(&self, result: Result<T, Error>) -> Result<T, Error> {
+        if let Err(e) = &result {
+            if let Some(write_error) = e.downcast_ref::<WriteError>() {
+                let count = self.write_failures.lock().record(&write_error.path);
+                if count == WRITE_FAILURE_WARN_COUNT {
+                    error!(
+                        "Writing {} failed {} times, sling data is being lost! \
+                        Check disk space and permissions of the sling folder",
+                        write_error.path.display(),
+                        count
+                    );
+                }
+            }
         }
+        result
+    }
+    pub fn startup_ready(&self, now: u64, delay_secs: u64) -> bool {
+        delay_secs == 0 || *self.graph_refreshed.lock() && now >= self.started_at + delay_secs
+    }
+    pub fn job_states_snapshot(&self) -> HashMap<ShortChannelId, Vec<JobState>> {
+        self.job_state.lock().clone()
+    }
+    pub fn force_stop_jobs(&self, chan_id: Option<ShortChannelId>) -> usize {
+        let mut stopped_tasks = Vec::new();
+        {
+            let mut job_states = self.job_state.lock();
+            let mut task_handles = self.task_handles.lock();
+            for (scid, jobstates) in job_states.iter_mut() {
+                if chan_id.is_some_and(|c| c != *scid) {
+                    continue;
+                }
+                for jobstate in jobstates.iter_mut() {
+                    let task = Task {
+                        chan_id: *scid,
+                        task_id: jobstate.id(),
+                    };
+                    if let Some(handle) = task_handles.remove(&task) {
+                        handle.abort();
+                    }
+                    jobstate.stop();
+                    jobstate.set_active(false);
+                    jobstate.statechange(JobMessage::Stopped);
+                    debug!("{}/{}: Force stopped job", scid, task.task_id);
+                    stopped_tasks.push(task);
+                }
+            }
+        }
+        let mut parallel_bans = self.parrallel_bans.lock();
+        for task in &stopped_tasks {
+            if let Some(task_bans) = parallel_bans.get_mut(&task.chan_id) {
+                task_bans.remove(&task.task_id);
+            }
+        }
+        parallel_bans.retain(|_, task_bans| !task_bans.is_empty());
+        stopped_tasks.len()
+    }
+    // a new task may already run in the same slot if the job was restarted
+    // right after this one stopped, its handle has to stay
+    pub fn task_finished(&self, task: &Task) {
+        let job_states = self.job_state.lock();
+        let restarted = job_states
+            .get(&task.chan_id)
+            .and_then(|jts| jts.iter().find(|jt| jt.id() == task.task_id))
+            .is_some_and(|jt| jt.is_active());
+        if !restarted {
+            self.task_handles.lock().remove(task);
+        }
+    }
+    pub fn is_maintenance(&self) -> bool {
+        *self.maintenance.lock()
+    }
+    // why jobs may not send payments right now, graph and stats keep refreshing
+    pub fn dispatch_paused(&self, config: &Config) -> Option<JobMessage> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        if self.is_maintenance() {
+            Some(JobMessage::Maintenance)
+        } else if config.mode.value == MODE_FOLLOWER {
+            Some(JobMessage::Follower)
+        } else if self.peer_data_stale(now, config.max_peer_data_age.value) {
+            Some(JobMessage::StaleData)
+        } else {
+            None
+        }
+    }
+    pub fn peer_data_stale(&self, now: u64, max_age_secs: u64) -> bool {
+        max_age_secs > 0 && now.saturating_sub(*self.peers_refreshed_at.lock()) > max_age_secs
+    }
+    // stats of channels without a loaded job stay in the sling folder itself
+    pub fn stats_dir(&self, sling_dir: &Path, chan_id: &ShortChannelId) -> PathBuf {
+        match self.loaded_jobs.lock().get(chan_id) {
+            Some(job) => job.stats_dir(sling_dir),
+            None => sling_dir.to_path_buf(),
+        }
+    }
+    // how many tasks of the job may dispatch, auto-tuned if paralleljobs_min is set
+    pub fn effective_parallelism(
+        &self,
+        chan_id: &ShortChannelId,
+        job: &Job,
+        config: &Config,
+    ) -> u8 {
+        let max = EffectiveJobConfig::new(job, config).paralleljobs;
+        match job.paralleljobs_min {
+            Some(min) => {
+                let min = min.min(max);
+                (*self.tuned_parallelism.lock().entry(*chan_id).or_insert(min)).clamp(min, max)
+            }
+            None => max,
+        }
+    }
+    pub fn retune_parallelism(
+        &self,
+        chan_id: &ShortChannelId,
+        job: &Job,
+        config: &Config,
+    ) -> Option<u8> {
+        let min = job.paralleljobs_min?;
+        let max = EffectiveJobConfig::new(job, config).paralleljobs;
+        let current = self.effective_parallelism(chan_id, job, config);
+        let mut job_states = self.job_state.lock();
+        let tuned = tune_parallelism(job_states.get_mut(chan_id)?, current, min.min(max), max);
+        self.tuned_parallelism.lock().insert(*chan_id, tuned);
+        if tuned != current {
+            Some(tuned)
+        } else {
+            None
+        }
+    }
+    pub fn tempban(&self, scid: ShortChannelId) -> bool {
+        if self.tempban_exempts.lock().contains(&scid) {
+            debug!("{} is exempt from tempbans", scid);
+            return false;
+        }
+        self.tempbans.lock().insert(
+            scid,
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        );
+        true
     }
     pub async fn read_excepts(&self) -> Result<(), Error> {
         let sling_dir = self.config.lock().sling_dir.clone();
         let excepts_chan_file = sling_dir.join(EXCEPTS_CHANS_FILE_NAME);
         let excepts_peers_file = sling_dir.join(EXCEPTS_PEERS_FILE_NAME);
+        let tempban_exempts_file = sling_dir.join(TEMPBAN_EXEMPTS_FILE_NAME);
         let excepts_chan_file_content = fs::read_to_string(excepts_chan_file.clone()).await;
         let excepts_peers_file_content = fs::read_to_string(excepts_peers_file.clone()).await;
+        let tempban_exempts_file_content = fs::read_to_string(tempban_exempts_file.clone()).await;
 
         create_sling_dir(&sling_dir).await?;
 
@@ -105,6 +660,28 @@ impl PluginState {
             PluginState::parse_excepts(excepts_chan_file_content, excepts_chan_file).await?;
         *self.excepts_peers.lock() =
             PluginState::parse_excepts(excepts_peers_file_content, excepts_peers_file).await?;
+        *self.tempban_exempts.lock() =
+            PluginState::parse_excepts(tempban_exempts_file_content, tempban_exempts_file).await?;
+        Ok(())
+    }
+    pub async fn read_route_cache(&self) -> Result<(), Error> {
+        let (sling_dir, max_age) = {
+            let config = self.config.lock();
+            (config.sling_dir.clone(), config.route_cache_max_age.value)
+        };
+        if max_age == 0 {
+            return Ok(());
+        }
+        let mut route_cache = RouteCache::read_from_file(&sling_dir).await?;
+        let dropped = route_cache.drop_expired(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            max_age * 60,
+        );
+        info!("Loaded route cache, dropped {} expired routes", dropped);
+        *self.route_cache.lock() = route_cache;
         Ok(())
     }
     async fn parse_excepts<T: FromStr + std::hash::Hash + Eq>(
@@ -159,18 +736,56 @@ pub struct Config {
     pub refresh_peers_interval: DynamicConfigOption<u64>,
     pub refresh_aliasmap_interval: DynamicConfigOption<u64>,
     pub refresh_gossmap_interval: DynamicConfigOption<u64>,
+    pub graph_max_channels: DynamicConfigOption<u64>,
+    pub graph_source: DynamicConfigOption<String>,
     pub reset_liquidity_interval: DynamicConfigOption<u64>,
+    pub liquidity_max_age: DynamicConfigOption<u64>,
+    pub liquidity_confidence_window: DynamicConfigOption<u64>,
+    pub flow_priors: DynamicConfigOption<bool>,
+    pub hop_results: DynamicConfigOption<bool>,
+    pub trace: DynamicConfigOption<bool>,
+    pub roi_warn: DynamicConfigOption<bool>,
+    pub verify_balance: DynamicConfigOption<bool>,
+    pub verify_own_payment: DynamicConfigOption<bool>,
     pub depleteuptopercent: DynamicConfigOption<f64>,
     pub depleteuptoamount: DynamicConfigOption<u64>,
     pub maxhops: DynamicConfigOption<u8>,
     pub candidates_min_age: DynamicConfigOption<u32>,
+    pub candidate_order: DynamicConfigOption<String>,
+    pub max_candidates_per_iteration: DynamicConfigOption<u64>,
+    pub candidates_cap_strategy: DynamicConfigOption<String>,
+    pub mode: DynamicConfigOption<String>,
+    pub exclude_alias: DynamicConfigOption<String>,
+    pub route_cache_max_age: DynamicConfigOption<u64>,
+    pub min_forward_volume: DynamicConfigOption<u64>,
+    pub max_peer_data_age: DynamicConfigOption<u64>,
+    pub rpc_concurrency: DynamicConfigOption<u64>,
     pub paralleljobs: DynamicConfigOption<u8>,
     pub timeoutpay: DynamicConfigOption<u16>,
+    pub max_route_attempts: DynamicConfigOption<u64>,
+    pub candidate_max_failures: DynamicConfigOption<u64>,
+    pub circuit_breaker_threshold: DynamicConfigOption<u64>,
+    pub node_ban_threshold: DynamicConfigOption<u64>,
+    pub node_ban_window: DynamicConfigOption<u64>,
+    pub startup_delay_secs: DynamicConfigOption<u64>,
+    pub min_funded_sats: DynamicConfigOption<u64>,
     pub max_htlc_count: DynamicConfigOption<u64>,
+    pub max_peer_htlc_count: DynamicConfigOption<u64>,
+    pub max_peer_htlc_value: DynamicConfigOption<u64>,
+    pub reliability_weight: DynamicConfigOption<f64>,
+    pub capacity_preference: DynamicConfigOption<i64>,
+    pub node_penalty: DynamicConfigOption<u64>,
+    pub graph_write_interval: DynamicConfigOption<u64>,
+    pub pays_sweep_interval: DynamicConfigOption<u64>,
+    pub stats_cache_size: DynamicConfigOption<u64>,
+    pub node_penalty_half_life: DynamicConfigOption<u64>,
+    pub post_success_command: DynamicConfigOption<String>,
     pub stats_delete_failures_age: DynamicConfigOption<u64>,
     pub stats_delete_failures_size: DynamicConfigOption<u64>,
     pub stats_delete_successes_age: DynamicConfigOption<u64>,
     pub stats_delete_successes_size: DynamicConfigOption<u64>,
+    pub stats_recent_window_days: DynamicConfigOption<u64>,
+    pub stats_rollup: DynamicConfigOption<bool>,
     pub cltv_delta: u32,
 }
 impl Config {
@@ -203,10 +818,50 @@ impl Config {
                 name: OPT_REFRESH_GOSSMAP_INTERVAL,
                 value: 10,
             },
+            graph_max_channels: DynamicConfigOption {
+                name: OPT_GRAPH_MAX_CHANNELS,
+                value: 0,
+            },
+            graph_source: DynamicConfigOption {
+                name: OPT_GRAPH_SOURCE,
+                value: GRAPH_SOURCE_GOSSIP_STORE.to_string(),
+            },
             reset_liquidity_interval: DynamicConfigOption {
                 name: OPT_RESET_LIQUIDITY_INTERVAL,
                 value: 360,
             },
+            liquidity_max_age: DynamicConfigOption {
+                name: OPT_LIQUIDITY_MAX_AGE,
+                value: 0,
+            },
+            liquidity_confidence_window: DynamicConfigOption {
+                name: OPT_LIQUIDITY_CONFIDENCE_WINDOW,
+                value: 0,
+            },
+            flow_priors: DynamicConfigOption {
+                name: OPT_FLOW_PRIORS,
+                value: false,
+            },
+            hop_results: DynamicConfigOption {
+                name: OPT_HOP_RESULTS,
+                value: false,
+            },
+            trace: DynamicConfigOption {
+                name: OPT_TRACE,
+                value: false,
+            },
+            roi_warn: DynamicConfigOption {
+                name: OPT_ROI_WARN,
+                value: false,
+            },
+            verify_balance: DynamicConfigOption {
+                name: OPT_VERIFY_BALANCE,
+                value: false,
+            },
+            verify_own_payment: DynamicConfigOption {
+                name: OPT_VERIFY_OWN_PAYMENT,
+                value: false,
+            },
             depleteuptopercent: DynamicConfigOption {
                 name: OPT_DEPLETEUPTOPERCENT,
                 value: 0.2,
@@ -223,6 +878,42 @@ impl Config {
                 name: OPT_CANDIDATES_MIN_AGE,
                 value: 0,
             },
+            candidate_order: DynamicConfigOption {
+                name: OPT_CANDIDATE_ORDER,
+                value: CANDIDATE_ORDER_CHEAPEST.to_string(),
+            },
+            max_candidates_per_iteration: DynamicConfigOption {
+                name: OPT_MAX_CANDIDATES_PER_ITERATION,
+                value: 0,
+            },
+            candidates_cap_strategy: DynamicConfigOption {
+                name: OPT_CANDIDATES_CAP_STRATEGY,
+                value: CANDIDATES_CAP_ROUND_ROBIN.to_string(),
+            },
+            mode: DynamicConfigOption {
+                name: OPT_MODE,
+                value: MODE_ACTIVE.to_string(),
+            },
+            exclude_alias: DynamicConfigOption {
+                name: OPT_EXCLUDE_ALIAS,
+                value: String::new(),
+            },
+            route_cache_max_age: DynamicConfigOption {
+                name: OPT_ROUTE_CACHE_MAX_AGE,
+                value: 0,
+            },
+            min_forward_volume: DynamicConfigOption {
+                name: OPT_MIN_FORWARD_VOLUME,
+                value: 0,
+            },
+            max_peer_data_age: DynamicConfigOption {
+                name: OPT_MAX_PEER_DATA_AGE,
+                value: 0,
+            },
+            rpc_concurrency: DynamicConfigOption {
+                name: OPT_RPC_CONCURRENCY,
+                value: RPC_CONCURRENCY_DEFAULT,
+            },
             paralleljobs: DynamicConfigOption {
                 name: OPT_PARALLELJOBS,
                 value: 1,
@@ -231,10 +922,78 @@ impl Config {
                 name: OPT_TIMEOUTPAY,
                 value: 120,
             },
+            max_route_attempts: DynamicConfigOption {
+                name: OPT_MAX_ROUTE_ATTEMPTS,
+                value: 0,
+            },
+            candidate_max_failures: DynamicConfigOption {
+                name: OPT_CANDIDATE_MAX_FAILURES,
+                value: 0,
+            },
+            circuit_breaker_threshold: DynamicConfigOption {
+                name: OPT_CIRCUIT_BREAKER_THRESHOLD,
+                value: 0,
+            },
+            node_ban_threshold: DynamicConfigOption {
+                name: OPT_NODE_BAN_THRESHOLD,
+                value: 0,
+            },
+            node_ban_window: DynamicConfigOption {
+                name: OPT_NODE_BAN_WINDOW,
+                value: 60,
+            },
+            startup_delay_secs: DynamicConfigOption {
+                name: OPT_STARTUP_DELAY_SECS,
+                value: 0,
+            },
+            min_funded_sats: DynamicConfigOption {
+                name: OPT_MIN_FUNDED_SATS,
+                value: 0,
+            },
             max_htlc_count: DynamicConfigOption {
                 name: OPT_MAX_HTLC_COUNT,
                 value: 5,
             },
+            max_peer_htlc_count: DynamicConfigOption {
+                name: OPT_MAX_PEER_HTLC_COUNT,
+                value: 0,
+            },
+            max_peer_htlc_value: DynamicConfigOption {
+                name: OPT_MAX_PEER_HTLC_VALUE,
+                value: 0,
+            },
+            reliability_weight: DynamicConfigOption {
+                name: OPT_RELIABILITY_WEIGHT,
+                value: 0.0,
+            },
+            capacity_preference: DynamicConfigOption {
+                name: OPT_CAPACITY_PREFERENCE,
+                value: 0,
+            },
+            node_penalty: DynamicConfigOption {
+                name: OPT_NODE_PENALTY,
+                value: 0,
+            },
+            graph_write_interval: DynamicConfigOption {
+                name: OPT_GRAPH_WRITE_INTERVAL,
+                value: 0,
+            },
+            pays_sweep_interval: DynamicConfigOption {
+                name: OPT_PAYS_SWEEP_INTERVAL,
+                value: 60,
+            },
+            stats_cache_size: DynamicConfigOption {
+                name: OPT_STATS_CACHE_SIZE,
+                value: 100,
+            },
+            node_penalty_half_life: DynamicConfigOption {
+                name: OPT_NODE_PENALTY_HALF_LIFE,
+                value: 60,
+            },
+            post_success_command: DynamicConfigOption {
+                name: OPT_POST_SUCCESS_COMMAND,
+                value: String::new(),
+            },
             stats_delete_failures_age: DynamicConfigOption {
                 name: OPT_STATS_DELETE_FAILURES_AGE,
                 value: 30,
@@ -251,9 +1010,280 @@ impl Config {
                 name: OPT_STATS_DELETE_SUCCESSES_SIZE,
                 value: 10_000,
             },
+            stats_recent_window_days: DynamicConfigOption {
+                name: OPT_STATS_RECENT_WINDOW_DAYS,
+                value: 30,
+            },
+            stats_rollup: DynamicConfigOption {
+                name: OPT_STATS_ROLLUP,
+                value: false,
+            },
             cltv_delta: 144,
         }
     }
+    pub fn option_values(&self) -> serde_json::Map<String, serde_json::Value> {
+        let mut values = serde_json::Map::new();
+        values.insert(
+            self.utf8.name.to_string(),
+            serde_json::json!(self.utf8.value),
+        );
+        values.insert(
+            self.refresh_peers_interval.name.to_string(),
+            serde_json::json!(self.refresh_peers_interval.value),
+        );
+        values.insert(
+            self.refresh_aliasmap_interval.name.to_string(),
+            serde_json::json!(self.refresh_aliasmap_interval.value),
+        );
+        values.insert(
+            self.refresh_gossmap_interval.name.to_string(),
+            serde_json::json!(self.refresh_gossmap_interval.value),
+        );
+        values.insert(
+            self.graph_max_channels.name.to_string(),
+            serde_json::json!(self.graph_max_channels.value),
+        );
+        values.insert(
+            self.graph_source.name.to_string(),
+            serde_json::json!(self.graph_source.value),
+        );
+        values.insert(
+            self.reset_liquidity_interval.name.to_string(),
+            serde_json::json!(self.reset_liquidity_interval.value),
+        );
+        values.insert(
+            self.liquidity_max_age.name.to_string(),
+            serde_json::json!(self.liquidity_max_age.value),
+        );
+        values.insert(
+            self.liquidity_confidence_window.name.to_string(),
+            serde_json::json!(self.liquidity_confidence_window.value),
+        );
+        values.insert(
+            self.flow_priors.name.to_string(),
+            serde_json::json!(self.flow_priors.value),
+        );
+        values.insert(
+            self.hop_results.name.to_string(),
+            serde_json::json!(self.hop_results.value),
+        );
+        values.insert(
+            self.trace.name.to_string(),
+            serde_json::json!(self.trace.value),
+        );
+        values.insert(
+            self.roi_warn.name.to_string(),
+            serde_json::json!(self.roi_warn.value),
+        );
+        values.insert(
+            self.verify_balance.name.to_string(),
+            serde_json::json!(self.verify_balance.value),
+        );
+        values.insert(
+            self.verify_own_payment.name.to_string(),
+            serde_json::json!(self.verify_own_payment.value),
+        );
+        values.insert(
+            self.depleteuptopercent.name.to_string(),
+            serde_json::json!(self.depleteuptopercent.value),
+        );
+        values.insert(
+            self.depleteuptoamount.name.to_string(),
+            serde_json::json!(self.depleteuptoamount.value),
+        );
+        values.insert(
+            self.maxhops.name.to_string(),
+            serde_json::json!(self.maxhops.value),
+        );
+        values.insert(
+            self.candidates_min_age.name.to_string(),
+            serde_json::json!(self.candidates_min_age.value),
+        );
+        values.insert(
+            self.candidate_order.name.to_string(),
+            serde_json::json!(self.candidate_order.value),
+        );
+        values.insert(
+            self.max_candidates_per_iteration.name.to_string(),
+            serde_json::json!(self.max_candidates_per_iteration.value),
+        );
+        values.insert(
+            self.candidates_cap_strategy.name.to_string(),
+            serde_json::json!(self.candidates_cap_strategy.value),
+        );
+        values.insert(
+            self.mode.name.to_string(),
+            serde_json::json!(self.mode.value),
+        );
+        values.insert(
+            self.exclude_alias.name.to_string(),
+            serde_json::json!(self.exclude_alias.value),
+        );
+        values.insert(
+            self.route_cache_max_age.name.to_string(),
+            serde_json::json!(self.route_cache_max_age.value),
+        );
+        values.insert(
+            self.min_forward_volume.name.to_string(),
+            serde_json::json!(self.min_forward_volume.value),
+        );
+        values.insert(
+            self.max_peer_data_age.name.to_string(),
+            serde_json::json!(self.max_peer_data_age.value),
+        );
+        values.insert(
+            self.rpc_concurrency.name.to_string(),
+            serde_json::json!(self.rpc_concurrency.value),
+        );
+        values.insert(
+            self.paralleljobs.name.to_string(),
+            serde_json::json!(self.paralleljobs.value),
+        );
+        values.insert(
+            self.timeoutpay.name.to_string(),
+            serde_json::json!(self.timeoutpay.value),
+        );
+        values.insert(
+            self.max_route_attempts.name.to_string(),
+            serde_json::json!(self.max_route_attempts.value),
+        );
+        values.insert(
+            self.candidate_max_failures.name.to_string(),
+            serde_json::json!(self.candidate_max_failures.value),
+        );
+        values.insert(
+            self.circuit_breaker_threshold.name.to_string(),
+            serde_json::json!(self.circuit_breaker_threshold.value),
+        );
+        values.insert(
+            self.node_ban_threshold.name.to_string(),
+            serde_json::json!(self.node_ban_threshold.value),
+        );
+        values.insert(
+            self.node_ban_window.name.to_string(),
+            serde_json::json!(self.node_ban_window.value),
+        );
+        values.insert(
+            self.startup_delay_secs.name.to_string(),
+            serde_json::json!(self.startup_delay_secs.value),
+        );
+        values.insert(
+            self.min_funded_sats.name.to_string(),
+            serde_json::json!(self.min_funded_sats.value),
+        );
+        values.insert(
+            self.max_htlc_count.name.to_string(),
+            serde_json::json!(self.max_htlc_count.value),
+        );
+        values.insert(
+            self.max_peer_htlc_count.name.to_string(),
+            serde_json::json!(self.max_peer_htlc_count.value),
+        );
+        values.insert(
+            self.max_peer_htlc_value.name.to_string(),
+            serde_json::json!(self.max_peer_htlc_value.value),
+        );
+        values.insert(
+            self.reliability_weight.name.to_string(),
+            serde_json::json!(self.reliability_weight.value),
+        );
+        values.insert(
+            self.capacity_preference.name.to_string(),
+            serde_json::json!(self.capacity_preference.value),
+        );
+        values.insert(
+            self.node_penalty.name.to_string(),
+            serde_json::json!(self.node_penalty.value),
+        );
+        values.insert(
+            self.graph_write_interval.name.to_string(),
+            serde_json::json!(self.graph_write_interval.value),
+        );
+        values.insert(
+            self.pays_sweep_interval.name.to_string(),
+            serde_json::json!(self.pays_sweep_interval.value),
+        );
+        values.insert(
+            self.stats_cache_size.name.to_string(),
+            serde_json::json!(self.stats_cache_size.value),
+        );
+        values.insert(
+            self.node_penalty_half_life.name.to_string(),
+            serde_json::json!(self.node_penalty_half_life.value),
+        );
+        // only tell whether a command is set, it may contain private paths
+        values.insert(
+            self.post_success_command.name.to_string(),
+            serde_json::json!(!self.post_success_command.value.is_empty()),
+        );
+        values.insert(
+            self.stats_delete_failures_age.name.to_string(),
+            serde_json::json!(self.stats_delete_failures_age.value),
+        );
+        values.insert(
+            self.stats_delete_failures_size.name.to_string(),
+            serde_json::json!(self.stats_delete_failures_size.value),
+        );
+        values.insert(
+            self.stats_delete_successes_age.name.to_string(),
+            serde_json::json!(self.stats_delete_successes_age.value),
+        );
+        values.insert(
+            self.stats_delete_successes_size.name.to_string(),
+            serde_json::json!(self.stats_delete_successes_size.value),
+        );
+        values.insert(
+            self.stats_recent_window_days.name.to_string(),
+            serde_json::json!(self.stats_recent_window_days.value),
+        );
+        values.insert(
+            self.stats_rollup.name.to_string(),
+            serde_json::json!(self.stats_rollup.value),
+        );
+        values
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct EffectiveJobConfig {
+    pub target: f64,
+    pub maxhops: u8,
+    pub depleteuptopercent: f64,
+    pub depleteuptoamount: u64,
+    pub paralleljobs: u8,
+    pub timeoutpay: u16,
+    pub candidates_min_age: u32,
+    pub max_htlc_count: u64,
+    pub dryrun: bool,
+}
+impl EffectiveJobConfig {
+    pub fn new(job: &Job, config: &Config) -> Self {
+        EffectiveJobConfig {
+            target: job.target.unwrap_or(0.5),
+            maxhops: job.maxhops.unwrap_or(config.maxhops.value),
+            depleteuptopercent: job
+                .depleteuptopercent
+                .unwrap_or(config.depleteuptopercent.value),
+            depleteuptoamount: job
+                .depleteuptoamount
+                .unwrap_or(config.depleteuptoamount.value),
+            paralleljobs: job.paralleljobs.unwrap_or(config.paralleljobs.value),
+            timeoutpay: config.timeoutpay.value,
+            candidates_min_age: config.candidates_min_age.value,
+            max_htlc_count: config.max_htlc_count.value,
+            dryrun: job.dryrun,
+        }
+    }
+    // maxhops 0 means no hop limit, the route is then only bounded by maxppm
+    pub fn route_limits(&self, job: &Job) -> (u8, Option<u64>) {
+        match self.maxhops {
+            0 => (
+                MAX_HOPS_HARD_CAP + 1,
+                Some(job.maxppm_for(job.sat_direction) as u64 * job.amount_msat / 1_000_000),
+            ),
+            h => (h + 1, None),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -268,6 +1298,13 @@ pub struct JobState {
     active: bool,
     should_stop: bool,
     id: u8,
+    last_route: Option<LastRoute>,
+    stuck_htlc: Option<StuckHtlc>,
+    too_expensive: Option<TooExpensive>,
+    consecutive_failures: u64,
+    circuit_open: bool,
+    window_attempts: u64,
+    window_successes: u64,
 }
 impl JobState {
     pub fn new(latest_state: JobMessage, id: u8) -> Self {
@@ -276,6 +1313,13 @@ impl JobState {
             active: true,
             should_stop: false,
             id,
+            last_route: None,
+            stuck_htlc: None,
+            too_expensive: None,
+            consecutive_failures: 0,
+            circuit_open: false,
+            window_attempts: 0,
+            window_successes: 0,
         }
     }
     pub fn missing() -> Self {
@@ -284,10 +1328,20 @@ impl JobState {
             active: false,
             should_stop: false,
             id: 0,
+            last_route: None,
+            stuck_htlc: None,
+            too_expensive: None,
+            consecutive_failures: 0,
+            circuit_open: false,
+            window_attempts: 0,
+            window_successes: 0,
         }
     }
 
     fn statechange(&mut self, latest_state: JobMessage) {
+        if !matches!(latest_state, JobMessage::TooExp) {
+            self.too_expensive = None;
+        }
         self.latest_state = latest_state;
     }
     pub fn state(&self) -> JobMessage {
@@ -308,6 +1362,304 @@ impl JobState {
     pub fn id(&self) -> u8 {
         self.id
     }
+    pub fn last_route(&self) -> Option<&LastRoute> {
+        self.last_route.as_ref()
+    }
+    pub fn stuck_htlc(&self) -> Option<&StuckHtlc> {
+        self.stuck_htlc.as_ref()
+    }
+    pub fn too_expensive(&self) -> Option<TooExpensive> {
+        self.too_expensive
+    }
+    pub fn record_attempt(&mut self, success: bool, threshold: u64) -> bool {
+        self.window_attempts += 1;
+        if success {
+            self.window_successes += 1;
+            self.consecutive_failures = 0;
+            return false;
+        }
+        self.consecutive_failures += 1;
+        if threshold > 0 && self.consecutive_failures >= threshold {
+            self.circuit_open = true;
+        }
+        self.circuit_open
+    }
+    pub fn consecutive_failures(&self) -> u64 {
+        self.consecutive_failures
+    }
+    pub fn success_ratio(&self) -> Option<f64> {
+        if self.window_attempts == 0 {
+            None
+        } else {
+            Some(self.window_successes as f64 / self.window_attempts as f64)
+        }
+    }
+    pub fn is_circuit_open(&self) -> bool {
+        self.circuit_open
+    }
+    pub fn reset_circuit(&mut self) {
+        self.consecutive_failures = 0;
+        self.circuit_open = false;
+        self.statechange(JobMessage::Stopped);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LastRouteHop {
+    pub short_channel_id: ShortChannelId,
+    pub peer_id: PublicKey,
+    pub amount_msat: u64,
+    pub fee_msat: u64,
+    pub delay: u32,
+}
+
+// the last successful route of each job, so restarts don't start cold
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct RouteCache {
+    routes: HashMap<ShortChannelId, CachedRoute>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CachedRoute {
+    pub amount_msat: u64,
+    pub route: Vec<LastRouteHop>,
+    pub saved_at: u64,
+}
+impl RouteCache {
+    pub fn insert(&mut self, chan_id: ShortChannelId, route: &[SendpayRoute], now: u64) {
+        self.routes.insert(
+            chan_id,
+            CachedRoute {
+                amount_msat: Amount::msat(&route.last().unwrap().amount_msat),
+                route: LastRoute::new(0, route, None, None).route,
+                saved_at: now,
+            },
+        );
+    }
+    pub fn remove(&mut self, chan_id: &ShortChannelId) {
+        self.routes.remove(chan_id);
+    }
+    pub fn drop_expired(&mut self, now: u64, max_age_secs: u64) -> usize {
+        let before = self.routes.len();
+        self.routes.retain(|_, r| r.saved_at + max_age_secs >= now);
+        before - self.routes.len()
+    }
+    // the cached route for amount_msat if all of its channels are still in the graph,
+    // no hop raised its fees and it is still below maxppm
+    pub fn get_valid(
+        &self,
+        chan_id: &ShortChannelId,
+        amount_msat: u64,
+        maxppm: u32,
+        graph: &LnGraph,
+        my_pubkey: &PublicKey,
+    ) -> Option<Vec<SendpayRoute>> {
+        let cached = self.routes.get(chan_id)?;
+        if cached.amount_msat != amount_msat
+            || feeppm_effective_from_amts(cached.route.first()?.amount_msat, amount_msat) > maxppm
+        {
+            return None;
+        }
+        let mut source = *my_pubkey;
+        let mut prev_amount_msat = None;
+        for hop in &cached.route {
+            match graph.get_channel(&source, &hop.short_channel_id) {
+                Ok(c) if c.destination == hop.peer_id => {
+                    if let Some(prev) = prev_amount_msat {
+                        let fee_msat = fee_total_msat_precise(
+                            c.fee_per_millionth,
+                            c.base_fee_millisatoshi,
+                            hop.amount_msat,
+                        )
+                        .ceil() as u64;
+                        if prev < hop.amount_msat + fee_msat {
+                            return None;
+                        }
+                    }
+                    source = hop.peer_id;
+                    prev_amount_msat = Some(hop.amount_msat);
+                }
+                _ => return None,
+            }
+        }
+        Some(
+            cached
+                .route
+                .iter()
+                .map(|hop| SendpayRoute {
+                    amount_msat: Amount::from_msat(hop.amount_msat),
+                    id: hop.peer_id,
+                    delay: hop.delay,
+                    channel: hop.short_channel_id,
+                })
+                .collect(),
+        )
+    }
+    pub async fn write_to_file(&self, sling_dir: &Path) -> Result<(), Error> {
+        write_file(
+            sling_dir.join(ROUTE_CACHE_FILE_NAME),
+            serde_json::to_string(self)?.as_bytes(),
+            false,
+        )
+        .await
+    }
+    pub async fn read_from_file(sling_dir: &Path) -> Result<RouteCache, Error> {
+        match fs::read_to_string(sling_dir.join(ROUTE_CACHE_FILE_NAME)).await {
+            Ok(content) => Ok(serde_json::from_str(&content)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(RouteCache::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LastRoute {
+    pub task_id: u8,
+    pub attempted_at: u64,
+    pub route: Vec<LastRouteHop>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure_index: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure: Option<FailureReb>,
+}
+impl LastRoute {
+    pub fn new(
+        task_id: u8,
+        route: &[SendpayRoute],
+        failure_index: Option<u8>,
+        failure: Option<FailureReb>,
+    ) -> Self {
+        let route = route
+            .iter()
+            .enumerate()
+            .map(|(i, hop)| LastRouteHop {
+                short_channel_id: hop.channel,
+                peer_id: hop.id,
+                amount_msat: Amount::msat(&hop.amount_msat),
+                fee_msat: match route.get(i + 1) {
+                    Some(next) => Amount::msat(&hop.amount_msat) - Amount::msat(&next.amount_msat),
+                    None => 0,
+                },
+                delay: hop.delay,
+            })
+            .collect();
+        LastRoute {
+            task_id,
+            attempted_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            route,
+            failure_index,
+            failure,
+        }
+    }
+}
+
+pub fn channel_last_route_update(
+    jobstates: Arc<Mutex<HashMap<ShortChannelId, Vec<JobState>>>>,
+    task: &Task,
+    last_route: LastRoute,
+) {
+    if let Some(js) = jobstates
+        .lock()
+        .get_mut(&task.chan_id)
+        .and_then(|jss| jss.iter_mut().find(|jt| jt.id() == task.task_id))
+    {
+        js.last_route = Some(last_route);
+    }
+}
+
+pub fn channel_htlc_stuck(
+    jobstates: Arc<Mutex<HashMap<ShortChannelId, Vec<JobState>>>>,
+    task: &Task,
+    stuck_htlc: StuckHtlc,
+) {
+    if let Some(js) = jobstates
+        .lock()
+        .get_mut(&task.chan_id)
+        .and_then(|jss| jss.iter_mut().find(|jt| jt.id() == task.task_id))
+    {
+        js.statechange(JobMessage::HtlcStuck);
+        js.stuck_htlc = Some(stuck_htlc);
+    }
+}
+
+pub fn channel_too_expensive(
+    jobstates: Arc<Mutex<HashMap<ShortChannelId, Vec<JobState>>>>,
+    task: &Task,
+    too_expensive: TooExpensive,
+) -> Result<(), Error> {
+    channel_jobstate_update(jobstates.clone(), task, &JobMessage::TooExp, true, false)?;
+    if let Some(js) = jobstates
+        .lock()
+        .get_mut(&task.chan_id)
+        .and_then(|jss| jss.iter_mut().find(|jt| jt.id() == task.task_id))
+    {
+        js.too_expensive = Some(too_expensive);
+    }
+    Ok(())
+}
+
+pub fn channel_htlc_resolved(
+    jobstates: Arc<Mutex<HashMap<ShortChannelId, Vec<JobState>>>>,
+    task: &Task,
+) {
+    if let Some(js) = jobstates
+        .lock()
+        .get_mut(&task.chan_id)
+        .and_then(|jss| jss.iter_mut().find(|jt| jt.id() == task.task_id))
+    {
+        if js.stuck_htlc.take().is_some() && matches!(js.state(), JobMessage::HtlcStuck) {
+            js.statechange(JobMessage::Rebalancing);
+        }
+    }
+}
+
+pub fn channel_attempt_update(
+    jobstates: Arc<Mutex<HashMap<ShortChannelId, Vec<JobState>>>>,
+    task: &Task,
+    success: bool,
+    threshold: u64,
+) -> Result<bool, Error> {
+    let mut jobstates_lock = jobstates.lock();
+    let jobstate = jobstates_lock
+        .get_mut(&task.chan_id)
+        .and_then(|jss| jss.iter_mut().find(|jt| jt.id() == task.task_id))
+        .ok_or(anyhow!("channel_attempt_update: Could not find task"))?;
+    Ok(jobstate.record_attempt(success, threshold))
+}
+
+// steps the parallelism once the job's tasks made PARALLEL_TUNE_WINDOW attempts
+pub fn tune_parallelism(job_states: &mut [JobState], current: u8, min: u8, max: u8) -> u8 {
+    let attempts = job_states.iter().map(|js| js.window_attempts).sum::<u64>();
+    if attempts < PARALLEL_TUNE_WINDOW {
+        return current;
+    }
+    let successes = job_states.iter().map(|js| js.window_successes).sum::<u64>();
+    for js in job_states.iter_mut() {
+        js.window_attempts = 0;
+        js.window_successes = 0;
+    }
+    let ratio = successes as f64 / attempts as f64;
+    if ratio >= PARALLEL_TUNE_UP_RATE {
+        current.saturating_add(1).min(max)
+    } else if ratio < PARALLEL_TUNE_DOWN_RATE {
+        current.saturating_sub(1).max(min)
+    } else {
+        current.clamp(min, max)
+    }
+}
+
+pub fn get_last_route(
+    jobstates: &HashMap<ShortChannelId, Vec<JobState>>,
+    chan_id: &ShortChannelId,
+) -> Option<LastRoute> {
+    jobstates
+        .get(chan_id)?
+        .iter()
+        .filter_map(|js| js.last_route())
+        .max_by_key(|lr| lr.attempted_at)
+        .cloned()
 }
 
 pub fn channel_jobstate_update(
@@ -355,14 +1707,82 @@ pub enum JobMessage {
     PeerNotFound,
     PeerNotReady,
     ChanNotNormal,
+    ChanNotFunded,
+    ChanDisabled,
+    ChanClosing,
+    CircuitOpen,
     GraphEmpty,
     ChanNotInGraph,
     NoRoute,
     TooExp,
+    MaxAttempts,
+    DryRun,
+    Maintenance,
     Stopping,
     Stopped,
     Error,
     NoJob,
+    HtlcStuck,
+    Follower,
+    TotalCapReached,
+    Throttled,
+    LowUtilization,
+    StaleData,
+    GroupWaiting,
+    OutsideSchedule,
+}
+impl JobMessage {
+    pub fn code(&self) -> u16 {
+        match self {
+            JobMessage::Starting => 1,
+            JobMessage::Rebalancing => 2,
+            JobMessage::Balanced => 3,
+            JobMessage::NoCandidates => 4,
+            JobMessage::HTLCcapped => 5,
+            JobMessage::Disconnected => 6,
+            JobMessage::PeerNotFound => 7,
+            JobMessage::PeerNotReady => 8,
+            JobMessage::ChanNotNormal => 9,
+            JobMessage::GraphEmpty => 10,
+            JobMessage::ChanNotInGraph => 11,
+            JobMessage::NoRoute => 12,
+            JobMessage::TooExp => 13,
+            JobMessage::MaxAttempts => 14,
+            JobMessage::DryRun => 15,
+            JobMessage::Maintenance => 16,
+            JobMessage::Stopping => 17,
+            JobMessage::Stopped => 18,
+            JobMessage::Error => 19,
+            JobMessage::NoJob => 20,
+            JobMessage::ChanClosing => 21,
+            JobMessage::CircuitOpen => 22,
+            JobMessage::ChanNotFunded => 23,
+            JobMessage::ChanDisabled => 24,
+            JobMessage::HtlcStuck => 25,
+            JobMessage::Follower => 26,
+            JobMessage::TotalCapReached => 27,
+            JobMessage::Throttled => 28,
+            JobMessage::LowUtilization => 29,
+            JobMessage::StaleData => 30,
+            JobMessage::GroupWaiting => 31,
+            JobMessage::OutsideSchedule => 32,
+        }
+    }
+    pub fn is_stuck(&self) -> bool {
+        matches!(
+            self,
+            JobMessage::NoCandidates
+                | JobMessage::HTLCcapped
+                | JobMessage::PeerNotReady
+                | JobMessage::GraphEmpty
+                | JobMessage::ChanNotInGraph
+                | JobMessage::NoRoute
+                | JobMessage::TooExp
+                | JobMessage::MaxAttempts
+                | JobMessage::CircuitOpen
+                | JobMessage::HtlcStuck
+        )
+    }
 }
 impl Display for JobMessage {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
@@ -376,14 +1796,264 @@ impl Display for JobMessage {
             JobMessage::PeerNotFound => write!(f, "PeerNotFound"),
             JobMessage::PeerNotReady => write!(f, "PeerNotReady"),
             JobMessage::ChanNotNormal => write!(f, "ChanNotNormal"),
+            JobMessage::ChanNotFunded => write!(f, "ChanNotFunded"),
+            JobMessage::ChanDisabled => write!(f, "ChanDisabled"),
+            JobMessage::ChanClosing => write!(f, "ChanClosing"),
+            JobMessage::CircuitOpen => write!(f, "CircuitOpen"),
             JobMessage::GraphEmpty => write!(f, "GraphEmpty"),
             JobMessage::ChanNotInGraph => write!(f, "ChanNotInGraph"),
             JobMessage::NoRoute => write!(f, "NoRoutes"),
             JobMessage::TooExp => write!(f, "NoCheapRoute"),
+            JobMessage::MaxAttempts => write!(f, "MaxAttempts"),
+            JobMessage::DryRun => write!(f, "DryRun"),
+            JobMessage::Maintenance => write!(f, "Maintenance"),
             JobMessage::Stopping => write!(f, "Stopping"),
             JobMessage::Stopped => write!(f, "Stopped"),
             JobMessage::Error => write!(f, "Error"),
             JobMessage::NoJob => write!(f, "NoJob"),
+            JobMessage::HtlcStuck => write!(f, "HtlcStuck"),
+            JobMessage::Follower => write!(f, "Follower"),
+            JobMessage::TotalCapReached => write!(f, "TotalCapReached"),
+            JobMessage::Throttled => write!(f, "Throttled"),
+            JobMessage::LowUtilization => write!(f, "LowUtilization"),
+            JobMessage::StaleData => write!(f, "StaleData"),
+            JobMessage::GroupWaiting => write!(f, "GroupWaiting"),
+            JobMessage::OutsideSchedule => write!(f, "OutsideSchedule"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RouteAttempts {
+    failed: u64,
+}
+impl RouteAttempts {
+    pub fn record(&mut self, success: bool, max_attempts: u64) -> bool {
+        if success {
+            self.failed = 0;
+            return false;
+        }
+        self.failed += 1;
+        if max_attempts > 0 && self.failed >= max_attempts {
+            self.failed = 0;
+            true
+        } else {
+            false
+        }
+    }
+    pub fn reset(&mut self) {
+        self.failed = 0;
+    }
+}
+
+// the amount of the next attempt, split towards min_amount while no route is
+// found and back to the job's full amount after a success or a break
+#[derive(Debug)]
+pub struct AttemptAmount {
+    full_msat: u64,
+    current_msat: u64,
+}
+impl AttemptAmount {
+    pub fn new(full_msat: u64) -> AttemptAmount {
+        AttemptAmount {
+            full_msat,
+            current_msat: full_msat,
+        }
+    }
+    pub fn current(&self) -> u64 {
+        self.current_msat
+    }
+    pub fn split(&mut self, job: &Job) -> Option<u64> {
+        let smaller = job.split_amount(self.current_msat)?;
+        self.current_msat = smaller;
+        Some(smaller)
+    }
+    pub fn record(&mut self, success: bool) {
+        if success {
+            self.reset();
+        }
+    }
+    pub fn reset(&mut self) {
+        self.current_msat = self.full_msat;
+    }
+}
+
+// consecutive failures of each candidate within one task, a candidate that
+// failed too often in a row is passed over until the others had their turn
+#[derive(Debug, Default)]
+pub struct CandidateFailures {
+    in_a_row: HashMap<ShortChannelId, u64>,
+    skipped: HashSet<ShortChannelId>,
+}
+impl CandidateFailures {
+    pub fn record(&mut self, candidate: ShortChannelId, success: bool, max_failures: u64) -> bool {
+        if max_failures == 0 {
+            return false;
+        }
+        if success {
+            self.in_a_row.remove(&candidate);
+            return false;
+        }
+        let failures = self.in_a_row.entry(candidate).or_default();
+        *failures += 1;
+        if *failures >= max_failures {
+            self.in_a_row.remove(&candidate);
+            self.skipped.insert(candidate);
+            true
+        } else {
+            false
+        }
+    }
+    // removes and returns the skipped candidates, once all of them were
+    // skipped a new round starts with the full list
+    pub fn rotate(&mut self, candidatelist: &mut Vec<ShortChannelId>) -> Vec<ShortChannelId> {
+        if self.skipped.is_empty() {
+            return Vec::new();
+        }
+        if candidatelist.iter().all(|c| self.skipped.contains(c)) {
+            self.skipped.clear();
+            return Vec::new();
+        }
+        let (skipped, kept) = candidatelist
+            .drain(..)
+            .partition(|c| self.skipped.contains(c));
+        *candidatelist = kept;
+        skipped
+    }
+    pub fn reset(&mut self) {
+        self.in_a_row.clear();
+        self.skipped.clear();
+    }
+}
+
+// idle connections are handed out again, at most `capacity` are in use at once
+pub struct RpcPool<C> {
+    idle: Mutex<Vec<C>>,
+    permits: Semaphore,
+    capacity: Mutex<usize>,
+}
+impl<C> RpcPool<C> {
+    pub fn new(capacity: usize) -> Self {
+        RpcPool {
+            idle: Mutex::new(Vec::new()),
+            permits: Semaphore::new(capacity),
+            capacity: Mutex::new(capacity),
+        }
+    }
+    // meant for startup, shrinking only takes away permits nobody is holding
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut current = self.capacity.lock();
+        if capacity > *current {
+            self.permits.add_permits(capacity - *current);
+            *current = capacity;
+        } else if let Ok(p) = self.permits.try_acquire_many((*current - capacity) as u32) {
+            p.forget();
+            *current = capacity;
+        } else {
+            warn!("rpc pool: could not shrink to {} connections", capacity);
+        }
+        self.idle.lock().truncate(*current);
+    }
+    pub async fn get<F, Fut>(&self, connect: F) -> Result<PooledRpc<'_, C>, Error>
+    where
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = Result<C, Error>>,
+    {
+        let permit = self.permits.acquire().await?;
+        let idle = self.idle.lock().pop();
+        let conn = match idle {
+            Some(c) => c,
+            None => connect().await?,
+        };
+        Ok(PooledRpc {
+            conn: Some(conn),
+            pool: self,
+            broken: false,
+            _permit: permit,
+        })
+    }
+}
+impl RpcPool<ClnRpc> {
+    pub async fn get_cln(&self, rpc_path: &Path) -> Result<PooledRpc<'_, ClnRpc>, Error> {
+        self.get(|| ClnRpc::new(rpc_path)).await
+    }
+}
+
+pub struct PooledRpc<'a, C> {
+    conn: Option<C>,
+    pool: &'a RpcPool<C>,
+    broken: bool,
+    _permit: SemaphorePermit<'a>,
+}
+impl<C> PooledRpc<'_, C> {
+    // rpc errors come with a code, transport errors don't and
+    // the connection can't be trusted anymore
+    pub fn discard_on_transport_error<T>(&mut self, result: &Result<T, RpcError>) {
+        if result.as_ref().is_err_and(|e| e.code.is_none()) {
+            self.broken = true;
+        }
+    }
+}
+impl<C> std::ops::Deref for PooledRpc<'_, C> {
+    type Target = C;
+    fn deref(&self) -> &C {
+        self.conn.as_ref().unwrap()
+    }
+}
+impl<C> std::ops::DerefMut for PooledRpc<'_, C> {
+    fn deref_mut(&mut self) -> &mut C {
+        self.conn.as_mut().unwrap()
+    }
+}
+impl<C> Drop for PooledRpc<'_, C> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            if !self.broken {
+                self.pool.idle.lock().push(conn);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RefreshBackoff {
+    name: &'static str,
+    interval: u64,
+    failed: u32,
+}
+impl RefreshBackoff {
+    pub fn new(name: &'static str, interval: u64) -> Self {
+        RefreshBackoff {
+            name,
+            interval,
+            failed: 0,
+        }
+    }
+    pub fn next_interval(&mut self, result: &Result<(), Error>) -> u64 {
+        match result {
+            Ok(()) => {
+                if self.failed > 0 {
+                    info!(
+                        "{}: succeeded again after {} failures, back to {}s interval",
+                        self.name, self.failed, self.interval
+                    );
+                    self.failed = 0;
+                }
+                self.interval
+            }
+            Err(e) => {
+                self.failed = self.failed.saturating_add(1);
+                let backoff = self
+                    .interval
+                    .max(1)
+                    .saturating_mul(2u64.saturating_pow(self.failed))
+                    .min(REFRESH_BACKOFF_MAX_SECS.max(self.interval));
+                warn!(
+                    "{}: failed {} time(s) in a row, retrying in {}s: {}",
+                    self.name, self.failed, backoff, e
+                );
+                backoff
+            }
         }
     }
 }
@@ -423,6 +2093,9 @@ pub struct DirectedChannelState {
     pub last_update: u32,
     pub liquidity: u64,
     pub liquidity_age: u64,
+    // when a payment last succeeded or failed through the channel, 0 if never
+    #[serde(default)]
+    pub confidence_age: u64,
 }
 impl DirectedChannelState {
     pub fn update(&mut self, channel_update: &ChannelUpdate) {
@@ -434,6 +2107,27 @@ impl DirectedChannelState {
         self.htlc_minimum_msat = channel_update.htlc_minimum_msat;
         self.htlc_maximum_msat = channel_update.htlc_maximum_msat;
     }
+    pub fn liquidity_belief(&self, now: u64, max_age: u64) -> u64 {
+        if max_age > 0 && self.liquidity_age + max_age * 60 < now {
+            min(self.liquidity, Amount::msat(&self.htlc_maximum_msat) / 4)
+        } else {
+            self.liquidity
+        }
+    }
+    pub fn liquidity_bounds(&self, now: u64, max_age: u64, confidence_window: u64) -> (u64, u64) {
+        let belief = self.liquidity_belief(now, max_age);
+        if confidence_window == 0 || self.confidence_age == 0 {
+            return (belief, belief);
+        }
+        let htlc_max = Amount::msat(&self.htlc_maximum_msat);
+        let window_secs = confidence_window * 60;
+        let age = min(now.saturating_sub(self.confidence_age), window_secs);
+        let spread = (htlc_max as u128 * age as u128 / window_secs as u128 / 2) as u64;
+        (
+            belief.saturating_sub(spread),
+            min(belief.saturating_add(spread), max(belief, htlc_max)),
+        )
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -448,6 +2142,36 @@ pub struct ExcludeGraph {
     pub exclude_peers: HashSet<PublicKey>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphExportFormat {
+    EdgeList,
+    GraphMl,
+    Dot,
+}
+impl FromStr for GraphExportFormat {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "edgelist" => Ok(GraphExportFormat::EdgeList),
+            "graphml" => Ok(GraphExportFormat::GraphMl),
+            "dot" => Ok(GraphExportFormat::Dot),
+            _ => Err(anyhow!(
+                "could not parse graph export format from `{}`, use edgelist, graphml or dot",
+                s
+            )),
+        }
+    }
+}
+impl GraphExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            GraphExportFormat::EdgeList => "tsv",
+            GraphExportFormat::GraphMl => "graphml",
+            GraphExportFormat::Dot => "dot",
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LnGraph {
     pub graph: HashMap<PublicKey, HashMap<DirectedChannel, DirectedChannelState>>,
@@ -458,23 +2182,345 @@ impl LnGraph {
             graph: HashMap::new(),
         }
     }
-    pub fn refresh_liquidity(&mut self, interval: u64) {
+    pub fn update(
+        &mut self,
+        data: &GraphData,
+        my_pubkey: &PublicKey,
+        flows: &HashMap<ShortChannelId, (u64, u64)>,
+        timestamp: u64,
+    ) -> Result<(), Error> {
+        for node_channels in self.graph.values_mut() {
+            for (dir_chan, dir_chan_state) in node_channels {
+                if let Some(update) = data.updates.get(dir_chan) {
+                    dir_chan_state.update(update);
+                }
+            }
+        }
+
+        for (ann_scid, chan_ann) in data.announcements.iter() {
+            let dir_chan_0 = DirectedChannel {
+                short_channel_id: *ann_scid,
+                direction: 0,
+            };
+            let dir_chan_1 = DirectedChannel {
+                short_channel_id: *ann_scid,
+                direction: 1,
+            };
+            for dir_chan in [dir_chan_0, dir_chan_1] {
+                if let Some(chan_update) = data.updates.get(&dir_chan) {
+                    if let Some(chan_amt) = data.amounts.get(ann_scid) {
+                        let (source, destination) = get_node_order(
+                            chan_update.direction,
+                            chan_ann.source,
+                            chan_ann.destination,
+                        )?;
+                        let new_dir_chan_state = DirectedChannelState {
+                            source,
+                            destination,
+                            active: chan_update.active,
+                            scid_alias: None,
+                            fee_per_millionth: chan_update.fee_per_millionth,
+                            base_fee_millisatoshi: chan_update.base_fee_millisatoshi,
+                            htlc_maximum_msat: chan_update.htlc_maximum_msat,
+                            htlc_minimum_msat: chan_update.htlc_minimum_msat,
+                            amount_msat: Amount::from_sat(*chan_amt),
+                            delay: chan_update.delay,
+                            liquidity: if source == *my_pubkey || destination == *my_pubkey {
+                                liquidity_prior(
+                                    chan_update.htlc_maximum_msat.msat(),
+                                    flows.get(ann_scid),
+                                    source == *my_pubkey,
+                                )
+                            } else {
+                                chan_update.htlc_maximum_msat.msat() / 2
+                            },
+                            liquidity_age: timestamp,
+                            confidence_age: 0,
+                            last_update: chan_update.last_update,
+                        };
+                        if let Some(graph_node_channels) = self.graph.get_mut(&source) {
+                            if let Some(old_dir_chan_state) = graph_node_channels.get_mut(&dir_chan)
+                            {
+                                old_dir_chan_state.update(chan_update);
+                            } else {
+                                graph_node_channels.insert(dir_chan, new_dir_chan_state);
+                            }
+                        } else {
+                            let mut first_chan = HashMap::new();
+                            first_chan.insert(dir_chan, new_dir_chan_state);
+                            self.graph.insert(source, first_chan);
+                        }
+                    }
+                }
+            }
+        }
+
+        for channels in self.graph.values_mut() {
+            channels.retain(|dir_chan, _| {
+                if data.full {
+                    !data.deleted.contains(&dir_chan.short_channel_id)
+                        && data.updates.contains_key(dir_chan)
+                } else {
+                    !data.deleted.contains(&dir_chan.short_channel_id)
+                }
+            })
+        }
+        Ok(())
+    }
+    pub fn refresh_liquidity(&mut self, interval: u64, pinned: &HashMap<DirectedChannel, u64>) {
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_secs();
         let mut count = 0;
         for (_node, channels) in self.graph.iter_mut() {
-            for channel_state in channels.values_mut() {
-                if channel_state.liquidity_age <= now - interval * 60 {
+            for (dir_chan, channel_state) in channels.iter_mut() {
+                if let Some(liquidity) = pinned.get(dir_chan) {
+                    channel_state.liquidity = *liquidity;
+                    channel_state.liquidity_age = now;
+                    channel_state.confidence_age = 0;
+                } else if channel_state.liquidity_age <= now - interval * 60 {
                     channel_state.liquidity = Amount::msat(&channel_state.htlc_maximum_msat) / 2;
                     channel_state.liquidity_age = now;
+                    channel_state.confidence_age = 0;
                     count += 1;
                 }
             }
         }
         info!("Reset liquidity belief on {} channels!", count);
     }
+    // the amount of a successful payment left the sending side of every hop
+    pub fn record_success(
+        &mut self,
+        route: &[SendpayRoute],
+        my_pubkey: &PublicKey,
+        now: u64,
+        confidence_window: u64,
+    ) {
+        if confidence_window == 0 {
+            return;
+        }
+        let mut source = *my_pubkey;
+        for hop in route {
+            if let Some(channels) = self.graph.get_mut(&source) {
+                for (dir_chan, channel_state) in channels.iter_mut() {
+                    if dir_chan.short_channel_id == hop.channel
+                        && channel_state.source != *my_pubkey
+                        && channel_state.destination != *my_pubkey
+                    {
+                        let amount_msat = Amount::msat(&hop.amount_msat);
+                        channel_state.liquidity =
+                            max(channel_state.liquidity, amount_msat) - amount_msat;
+                        channel_state.confidence_age = now;
+                    }
+                }
+            }
+            source = hop.id;
+        }
+    }
+    pub fn pin_liquidity(
+        &mut self,
+        dir_chan: &DirectedChannel,
+        liquidity: u64,
+    ) -> Result<(), Error> {
+        let channel_state = self
+            .graph
+            .values_mut()
+            .find_map(|channels| channels.get_mut(dir_chan))
+            .ok_or(anyhow!("{} not found in graph", dir_chan))?;
+        channel_state.liquidity = liquidity;
+        channel_state.liquidity_age = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        channel_state.confidence_age = 0;
+        Ok(())
+    }
+    pub fn prune(&mut self, my_pubkey: &PublicKey, max_channels: usize) -> usize {
+        let mut distances = HashMap::new();
+        distances.insert(*my_pubkey, 0);
+        let mut queue = VecDeque::from([*my_pubkey]);
+        while let Some(node) = queue.pop_front() {
+            let distance = distances[&node];
+            if let Some(node_channels) = self.graph.get(&node) {
+                for channel_state in node_channels.values() {
+                    if let Entry::Vacant(e) = distances.entry(channel_state.destination) {
+                        e.insert(distance + 1);
+                        queue.push_back(channel_state.destination);
+                    }
+                }
+            }
+        }
+
+        let mut channels: HashMap<ShortChannelId, (u32, u64)> = HashMap::new();
+        for node_channels in self.graph.values() {
+            for (dir_chan, channel_state) in node_channels {
+                let distance = min(
+                    *distances.get(&channel_state.source).unwrap_or(&u32::MAX),
+                    *distances
+                        .get(&channel_state.destination)
+                        .unwrap_or(&u32::MAX),
+                );
+                let entry = channels
+                    .entry(dir_chan.short_channel_id)
+                    .or_insert((distance, Amount::msat(&channel_state.amount_msat)));
+                entry.0 = min(entry.0, distance);
+            }
+        }
+        if channels.len() <= max_channels {
+            return 0;
+        }
+
+        let mut ranked = channels.into_iter().collect::<Vec<_>>();
+        ranked.sort_by(|(_, (dist_a, amt_a)), (_, (dist_b, amt_b))| {
+            dist_a.cmp(dist_b).then(amt_b.cmp(amt_a))
+        });
+        let keep = ranked
+            .iter()
+            .enumerate()
+            .filter(|(i, (_, (distance, _)))| *i < max_channels || *distance == 0)
+            .map(|(_, (scid, _))| *scid)
+            .collect::<HashSet<ShortChannelId>>();
+        for node_channels in self.graph.values_mut() {
+            node_channels.retain(|dir_chan, _| keep.contains(&dir_chan.short_channel_id));
+        }
+        self.graph.retain(|_, v| !v.is_empty());
+        ranked.len() - keep.len()
+    }
+    pub fn neighborhood(&self, center: &PublicKey, hops: u32) -> HashSet<PublicKey> {
+        let mut nodes = HashSet::from([*center]);
+        let mut frontier = vec![*center];
+        for _ in 0..hops {
+            let mut next = Vec::new();
+            for node in &frontier {
+                if let Some(node_channels) = self.graph.get(node) {
+                    for channel_state in node_channels.values() {
+                        if nodes.insert(channel_state.destination) {
+                            next.push(channel_state.destination);
+                        }
+                    }
+                }
+            }
+            frontier = next;
+        }
+        nodes
+    }
+    pub fn export(
+        &self,
+        nodes: Option<&HashSet<PublicKey>>,
+        alias_map: &HashMap<PublicKey, String>,
+        format: GraphExportFormat,
+        now: u64,
+        liquidity_max_age: u64,
+    ) -> (String, usize, usize) {
+        let included = |node: &PublicKey| nodes.is_none_or(|n| n.contains(node));
+        let mut edges = self
+            .graph
+            .values()
+            .flat_map(|node_channels| node_channels.iter())
+            .filter(|(_, state)| included(&state.source) && included(&state.destination))
+            .collect::<Vec<_>>();
+        edges.sort_by_key(|(dir_chan, _)| (dir_chan.short_channel_id, dir_chan.direction));
+        let mut node_list = edges
+            .iter()
+            .flat_map(|(_, state)| [state.source, state.destination])
+            .chain(nodes.into_iter().flatten().copied())
+            .collect::<Vec<_>>();
+        node_list.sort();
+        node_list.dedup();
+        let label = |node: &PublicKey| match alias_map.get(node) {
+            Some(alias) => alias.clone(),
+            None => node.to_string(),
+        };
+
+        let mut out = String::new();
+        match format {
+            GraphExportFormat::EdgeList => {
+                out.push_str(
+                    "source\tsource_alias\tdestination\tdestination_alias\tshort_channel_id\t\
+                     base_fee_msat\tfee_ppm\tliquidity_msat\n",
+                );
+                for (dir_chan, state) in &edges {
+                    out.push_str(&format!(
+                        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                        state.source,
+                        label(&state.source).replace(['\t', '\n'], " "),
+                        state.destination,
+                        label(&state.destination).replace(['\t', '\n'], " "),
+                        dir_chan.short_channel_id,
+                        state.base_fee_millisatoshi,
+                        state.fee_per_millionth,
+                        state.liquidity_belief(now, liquidity_max_age),
+                    ));
+                }
+            }
+            GraphExportFormat::GraphMl => {
+                let escape = |s: &str| {
+                    s.replace('&', "&amp;")
+                        .replace('<', "&lt;")
+                        .replace('>', "&gt;")
+                        .replace('"', "&quot;")
+                };
+                out.push_str(
+                    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                     <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n\
+                     <key id=\"alias\" for=\"node\" attr.name=\"alias\" attr.type=\"string\"/>\n\
+                     <key id=\"scid\" for=\"edge\" attr.name=\"short_channel_id\" attr.type=\"string\"/>\n\
+                     <key id=\"base_fee\" for=\"edge\" attr.name=\"base_fee_msat\" attr.type=\"long\"/>\n\
+                     <key id=\"fee_ppm\" for=\"edge\" attr.name=\"fee_ppm\" attr.type=\"long\"/>\n\
+                     <key id=\"liquidity\" for=\"edge\" attr.name=\"liquidity_msat\" attr.type=\"long\"/>\n\
+                     <graph id=\"sling\" edgedefault=\"directed\">\n",
+                );
+                for node in &node_list {
+                    out.push_str(&format!(
+                        "<node id=\"{}\"><data key=\"alias\">{}</data></node>\n",
+                        node,
+                        escape(&label(node))
+                    ));
+                }
+                for (dir_chan, state) in &edges {
+                    out.push_str(&format!(
+                        "<edge source=\"{}\" target=\"{}\">\
+                         <data key=\"scid\">{}</data>\
+                         <data key=\"base_fee\">{}</data>\
+                         <data key=\"fee_ppm\">{}</data>\
+                         <data key=\"liquidity\">{}</data></edge>\n",
+                        state.source,
+                        state.destination,
+                        dir_chan.short_channel_id,
+                        state.base_fee_millisatoshi,
+                        state.fee_per_millionth,
+                        state.liquidity_belief(now, liquidity_max_age),
+                    ));
+                }
+                out.push_str("</graph>\n</graphml>\n");
+            }
+            GraphExportFormat::Dot => {
+                let escape = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+                out.push_str("digraph sling {\n");
+                for node in &node_list {
+                    out.push_str(&format!(
+                        "  \"{}\" [label=\"{}\"];\n",
+                        node,
+                        escape(&label(node))
+                    ));
+                }
+                for (dir_chan, state) in &edges {
+                    out.push_str(&format!(
+                        "  \"{}\" -> \"{}\" [label=\"{} {}+{}ppm {}msat\"];\n",
+                        state.source,
+                        state.destination,
+                        dir_chan.short_channel_id,
+                        state.base_fee_millisatoshi,
+                        state.fee_per_millionth,
+                        state.liquidity_belief(now, liquidity_max_age),
+                    ));
+                }
+                out.push_str("}\n");
+            }
+        }
+        (out, node_list.len(), edges.len())
+    }
     pub fn get_channel(
         &self,
         source: &PublicKey,
@@ -499,6 +2545,7 @@ impl LnGraph {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn edges(
         &self,
         keypair: &PublicKeyPair,
@@ -507,13 +2554,15 @@ impl LnGraph {
         candidatelist: &[ShortChannelId],
         tempbans: &HashMap<ShortChannelId, u64>,
         parallel_bans: &[DirectedChannel],
+        liquidity_max_age: u64,
+        liquidity_confidence_window: u64,
     ) -> Vec<(&DirectedChannel, &DirectedChannelState)> {
         if let Some(node_channels) = self.graph.get(&keypair.other_pubkey) {
-            let twow_ago = SystemTime::now()
+            let now = SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .unwrap()
-                .as_secs()
-                - 60 * 60 * 24 * 14;
+                .as_secs();
+            let twow_ago = now - 60 * 60 * 24 * 14;
             node_channels
                 .iter()
                 .filter(|(dir_chan, dir_chan_state)| {
@@ -535,8 +2584,10 @@ impl LnGraph {
                             .contains(&dir_chan.short_channel_id)
                         && !tempbans.contains_key(&dir_chan.short_channel_id)
                         && !parallel_bans.contains(dir_chan)
-                        && dir_chan_state.liquidity >= job.amount_msat
-                        && Amount::msat(&dir_chan_state.htlc_minimum_msat) <= job.amount_msat
+                        && dir_chan_state
+                            .liquidity_bounds(now, liquidity_max_age, liquidity_confidence_window)
+                            .0
+                            >= job.amount_msat
                         && Amount::msat(&dir_chan_state.htlc_maximum_msat) >= job.amount_msat
                         && !exclude_graph.exclude_peers.contains(&dir_chan_state.source)
                         && !exclude_graph
@@ -560,44 +2611,127 @@ impl LnGraph {
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(from = "SuccessRebRecord")]
 pub struct SuccessReb {
     pub amount_msat: u64,
     pub fee_ppm: u32,
-    pub channel_partner: ShortChannelId,
+    pub fee_msat: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_partner: Option<ShortChannelId>,
     pub hops: u8,
     pub completed_at: u64,
 }
+// records written before fee_msat existed only have the ppm
+#[derive(Deserialize)]
+struct SuccessRebRecord {
+    amount_msat: u64,
+    fee_ppm: u32,
+    fee_msat: Option<u64>,
+    channel_partner: Option<ShortChannelId>,
+    hops: u8,
+    completed_at: u64,
+}
+impl From<SuccessRebRecord> for SuccessReb {
+    fn from(record: SuccessRebRecord) -> Self {
+        SuccessReb {
+            amount_msat: record.amount_msat,
+            fee_ppm: record.fee_ppm,
+            fee_msat: record
+                .fee_msat
+                .unwrap_or(record.amount_msat * record.fee_ppm as u64 / 1_000_000),
+            channel_partner: record.channel_partner,
+            hops: record.hops,
+            completed_at: record.completed_at,
+        }
+    }
+}
+// whether our side of the channel moved by about amount_msat in the direction of the job,
+// a little slack for forwards settling in the meantime
+pub fn balance_moved(
+    sat_direction: SatDirection,
+    to_us_before_msat: u64,
+    to_us_after_msat: u64,
+    amount_msat: u64,
+) -> bool {
+    let moved_msat = match sat_direction {
+        SatDirection::Pull => to_us_after_msat.saturating_sub(to_us_before_msat),
+        SatDirection::Push => to_us_before_msat.saturating_sub(to_us_after_msat),
+    };
+    moved_msat * 10 >= amount_msat * 9
+}
 impl SuccessReb {
+    // for attempts that timed out: only a success if our side of the job's channel
+    // moved by at least the amount of the route
+    pub fn from_balance_change(
+        route: &[SendpayRoute],
+        sat_direction: SatDirection,
+        to_us_before_msat: u64,
+        to_us_after_msat: u64,
+        completed_at: u64,
+    ) -> Option<SuccessReb> {
+        let amount_msat = Amount::msat(&route.last()?.amount_msat);
+        let moved = match sat_direction {
+            SatDirection::Pull => to_us_after_msat >= to_us_before_msat + amount_msat,
+            SatDirection::Push => to_us_before_msat >= to_us_after_msat + amount_msat,
+        };
+        if !moved {
+            return None;
+        }
+        SuccessReb::from_route(route, sat_direction, completed_at)
+    }
+    pub fn from_route(
+        route: &[SendpayRoute],
+        sat_direction: SatDirection,
+        completed_at: u64,
+    ) -> Option<SuccessReb> {
+        let sent_msat = Amount::msat(&route.first()?.amount_msat);
+        let amount_msat = Amount::msat(&route.last()?.amount_msat);
+        Some(SuccessReb {
+            amount_msat,
+            fee_ppm: feeppm_effective_from_amts(sent_msat, amount_msat),
+            fee_msat: sent_msat - amount_msat,
+            channel_partner: Some(match sat_direction {
+                SatDirection::Pull => route.first()?.channel,
+                SatDirection::Push => route.last()?.channel,
+            }),
+            hops: (route.len() - 1) as u8,
+            completed_at,
+        })
+    }
     pub async fn write_to_file(
         &self,
         chan_id: ShortChannelId,
         sling_dir: &Path,
     ) -> Result<(), Error> {
         let serialized = serde_json::to_string(self)?;
-        let mut file = OpenOptions::new()
-            .append(true)
-            .create(true)
-            .open(sling_dir.join(chan_id.to_string() + SUCCESSES_SUFFIX))
-            .await?;
-        file.write_all(format!("{}\n", serialized).as_bytes())
-            .await?;
-        Ok(())
+        write_file(
+            sling_dir.join(chan_id.to_string() + SUCCESSES_SUFFIX),
+            format!("{}\n", serialized).as_bytes(),
+            true,
+        )
+        .await
     }
 
     pub async fn read_from_file(
         sling_dir: &Path,
         chan_id: &ShortChannelId,
     ) -> Result<Vec<SuccessReb>, Error> {
-        let contents =
-            tokio::fs::read_to_string(sling_dir.join(chan_id.to_string() + SUCCESSES_SUFFIX))
-                .await?;
-        let mut vec = vec![];
-        for line in contents.lines() {
-            vec.push(serde_json::from_str(line)?);
+        let file_name = chan_id.to_string() + SUCCESSES_SUFFIX;
+        let contents = tokio::fs::read_to_string(sling_dir.join(&file_name)).await?;
+        Ok(parse_json_lines(&contents, &file_name))
+    }
+}
+fn parse_json_lines<T: DeserializeOwned>(contents: &str, file_name: &str) -> Vec<T> {
+    let mut vec = vec![];
+    for (i, line) in contents.lines().enumerate() {
+        match serde_json::from_str(line) {
+            Ok(o) => vec.push(o),
+            Err(e) => warn!("{}: skipping malformed line {}: {}", file_name, i + 1, e),
         }
-        Ok(vec)
     }
+    vec
 }
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct FailureReb {
     pub amount_msat: u64,
@@ -614,28 +2748,98 @@ impl FailureReb {
         sling_dir: &Path,
     ) -> Result<(), Error> {
         let serialized = serde_json::to_string(self)?;
-        let mut file = OpenOptions::new()
-            .append(true)
-            .create(true)
-            .open(sling_dir.join(chan_id.to_string() + FAILURES_SUFFIX))
-            .await?;
-        file.write_all(format!("{}\n", serialized).as_bytes())
-            .await?;
-        Ok(())
+        write_file(
+            sling_dir.join(chan_id.to_string() + FAILURES_SUFFIX),
+            format!("{}\n", serialized).as_bytes(),
+            true,
+        )
+        .await
     }
 
     pub async fn read_from_file(
         sling_dir: &Path,
         chan_id: &ShortChannelId,
     ) -> Result<Vec<FailureReb>, Error> {
-        let contents =
-            tokio::fs::read_to_string(sling_dir.join(chan_id.to_string() + FAILURES_SUFFIX))
-                .await?;
-        let mut vec = vec![];
-        for line in contents.lines() {
-            vec.push(serde_json::from_str(line)?);
+        let file_name = chan_id.to_string() + FAILURES_SUFFIX;
+        let contents = tokio::fs::read_to_string(sling_dir.join(&file_name)).await?;
+        Ok(parse_json_lines(&contents, &file_name))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct HopResult {
+    pub short_channel_id: ShortChannelId,
+    pub direction: u32,
+    pub amount_msat: u64,
+    pub success: bool,
+    pub created_at: u64,
+}
+impl HopResult {
+    // erring_index is the failing node, 0 is us. Hops after it were never tried
+    pub fn from_route(
+        route: &[SendpayRoute],
+        my_pubkey: &PublicKey,
+        erring_index: Option<usize>,
+        created_at: u64,
+    ) -> Vec<HopResult> {
+        let tried = erring_index.map_or(route.len(), |i| min(i + 1, route.len()));
+        let mut source = *my_pubkey;
+        let mut results = Vec::with_capacity(tried);
+        for (i, hop) in route.iter().take(tried).enumerate() {
+            results.push(HopResult {
+                short_channel_id: hop.channel,
+                direction: if source.serialize() < hop.id.serialize() {
+                    0
+                } else {
+                    1
+                },
+                amount_msat: Amount::msat(&hop.amount_msat),
+                success: erring_index.is_none_or(|e| i < e),
+                created_at,
+            });
+            source = hop.id;
         }
-        Ok(vec)
+        results
+    }
+
+    pub async fn write_to_file(results: &[HopResult], sling_dir: &Path) -> Result<(), Error> {
+        let mut lines = String::new();
+        for result in results {
+            lines.push_str(&serde_json::to_string(result)?);
+            lines.push('\n');
+        }
+        write_file(
+            sling_dir.join(HOP_RESULTS_FILE_NAME),
+            lines.as_bytes(),
+            true,
+        )
+        .await
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct CandidateExclusion {
+    pub short_channel_id: ShortChannelId,
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct TraceRecord {
+    pub chan_id: ShortChannelId,
+    pub task_id: u8,
+    pub amount_msat: u64,
+    pub candidates: Vec<ShortChannelId>,
+    pub excluded: Vec<CandidateExclusion>,
+    pub route: Vec<ShortChannelId>,
+    pub fee_ppm: Option<u32>,
+    pub outcome: String,
+    pub created_at: u64,
+}
+impl TraceRecord {
+    pub async fn write_to_file(&self, sling_dir: &Path) -> Result<(), Error> {
+        let mut line = serde_json::to_string(self)?;
+        line.push('\n');
+        write_file(sling_dir.join(TRACE_FILE_NAME), line.as_bytes(), true).await
     }
 }
 
@@ -647,6 +2851,17 @@ pub struct StatSummary {
     pub status: String,
     pub rebamount: String,
     pub w_feeppm: u64,
+    pub hops: String,
     pub last_route_taken: String,
     pub last_success_reb: String,
+    #[tabled(skip)]
+    pub active: bool,
+    #[tabled(skip)]
+    pub stuck: bool,
+    #[tabled(skip)]
+    pub imbalance: f64,
+    #[tabled(skip)]
+    pub last_success: u64,
+    #[tabled(skip)]
+    pub tags: Vec<String>,
 }
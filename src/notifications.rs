@@ -1,6 +1,6 @@
 use anyhow::Error;
 use cln_plugin::Plugin;
-use log::debug;
+use log::{debug, warn};
 
 use crate::{model::PluginState, write_graph};
 
@@ -9,6 +9,18 @@ pub async fn shutdown_handler(
     _v: serde_json::Value,
 ) -> Result<(), Error> {
     debug!("Got shutdown notification");
-    write_graph(plugin.clone()).await?;
+    if let Err(e) = write_graph(plugin.clone()).await {
+        warn!("Error writing graph on shutdown: {}", e);
+    }
+    let (sling_dir, route_cache_max_age) = {
+        let config = plugin.state().config.lock();
+        (config.sling_dir.clone(), config.route_cache_max_age.value)
+    };
+    if route_cache_max_age > 0 {
+        let route_cache = plugin.state().route_cache.lock().clone();
+        if let Err(e) = route_cache.write_to_file(&sling_dir).await {
+            warn!("Error writing route cache on shutdown: {}", e);
+        }
+    }
     plugin.shutdown()
 }
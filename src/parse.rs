@@ -1,23 +1,40 @@
-use std::str::FromStr;
+use std::{collections::BTreeMap, str::FromStr};
 
 use anyhow::anyhow;
 use cln_plugin::Error;
-use cln_rpc::primitives::ShortChannelId;
-use sling::{Job, SatDirection};
+use cln_rpc::primitives::{PublicKey, ShortChannelId};
+use sling::{Job, RouteObjective, SatDirection, Schedule};
 
-pub async fn parse_job(args: serde_json::Value) -> Result<(ShortChannelId, Job), Error> {
+pub async fn parse_job(args: serde_json::Value) -> Result<(Option<ShortChannelId>, Job), Error> {
     let valid_keys = [
         "scid",
         "direction",
         "amount",
+        "min_amount",
+        "amount_fraction",
         "maxppm",
+        "maxppm_pull",
+        "maxppm_push",
         "outppm",
         "target",
         "maxhops",
         "candidates",
+        "candidates_backup",
         "depleteuptopercent",
         "depleteuptoamount",
         "paralleljobs",
+        "paralleljobs_min",
+        "peer",
+        "dryrun",
+        "via_node",
+        "objective",
+        "tags",
+        "max_total_sat",
+        "min_rebalance_sat",
+        "subdir",
+        "note",
+        "group",
+        "schedule",
     ];
 
     match args {
@@ -29,11 +46,20 @@ pub async fn parse_job(args: serde_json::Value) -> Result<(ShortChannelId, Job),
             }
 
             let chan_id = match ar.get("scid") {
-                Some(scid) => ShortChannelId::from_str(
+                Some(scid) => Some(parse_scid(
                     scid.as_str().ok_or(anyhow!("invalid string for scid"))?,
-                )?,
-                None => return Err(anyhow!("Missing scid")),
+                )?),
+                None => None,
+            };
+            let peer = match ar.get("peer") {
+                Some(p) => Some(PublicKey::from_str(
+                    p.as_str().ok_or(anyhow!("invalid string for peer"))?,
+                )?),
+                None => None,
             };
+            if chan_id.is_none() && peer.is_none() {
+                return Err(anyhow!("Missing scid or peer"));
+            }
 
             let sat_direction = match ar.get("direction") {
                 Some(dir) => SatDirection::from_str(
@@ -52,14 +78,44 @@ pub async fn parse_job(args: serde_json::Value) -> Result<(ShortChannelId, Job),
                 }
                 None => return Err(anyhow!("Missing amount")),
             };
-            if amount_msat == 0 {
-                return Err(anyhow!("amount must be greater than 0"));
-            }
+
+            let min_amount_msat = match ar.get("min_amount") {
+                Some(amt) => Some(
+                    amt.as_u64()
+                        .ok_or(anyhow!("min_amount must be a positive integer"))?
+                        * 1_000,
+                ),
+                None => None,
+            };
+
+            let amount_fraction = match ar.get("amount_fraction") {
+                Some(f) => Some(
+                    f.as_f64()
+                        .ok_or(anyhow!("amount_fraction must be a floating point"))?,
+                ),
+                None => None,
+            };
 
             let maxppm = match ar.get("maxppm") {
                 Some(ppm) => ppm.as_u64().ok_or(anyhow!("maxppm must be an integer"))? as u32,
                 None => return Err(anyhow!("Missing maxppm")),
             };
+            let maxppm_pull = match ar.get("maxppm_pull") {
+                Some(ppm) => Some(
+                    ppm.as_u64()
+                        .ok_or(anyhow!("maxppm_pull must be an integer"))?
+                        as u32,
+                ),
+                None => None,
+            };
+            let maxppm_push = match ar.get("maxppm_push") {
+                Some(ppm) => Some(
+                    ppm.as_u64()
+                        .ok_or(anyhow!("maxppm_push must be an integer"))?
+                        as u32,
+                ),
+                None => None,
+            };
 
             let outppm = match ar.get("outppm") {
                 Some(o) => Some(o.as_u64().ok_or(anyhow!("outppm must be an integer"))?),
@@ -78,11 +134,6 @@ pub async fn parse_job(args: serde_json::Value) -> Result<(ShortChannelId, Job),
                 Some(h) => Some(h.as_u64().ok_or(anyhow!("maxhops must be an integer"))? as u8),
                 None => None,
             };
-            if let Some(h) = maxhops {
-                if h < 2 {
-                    return Err(anyhow!("maxhops must be atleast 2"));
-                }
-            }
 
             let depleteuptopercent = match ar.get("depleteuptopercent") {
                 Some(dp) => Some(
@@ -91,11 +142,6 @@ pub async fn parse_job(args: serde_json::Value) -> Result<(ShortChannelId, Job),
                 ),
                 None => None,
             };
-            if let Some(dp) = depleteuptopercent {
-                if !(0.0..1.0).contains(&dp) {
-                    return Err(anyhow!("depleteuptopercent must be between 0.0 and <1.0"));
-                }
-            }
 
             let depleteuptoamount = match ar.get("depleteuptoamount") {
                 Some(h) => Some(
@@ -114,11 +160,92 @@ pub async fn parse_job(args: serde_json::Value) -> Result<(ShortChannelId, Job),
                 ),
                 None => None,
             };
-            if let Some(h) = paralleljobs {
-                if h < 1 {
-                    return Err(anyhow!("paralleljobs must be atleast 1"));
+            let paralleljobs_min = match ar.get("paralleljobs_min") {
+                Some(h) => Some(
+                    h.as_u64()
+                        .ok_or(anyhow!("paralleljobs_min must be an integer"))?
+                        as u8,
+                ),
+                None => None,
+            };
+
+            let via_node = match ar.get("via_node") {
+                Some(v) => Some(PublicKey::from_str(
+                    v.as_str().ok_or(anyhow!("invalid string for via_node"))?,
+                )?),
+                None => None,
+            };
+            let objective = match ar.get("objective") {
+                Some(o) => Some(RouteObjective::from_str(
+                    o.as_str().ok_or(anyhow!("invalid string for objective"))?,
+                )?),
+                None => None,
+            };
+            let max_total_sat = match ar.get("max_total_sat") {
+                Some(m) => Some(
+                    m.as_u64()
+                        .ok_or(anyhow!("max_total_sat must be an integer"))?,
+                ),
+                None => None,
+            };
+            let min_rebalance_sat = match ar.get("min_rebalance_sat") {
+                Some(m) => Some(
+                    m.as_u64()
+                        .ok_or(anyhow!("min_rebalance_sat must be an integer"))?,
+                ),
+                None => None,
+            };
+            let subdir = match ar.get("subdir") {
+                Some(s) => Some(
+                    s.as_str()
+                        .ok_or(anyhow!("subdir must be a string"))?
+                        .to_string(),
+                ),
+                None => None,
+            };
+            let note = match ar.get("note") {
+                Some(n) => Some(
+                    n.as_str()
+                        .ok_or(anyhow!("note must be a string"))?
+                        .to_string(),
+                ),
+                None => None,
+            };
+            let group = match ar.get("group") {
+                Some(g) => Some(
+                    g.as_str()
+                        .ok_or(anyhow!("group must be a string"))?
+                        .to_string(),
+                ),
+                None => None,
+            };
+            let schedule = match ar.get("schedule") {
+                Some(s) => Some(
+                    s.as_str()
+                        .ok_or(anyhow!("schedule must be a string"))?
+                        .to_string(),
+                ),
+                None => None,
+            };
+
+            let tags = match ar.get("tags") {
+                Some(t) => {
+                    let mut tmptags = Vec::new();
+                    for tag in t.as_array().ok_or(anyhow!("Invalid array for tags"))? {
+                        tmptags.push(
+                            tag.as_str()
+                                .ok_or(anyhow!("invalid string in tags"))?
+                                .to_string(),
+                        );
+                    }
+                    Some(tmptags)
                 }
-            }
+                None => None,
+            };
+            let dryrun = match ar.get("dryrun") {
+                Some(d) => d.as_bool().ok_or(anyhow!("dryrun must be a boolean"))?,
+                None => false,
+            };
 
             let candidatelist = {
                 let mut tmpcandidatelist = Vec::new();
@@ -128,38 +255,183 @@ pub async fn parse_job(args: serde_json::Value) -> Result<(ShortChannelId, Job),
                             .as_array()
                             .ok_or(anyhow!("Invalid array for candidate list"))?
                         {
-                            tmpcandidatelist.push(ShortChannelId::from_str(
-                                candidate.as_str().ok_or(anyhow!(
-                                    "invalid string for channel id in candidate list"
-                                ))?,
-                            )?);
+                            tmpcandidatelist.push(parse_scid(candidate.as_str().ok_or(
+                                anyhow!("invalid string for channel id in candidate list"),
+                            )?)?);
                         }
                         Some(tmpcandidatelist)
                     }
                     None => None,
                 }
             };
-            if outppm.is_none() && candidatelist.is_none() {
-                return Err(anyhow!(
-                    "Atleast one of outppm and candidatelist need to be set."
-                ));
-            }
-            Ok((
-                chan_id,
-                Job {
-                    sat_direction,
-                    amount_msat,
-                    outppm,
-                    maxppm,
-                    candidatelist,
-                    target,
-                    maxhops,
-                    depleteuptopercent,
-                    depleteuptoamount,
-                    paralleljobs,
-                },
-            ))
+            let candidatelist_backup = match ar.get("candidates_backup") {
+                Some(candidates) => {
+                    let mut tmpcandidatelist = Vec::new();
+                    for candidate in candidates
+                        .as_array()
+                        .ok_or(anyhow!("Invalid array for backup candidate list"))?
+                    {
+                        tmpcandidatelist.push(parse_scid(candidate.as_str().ok_or(anyhow!(
+                            "invalid string for channel id in backup candidate list"
+                        ))?)?);
+                    }
+                    Some(tmpcandidatelist)
+                }
+                None => None,
+            };
+            let job = Job {
+                sat_direction,
+                amount_msat,
+                min_amount_msat,
+                amount_fraction,
+                outppm,
+                maxppm,
+                maxppm_pull,
+                maxppm_push,
+                candidatelist,
+                candidatelist_backup,
+                target,
+                maxhops,
+                depleteuptopercent,
+                depleteuptoamount,
+                paralleljobs,
+                paralleljobs_min,
+                peer,
+                dryrun,
+                via_node,
+                objective,
+                tags,
+                max_total_sat,
+                min_rebalance_sat,
+                subdir,
+                note,
+                group,
+                schedule,
+            };
+            validate_job(&job)?;
+            Ok((chan_id, job))
         }
         other => Err(anyhow!("Invalid arguments: {}", other.to_string())),
     }
 }
+
+pub fn validate_job(job: &Job) -> Result<(), Error> {
+    if job.amount_msat == 0 {
+        return Err(anyhow!("amount must be greater than 0"));
+    }
+    if let Some(m) = job.min_amount_msat {
+        if m == 0 || m >= job.amount_msat {
+            return Err(anyhow!(
+                "min_amount must be greater than 0 and less than amount"
+            ));
+        }
+    }
+    if let Some(f) = job.amount_fraction {
+        if f <= 0.0 || f > 1.0 {
+            return Err(anyhow!("amount_fraction must be between >0.0 and 1.0"));
+        }
+    }
+    if job.max_total_sat == Some(0) {
+        return Err(anyhow!("max_total_sat must be greater than 0"));
+    }
+    if let Some(h) = job.maxhops {
+        if h == 1 {
+            return Err(anyhow!("maxhops must be atleast 2 or 0 for no limit"));
+        }
+    }
+    if let Some(dp) = job.depleteuptopercent {
+        if !(0.0..1.0).contains(&dp) {
+            return Err(anyhow!("depleteuptopercent must be between 0.0 and <1.0"));
+        }
+    }
+    if let Some(h) = job.paralleljobs {
+        if h < 1 {
+            return Err(anyhow!("paralleljobs must be atleast 1"));
+        }
+    }
+    if let Some(pm) = job.paralleljobs_min {
+        if pm < 1 || job.paralleljobs.is_some_and(|pj| pm > pj) {
+            return Err(anyhow!(
+                "paralleljobs_min must be atleast 1 and not more than paralleljobs"
+            ));
+        }
+    }
+    if let Some(s) = &job.subdir {
+        if s.is_empty() || s == "." || s == ".." || s.contains(['/', '\\']) {
+            return Err(anyhow!(
+                "subdir must be a plain directory name inside the sling folder"
+            ));
+        }
+    }
+    if job.group.as_ref().is_some_and(|g| g.trim().is_empty()) {
+        return Err(anyhow!("group must not be empty"));
+    }
+    if let Some(s) = &job.schedule {
+        Schedule::from_str(s)?;
+    }
+    if job.outppm.is_none() && job.candidatelist.is_none() {
+        return Err(anyhow!(
+            "Atleast one of outppm and candidatelist need to be set."
+        ));
+    }
+    Ok(())
+}
+
+// fields of the job entry override the ones of its template
+pub fn resolve_job_template(
+    job: serde_json::Value,
+    templates: &BTreeMap<String, serde_json::Value>,
+) -> Result<Job, Error> {
+    let mut fields = match job {
+        serde_json::Value::Object(o) => o,
+        other => return Err(anyhow!("job is not an object: {}", other)),
+    };
+    let resolved = match fields.remove("template") {
+        Some(serde_json::Value::String(name)) => {
+            let mut resolved = match templates.get(&name) {
+                Some(serde_json::Value::Object(t)) => t.clone(),
+                Some(_) => return Err(anyhow!("template {} is not an object", name)),
+                None => return Err(anyhow!("template {} not found", name)),
+            };
+            resolved.remove("template");
+            resolved.extend(fields);
+            resolved
+        }
+        Some(other) => return Err(anyhow!("invalid template name: {}", other)),
+        None => fields,
+    };
+    Ok(serde_json::from_value(serde_json::Value::Object(resolved))?)
+}
+
+pub fn parse_scid(input: &str) -> Result<ShortChannelId, Error> {
+    let trimmed = input.trim();
+    let parts: Vec<&str> = if trimmed.contains(['x', 'X']) {
+        trimmed.split(['x', 'X']).collect()
+    } else if trimmed.contains(':') {
+        trimmed.split(':').collect()
+    } else {
+        vec![trimmed]
+    };
+    if parts
+        .iter()
+        .any(|p| p.is_empty() || !p.chars().all(|c| c.is_ascii_digit()))
+    {
+        return Err(anyhow!("Malformed short_channel_id: {}", input));
+    }
+    let (block, tx, out) = match parts.as_slice() {
+        [block, tx, out] => (
+            block.parse::<u64>()?,
+            tx.parse::<u64>()?,
+            out.parse::<u64>()?,
+        ),
+        [numeric] => {
+            let n = numeric.parse::<u64>()?;
+            (n >> 40, (n >> 16) & 0xFF_FFFF, n & 0xFFFF)
+        }
+        _ => return Err(anyhow!("Malformed short_channel_id: {}", input)),
+    };
+    if block > 0xFF_FFFF || tx > 0xFF_FFFF || out > 0xFFFF {
+        return Err(anyhow!("short_channel_id out of range: {}", input));
+    }
+    ShortChannelId::from_str(&format!("{}x{}x{}", block, tx, out))
+}
@@ -1,22 +1,28 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::anyhow;
 use cln_plugin::{Error, Plugin};
 use cln_rpc::{
     model::{
-        requests::{SendpayRequest, SendpayRoute, WaitsendpayRequest},
-        responses::SendpayResponse,
+        requests::{ListsendpaysRequest, SendpayRequest, SendpayRoute, WaitsendpayRequest},
+        responses::{ListsendpaysPaymentsStatus, SendpayResponse},
     },
     primitives::{Amount, Sha256, ShortChannelId},
     ClnRpc,
 };
 use log::{debug, info, warn};
-use sling::{Job, SatDirection};
+use sling::{Job, SatDirection, StuckHtlc};
 use tokio::time::Instant;
 
 use crate::{
-    errors::WaitsendpayErrorData, feeppm_effective_from_amts, my_sleep, Config, FailureReb,
-    PluginState, SuccessReb, Task,
+    balance_moved, channel_last_route_update, errors::WaitsendpayErrorData,
+    feeppm_effective_from_amts, is_own_payment, my_sleep, run_post_success_command, sendpay_status,
+    tasks::refresh_listpeerchannels, track_pending_payment, ArrivedPay, Config, FailureReb,
+    HopResult, LastRoute, PluginState, SuccessReb, Task, PENDING_PAYMENT_POLL_SECS,
+    STUCK_HTLC_MARGIN_SECS,
 };
 
 #[allow(clippy::too_many_arguments)]
@@ -29,8 +35,20 @@ pub async fn waitsendpay_response(
     job: &Job,
     route: &[SendpayRoute],
     success_route: &mut Option<Vec<SendpayRoute>>,
+    to_us_before_msat: Option<u64>,
 ) -> Result<Option<ShortChannelId>, Error> {
-    let mut rpc = ClnRpc::new(&config.rpc_path).await?;
+    // blocks until the payment resolves, don't hold a pooled connection for that long
+    let mut rpc = match ClnRpc::new(&config.rpc_path).await {
+        Ok(r) => r,
+        Err(e) => {
+            plugin
+                .state()
+                .pays
+                .write()
+                .remove(&payment_hash.to_string());
+            return Err(e);
+        }
+    };
     match rpc
         .call_typed(&WaitsendpayRequest {
             payment_hash,
@@ -41,6 +59,11 @@ pub async fn waitsendpay_response(
         .await
     {
         Ok(o) => {
+            let arrived = plugin
+                .state()
+                .pays
+                .write()
+                .take_arrived(&payment_hash.to_string());
             info!(
                 "{}/{}: Rebalance SUCCESSFULL after {}s. Sent {}sats plus {}msats fee",
                 task.chan_id,
@@ -50,31 +73,82 @@ pub async fn waitsendpay_response(
                 Amount::msat(&o.amount_sent_msat) - Amount::msat(&o.amount_msat.unwrap()),
             );
 
-            SuccessReb {
+            let success_reb = SuccessReb {
                 amount_msat: Amount::msat(&o.amount_msat.unwrap()),
                 fee_ppm: feeppm_effective_from_amts(
                     Amount::msat(&o.amount_sent_msat),
                     Amount::msat(&o.amount_msat.unwrap()),
                 ),
-                channel_partner: match job.sat_direction {
+                fee_msat: Amount::msat(&o.amount_sent_msat) - Amount::msat(&o.amount_msat.unwrap()),
+                channel_partner: Some(match job.sat_direction {
                     SatDirection::Pull => route.first().unwrap().channel,
                     SatDirection::Push => route.last().unwrap().channel,
-                },
+                }),
                 hops: (route.len() - 1) as u8,
                 completed_at: o.completed_at.unwrap() as u64,
+            };
+            if config.verify_balance.value {
+                warn_unmoved_balance(
+                    plugin,
+                    task,
+                    job,
+                    route,
+                    to_us_before_msat,
+                    success_reb.amount_msat,
+                )
+                .await;
             }
-            .write_to_file(task.chan_id, &config.sling_dir)
-            .await?;
+            if own_payment_verified(config, task, route, arrived.as_ref()) {
+                plugin.state().track_write(
+                    success_reb
+                        .write_to_file(task.chan_id, &job.stats_dir(&config.sling_dir))
+                        .await,
+                )?;
+            }
+            if config.hop_results.value {
+                plugin.state().track_write(
+                    HopResult::write_to_file(
+                        &HopResult::from_route(
+                            route,
+                            &config.pubkey,
+                            None,
+                            o.completed_at.unwrap() as u64,
+                        ),
+                        &config.sling_dir,
+                    )
+                    .await,
+                )?;
+            }
+            run_post_success_command(
+                &config.post_success_command.value,
+                &task.chan_id,
+                &success_reb,
+            );
+            channel_last_route_update(
+                plugin.state().job_state.clone(),
+                task,
+                LastRoute::new(task.task_id, route, None, None),
+            );
+            plugin.state().graph.lock().record_success(
+                route,
+                &config.pubkey,
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs(),
+                config.liquidity_confidence_window.value,
+            );
             *success_route = Some(route.to_vec());
             Ok(None)
         }
         Err(err) => {
             *success_route = None;
-            plugin
-                .state()
-                .pays
-                .write()
-                .remove(&payment_hash.to_string());
+            let arrived = {
+                let mut pays = plugin.state().pays.write();
+                let arrived = pays.take_arrived(&payment_hash.to_string());
+                pays.remove(&payment_hash.to_string());
+                arrived
+            };
             let mut special_stop = false;
             let ws_code = if let Some(c) = err.code {
                 c
@@ -95,6 +169,35 @@ pub async fn waitsendpay_response(
                     now.elapsed().as_secs().to_string(),
                     err.message,
                 );
+                if let Some(success_reb) = timed_out_success(
+                    plugin,
+                    config,
+                    payment_hash,
+                    job,
+                    route,
+                    to_us_before_msat,
+                    arrived.as_ref(),
+                )
+                .await
+                .filter(|_| own_payment_verified(config, task, route, arrived.as_ref()))
+                {
+                    warn!(
+                        "{}/{}: the payment went through despite the timeout, \
+                        recording it as a success",
+                        task.chan_id, task.task_id
+                    );
+                    plugin.state().track_write(
+                        success_reb
+                            .write_to_file(task.chan_id, &job.stats_dir(&config.sling_dir))
+                            .await,
+                    )?;
+                    channel_last_route_update(
+                        plugin.state().job_state.clone(),
+                        task,
+                        LastRoute::new(task.task_id, route, None, None),
+                    );
+                    return Ok(None);
+                }
                 let temp_ban_route = &route[..route.len() - 1];
                 let mut source = temp_ban_route.first().unwrap().id;
                 for hop in temp_ban_route {
@@ -119,6 +222,7 @@ pub async fn waitsendpay_response(
                                         .duration_since(UNIX_EPOCH)
                                         .unwrap()
                                         .as_secs();
+                                    x.confidence_age = x.liquidity_age;
                                     Some(x)
                                 } else {
                                     None
@@ -126,8 +230,8 @@ pub async fn waitsendpay_response(
                             });
                     }
                 }
-                FailureReb {
-                    amount_msat: job.amount_msat,
+                let failure = FailureReb {
+                    amount_msat: Amount::msat(&route.last().unwrap().amount_msat),
                     failure_reason: "WAITSENDPAY_TIMEOUT".to_string(),
                     failure_node: config.pubkey,
                     channel_partner: match job.sat_direction {
@@ -139,9 +243,47 @@ pub async fn waitsendpay_response(
                         .duration_since(UNIX_EPOCH)
                         .unwrap()
                         .as_secs(),
-                }
-                .write_to_file(task.chan_id, &config.sling_dir)
-                .await?;
+                };
+                plugin.state().track_write(
+                    failure
+                        .write_to_file(task.chan_id, &job.stats_dir(&config.sling_dir))
+                        .await,
+                )?;
+                channel_last_route_update(
+                    plugin.state().job_state.clone(),
+                    task,
+                    LastRoute::new(task.task_id, route, None, Some(failure)),
+                );
+                // waitsendpay waited the whole sling-timeoutpay since the payment was sent
+                let sent_at = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs()
+                    .saturating_sub(config.timeoutpay.value as u64);
+                let stuck_htlc = StuckHtlc {
+                    payment_hash: payment_hash.to_string(),
+                    short_channel_id: match job.sat_direction {
+                        SatDirection::Pull => route.last().unwrap().channel,
+                        SatDirection::Push => route.first().unwrap().channel,
+                    },
+                    amount_msat: Amount::msat(&route.last().unwrap().amount_msat),
+                    sent_at,
+                };
+                let stuck_at = sent_at + config.timeoutpay.value as u64 + STUCK_HTLC_MARGIN_SECS;
+                let plugin = plugin.clone();
+                let rpc_path = config.rpc_path.clone();
+                let task = *task;
+                tokio::spawn(async move {
+                    track_pending_payment(
+                        plugin.state().job_state.clone(),
+                        task,
+                        stuck_htlc,
+                        stuck_at,
+                        Duration::from_secs(PENDING_PAYMENT_POLL_SECS),
+                        || listsendpays_status(&plugin, &rpc_path, payment_hash),
+                    )
+                    .await
+                });
                 Ok(None)
             } else if let Some(d) = err.data {
                 let ws_error = serde_json::from_value::<WaitsendpayErrorData>(d)?;
@@ -169,7 +311,7 @@ pub async fn waitsendpay_response(
                     _ => (),
                 }
 
-                FailureReb {
+                let failure = FailureReb {
                     amount_msat: ws_error.amount_msat.unwrap().msat(),
                     failure_reason: ws_error.failcodename.clone(),
                     failure_node: ws_error.erring_node,
@@ -179,9 +321,66 @@ pub async fn waitsendpay_response(
                     },
                     hops: (route.len() - 1) as u8,
                     created_at: ws_error.created_at,
+                };
+                plugin.state().track_write(
+                    failure
+                        .write_to_file(task.chan_id, &job.stats_dir(&config.sling_dir))
+                        .await,
+                )?;
+                if config.hop_results.value {
+                    plugin.state().track_write(
+                        HopResult::write_to_file(
+                            &HopResult::from_route(
+                                route,
+                                &config.pubkey,
+                                Some(ws_error.erring_index as usize),
+                                ws_error.created_at,
+                            ),
+                            &config.sling_dir,
+                        )
+                        .await,
+                    )?;
+                }
+                if ws_error.erring_node != config.pubkey && config.node_penalty.value > 0 {
+                    plugin.state().node_penalties.lock().record_failure(
+                        ws_error.erring_node,
+                        SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap()
+                            .as_secs(),
+                        config.node_penalty_half_life.value * 60,
+                    );
                 }
-                .write_to_file(task.chan_id, &config.sling_dir)
-                .await?;
+                if ws_error.erring_node != config.pubkey
+                    && plugin.state().node_bans.lock().record_failure(
+                        ws_error.erring_node,
+                        SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap()
+                            .as_secs(),
+                        config.node_ban_threshold.value,
+                        config.node_ban_window.value * 60,
+                    )
+                {
+                    info!(
+                        "{}/{}: {} failed {} times in the last {}m, avoiding it for now",
+                        task.chan_id,
+                        task.task_id,
+                        ws_error.erring_node,
+                        config.node_ban_threshold.value,
+                        config.node_ban_window.value
+                    );
+                }
+                channel_last_route_update(
+                    plugin.state().job_state.clone(),
+                    task,
+                    LastRoute::new(
+                        task.task_id,
+                        route,
+                        Some(ws_error.erring_index),
+                        Some(failure),
+                    ),
+                );
                 if special_stop {
                     return Err(anyhow!(
                         "{}/{}: UNEXPECTED waitsendpay failure after {}s: {}",
@@ -200,13 +399,7 @@ pub async fn waitsendpay_response(
                     if err.message.contains("Too many HTLCs") {
                         my_sleep(3, plugin.state().job_state.clone(), task).await;
                     } else {
-                        plugin.state().tempbans.lock().insert(
-                            route.last().unwrap().channel,
-                            SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .unwrap()
-                                .as_secs(),
-                        );
+                        plugin.state().tempban(route.last().unwrap().channel);
                     }
                 } else if ws_error.erring_channel == route.first().unwrap().channel {
                     warn!(
@@ -218,13 +411,7 @@ pub async fn waitsendpay_response(
                     if err.message.contains("Too many HTLCs") {
                         my_sleep(3, plugin.state().job_state.clone(), task).await;
                     } else {
-                        plugin.state().tempbans.lock().insert(
-                            route.first().unwrap().channel,
-                            SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .unwrap()
-                                .as_secs(),
-                        );
+                        plugin.state().tempban(route.first().unwrap().channel);
                     }
                 } else {
                     debug!(
@@ -249,6 +436,7 @@ pub async fn waitsendpay_response(
                                     .duration_since(UNIX_EPOCH)
                                     .unwrap()
                                     .as_secs();
+                                x.confidence_age = x.liquidity_age;
                                 Some(x)
                             } else {
                                 None
@@ -269,6 +457,138 @@ pub async fn waitsendpay_response(
     }
 }
 
+// our side of the job's channel after refreshing the channels
+async fn current_to_us_msat(
+    plugin: &Plugin<PluginState>,
+    job: &Job,
+    route: &[SendpayRoute],
+) -> Option<u64> {
+    let sling_chan = match job.sat_direction {
+        SatDirection::Pull => route.last()?.channel,
+        SatDirection::Push => route.first()?.channel,
+    };
+    if let Err(e) = refresh_listpeerchannels(plugin).await {
+        warn!("Could not refresh channels: {}", e);
+        return None;
+    }
+    plugin
+        .state()
+        .peer_channels
+        .lock()
+        .get(&sling_chan)
+        .and_then(|c| c.to_us_msat)
+        .map(|a| Amount::msat(&a))
+}
+
+// waitsendpay confirmed the payment so it is recorded either way, a balance
+// that did not move is only worth a warning
+async fn warn_unmoved_balance(
+    plugin: &Plugin<PluginState>,
+    task: &Task,
+    job: &Job,
+    route: &[SendpayRoute],
+    to_us_before_msat: Option<u64>,
+    amount_msat: u64,
+) {
+    let to_us_before_msat = match to_us_before_msat {
+        Some(b) => b,
+        None => return,
+    };
+    let to_us_after_msat = match current_to_us_msat(plugin, job, route).await {
+        Some(a) => a,
+        None => return,
+    };
+    if !balance_moved(
+        job.sat_direction,
+        to_us_before_msat,
+        to_us_after_msat,
+        amount_msat,
+    ) {
+        warn!(
+            "{}/{}: rebalance of {}msat reported as success but our balance went from {}msat \
+            to {}msat",
+            task.chan_id, task.task_id, amount_msat, to_us_before_msat, to_us_after_msat
+        );
+    }
+}
+
+// with sling-verify-own-payment a success only counts if our own htlc came back,
+// a balance change could as well be another rebalancer
+pub fn own_payment_verified(
+    config: &Config,
+    task: &Task,
+    route: &[SendpayRoute],
+    arrived: Option<&ArrivedPay>,
+) -> bool {
+    if !config.verify_own_payment.value || is_own_payment(arrived, route) {
+        return true;
+    }
+    warn!(
+        "{}/{}: our own payment did not arrive over {}, not recording it as a success",
+        task.chan_id,
+        task.task_id,
+        route.last().unwrap().channel
+    );
+    false
+}
+
+pub async fn listsendpays_status(
+    plugin: &Plugin<PluginState>,
+    rpc_path: &Path,
+    payment_hash: Sha256,
+) -> Result<Option<ListsendpaysPaymentsStatus>, Error> {
+    let mut rpc = plugin.state().rpc_pool.get_cln(rpc_path).await?;
+    let result = rpc
+        .call_typed(&ListsendpaysRequest {
+            bolt11: None,
+            index: None,
+            limit: None,
+            payment_hash: Some(payment_hash),
+            start: None,
+            status: None,
+        })
+        .await;
+    rpc.discard_on_transport_error(&result);
+    Ok(sendpay_status(&result?.payments))
+}
+
+// waitsendpay timing out doesn't mean the payment failed, ask lightningd about the
+// payment itself. If it can't tell, our own htlc has to have come back over the job's
+// channel and moved its balance, a balance change alone could be someone else's
+async fn timed_out_success(
+    plugin: &Plugin<PluginState>,
+    config: &Config,
+    payment_hash: Sha256,
+    job: &Job,
+    route: &[SendpayRoute],
+    to_us_before_msat: Option<u64>,
+    arrived: Option<&ArrivedPay>,
+) -> Option<SuccessReb> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    match listsendpays_status(plugin, &config.rpc_path, payment_hash).await {
+        Ok(Some(ListsendpaysPaymentsStatus::COMPLETE)) => {
+            return SuccessReb::from_route(route, job.sat_direction, now)
+        }
+        Ok(_) => return None,
+        Err(e) => warn!("Could not look up payment {}: {}", payment_hash, e),
+    }
+    if !is_own_payment(arrived, route) {
+        return None;
+    }
+    let to_us_before_msat = to_us_before_msat?;
+    let to_us_after_msat = current_to_us_msat(plugin, job, route).await?;
+    SuccessReb::from_balance_change(
+        route,
+        job.sat_direction,
+        to_us_before_msat,
+        to_us_after_msat,
+        now,
+    )
+}
+
 #[allow(clippy::too_many_arguments)]
 pub async fn sendpay_response(
     plugin: &Plugin<PluginState>,
@@ -280,8 +600,8 @@ pub async fn sendpay_response(
     route: &[SendpayRoute],
     success_route: &mut Option<Vec<SendpayRoute>>,
 ) -> Result<Option<SendpayResponse>, Error> {
-    let mut rpc = ClnRpc::new(&config.rpc_path).await?;
-    match rpc
+    let mut rpc = plugin.state().rpc_pool.get_cln(&config.rpc_path).await?;
+    let result = rpc
         .call_typed(&SendpayRequest {
             route: route.to_vec(),
             payment_hash,
@@ -295,14 +615,19 @@ pub async fn sendpay_response(
             description: None,
             payment_metadata: None,
         })
-        .await
-    {
+        .await;
+    rpc.discard_on_transport_error(&result);
+    drop(rpc);
+    match result {
         Ok(resp) => {
-            plugin
-                .state()
-                .pays
-                .write()
-                .insert(payment_hash.to_string(), preimage);
+            plugin.state().pays.write().insert(
+                payment_hash.to_string(),
+                preimage,
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs(),
+            );
             Ok(Some(resp))
         }
         Err(e) => {
@@ -311,16 +636,10 @@ pub async fn sendpay_response(
                     "{}/{}: First peer not ready, banning it for now...",
                     task.chan_id, task.task_id
                 );
-                plugin.state().tempbans.lock().insert(
-                    route.first().unwrap().channel,
-                    SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs(),
-                );
+                plugin.state().tempban(route.first().unwrap().channel);
                 *success_route = None;
-                FailureReb {
-                    amount_msat: job.amount_msat,
+                let failure = FailureReb {
+                    amount_msat: Amount::msat(&route.last().unwrap().amount_msat),
                     failure_reason: "FIRST_PEER_NOT_READY".to_string(),
                     failure_node: route.first().unwrap().id,
                     channel_partner: match job.sat_direction {
@@ -332,9 +651,17 @@ pub async fn sendpay_response(
                         .duration_since(UNIX_EPOCH)
                         .unwrap()
                         .as_secs(),
-                }
-                .write_to_file(task.chan_id, &config.sling_dir)
-                .await?;
+                };
+                plugin.state().track_write(
+                    failure
+                        .write_to_file(task.chan_id, &job.stats_dir(&config.sling_dir))
+                        .await,
+                )?;
+                channel_last_route_update(
+                    plugin.state().job_state.clone(),
+                    task,
+                    LastRoute::new(task.task_id, route, Some(1), Some(failure)),
+                );
                 return Ok(None);
             }
 
@@ -1,18 +1,34 @@
-use std::{cmp::Ordering, collections::BTreeMap, path::Path, str::FromStr, time::Duration};
+use std::{
+    cmp::{min, Ordering},
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::anyhow;
 use bitcoin::secp256k1::PublicKey;
 use cln_plugin::{Error, Plugin};
-use cln_rpc::primitives::ShortChannelId;
+use cln_rpc::primitives::{Amount, ShortChannelId};
 use log::{debug, info, warn};
 use serde_json::json;
-use sling::Job;
+use sling::{DirectedChannel, Job, SatDirection};
 use tokio::{fs, time};
 
 use crate::{
-    channel_jobstate_update, get_normal_channel_from_listpeerchannels, parse::parse_job, read_jobs,
-    refresh_joblists, slings::sling, write_excepts, write_job, JobMessage, JobState, PluginState,
-    Task, EXCEPTS_CHANS_FILE_NAME, EXCEPTS_PEERS_FILE_NAME, JOB_FILE_NAME, PLUGIN_NAME,
+    channel_jobstate_update, check_candidatelist, diagnostics, diff_jobs, get_job_channels,
+    get_last_route, get_normal_channel_from_listpeerchannels, job_target_room,
+    parse::{parse_job, parse_scid, resolve_job_template, validate_job},
+    read_jobs, read_jobs_file, read_templates, refresh_joblists, resolve_job_chan_id,
+    slings::{build_candidatelist, sling, sourceable_liquidity},
+    sorted_excepts,
+    stats::{except_chan_impact, suggest_maxppm},
+    update_excepts,
+    util::{channel_imbalance, unmanaged_channels},
+    warn_conflicting_jobs, write_excepts, write_job, EffectiveJobConfig, GraphExportFormat,
+    JobMessage, JobState, PluginState, SuccessReb, Task, EXCEPTS_CHANS_FILE_NAME,
+    EXCEPTS_PEERS_FILE_NAME, GRAPH_EXPORT_FILE_NAME, JOB_FILE_NAME, PLUGIN_NAME,
+    TEMPBAN_EXEMPTS_FILE_NAME,
 };
 
 pub async fn slingjob(
@@ -21,13 +37,72 @@ pub async fn slingjob(
 ) -> Result<serde_json::Value, Error> {
     let sling_dir = Path::new(&p.configuration().lightning_dir).join(PLUGIN_NAME);
 
-    let (chan_id, job) = parse_job(v).await?;
+    let (chan_id, mut job) = parse_job(v).await?;
 
     let peer_channels = p.state().peer_channels.lock().clone();
+    let chan_id = resolve_job_chan_id(&peer_channels, chan_id, job.peer)?;
     let our_listpeers_channel = get_normal_channel_from_listpeerchannels(&peer_channels, &chan_id);
 
+    check_candidatelist(&chan_id, &mut job, &peer_channels.keys().copied().collect())?;
+
     if our_listpeers_channel.is_some() {
-        write_job(p.clone(), sling_dir, chan_id, Some(job), false).await?;
+        write_job(
+            p.clone(),
+            sling_dir,
+            chan_id,
+            Some(serde_json::to_value(job)?),
+            false,
+        )
+        .await?;
+        Ok(json!({"result":"success"}))
+    } else {
+        Err(anyhow!(
+            "Could not find channel or not in CHANNELD_NORMAL state: {}",
+            chan_id
+        ))
+    }
+}
+
+pub async fn slingaddfromtemplate(
+    p: Plugin<PluginState>,
+    args: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let sling_dir = Path::new(&p.configuration().lightning_dir).join(PLUGIN_NAME);
+    let (chan_id, template, direction) = match args {
+        serde_json::Value::Array(a) if a.len() == 3 => match (&a[0], &a[1], &a[2]) {
+            (
+                serde_json::Value::String(scid),
+                serde_json::Value::String(template),
+                serde_json::Value::String(direction),
+            ) => (
+                parse_scid(scid)?,
+                template.clone(),
+                SatDirection::from_str(direction)?,
+            ),
+            _ => {
+                return Err(anyhow!(
+                    "short_channel_id, template and direction must be strings"
+                ))
+            }
+        },
+        _ => {
+            return Err(anyhow!(
+                "Please provide a short_channel_id, a template name and a direction"
+            ))
+        }
+    };
+
+    // the job keeps referencing the template, later template edits apply to it
+    let raw_job = json!({"template": template, "sat_direction": direction.to_string()});
+    let templates = read_templates(&sling_dir).await?;
+    let mut job = resolve_job_template(raw_job.clone(), &templates)?;
+    validate_job(&job)?;
+
+    let peer_channels = p.state().peer_channels.lock().clone();
+    check_candidatelist(&chan_id, &mut job, &peer_channels.keys().copied().collect())?;
+
+    if get_normal_channel_from_listpeerchannels(&peer_channels, &chan_id).is_some() {
+        write_job(p.clone(), sling_dir, chan_id, Some(raw_job), false).await?;
         Ok(json!({"result":"success"}))
     } else {
         Err(anyhow!(
@@ -55,10 +130,7 @@ pub async fn slinggo(
 
     let mut spawn_count = 0;
 
-    let default_paralleljobs;
-    {
-        default_paralleljobs = p.state().config.lock().paralleljobs.value
-    }
+    let config = p.state().config.lock().clone();
 
     match args {
         serde_json::Value::Array(a) => match a.len().cmp(&(1_usize)) {
@@ -69,7 +141,7 @@ pub async fn slinggo(
             }
             Ordering::Equal => match a.first().unwrap() {
                 serde_json::Value::String(start_id) => {
-                    let scid = ShortChannelId::from_str(start_id)?;
+                    let scid = parse_scid(start_id)?;
                     jobs.retain(|chanid, _j| chanid == &scid)
                 }
                 _ => return Err(anyhow!("invalid short_channel_id")),
@@ -89,10 +161,7 @@ pub async fn slinggo(
     }
 
     for (chan_id, job) in jobs {
-        let parallel_jobs = match job.paralleljobs {
-            Some(pj) => pj,
-            None => default_paralleljobs,
-        };
+        let parallel_jobs = EffectiveJobConfig::new(&job, &config).paralleljobs;
         for i in 1..=parallel_jobs {
             {
                 let mut job_states = p.state().job_state.lock();
@@ -103,7 +172,7 @@ pub async fn slinggo(
                         .iter()
                         .find(|jt| jt.id() == i)
                     {
-                        Some(jobstate) => !jobstate.is_active(),
+                        Some(jobstate) => !jobstate.is_active() && !jobstate.is_circuit_open(),
                         None => true,
                     }
                 {
@@ -121,7 +190,7 @@ pub async fn slinggo(
                                 .insert(chan_id, vec![JobState::new(JobMessage::Starting, i)]);
                         }
                     }
-                    tokio::spawn(async move {
+                    let handle = tokio::spawn(async move {
                         let task = Task {
                             chan_id,
                             task_id: i,
@@ -149,7 +218,15 @@ pub async fn slinggo(
                             .parrallel_bans
                             .lock()
                             .retain(|_, task_bans| !task_bans.is_empty());
+                        plugin.state().task_finished(&task);
                     });
+                    p.state().task_handles.lock().insert(
+                        Task {
+                            chan_id,
+                            task_id: i,
+                        },
+                        handle.abort_handle(),
+                    );
                 }
             }
             tokio::time::sleep(Duration::from_millis(20)).await;
@@ -166,6 +243,29 @@ pub async fn slingstop(
     let stopped_count;
     {
         match args {
+            serde_json::Value::Array(mut a) if a.iter().any(is_force_arg) => {
+                a.retain(|arg| !is_force_arg(arg));
+                let chan_id = match a.len().cmp(&(1_usize)) {
+                    Ordering::Greater => {
+                        return Err(anyhow!(
+                            "Please provide exactly one short_channel_id or nothing"
+                        ))
+                    }
+                    Ordering::Equal => match a.first().unwrap() {
+                        serde_json::Value::String(stop_id) => {
+                            let scid = parse_scid(stop_id)?;
+                            if !p.state().job_state.lock().contains_key(&scid) {
+                                return Err(anyhow!("{}: No job running", scid));
+                            }
+                            Some(scid)
+                        }
+                        _ => return Err(anyhow!("invalid short_channel_id")),
+                    },
+                    Ordering::Less => None,
+                };
+                stopped_count = p.state().force_stop_jobs(chan_id);
+                info!("Force stopped {} job(s)", stopped_count);
+            }
             serde_json::Value::Array(a) => match a.len().cmp(&(1_usize)) {
                 Ordering::Greater => {
                     return Err(anyhow!(
@@ -174,7 +274,7 @@ pub async fn slingstop(
                 }
                 Ordering::Equal => match a.first().unwrap() {
                     serde_json::Value::String(stop_id) => {
-                        let scid = ShortChannelId::from_str(stop_id)?;
+                        let scid = parse_scid(stop_id)?;
                         {
                             let mut job_states = p.state().job_state.lock().clone();
                             if job_states.contains_key(&scid) {
@@ -267,6 +367,109 @@ pub async fn slingstop(
     Ok(json!({ "stopped_count": stopped_count }))
 }
 
+fn is_force_arg(arg: &serde_json::Value) -> bool {
+    matches!(arg, serde_json::Value::String(s) if s.eq("force") || s.eq("--force"))
+}
+
+pub async fn slingresetcircuit(
+    p: Plugin<PluginState>,
+    args: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let chan_id = match args {
+        serde_json::Value::Array(a) => match a.len().cmp(&(1_usize)) {
+            Ordering::Greater => {
+                return Err(anyhow!(
+                    "Please provide exactly one short_channel_id or nothing"
+                ))
+            }
+            Ordering::Equal => match a.first().unwrap() {
+                serde_json::Value::String(s) => Some(parse_scid(s)?),
+                _ => return Err(anyhow!("invalid short_channel_id")),
+            },
+            Ordering::Less => None,
+        },
+        e => {
+            return Err(anyhow!(
+                "sling-reset-circuit: invalid arguments, expected array, got: {}",
+                e
+            ))
+        }
+    };
+    let mut reset_count = 0;
+    for (scid, jobstates) in p.state().job_state.lock().iter_mut() {
+        if chan_id.is_some() && chan_id != Some(*scid) {
+            continue;
+        }
+        for jobstate in jobstates.iter_mut().filter(|js| js.is_circuit_open()) {
+            info!(
+                "{}/{}: Resetting circuit after {} failed payments.",
+                scid,
+                jobstate.id(),
+                jobstate.consecutive_failures()
+            );
+            jobstate.reset_circuit();
+            reset_count += 1;
+        }
+    }
+    Ok(json!({ "reset_count": reset_count }))
+}
+
+pub async fn slingreloadjobs(
+    p: Plugin<PluginState>,
+    args: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    match args {
+        serde_json::Value::Array(a) if a.is_empty() => (),
+        serde_json::Value::Object(o) if o.is_empty() => (),
+        _ => return Err(anyhow!("sling-reload-jobs takes no arguments")),
+    }
+    let sling_dir = Path::new(&p.configuration().lightning_dir).join(PLUGIN_NAME);
+    let own_channels = p.state().peer_channels.lock().keys().copied().collect();
+    let new_jobs = read_jobs_file(&sling_dir.join(JOB_FILE_NAME), &own_channels).await?;
+    let old_jobs = p.state().loaded_jobs.lock().clone();
+    warn_conflicting_jobs(&new_jobs, &p.state().peer_channels.lock());
+    let diff = diff_jobs(&old_jobs, &new_jobs);
+
+    let is_running = |chan_id: &ShortChannelId| {
+        p.state()
+            .job_state
+            .lock()
+            .get(chan_id)
+            .is_some_and(|jts| jts.iter().any(|jt| jt.is_active()))
+    };
+    let mut restart = diff.added.clone();
+    for chan_id in &diff.removed {
+        if is_running(chan_id) {
+            slingstop(p.clone(), json!([chan_id.to_string()])).await?;
+        }
+        p.state().job_state.lock().remove(chan_id);
+        info!("{}: job removed from {}", chan_id, JOB_FILE_NAME);
+    }
+    for chan_id in &diff.changed {
+        if is_running(chan_id) {
+            slingstop(p.clone(), json!([chan_id.to_string()])).await?;
+            restart.push(*chan_id);
+        }
+        p.state().job_state.lock().remove(chan_id);
+        info!("{}: job changed in {}", chan_id, JOB_FILE_NAME);
+    }
+    *p.state().loaded_jobs.lock() = new_jobs;
+    refresh_joblists(p.clone()).await?;
+
+    for chan_id in &restart {
+        if let Err(e) = slinggo(p.clone(), json!([chan_id.to_string()])).await {
+            warn!("{}: could not start reloaded job: {}", chan_id, e);
+        }
+    }
+
+    Ok(json!({
+        "added": diff.added,
+        "removed": diff.removed,
+        "updated": diff.changed,
+        "unchanged": diff.unchanged.len(),
+    }))
+}
+
 pub async fn slingjobsettings(
     p: Plugin<PluginState>,
     args: serde_json::Value,
@@ -291,7 +494,7 @@ pub async fn slingjobsettings(
                     .unwrap()
                     .as_str()
                     .ok_or(anyhow!("invalid input, not a string"))?;
-                let scid = ShortChannelId::from_str(scid_str)?;
+                let scid = parse_scid(scid_str)?;
                 let job = jobs.get(&scid).ok_or(anyhow!("channel not found"))?;
                 json_jobs.insert(scid, job.clone());
             }
@@ -324,10 +527,11 @@ pub async fn slingdeletejob(
                             slingstop(p.clone(), serde_json::Value::Array(vec![])).await?;
                             let jobfile = sling_dir.join(JOB_FILE_NAME);
                             fs::remove_file(jobfile).await?;
+                            p.state().loaded_jobs.lock().clear();
                             info!("Deleted all jobs");
                         }
                         _ => {
-                            let scid = ShortChannelId::from_str(i)?;
+                            let scid = parse_scid(i)?;
                             write_job(p, sling_dir, scid, None, true).await?;
                         }
                     },
@@ -362,73 +566,46 @@ pub async fn slingexceptchan(
     };
     if input_array.len() > 2 || input_array.is_empty() {
         return Err(anyhow!(
-            "Please either provide `add`/`remove` and a short_channel_id or just `list`"
+            "Please either provide `add`/`remove`/`preview` and a short_channel_id or just `list`"
         ));
     }
     let command = match input_array.first().unwrap() {
         serde_json::Value::String(i) => i,
         _ => {
             return Err(anyhow!(
-                "Use `add`/`remove` and a short_channel_id or just `list`"
+                "Use `add`/`remove`/`preview` and a short_channel_id or just `list`"
             ))
         }
     };
     if input_array.len() == 2 {
         let scid = match input_array.get(1).unwrap() {
-            serde_json::Value::String(s) => ShortChannelId::from_str(s)?,
+            serde_json::Value::String(s) => parse_scid(s)?,
             o => return Err(anyhow!("not a vaild short_channel_id: {}", o)),
         };
-        {
-            let mut excepts = plugin.state().excepts_chans.lock();
-            let mut contains = false;
-            for chan_id in excepts.iter() {
-                if chan_id == &scid {
-                    contains = true;
-                }
-            }
-            match command {
-                opt if opt.eq("add") => {
-                    if contains {
-                        return Err(anyhow!("{} is already in excepts", scid));
-                    }
-                    let pull_jobs = plugin.state().pull_jobs.lock().clone();
-                    let push_jobs = plugin.state().push_jobs.lock().clone();
-                    if peer_channels.contains_key(&scid) {
-                        if pull_jobs.contains(&scid) || push_jobs.contains(&scid) {
-                            return Err(anyhow!(
-                                "this channel has a job already and can't be an except too"
-                            ));
-                        }
-                        excepts.insert(scid);
-                    } else {
-                        excepts.insert(scid);
-                    }
-                }
-                opt if opt.eq("remove") => {
-                    if contains {
-                        excepts.retain(|&x| x != scid);
-                    } else {
-                        return Err(anyhow!(
-                            "short_channel_id {} not in excepts, nothing to remove",
-                            scid
-                        ));
-                    }
-                }
-                _ => {
-                    return Err(anyhow!(
-                        "Use `add`/`remove` and a short_channel_id or just `list`"
-                    ))
-                }
+        if command.eq("preview") {
+            return except_chan_preview(&plugin, scid).await;
+        }
+        if command.eq("add") {
+            let pull_jobs = plugin.state().pull_jobs.lock().clone();
+            let push_jobs = plugin.state().push_jobs.lock().clone();
+            if peer_channels.contains_key(&scid)
+                && (pull_jobs.contains(&scid) || push_jobs.contains(&scid))
+            {
+                return Err(anyhow!(
+                    "this channel has a job already and can't be an except too"
+                ));
             }
         }
+        update_excepts(&mut plugin.state().excepts_chans.lock(), command, scid)?;
         let excepts = plugin.state().excepts_chans.lock().clone();
         let sling_dir = Path::new(&plugin.configuration().lightning_dir).join(PLUGIN_NAME);
+        let list = sorted_excepts(&excepts);
         write_excepts(excepts, EXCEPTS_CHANS_FILE_NAME, &sling_dir).await?;
-        Ok(json!({ "result": "success" }))
+        Ok(json!({ "result": "success", "excepts": list }))
     } else {
         let excepts = plugin.state().excepts_chans.lock();
         match command {
-            opt if opt.eq("list") => Ok(json!(excepts.clone())),
+            opt if opt.eq("list") => Ok(json!(sorted_excepts(&excepts))),
             _ => Err(anyhow!(
                 "unknown commmand, did you misspell `list` or forgot the scid?"
             )),
@@ -436,6 +613,69 @@ pub async fn slingexceptchan(
     }
 }
 
+async fn except_chan_preview(
+    plugin: &Plugin<PluginState>,
+    scid: ShortChannelId,
+) -> Result<serde_json::Value, Error> {
+    let sling_dir = Path::new(&plugin.configuration().lightning_dir).join(PLUGIN_NAME);
+    let config = plugin.state().config.lock().clone();
+    let peer_channels = plugin.state().peer_channels.lock().clone();
+    let tempbans = plugin.state().tempbans.lock().clone();
+    let jobs = plugin.state().loaded_jobs.lock().clone();
+    let pull_jobs = plugin.state().pull_jobs.lock().clone();
+    let push_jobs = plugin.state().push_jobs.lock().clone();
+    #[allow(clippy::clone_on_copy)]
+    let blockheight = plugin.state().blockheight.lock().clone();
+
+    let mut current_candidates = HashMap::new();
+    {
+        let graph = plugin.state().graph.lock();
+        for (chan_id, job) in jobs
+            .iter()
+            .filter(|(c, _)| pull_jobs.contains(c) || push_jobs.contains(c))
+        {
+            current_candidates.insert(
+                *chan_id,
+                build_candidatelist(
+                    &peer_channels,
+                    job,
+                    &graph,
+                    &tempbans,
+                    &config,
+                    job.candidatelist.as_ref(),
+                    blockheight,
+                ),
+            );
+        }
+    }
+    let mut successes = HashMap::new();
+    for (chan_id, job) in &jobs {
+        if !current_candidates.contains_key(chan_id) {
+            continue;
+        }
+        if let Ok(o) = SuccessReb::read_from_file(&job.stats_dir(&sling_dir), chan_id).await {
+            successes.insert(*chan_id, o);
+        }
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let impacts = except_chan_impact(
+        &scid,
+        &jobs,
+        &current_candidates,
+        &successes,
+        now,
+        config.stats_recent_window_days.value,
+    );
+    Ok(json!({
+        "short_channel_id": scid.to_string(),
+        "excepted": plugin.state().excepts_chans.lock().contains(&scid),
+        "dependent_jobs": impacts,
+    }))
+}
+
 pub async fn slingexceptpeer(
     plugin: Plugin<PluginState>,
     args: serde_json::Value,
@@ -468,65 +708,564 @@ pub async fn slingexceptpeer(
             serde_json::Value::String(s) => PublicKey::from_str(s)?,
             o => return Err(anyhow!("invaild node_id: {}", o)),
         };
+        if command.eq("add") {
+            let pull_jobs = plugin.state().pull_jobs.lock().clone();
+            let push_jobs = plugin.state().push_jobs.lock().clone();
+            let all_jobs: Vec<ShortChannelId> =
+                pull_jobs.into_iter().chain(push_jobs.into_iter()).collect();
+            let mut all_job_peers: Vec<PublicKey> = vec![];
+            debug!("{:?}", all_jobs);
+            for job in &all_jobs {
+                match peer_channels.get(job) {
+                    Some(peer) => all_job_peers.push(peer.peer_id),
+                    None => return Err(anyhow!("peer not found")),
+                };
+            }
+            if all_job_peers.contains(&pubkey) {
+                return Err(anyhow!(
+                    "this peer has a job already and can't be an except too"
+                ));
+            }
+        }
+        update_excepts(&mut plugin.state().excepts_peers.lock(), command, pubkey)?;
+        let excepts = plugin.state().excepts_peers.lock().clone();
+        let sling_dir = Path::new(&plugin.configuration().lightning_dir).join(PLUGIN_NAME);
+        let list = sorted_excepts(&excepts);
+        write_excepts::<PublicKey>(excepts, EXCEPTS_PEERS_FILE_NAME, &sling_dir).await?;
+        Ok(json!({ "result": "success", "excepts": list }))
+    } else {
+        let excepts = plugin.state().excepts_peers.lock();
+        match command {
+            opt if opt.eq("list") => Ok(json!(sorted_excepts(&excepts))),
+            _ => Err(anyhow!(
+                "unknown commmand, use `list` or forgot the node_id?"
+            )),
+        }
+    }
+}
+
+pub async fn slingtempbanexempt(
+    plugin: Plugin<PluginState>,
+    args: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let array = match args {
+        serde_json::Value::Array(a) => a,
+        e => {
+            return Err(anyhow!(
+                "sling-tempban-exempt: invalid arguments, expected array, got {}",
+                e
+            ))
+        }
+    };
+    if array.len() > 2 || array.is_empty() {
+        return Err(anyhow!(
+            "Either provide `add`/`remove` and a short_channel_id or just `list`"
+        ));
+    }
+    let command = match array.first().unwrap() {
+        serde_json::Value::String(c) => c,
+        _ => {
+            return Err(anyhow!(
+                "Invalid command. Use `add`/`remove` <short_channel_id> or `list`"
+            ))
+        }
+    };
+    if array.len() == 2 {
+        let scid = match array.get(1).unwrap() {
+            serde_json::Value::String(s) => parse_scid(s)?,
+            o => return Err(anyhow!("not a vaild short_channel_id: {}", o)),
+        };
         {
-            let mut excepts_peers = plugin.state().excepts_peers.lock();
-            let contains = excepts_peers.contains(&pubkey);
+            let mut exempts = plugin.state().tempban_exempts.lock();
             match command {
                 opt if opt.eq("add") => {
-                    if contains {
-                        return Err(anyhow!("{} is already in excepts", pubkey));
-                    }
-                    let pull_jobs = plugin.state().pull_jobs.lock().clone();
-                    let push_jobs = plugin.state().push_jobs.lock().clone();
-                    let all_jobs: Vec<ShortChannelId> =
-                        pull_jobs.into_iter().chain(push_jobs.into_iter()).collect();
-                    let mut all_job_peers: Vec<PublicKey> = vec![];
-                    debug!("{:?}", all_jobs);
-                    for job in &all_jobs {
-                        match peer_channels.get(job) {
-                            Some(peer) => all_job_peers.push(peer.peer_id),
-                            None => return Err(anyhow!("peer not found")),
-                        };
+                    if !exempts.insert(scid) {
+                        return Err(anyhow!("{} is already exempt from tempbans", scid));
                     }
-                    if all_job_peers.contains(&pubkey) {
-                        return Err(anyhow!(
-                            "this peer has a job already and can't be an except too"
-                        ));
-                    }
-                    excepts_peers.insert(pubkey);
+                    plugin.state().tempbans.lock().remove(&scid);
                 }
                 opt if opt.eq("remove") => {
-                    if contains {
-                        excepts_peers.retain(|&x| x != pubkey);
-                    } else {
+                    if !exempts.remove(&scid) {
                         return Err(anyhow!(
-                            "node_id {} not in excepts, nothing to remove",
-                            pubkey
+                            "short_channel_id {} not exempt from tempbans, nothing to remove",
+                            scid
                         ));
                     }
                 }
                 _ => {
                     return Err(anyhow!(
-                        "Unknown commmand. Use `add`/`remove` <node_id> or `list`"
+                        "Unknown commmand. Use `add`/`remove` <short_channel_id> or `list`"
                     ))
                 }
             }
         }
-        let excepts = plugin.state().excepts_peers.lock().clone();
+        let exempts = plugin.state().tempban_exempts.lock().clone();
         let sling_dir = Path::new(&plugin.configuration().lightning_dir).join(PLUGIN_NAME);
-        write_excepts::<PublicKey>(excepts, EXCEPTS_PEERS_FILE_NAME, &sling_dir).await?;
+        write_excepts(exempts, TEMPBAN_EXEMPTS_FILE_NAME, &sling_dir).await?;
         Ok(json!({ "result": "success" }))
     } else {
-        let excepts = plugin.state().excepts_peers.lock();
+        let exempts = plugin.state().tempban_exempts.lock();
         match command {
-            opt if opt.eq("list") => Ok(json!(excepts.clone())),
+            opt if opt.eq("list") => Ok(json!(exempts.clone())),
             _ => Err(anyhow!(
-                "unknown commmand, use `list` or forgot the node_id?"
+                "unknown commmand, use `list` or forgot the short_channel_id?"
             )),
         }
     }
 }
 
+pub async fn slingmaintenance(
+    p: Plugin<PluginState>,
+    args: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    match args {
+        serde_json::Value::Array(a) => match a.len().cmp(&(1_usize)) {
+            Ordering::Greater => return Err(anyhow!("Please provide `on`, `off` or nothing")),
+            Ordering::Equal => {
+                let maintenance = match a.first().unwrap() {
+                    serde_json::Value::String(s) if s.eq("on") => true,
+                    serde_json::Value::String(s) if s.eq("off") => false,
+                    serde_json::Value::Bool(b) => *b,
+                    _ => return Err(anyhow!("Please provide `on`, `off` or nothing")),
+                };
+                *p.state().maintenance.lock() = maintenance;
+                if maintenance {
+                    info!("Maintenance mode on, jobs will not send any payments");
+                } else {
+                    info!("Maintenance mode off");
+                }
+            }
+            Ordering::Less => (),
+        },
+        e => {
+            return Err(anyhow!(
+                "sling-maintenance: invalid arguments, expected array, got: {}",
+                e
+            ))
+        }
+    }
+    Ok(json!({ "maintenance": p.state().is_maintenance() }))
+}
+
+pub async fn slingjobconfig(
+    p: Plugin<PluginState>,
+    args: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let mut jobs = read_jobs(
+        &Path::new(&p.configuration().lightning_dir).join(PLUGIN_NAME),
+        &p,
+    )
+    .await?;
+    match args {
+        serde_json::Value::Array(a) => match a.len().cmp(&(1_usize)) {
+            Ordering::Greater => {
+                return Err(anyhow!(
+                    "Please provide exactly one short_channel_id or nothing"
+                ))
+            }
+            Ordering::Equal => match a.first().unwrap() {
+                serde_json::Value::String(s) => {
+                    let scid = parse_scid(s)?;
+                    jobs.retain(|chanid, _j| chanid == &scid);
+                    if jobs.is_empty() {
+                        return Err(anyhow!("Shortchannelid not found in jobs"));
+                    }
+                }
+                _ => return Err(anyhow!("invalid short_channel_id")),
+            },
+            Ordering::Less => (),
+        },
+        e => {
+            return Err(anyhow!(
+                "sling-job-config: invalid arguments, expected array, got: {}",
+                e
+            ))
+        }
+    }
+    let config = p.state().config.lock().clone();
+    let mut result = BTreeMap::new();
+    for (chan_id, job) in &jobs {
+        result.insert(
+            chan_id.to_string(),
+            serde_json::to_value(EffectiveJobConfig::new(job, &config))?,
+        );
+    }
+    Ok(json!(result))
+}
+
+pub async fn slinglastroute(
+    p: Plugin<PluginState>,
+    args: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let chan_id = match args {
+        serde_json::Value::Array(a) if a.len() == 1 => match a.first().unwrap() {
+            serde_json::Value::String(s) => parse_scid(s)?,
+            _ => return Err(anyhow!("invalid short_channel_id")),
+        },
+        _ => return Err(anyhow!("Please provide exactly one short_channel_id")),
+    };
+    match get_last_route(&p.state().job_state.lock(), &chan_id) {
+        Some(last_route) => Ok(json!({ chan_id.to_string(): last_route })),
+        None => Err(anyhow!("No route attempted yet for {}", chan_id)),
+    }
+}
+
+pub async fn slingpinliquidity(
+    p: Plugin<PluginState>,
+    args: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let input_array = match args {
+        serde_json::Value::Array(a) => a,
+        e => {
+            return Err(anyhow!(
+                "sling-pin-liquidity: invalid arguments, expected array, got: {}",
+                e
+            ))
+        }
+    };
+    if !input_array.is_empty() {
+        if input_array.len() != 3 {
+            return Err(anyhow!(
+                "Please provide a short_channel_id, a direction and the liquidity in msat or nothing to list pins"
+            ));
+        }
+        let dir_chan = DirectedChannel {
+            short_channel_id: match input_array.first().unwrap() {
+                serde_json::Value::String(s) => parse_scid(s)?,
+                o => return Err(anyhow!("not a valid short_channel_id: {}", o)),
+            },
+            direction: match input_array.get(1).unwrap().as_u64() {
+                Some(d) if d <= 1 => d as u32,
+                _ => return Err(anyhow!("direction must be 0 or 1")),
+            },
+        };
+        let liquidity = input_array
+            .get(2)
+            .unwrap()
+            .as_u64()
+            .ok_or(anyhow!("liquidity must be a positive integer in msat"))?;
+        p.state().graph.lock().pin_liquidity(&dir_chan, liquidity)?;
+        info!("Pinned liquidity of {} to {}msat", dir_chan, liquidity);
+        p.state()
+            .pinned_liquidity
+            .lock()
+            .insert(dir_chan, liquidity);
+    }
+    let pinned = p.state().pinned_liquidity.lock().clone();
+    Ok(json!(pinned
+        .iter()
+        .map(|(dir_chan, liquidity)| (dir_chan.to_string(), *liquidity))
+        .collect::<BTreeMap<String, u64>>()))
+}
+
+pub async fn slingunpinliquidity(
+    p: Plugin<PluginState>,
+    args: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let input_array = match args {
+        serde_json::Value::Array(a) if !a.is_empty() && a.len() <= 2 => a,
+        _ => {
+            return Err(anyhow!(
+                "Please provide a short_channel_id and optionally a direction"
+            ))
+        }
+    };
+    let scid = match input_array.first().unwrap() {
+        serde_json::Value::String(s) => parse_scid(s)?,
+        o => return Err(anyhow!("not a valid short_channel_id: {}", o)),
+    };
+    let direction = match input_array.get(1) {
+        Some(d) => match d.as_u64() {
+            Some(d) if d <= 1 => Some(d as u32),
+            _ => return Err(anyhow!("direction must be 0 or 1")),
+        },
+        None => None,
+    };
+    let mut pinned = p.state().pinned_liquidity.lock();
+    let count = pinned.len();
+    pinned.retain(|dir_chan, _| {
+        dir_chan.short_channel_id != scid || direction.is_some_and(|d| d != dir_chan.direction)
+    });
+    if pinned.len() == count {
+        return Err(anyhow!("{} has no pinned liquidity", scid));
+    }
+    Ok(json!({ "unpinned": count - pinned.len() }))
+}
+
+pub async fn slingsourceable(
+    p: Plugin<PluginState>,
+    args: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let input_array = match args {
+        serde_json::Value::Array(a) if !a.is_empty() && a.len() <= 2 => a,
+        _ => {
+            return Err(anyhow!(
+                "Please provide a short_channel_id and optionally a direction"
+            ))
+        }
+    };
+    let chan_id = match input_array.first().unwrap() {
+        serde_json::Value::String(s) => parse_scid(s)?,
+        o => return Err(anyhow!("not a valid short_channel_id: {}", o)),
+    };
+    let direction = match input_array.get(1) {
+        Some(serde_json::Value::String(s)) => Some(SatDirection::from_str(s)?),
+        Some(o) => return Err(anyhow!("not a valid direction: {}", o)),
+        None => None,
+    };
+    let jobs = read_jobs(
+        &Path::new(&p.configuration().lightning_dir).join(PLUGIN_NAME),
+        &p,
+    )
+    .await?;
+    let job = match jobs.get(&chan_id) {
+        Some(j) if direction.is_none_or(|d| d == j.sat_direction) => j.clone(),
+        _ => serde_json::from_value::<Job>(json!({
+            "sat_direction": direction.unwrap_or(SatDirection::Pull).to_string(),
+            "amount_msat": 100_000_000,
+            "maxppm": u32::MAX,
+        }))?,
+    };
+
+    let config = p.state().config.lock().clone();
+    let peer_channels = p.state().peer_channels.lock().clone();
+    let tempbans = p.state().tempbans.lock().clone();
+    let excepts_chans = p.state().excepts_chans.lock().clone();
+    let excepts_peers = p.state().excepts_peers.lock().clone();
+    let alias_map = p.state().alias_peer_map.lock().clone();
+    #[allow(clippy::clone_on_copy)]
+    let blockheight = p.state().blockheight.lock().clone();
+
+    if !peer_channels.contains_key(&chan_id) {
+        return Err(anyhow!("Channel {} not found", chan_id));
+    }
+    let job_channels = get_job_channels(
+        &peer_channels,
+        &chan_id,
+        &job,
+        config.min_funded_sats.value * 1_000,
+    );
+    let target_room_msat = job_target_room(&job, &job_channels);
+
+    let candidatelist = {
+        let graph = p.state().graph.lock();
+        build_candidatelist(
+            &peer_channels,
+            &job,
+            &graph,
+            &tempbans,
+            &config,
+            job.candidatelist.as_ref(),
+            blockheight,
+        )
+    }
+    .into_iter()
+    .filter(|scid| {
+        !excepts_chans.contains(scid)
+            && peer_channels
+                .get(scid)
+                .is_some_and(|c| !excepts_peers.contains(&c.peer_id))
+    })
+    .collect::<Vec<ShortChannelId>>();
+    let sourceable = sourceable_liquidity(&peer_channels, &candidatelist, &job, &config);
+    let candidates_total_msat = sourceable.iter().map(|(_, msat)| *msat).sum::<u64>();
+
+    Ok(json!({
+        "short_channel_id": chan_id.to_string(),
+        "direction": job.sat_direction.to_string(),
+        "target_room_msat": target_room_msat,
+        "candidates_total_msat": candidates_total_msat,
+        "sourceable_msat": min(candidates_total_msat, target_room_msat),
+        "candidates": sourceable
+            .iter()
+            .map(|(scid, msat)| {
+                let peer_id = peer_channels.get(scid).unwrap().peer_id;
+                json!({
+                    "short_channel_id": scid.to_string(),
+                    "alias": alias_map
+                        .get(&peer_id)
+                        .cloned()
+                        .unwrap_or(peer_id.to_string()),
+                    "sourceable_msat": msat,
+                })
+            })
+            .collect::<Vec<serde_json::Value>>(),
+    }))
+}
+
+pub async fn slingsuggestmaxppm(
+    p: Plugin<PluginState>,
+    args: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let chan_id = match args {
+        serde_json::Value::Array(a) if a.len() == 1 => match a.first().unwrap() {
+            serde_json::Value::String(s) => parse_scid(s)?,
+            o => return Err(anyhow!("not a valid short_channel_id: {}", o)),
+        },
+        _ => return Err(anyhow!("Please provide exactly one short_channel_id")),
+    };
+    let sling_dir = Path::new(&p.configuration().lightning_dir).join(PLUGIN_NAME);
+    let peer_id = p
+        .state()
+        .peer_channels
+        .lock()
+        .get(&chan_id)
+        .ok_or_else(|| anyhow!("Channel {} not found", chan_id))?
+        .peer_id;
+    let stats_dir = p.state().stats_dir(&sling_dir, &chan_id);
+    let successes = match SuccessReb::read_from_file(&stats_dir, &chan_id).await {
+        Ok(o) => o,
+        Err(e) => {
+            debug!("{}: Could not get any successes: {}", chan_id, e);
+            Vec::new()
+        }
+    };
+    let my_pubkey = p.state().config.lock().pubkey;
+    let suggestion = suggest_maxppm(&successes, &p.state().graph.lock(), &peer_id, &my_pubkey)
+        .ok_or_else(|| {
+            anyhow!(
+                "{}: no successful rebalances and no graph fees toward the peer to base a suggestion on",
+                chan_id
+            )
+        })?;
+    let jobs = read_jobs(&sling_dir, &p).await?;
+
+    let mut result = json!({
+        "short_channel_id": chan_id.to_string(),
+    });
+    let map = result.as_object_mut().unwrap();
+    map.extend(
+        serde_json::to_value(&suggestion)?
+            .as_object()
+            .unwrap()
+            .clone(),
+    );
+    if let Some(job) = jobs.get(&chan_id) {
+        map.insert(
+            "current_maxppm".to_string(),
+            json!(job.maxppm_for(job.sat_direction)),
+        );
+    }
+    Ok(result)
+}
+
+pub async fn slingunmanaged(
+    p: Plugin<PluginState>,
+    _args: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let jobs = read_jobs(
+        &Path::new(&p.configuration().lightning_dir).join(PLUGIN_NAME),
+        &p,
+    )
+    .await?;
+    let peer_channels = p.state().peer_channels.lock().clone();
+    let pull_jobs = p.state().pull_jobs.lock().clone();
+    let push_jobs = p.state().push_jobs.lock().clone();
+    let alias_map = p.state().alias_peer_map.lock().clone();
+
+    Ok(json!({
+        "unmanaged": unmanaged_channels(&peer_channels, &pull_jobs, &push_jobs, &jobs)
+            .into_iter()
+            .map(|c| {
+                json!({
+                    "short_channel_id": c.short_channel_id.unwrap().to_string(),
+                    "alias": alias_map
+                        .get(&c.peer_id)
+                        .cloned()
+                        .unwrap_or(c.peer_id.to_string()),
+                    "total_msat": Amount::msat(&c.total_msat.unwrap()),
+                    "to_us_msat": Amount::msat(&c.to_us_msat.unwrap()),
+                    "imbalance": (channel_imbalance(c) * 100.0).round() / 100.0,
+                })
+            })
+            .collect::<Vec<serde_json::Value>>(),
+    }))
+}
+
+pub async fn slingexportgraph(
+    p: Plugin<PluginState>,
+    args: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let input_array = match args {
+        serde_json::Value::Array(a) if a.len() <= 2 => a,
+        _ => {
+            return Err(anyhow!(
+                "Please provide optionally a format (edgelist, graphml or dot) and a number of hops"
+            ))
+        }
+    };
+    let format = match input_array.first() {
+        Some(serde_json::Value::String(s)) => GraphExportFormat::from_str(s)?,
+        Some(o) => return Err(anyhow!("not a valid format: {}", o)),
+        None => GraphExportFormat::Dot,
+    };
+    let hops = match input_array.get(1) {
+        Some(serde_json::Value::Number(n)) => match n.as_u64() {
+            Some(h) => Some(h as u32),
+            None => return Err(anyhow!("hops must be a positive integer, not {}", n)),
+        },
+        Some(serde_json::Value::String(s)) => Some(s.parse::<u32>()?),
+        Some(o) => return Err(anyhow!("not a valid number of hops: {}", o)),
+        None => None,
+    };
+    let config = p.state().config.lock().clone();
+    let alias_map = p.state().alias_peer_map.lock().clone();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let (export, nodes, edges) = {
+        let graph = p.state().graph.lock();
+        let neighborhood = hops.map(|h| graph.neighborhood(&config.pubkey, h));
+        graph.export(
+            neighborhood.as_ref(),
+            &alias_map,
+            format,
+            now,
+            config.liquidity_max_age.value,
+        )
+    };
+    let file = config
+        .sling_dir
+        .join(format!("{}.{}", GRAPH_EXPORT_FILE_NAME, format.extension()));
+    fs::write(&file, export).await?;
+    info!(
+        "Exported {} nodes and {} edges to {}",
+        nodes,
+        edges,
+        file.display()
+    );
+    Ok(json!({
+        "file": file.to_string_lossy(),
+        "nodes": nodes,
+        "edges": edges,
+    }))
+}
+
+pub async fn slingdiagnostics(
+    p: Plugin<PluginState>,
+    args: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let redact = match args {
+        serde_json::Value::Array(a) if a.is_empty() => false,
+        serde_json::Value::Array(a) if a.len() == 1 => match a.first().unwrap() {
+            serde_json::Value::Bool(b) => *b,
+            serde_json::Value::String(s) => s.parse::<bool>()?,
+            _ => return Err(anyhow!("redact must be true or false")),
+        },
+        serde_json::Value::Object(o) => match o.get("redact") {
+            Some(r) => r
+                .as_bool()
+                .ok_or_else(|| anyhow!("redact must be true or false"))?,
+            None => false,
+        },
+        _ => return Err(anyhow!("Please provide only `redact` or nothing")),
+    };
+    let jobs = read_jobs(
+        &Path::new(&p.configuration().lightning_dir).join(PLUGIN_NAME),
+        &p,
+    )
+    .await?;
+    Ok(diagnostics(p.state(), &jobs, redact))
+}
+
 pub async fn slingversion(
     _p: Plugin<PluginState>,
     _args: serde_json::Value,
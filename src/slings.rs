@@ -1,5 +1,6 @@
 use anyhow::{anyhow, Error};
 
+use chrono::Utc;
 use cln_plugin::Plugin;
 
 use cln_rpc::model::requests::SendpayRoute;
@@ -8,28 +9,60 @@ use cln_rpc::primitives::*;
 
 use log::{debug, info, warn};
 
-use sling::{DirectedChannel, Job, SatDirection};
+use sling::{has_balance_fields, DirectedChannel, Job, SatDirection, TooExpensive};
 use std::cmp::{max, min};
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use tokio::time::Instant;
 
 use crate::dijkstra::dijkstra;
 use crate::model::{
-    Config, DijkstraNode, ExcludeGraph, JobMessage, PluginState, PublicKeyPair, Task,
+    AttemptAmount, CandidateExclusion, CandidateFailures, Config, DijkstraNode, EffectiveJobConfig,
+    ExcludeGraph, JobMessage, JobState, PluginState, PublicKeyPair, RouteAttempts, Task,
+    TraceRecord, CANDIDATES_CAP_CHEAPEST_HISTORY, CANDIDATES_CAP_MOST_IMBALANCED,
+    CANDIDATE_ORDER_CHEAPEST, CANDIDATE_ORDER_LARGEST_FIRST, CANDIDATE_ORDER_MOST_OVERFULL,
+    CANDIDATE_ORDER_MOST_OVER_TARGET, GRAPH_EMPTY_RETRY_MIN_SECS,
 };
 use crate::response::{sendpay_response, waitsendpay_response};
+use crate::stats::{cached_lifetime_rebalanced_msat, is_low_utilization, job_target_room};
 use crate::util::{
-    feeppm_effective, feeppm_effective_from_amts, get_normal_channel_from_listpeerchannels,
-    get_preimage_paymend_hash_pair, get_total_htlc_count, is_channel_normal, my_sleep,
+    channel_funding_pending, channel_locally_disabled, dryrun_route, feeppm_effective,
+    feeppm_effective_from_amts, get_job_channels, get_peer_htlc_counts, get_peer_htlc_value_usage,
+    get_preimage_paymend_hash_pair, is_htlc_capped, job_stop_reason, my_sleep, pick_job_channel,
+    validate_circular_route,
+};
+use crate::{
+    channel_attempt_update, channel_jobstate_update, channel_too_expensive,
+    get_remote_feeppm_effective, wait_for_gossip, wait_for_graph, LnGraph,
 };
-use crate::{channel_jobstate_update, get_remote_feeppm_effective, wait_for_gossip, LnGraph};
 
 pub async fn sling(job: &Job, task: &Task, plugin: &Plugin<PluginState>) -> Result<(), Error> {
     wait_for_gossip(plugin, task).await?;
 
     let mut success_route: Option<Vec<SendpayRoute>> = None;
+    if plugin.state().config.lock().route_cache_max_age.value > 0 {
+        let my_pubkey = plugin.state().config.lock().pubkey;
+        success_route = plugin.state().route_cache.lock().get_valid(
+            &task.chan_id,
+            job.amount_msat,
+            job.maxppm_for(job.sat_direction),
+            &plugin.state().graph.lock(),
+            &my_pubkey,
+        );
+        if success_route.is_some() {
+            info!(
+                "{}/{}: starting with cached route",
+                task.chan_id, task.task_id
+            );
+        }
+    }
+    let mut route_attempts = RouteAttempts::default();
+    let mut candidate_failures = CandidateFailures::default();
+    let mut last_sling_chan = task.chan_id;
+    let mut attempt_amount = AttemptAmount::new(job.amount_msat);
+    let mut cheapest_rejected_feeppm: Option<u32> = None;
     'outer: loop {
         let now = Instant::now();
         let should_stop = plugin
@@ -55,13 +88,19 @@ pub async fn sling(job: &Job, task: &Task, plugin: &Plugin<PluginState>) -> Resu
         }
 
         let config = plugin.state().config.lock().clone();
+        let job = &job.with_amount(attempt_amount.current());
 
         let tempbans = plugin.state().tempbans.lock().clone();
         let peer_channels = plugin.state().peer_channels.lock().clone();
-        let other_peer = peer_channels
-            .get(&task.chan_id)
-            .ok_or(anyhow!("other_peer: channel not found"))?
-            .peer_id;
+        let other_peer = match job.peer {
+            Some(peer) => peer,
+            None => {
+                peer_channels
+                    .get(&task.chan_id)
+                    .ok_or(anyhow!("other_peer: channel not found"))?
+                    .peer_id
+            }
+        };
 
         if let Some(r) = health_check(
             plugin,
@@ -80,6 +119,43 @@ pub async fn sling(job: &Job, task: &Task, plugin: &Plugin<PluginState>) -> Resu
             break 'outer;
         }
 
+        let graph_empty = plugin.state().graph.lock().graph.is_empty();
+        if graph_empty {
+            wait_for_graph(
+                &plugin.state().graph,
+                plugin.state().job_state.clone(),
+                task,
+                GRAPH_EMPTY_RETRY_MIN_SECS,
+            )
+            .await?;
+            success_route = None;
+            continue 'outer;
+        }
+
+        if let Some(paused) = plugin.state().dispatch_paused(&config) {
+            channel_jobstate_update(plugin.state().job_state.clone(), task, &paused, true, false)?;
+            success_route = None;
+            my_sleep(10, plugin.state().job_state.clone(), task).await;
+            continue 'outer;
+        }
+
+        if task.task_id
+            > plugin
+                .state()
+                .effective_parallelism(&task.chan_id, job, &config)
+        {
+            channel_jobstate_update(
+                plugin.state().job_state.clone(),
+                task,
+                &JobMessage::Throttled,
+                true,
+                false,
+            )?;
+            success_route = None;
+            my_sleep(10, plugin.state().job_state.clone(), task).await;
+            continue 'outer;
+        }
+
         channel_jobstate_update(
             plugin.state().job_state.clone(),
             task,
@@ -88,8 +164,25 @@ pub async fn sling(job: &Job, task: &Task, plugin: &Plugin<PluginState>) -> Resu
             false,
         )?;
 
+        let sling_chan = pick_job_channel(
+            job,
+            &get_job_channels(
+                &peer_channels,
+                &task.chan_id,
+                job,
+                config.min_funded_sats.value * 1_000,
+            ),
+            config.max_htlc_count.value,
+        )
+        .unwrap_or(task.chan_id);
+        if sling_chan != last_sling_chan {
+            success_route = None;
+            last_sling_chan = sling_chan;
+        }
+
+        let mut trace = None;
         let route = {
-            let nr = next_route(
+            let mut nr = next_route(
                 plugin,
                 &config,
                 &peer_channels,
@@ -100,10 +193,57 @@ pub async fn sling(job: &Job, task: &Task, plugin: &Plugin<PluginState>) -> Resu
                     my_pubkey: config.pubkey,
                     other_pubkey: other_peer,
                 },
+                &sling_chan,
                 &mut success_route,
+                &mut trace,
+                &mut candidate_failures,
             )
             .await;
             if nr.is_err() || nr.as_ref().unwrap().is_empty() {
+                if let Some(backup_job) = job.backup_job() {
+                    info!(
+                        "{}/{}: primary candidates exhausted, trying backup candidates...",
+                        task.chan_id, task.task_id
+                    );
+                    success_route = None;
+                    nr = next_route(
+                        plugin,
+                        &config,
+                        &peer_channels,
+                        &backup_job,
+                        &tempbans,
+                        task,
+                        &PublicKeyPair {
+                            my_pubkey: config.pubkey,
+                            other_pubkey: other_peer,
+                        },
+                        &sling_chan,
+                        &mut success_route,
+                        &mut trace,
+                        &mut candidate_failures,
+                    )
+                    .await;
+                    if matches!(&nr, Ok(r) if !r.is_empty()) {
+                        channel_jobstate_update(
+                            plugin.state().job_state.clone(),
+                            task,
+                            &JobMessage::Rebalancing,
+                            true,
+                            false,
+                        )?;
+                    }
+                }
+            }
+            if nr.is_err() || nr.as_ref().unwrap().is_empty() {
+                write_trace(plugin, &mut trace, &config, None, "no route").await;
+                if let Some(smaller) = attempt_amount.split(job) {
+                    info!(
+                        "{}/{}: could not find a route for {}msat, trying {}msat...",
+                        task.chan_id, task.task_id, job.amount_msat, smaller
+                    );
+                    success_route = None;
+                    continue 'outer;
+                }
                 info!(
                     "{}/{}: could not find a route. Sleeping...",
                     task.chan_id, task.task_id
@@ -116,6 +256,8 @@ pub async fn sling(job: &Job, task: &Task, plugin: &Plugin<PluginState>) -> Resu
                     false,
                 )?;
                 success_route = None;
+                route_attempts.reset();
+                attempt_amount.reset();
                 my_sleep(600, plugin.state().job_state.clone(), task).await;
                 continue 'outer;
             }
@@ -135,22 +277,40 @@ pub async fn sling(job: &Job, task: &Task, plugin: &Plugin<PluginState>) -> Resu
             now.elapsed().as_millis().to_string()
         );
 
-        if fee_ppm_effective > job.maxppm {
+        if fee_ppm_effective > job.maxppm_for(job.sat_direction) {
+            cheapest_rejected_feeppm = cheaper_feeppm(cheapest_rejected_feeppm, fee_ppm_effective);
+            write_trace(
+                plugin,
+                &mut trace,
+                &config,
+                Some(fee_ppm_effective),
+                "too expensive",
+            )
+            .await;
+            if let Some(smaller) = attempt_amount.split(job) {
+                info!(
+                    "{}/{}: route not cheap enough for {}msat, trying {}msat...",
+                    task.chan_id, task.task_id, job.amount_msat, smaller
+                );
+                success_route = None;
+                continue 'outer;
+            }
+            let too_expensive = TooExpensive {
+                cheapest_feeppm: cheapest_rejected_feeppm.take().unwrap_or(fee_ppm_effective),
+                maxppm: job.maxppm_for(job.sat_direction),
+            };
             info!(
-                "{}/{}: route not cheap enough! Sleeping...",
-                task.chan_id, task.task_id
+                "{}/{}: route not cheap enough! Cheapest available was {}ppm, maxppm is {}ppm. Sleeping...",
+                task.chan_id, task.task_id, too_expensive.cheapest_feeppm, too_expensive.maxppm
             );
-            channel_jobstate_update(
-                plugin.state().job_state.clone(),
-                task,
-                &JobMessage::TooExp,
-                true,
-                false,
-            )?;
+            channel_too_expensive(plugin.state().job_state.clone(), task, too_expensive)?;
             my_sleep(600, plugin.state().job_state.clone(), task).await;
             success_route = None;
+            route_attempts.reset();
+            attempt_amount.reset();
             continue 'outer;
         }
+        cheapest_rejected_feeppm = None;
 
         {
             let alias_map = plugin.state().alias_peer_map.lock();
@@ -167,7 +327,34 @@ pub async fn sling(job: &Job, task: &Task, plugin: &Plugin<PluginState>) -> Resu
             }
         }
 
+        if dryrun_route(
+            plugin.state().job_state.clone(),
+            task,
+            job,
+            &route,
+            fee_ppm_effective,
+        )? {
+            write_trace(
+                plugin,
+                &mut trace,
+                &config,
+                Some(fee_ppm_effective),
+                "dryrun",
+            )
+            .await;
+            success_route = None;
+            my_sleep(60, plugin.state().job_state.clone(), task).await;
+            continue 'outer;
+        }
+
         let (preimage, payment_hash) = get_preimage_paymend_hash_pair();
+        let to_us_before_msat = plugin
+            .state()
+            .peer_channels
+            .lock()
+            .get(&sling_chan)
+            .and_then(|c| c.to_us_msat)
+            .map(|a| Amount::msat(&a));
         // debug!(
         //     "{}: Made preimage and payment_hash: {} Total: {}ms",
         //     chan_id.to_string(),
@@ -191,10 +378,26 @@ pub async fn sling(job: &Job, task: &Task, plugin: &Plugin<PluginState>) -> Resu
                 if let Some(resp) = o {
                     resp
                 } else {
+                    write_trace(
+                        plugin,
+                        &mut trace,
+                        &config,
+                        Some(fee_ppm_effective),
+                        "failure",
+                    )
+                    .await;
                     continue;
                 }
             }
             Err(e) => {
+                write_trace(
+                    plugin,
+                    &mut trace,
+                    &config,
+                    Some(fee_ppm_effective),
+                    "error",
+                )
+                .await;
                 channel_jobstate_update(
                     plugin.state().job_state.clone(),
                     task,
@@ -213,7 +416,11 @@ pub async fn sling(job: &Job, task: &Task, plugin: &Plugin<PluginState>) -> Resu
             now.elapsed().as_millis().to_string()
         );
 
-        match waitsendpay_response(
+        let sent_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let wait_response = waitsendpay_response(
             plugin,
             &config,
             send_response.payment_hash,
@@ -222,11 +429,21 @@ pub async fn sling(job: &Job, task: &Task, plugin: &Plugin<PluginState>) -> Resu
             job,
             &route,
             &mut success_route,
+            to_us_before_msat,
         )
-        .await
-        {
+        .await;
+
+        match wait_response {
             Ok(o) => o,
             Err(e) => {
+                write_trace(
+                    plugin,
+                    &mut trace,
+                    &config,
+                    Some(fee_ppm_effective),
+                    "error",
+                )
+                .await;
                 channel_jobstate_update(
                     plugin.state().job_state.clone(),
                     task,
@@ -238,6 +455,95 @@ pub async fn sling(job: &Job, task: &Task, plugin: &Plugin<PluginState>) -> Resu
                 break 'outer;
             }
         };
+
+        if config.route_cache_max_age.value > 0 {
+            if success_route.is_some() {
+                plugin
+                    .state()
+                    .route_cache
+                    .lock()
+                    .insert(task.chan_id, &route, sent_at);
+            } else {
+                plugin.state().route_cache.lock().remove(&task.chan_id);
+            }
+        }
+
+        write_trace(
+            plugin,
+            &mut trace,
+            &config,
+            Some(fee_ppm_effective),
+            if success_route.is_some() {
+                "success"
+            } else {
+                "failure"
+            },
+        )
+        .await;
+
+        if channel_attempt_update(
+            plugin.state().job_state.clone(),
+            task,
+            success_route.is_some(),
+            config.circuit_breaker_threshold.value,
+        )? {
+            warn!(
+                "{}/{}: {} payments failed in a row. Disabling job until sling-reset-circuit.",
+                task.chan_id, task.task_id, config.circuit_breaker_threshold.value
+            );
+            channel_jobstate_update(
+                plugin.state().job_state.clone(),
+                task,
+                &JobMessage::CircuitOpen,
+                false,
+                true,
+            )?;
+            break 'outer;
+        }
+        if let Some(tuned) = plugin
+            .state()
+            .retune_parallelism(&task.chan_id, job, &config)
+        {
+            info!(
+                "{}/{}: auto-tuned parallelism to {}",
+                task.chan_id, task.task_id, tuned
+            );
+        }
+
+        let candidate = match job.sat_direction {
+            SatDirection::Pull => route.first(),
+            SatDirection::Push => route.last(),
+        };
+        if let Some(hop) = candidate {
+            if candidate_failures.record(
+                hop.channel,
+                success_route.is_some(),
+                config.candidate_max_failures.value,
+            ) {
+                info!(
+                    "{}/{}: candidate {} failed {} times in a row, trying other candidates first",
+                    task.chan_id, task.task_id, hop.channel, config.candidate_max_failures.value
+                );
+            }
+        }
+
+        attempt_amount.record(success_route.is_some());
+        if route_attempts.record(success_route.is_some(), config.max_route_attempts.value) {
+            info!(
+                "{}/{}: {} routes failed in a row. Sleeping...",
+                task.chan_id, task.task_id, config.max_route_attempts.value
+            );
+            channel_jobstate_update(
+                plugin.state().job_state.clone(),
+                task,
+                &JobMessage::MaxAttempts,
+                true,
+                false,
+            )?;
+            success_route = None;
+            candidate_failures.reset();
+            my_sleep(600, plugin.state().job_state.clone(), task).await;
+        }
     }
     if let Some(tk) = plugin.state().parrallel_bans.lock().get_mut(&task.chan_id) {
         tk.remove(&task.task_id);
@@ -255,12 +561,16 @@ async fn next_route(
     tempbans: &HashMap<ShortChannelId, u64>,
     task: &Task,
     keypair: &PublicKeyPair,
+    sling_chan: &ShortChannelId,
     success_route: &mut Option<Vec<SendpayRoute>>,
+    trace: &mut Option<TraceRecord>,
+    candidate_failures: &mut CandidateFailures,
 ) -> Result<Vec<SendpayRoute>, Error> {
+    let failure_rates = plugin.state().failure_rates.lock().clone();
     let graph = plugin.state().graph.lock();
     #[allow(clippy::clone_on_copy)]
     let blockheight = plugin.state().blockheight.lock().clone();
-    let candidatelist;
+    let mut candidatelist;
     if let Some(c) = &job.candidatelist {
         if !c.is_empty() {
             candidatelist = build_candidatelist(
@@ -294,6 +604,112 @@ async fn next_route(
             blockheight,
         )
     }
+    let mut excluded = if config.trace.value {
+        candidate_exclusions(
+            peer_channels,
+            job,
+            &graph,
+            tempbans,
+            config,
+            job.candidatelist.as_ref().filter(|c| !c.is_empty()),
+            blockheight,
+        )
+        .1
+    } else {
+        Vec::new()
+    };
+    if candidatelist.contains(sling_chan) {
+        candidatelist.retain(|c| c != sling_chan);
+        excluded.push(CandidateExclusion {
+            short_channel_id: *sling_chan,
+            reason: "channel being rebalanced".to_string(),
+        });
+        if job
+            .candidatelist
+            .as_ref()
+            .is_some_and(|c| c.contains(sling_chan))
+        {
+            warn!(
+                "{}/{}: skipping candidate {}, it is the channel we are rebalancing",
+                task.chan_id, task.task_id, sling_chan
+            );
+        }
+    }
+    if config.candidate_order.value == CANDIDATE_ORDER_MOST_OVER_TARGET {
+        sort_candidates_most_over_target(
+            &mut candidatelist,
+            job.sat_direction,
+            peer_channels,
+            &plugin.state().loaded_jobs.lock(),
+        );
+    }
+    if !config.exclude_alias.value.is_empty() {
+        let alias_map = plugin.state().alias_peer_map.lock();
+        let mut alias_blocklist = plugin.state().alias_blocklist.lock();
+        candidatelist.retain(|c| match peer_channels.get(c) {
+            Some(channel)
+                if alias_blocklist.is_blocked(
+                    &config.exclude_alias.value,
+                    &channel.peer_id,
+                    &alias_map,
+                ) =>
+            {
+                excluded.push(CandidateExclusion {
+                    short_channel_id: *c,
+                    reason: "peer alias excluded".to_string(),
+                });
+                false
+            }
+            _ => true,
+        });
+    }
+    for skipped in candidate_failures.rotate(&mut candidatelist) {
+        excluded.push(CandidateExclusion {
+            short_channel_id: skipped,
+            reason: "failed too often in a row".to_string(),
+        });
+    }
+    let uncapped = candidatelist.clone();
+    cap_candidates(
+        &mut candidatelist,
+        config.max_candidates_per_iteration.value,
+        &config.candidates_cap_strategy.value,
+        job.sat_direction,
+        peer_channels,
+        &plugin.state().candidate_feeppms.lock(),
+        plugin
+            .state()
+            .candidate_rotation
+            .lock()
+            .entry(task.chan_id)
+            .or_default(),
+    );
+
+    if config.trace.value {
+        excluded.extend(
+            uncapped
+                .into_iter()
+                .filter(|c| !candidatelist.contains(c))
+                .map(|c| CandidateExclusion {
+                    short_channel_id: c,
+                    reason: "over max-candidates-per-iteration".to_string(),
+                }),
+        );
+        *trace = Some(TraceRecord {
+            chan_id: task.chan_id,
+            task_id: task.task_id,
+            amount_msat: job.amount_msat,
+            candidates: candidatelist.clone(),
+            excluded,
+            route: Vec::new(),
+            fee_ppm: None,
+            outcome: String::new(),
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        });
+    }
 
     debug!(
         "{}/{}: Candidates: {}",
@@ -336,13 +752,22 @@ async fn next_route(
         return Err(anyhow!("No candidates found"));
     }
 
+    let ordered_candidates = (job.sat_direction == SatDirection::Pull
+        && config.candidate_order.value != CANDIDATE_ORDER_CHEAPEST)
+        || config.candidate_order.value == CANDIDATE_ORDER_MOST_OVER_TARGET;
     let mut route = Vec::new();
     match success_route {
         Some(prev_route) => {
             if match job.sat_direction {
+                SatDirection::Pull if ordered_candidates => {
+                    candidatelist[0] == prev_route.first().unwrap().channel
+                }
                 SatDirection::Pull => candidatelist
                     .iter()
                     .any(|c| c == &prev_route.first().unwrap().channel),
+                SatDirection::Push if ordered_candidates => {
+                    candidatelist[0] == prev_route.last().unwrap().channel
+                }
                 SatDirection::Push => candidatelist
                     .iter()
                     .any(|c| c == &prev_route.last().unwrap().channel),
@@ -376,61 +801,95 @@ async fn next_route(
             let mut pull_jobs = plugin.state().pull_jobs.lock().clone();
             let mut push_jobs = plugin.state().push_jobs.lock().clone();
             let excepts = plugin.state().excepts_chans.lock().clone();
-            let excepts_peers = plugin.state().excepts_peers.lock().clone();
+            let mut excepts_peers = plugin.state().excepts_peers.lock().clone();
+            excepts_peers.extend(
+                plugin
+                    .state()
+                    .node_bans
+                    .lock()
+                    .banned()
+                    .into_iter()
+                    .filter(|node| node != &keypair.other_pubkey),
+            );
             for except in &excepts {
                 pull_jobs.insert(*except);
                 push_jobs.insert(*except);
             }
-            let max_hops = match job.maxhops {
-                Some(h) => h + 1,
-                None => config.maxhops.value + 1,
-            };
+            let node_penalties = plugin.state().node_penalties.lock().penalties_ppm(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs(),
+                config.node_penalty.value,
+                config.node_penalty_half_life.value * 60,
+            );
+            let (max_hops, fee_budget_msat) =
+                EffectiveJobConfig::new(job, config).route_limits(job);
             match job.sat_direction {
                 SatDirection::Pull => {
-                    let slingchan_inc =
-                        match graph.get_channel(&keypair.other_pubkey, &task.chan_id) {
-                            Ok(in_chan) => in_chan,
-                            Err(_) => {
-                                warn!(
-                                    "{}/{}: channel not found in graph!",
-                                    task.chan_id, task.task_id
-                                );
-                                channel_jobstate_update(
-                                    plugin.state().job_state.clone(),
-                                    task,
-                                    &JobMessage::ChanNotInGraph,
-                                    true,
-                                    false,
-                                )?;
-                                return Err(anyhow!("channel not found in graph"));
-                            }
-                        };
-                    route = dijkstra(
-                        &keypair.my_pubkey,
-                        &graph,
-                        &keypair.my_pubkey,
-                        &keypair.other_pubkey,
-                        &DijkstraNode {
-                            score: 0,
-                            destination: keypair.my_pubkey,
-                            channel_state: slingchan_inc,
-                            hops: 0,
-                            short_channel_id: task.chan_id,
-                        },
-                        job,
-                        &candidatelist,
-                        max_hops,
-                        &ExcludeGraph {
-                            exclude_chans: pull_jobs,
-                            exclude_peers: excepts_peers,
-                        },
-                        config.cltv_delta,
-                        tempbans,
-                        &task_bans,
-                    )?;
+                    let slingchan_inc = match graph.get_channel(&keypair.other_pubkey, sling_chan) {
+                        Ok(in_chan) => in_chan,
+                        Err(_) => {
+                            warn!(
+                                "{}/{}: channel not found in graph!",
+                                task.chan_id, task.task_id
+                            );
+                            channel_jobstate_update(
+                                plugin.state().job_state.clone(),
+                                task,
+                                &JobMessage::ChanNotInGraph,
+                                true,
+                                false,
+                            )?;
+                            return Err(anyhow!("channel not found in graph"));
+                        }
+                    };
+                    let exclude_graph = ExcludeGraph {
+                        exclude_chans: pull_jobs,
+                        exclude_peers: excepts_peers,
+                    };
+                    // ordered candidates are tried one at a time,
+                    // cheapest lets dijkstra pick among all of them
+                    let candidate_groups: Vec<&[ShortChannelId]> = if ordered_candidates {
+                        candidatelist.chunks(1).collect()
+                    } else {
+                        vec![&candidatelist]
+                    };
+                    for candidates in candidate_groups {
+                        route = dijkstra(
+                            &keypair.my_pubkey,
+                            &graph,
+                            &keypair.my_pubkey,
+                            &keypair.other_pubkey,
+                            &DijkstraNode {
+                                score: 0,
+                                destination: keypair.my_pubkey,
+                                channel_state: slingchan_inc,
+                                hops: 0,
+                                short_channel_id: *sling_chan,
+                            },
+                            job,
+                            candidates,
+                            max_hops,
+                            &exclude_graph,
+                            config.cltv_delta,
+                            tempbans,
+                            &task_bans,
+                            &failure_rates,
+                            config.reliability_weight.value,
+                            config.liquidity_max_age.value,
+                            config.liquidity_confidence_window.value,
+                            fee_budget_msat,
+                            config.capacity_preference.value,
+                            &node_penalties,
+                        )?;
+                        if !route.is_empty() {
+                            break;
+                        }
+                    }
                 }
                 SatDirection::Push => {
-                    let slingchan_out = match graph.get_channel(&keypair.my_pubkey, &task.chan_id) {
+                    let slingchan_out = match graph.get_channel(&keypair.my_pubkey, sling_chan) {
                         Ok(out_chan) => out_chan,
                         Err(_) => {
                             warn!(
@@ -447,33 +906,60 @@ async fn next_route(
                             return Err(anyhow!("channel not found in graph!"));
                         }
                     };
-                    route = dijkstra(
-                        &keypair.my_pubkey,
-                        &graph,
-                        &keypair.other_pubkey,
-                        &keypair.my_pubkey,
-                        &DijkstraNode {
-                            score: 0,
-                            destination: keypair.other_pubkey,
-                            channel_state: slingchan_out,
-                            hops: 0,
-                            short_channel_id: task.chan_id,
-                        },
-                        job,
-                        &candidatelist,
-                        max_hops,
-                        &ExcludeGraph {
-                            exclude_chans: push_jobs,
-                            exclude_peers: excepts_peers,
-                        },
-                        config.cltv_delta,
-                        tempbans,
-                        &task_bans,
-                    )?;
+                    let exclude_graph = ExcludeGraph {
+                        exclude_chans: push_jobs,
+                        exclude_peers: excepts_peers,
+                    };
+                    let candidate_groups: Vec<&[ShortChannelId]> = if ordered_candidates {
+                        candidatelist.chunks(1).collect()
+                    } else {
+                        vec![&candidatelist]
+                    };
+                    for candidates in candidate_groups {
+                        route = dijkstra(
+                            &keypair.my_pubkey,
+                            &graph,
+                            &keypair.other_pubkey,
+                            &keypair.my_pubkey,
+                            &DijkstraNode {
+                                score: 0,
+                                destination: keypair.other_pubkey,
+                                channel_state: slingchan_out,
+                                hops: 0,
+                                short_channel_id: *sling_chan,
+                            },
+                            job,
+                            candidates,
+                            max_hops,
+                            &exclude_graph,
+                            config.cltv_delta,
+                            tempbans,
+                            &task_bans,
+                            &failure_rates,
+                            config.reliability_weight.value,
+                            config.liquidity_max_age.value,
+                            config.liquidity_confidence_window.value,
+                            fee_budget_msat,
+                            config.capacity_preference.value,
+                            &node_penalties,
+                        )?;
+                        if !route.is_empty() {
+                            break;
+                        }
+                    }
                 }
             }
         }
     }
+    if !route.is_empty() {
+        if let Err(e) = validate_circular_route(&route, &keypair.my_pubkey) {
+            warn!(
+                "{}/{}: rejecting malformed route: {}",
+                task.chan_id, task.task_id, e
+            );
+            return Err(e);
+        }
+    }
     if route.len() >= 3 {
         let route_claim_chan = route[route.len() / 2].channel;
         let route_claim_peer = route[(route.len() / 2) - 1].id;
@@ -510,9 +996,99 @@ async fn next_route(
     } else if let Some(tk) = parallel_bans.get_mut(&task.chan_id) {
         tk.remove(&task.task_id);
     };
+    if let Some(t) = trace {
+        t.route = route.iter().map(|r| r.channel).collect();
+    }
     Ok(route)
 }
 
+fn nothing_to_do(
+    job: &Job,
+    job_channels: &[ListpeerchannelsChannels],
+    chan_id: &ShortChannelId,
+) -> bool {
+    job.is_balanced(job_channels, chan_id)
+        || job_channels.iter().all(|c| match job.sat_direction {
+            SatDirection::Pull => Amount::msat(&c.receivable_msat.unwrap()) < job.amount_msat,
+            SatDirection::Push => Amount::msat(&c.spendable_msat.unwrap()) < job.amount_msat,
+        })
+}
+
+// members that failed or went to sleep give the group turn to the next one
+pub fn group_members_ready(
+    job_states: &HashMap<ShortChannelId, Vec<JobState>>,
+) -> HashSet<ShortChannelId> {
+    job_states
+        .iter()
+        .filter(|(_, states)| {
+            states.iter().any(|s| {
+                s.is_active()
+                    && matches!(
+                        s.state(),
+                        JobMessage::Starting | JobMessage::Rebalancing | JobMessage::GroupWaiting
+                    )
+            })
+        })
+        .map(|(scid, _)| *scid)
+        .collect()
+}
+
+// of the ready members of a group that still have work, the one furthest
+// from its target relative to its size goes first
+pub fn group_member_to_work(
+    group: &str,
+    jobs: &BTreeMap<ShortChannelId, Job>,
+    peer_channels: &HashMap<ShortChannelId, ListpeerchannelsChannels>,
+    ready: &HashSet<ShortChannelId>,
+    min_funded_msat: u64,
+) -> Option<ShortChannelId> {
+    let mut most_imbalanced: Option<(ShortChannelId, f64)> = None;
+    for (chan_id, job) in jobs {
+        if job.group.as_deref() != Some(group) || !ready.contains(chan_id) {
+            continue;
+        }
+        let job_channels = get_job_channels(peer_channels, chan_id, job, min_funded_msat);
+        if job_channels.is_empty() || nothing_to_do(job, &job_channels, chan_id) {
+            continue;
+        }
+        let total_msat = job_channels
+            .iter()
+            .map(|c| Amount::msat(&c.total_msat.unwrap()))
+            .sum::<u64>();
+        let imbalance = job_target_room(job, &job_channels) as f64 / total_msat.max(1) as f64;
+        if most_imbalanced.is_none_or(|(_, i)| imbalance > i) {
+            most_imbalanced = Some((*chan_id, imbalance));
+        }
+    }
+    most_imbalanced.map(|(chan_id, _)| chan_id)
+}
+
+pub fn cheaper_feeppm(cheapest: Option<u32>, fee_ppm: u32) -> Option<u32> {
+    Some(cheapest.map_or(fee_ppm, |c| c.min(fee_ppm)))
+}
+
+async fn write_trace(
+    plugin: &Plugin<PluginState>,
+    trace: &mut Option<TraceRecord>,
+    config: &Config,
+    fee_ppm: Option<u32>,
+    outcome: &str,
+) {
+    if let Some(mut record) = trace.take() {
+        record.fee_ppm = fee_ppm;
+        record.outcome = outcome.to_string();
+        if let Err(e) = plugin
+            .state()
+            .track_write(record.write_to_file(&config.sling_dir).await)
+        {
+            warn!(
+                "{}/{}: Error writing trace: {}",
+                record.chan_id, record.task_id, e
+            );
+        }
+    }
+}
+
 async fn health_check(
     plugin: &Plugin<PluginState>,
     config: &Config,
@@ -523,129 +1099,246 @@ async fn health_check(
     tempbans: &HashMap<ShortChannelId, u64>,
 ) -> Result<Option<bool>, Error> {
     let job_states = plugin.state().job_state.clone();
-    let our_listpeers_channel =
-        get_normal_channel_from_listpeerchannels(peer_channels, &task.chan_id);
-    if let Some(channel) = our_listpeers_channel {
-        if is_channel_normal(&channel) {
-            if job.is_balanced(&channel, &task.chan_id)
-                || match job.sat_direction {
-                    SatDirection::Pull => {
-                        Amount::msat(&channel.receivable_msat.unwrap()) < job.amount_msat
-                    }
-                    SatDirection::Push => {
-                        Amount::msat(&channel.spendable_msat.unwrap()) < job.amount_msat
-                    }
-                }
-            {
-                info!(
-                    "{}/{}: already balanced. Taking a break...",
-                    task.chan_id, task.task_id
-                );
-                channel_jobstate_update(
-                    job_states.clone(),
-                    task,
-                    &JobMessage::Balanced,
-                    true,
-                    false,
-                )?;
-                my_sleep(600, job_states.clone(), task).await;
-                Ok(Some(true))
-            } else if get_total_htlc_count(&channel) > config.max_htlc_count.value {
-                info!(
-                    "{}/{}: already more than {} pending htlcs. Taking a break...",
-                    task.chan_id, task.task_id, config.max_htlc_count.value
-                );
-                channel_jobstate_update(
-                    job_states.clone(),
-                    task,
-                    &JobMessage::HTLCcapped,
-                    true,
-                    false,
-                )?;
-                my_sleep(10, job_states.clone(), task).await;
-                Ok(Some(true))
-            } else {
-                match peer_channels.values().find(|x| x.peer_id == other_peer) {
-                    Some(p) => {
-                        if !p.peer_connected {
-                            info!(
-                                "{}/{}: not connected. Taking a break...",
-                                task.chan_id, task.task_id
-                            );
-                            channel_jobstate_update(
-                                job_states.clone(),
-                                task,
-                                &JobMessage::Disconnected,
-                                true,
-                                false,
-                            )?;
-                            my_sleep(60, job_states.clone(), task).await;
-                            Ok(Some(true))
-                        } else if tempbans.contains_key(&task.chan_id) {
-                            info!(
-                                "{}/{}: Job peer not ready. Taking a break...",
-                                task.chan_id, task.task_id
-                            );
-                            channel_jobstate_update(
-                                job_states.clone(),
-                                task,
-                                &JobMessage::PeerNotReady,
-                                true,
-                                false,
-                            )?;
-                            my_sleep(20, job_states.clone(), task).await;
-                            Ok(Some(true))
-                        } else {
-                            Ok(None)
-                        }
-                    }
-                    None => {
-                        channel_jobstate_update(
-                            job_states.clone(),
-                            task,
-                            &JobMessage::PeerNotFound,
-                            false,
-                            true,
-                        )?;
-                        warn!(
-                            "{}/{}: peer not found. Stopping job.",
-                            task.chan_id, task.task_id
-                        );
-                        Ok(Some(false))
-                    }
-                }
-            }
-        } else {
-            warn!(
-                "{}/{}: not in CHANNELD_NORMAL state. Stopping Job.",
+    if let Some(max_total_sat) = job.max_total_sat {
+        let total_msat = cached_lifetime_rebalanced_msat(
+            &plugin.state().stats_cache,
+            config.stats_cache_size.value as usize,
+            &job.stats_dir(&config.sling_dir),
+            &task.chan_id,
+        )
+        .await?;
+        if job.total_cap_reached(total_msat) {
+            info!(
+                "{}/{}: rebalanced {}sats of max_total_sat {}sats. Stopping job.",
+                task.chan_id,
+                task.task_id,
+                total_msat / 1_000,
+                max_total_sat
+            );
+            channel_jobstate_update(job_states, task, &JobMessage::TotalCapReached, false, true)?;
+            return Ok(Some(false));
+        }
+    }
+    let min_funded_msat = config.min_funded_sats.value * 1_000;
+    if channel_funding_pending(peer_channels, &task.chan_id, min_funded_msat) {
+        info!(
+            "{}/{}: channel is not funded yet. Taking a break...",
+            task.chan_id, task.task_id
+        );
+        channel_jobstate_update(
+            job_states.clone(),
+            task,
+            &JobMessage::ChanNotFunded,
+            true,
+            false,
+        )?;
+        my_sleep(60, job_states.clone(), task).await;
+        return Ok(Some(true));
+    }
+    if let Some(reason) = job_stop_reason(peer_channels, &task.chan_id, job) {
+        match peer_channels.get(&task.chan_id) {
+            Some(_) if job.peer.is_some() => warn!(
+                "{}/{}: no usable channel left with the peer. Stopping job.",
                 task.chan_id, task.task_id
+            ),
+            Some(chan) => warn!(
+                "{}/{}: channel is in state {:?}. Stopping job.",
+                task.chan_id, task.task_id, chan.state
+            ),
+            None => warn!(
+                "{}/{}: not found. Stopping Job.",
+                task.chan_id, task.task_id
+            ),
+        }
+        channel_jobstate_update(job_states, task, &reason, false, true)?;
+        return Ok(Some(false));
+    }
+    let job_channels = get_job_channels(peer_channels, &task.chan_id, job, min_funded_msat);
+    if job_channels.is_empty() {
+        debug!(
+            "{}/{}: no funded channel with complete balance fields. Taking a break...",
+            task.chan_id, task.task_id
+        );
+        channel_jobstate_update(
+            job_states.clone(),
+            task,
+            &JobMessage::ChanNotNormal,
+            true,
+            false,
+        )?;
+        my_sleep(60, job_states.clone(), task).await;
+        return Ok(Some(true));
+    }
+    if job.outside_schedule(Utc::now()) {
+        info!(
+            "{}/{}: outside of schedule {}. Taking a break...",
+            task.chan_id,
+            task.task_id,
+            job.schedule.as_deref().unwrap_or_default()
+        );
+        channel_jobstate_update(
+            job_states.clone(),
+            task,
+            &JobMessage::OutsideSchedule,
+            true,
+            false,
+        )?;
+        my_sleep(60, job_states.clone(), task).await;
+        return Ok(Some(true));
+    }
+    if config.min_forward_volume.value > 0 {
+        let low_utilization =
+            plugin
+                .state()
+                .forward_volumes
+                .lock()
+                .as_ref()
+                .is_some_and(|volumes| {
+                    is_low_utilization(
+                        volumes,
+                        &job_channels,
+                        config.min_forward_volume.value.saturating_mul(1_000),
+                    )
+                });
+        if low_utilization {
+            info!(
+                "{}/{}: forwarded less than {}sats recently. Taking a break...",
+                task.chan_id, task.task_id, config.min_forward_volume.value
             );
             channel_jobstate_update(
                 job_states.clone(),
                 task,
-                &JobMessage::ChanNotNormal,
-                false,
+                &JobMessage::LowUtilization,
                 true,
+                false,
             )?;
-            Ok(Some(false))
+            my_sleep(600, job_states.clone(), task).await;
+            return Ok(Some(true));
         }
-    } else {
-        warn!(
-            "{}/{}: not found. Stopping Job.",
+    }
+    let locally_disabled = {
+        let graph = plugin.state().graph.lock();
+        job_channels
+            .iter()
+            .all(|c| channel_locally_disabled(&graph, &config.pubkey, &c.short_channel_id.unwrap()))
+    };
+    let group_turn = match &job.group {
+        Some(group) => {
+            let jobs = plugin.state().loaded_jobs.lock().clone();
+            let ready = group_members_ready(&plugin.state().job_state.lock());
+            group_member_to_work(group, &jobs, peer_channels, &ready, min_funded_msat)
+        }
+        None => Some(task.chan_id),
+    };
+    if nothing_to_do(job, &job_channels, &task.chan_id) {
+        info!(
+            "{}/{}: already balanced. Taking a break...",
             task.chan_id, task.task_id
         );
+        channel_jobstate_update(job_states.clone(), task, &JobMessage::Balanced, true, false)?;
+        my_sleep(600, job_states.clone(), task).await;
+        Ok(Some(true))
+    } else if group_turn.is_some_and(|scid| scid != task.chan_id) {
+        info!(
+            "{}/{}: {} is further from the group target. Taking a break...",
+            task.chan_id,
+            task.task_id,
+            group_turn.unwrap()
+        );
         channel_jobstate_update(
             job_states.clone(),
             task,
-            &JobMessage::ChanNotNormal,
+            &JobMessage::GroupWaiting,
+            true,
             false,
+        )?;
+        my_sleep(60, job_states.clone(), task).await;
+        Ok(Some(true))
+    } else if job_channels
+        .iter()
+        .all(|c| is_htlc_capped(c, config.max_htlc_count.value))
+    {
+        info!(
+            "{}/{}: already more than {} pending htlcs. Taking a break...",
+            task.chan_id, task.task_id, config.max_htlc_count.value
+        );
+        channel_jobstate_update(
+            job_states.clone(),
+            task,
+            &JobMessage::HTLCcapped,
             true,
+            false,
         )?;
-        Ok(Some(false))
+        my_sleep(10, job_states.clone(), task).await;
+        Ok(Some(true))
+    } else {
+        match peer_channels.values().find(|x| x.peer_id == other_peer) {
+            Some(p) => {
+                if !p.peer_connected {
+                    info!(
+                        "{}/{}: not connected. Taking a break...",
+                        task.chan_id, task.task_id
+                    );
+                    channel_jobstate_update(
+                        job_states.clone(),
+                        task,
+                        &JobMessage::Disconnected,
+                        true,
+                        false,
+                    )?;
+                    my_sleep(60, job_states.clone(), task).await;
+                    Ok(Some(true))
+                } else if locally_disabled {
+                    info!(
+                        "{}/{}: channel disabled on our side. Taking a break...",
+                        task.chan_id, task.task_id
+                    );
+                    channel_jobstate_update(
+                        job_states.clone(),
+                        task,
+                        &JobMessage::ChanDisabled,
+                        true,
+                        false,
+                    )?;
+                    my_sleep(60, job_states.clone(), task).await;
+                    Ok(Some(true))
+                } else if tempbans.contains_key(&task.chan_id) {
+                    info!(
+                        "{}/{}: Job peer not ready. Taking a break...",
+                        task.chan_id, task.task_id
+                    );
+                    channel_jobstate_update(
+                        job_states.clone(),
+                        task,
+                        &JobMessage::PeerNotReady,
+                        true,
+                        false,
+                    )?;
+                    my_sleep(20, job_states.clone(), task).await;
+                    Ok(Some(true))
+                } else {
+                    Ok(None)
+                }
+            }
+            None => {
+                channel_jobstate_update(
+                    job_states.clone(),
+                    task,
+                    &JobMessage::PeerNotFound,
+                    false,
+                    true,
+                )?;
+                warn!(
+                    "{}/{}: peer not found. Stopping job.",
+                    task.chan_id, task.task_id
+                );
+                Ok(Some(false))
+            }
+        }
     }
 }
 
-fn build_candidatelist(
+pub fn build_candidatelist(
     peer_channels: &HashMap<ShortChannelId, ListpeerchannelsChannels>,
     job: &Job,
     graph: &LnGraph,
@@ -654,87 +1347,311 @@ fn build_candidatelist(
     custom_candidates: Option<&Vec<ShortChannelId>>,
     blockheight: u32,
 ) -> Vec<ShortChannelId> {
+    let (mut candidatelist, _excluded) = candidate_exclusions(
+        peer_channels,
+        job,
+        graph,
+        tempbans,
+        config,
+        custom_candidates,
+        blockheight,
+    );
+
+    if job.sat_direction == SatDirection::Pull {
+        if config.candidate_order.value == CANDIDATE_ORDER_LARGEST_FIRST {
+            sort_candidates_largest_first(&mut candidatelist, peer_channels);
+        } else if config.candidate_order.value == CANDIDATE_ORDER_MOST_OVERFULL {
+            sort_candidates_most_overfull(&mut candidatelist, peer_channels);
+        }
+    }
+
+    candidatelist
+}
+
+// the eligible candidates and why every other channel was left out
+pub fn candidate_exclusions(
+    peer_channels: &HashMap<ShortChannelId, ListpeerchannelsChannels>,
+    job: &Job,
+    graph: &LnGraph,
+    tempbans: &HashMap<ShortChannelId, u64>,
+    config: &Config,
+    custom_candidates: Option<&Vec<ShortChannelId>>,
+    blockheight: u32,
+) -> (Vec<ShortChannelId>, Vec<CandidateExclusion>) {
     let mut candidatelist = Vec::<ShortChannelId>::new();
+    let mut excluded = Vec::new();
 
-    let depleteuptopercent = match job.depleteuptopercent {
-        Some(dp) => dp,
-        None => config.depleteuptopercent.value,
-    };
-    let depleteuptoamount = match job.depleteuptoamount {
-        Some(dp) => dp,
-        None => config.depleteuptoamount.value,
-    };
+    let effective = EffectiveJobConfig::new(job, config);
+    let depleteuptopercent = effective.depleteuptopercent;
+    let depleteuptoamount = effective.depleteuptoamount;
+    let peer_htlc_counts = get_peer_htlc_counts(peer_channels);
+    let peer_htlc_value_usage = get_peer_htlc_value_usage(peer_channels, job.sat_direction);
 
     for channel in peer_channels.values() {
         if let Some(scid) = channel.short_channel_id {
-            if matches!(
+            let reason = if !matches!(
                 channel.state,
                 ListpeerchannelsChannelsState::CHANNELD_NORMAL
                     | ListpeerchannelsChannelsState::CHANNELD_AWAITING_SPLICE
-            ) && channel.peer_connected
-                && match custom_candidates {
-                    Some(c) => c.iter().any(|c| *c == scid),
-                    None => true,
-                }
-                && scid.block() <= blockheight - config.candidates_min_age.value
-            {
-                let chan_in_ppm = match get_remote_feeppm_effective(
+            ) {
+                Some("channel not normal")
+            } else if !channel.peer_connected {
+                Some("peer disconnected")
+            } else if !match custom_candidates {
+                Some(c) => c.contains(&scid),
+                None => true,
+            } {
+                Some("not in candidatelist")
+            } else if scid.block() > blockheight - config.candidates_min_age.value {
+                Some("younger than candidates-min-age")
+            } else {
+                candidate_exclusion_reason(
                     channel,
-                    graph,
                     scid,
-                    job.amount_msat,
-                    &config.version,
-                ) {
-                    Ok(o) => o,
-                    Err(_) => continue,
-                };
-
-                let to_us_msat = Amount::msat(&channel.to_us_msat.unwrap());
-                let total_msat = Amount::msat(&channel.total_msat.unwrap());
-                let chan_out_ppm = feeppm_effective(
-                    channel.fee_proportional_millionths.unwrap(),
-                    Amount::msat(&channel.fee_base_msat.unwrap()) as u32,
-                    job.amount_msat,
-                );
-
-                if match job.sat_direction {
-                    SatDirection::Pull => {
-                        to_us_msat
-                            > max(
-                                job.amount_msat + 10_000_000,
-                                min(
-                                    (depleteuptopercent * total_msat as f64) as u64,
-                                    depleteuptoamount,
-                                ),
-                            )
-                            && match job.outppm {
-                                Some(out) => chan_out_ppm <= out,
-                                None => true,
-                            }
-                    }
-                    SatDirection::Push => {
-                        total_msat - to_us_msat
-                            > max(
-                                job.amount_msat + 10_000_000,
-                                min(
-                                    (depleteuptopercent * total_msat as f64) as u64,
-                                    depleteuptoamount,
-                                ),
-                            )
-                            && match job.outppm {
-                                Some(out) => chan_out_ppm >= out,
-                                None => true,
-                            }
-                            && job.maxppm as u64 >= chan_in_ppm
-                    }
-                } && !tempbans.contains_key(&scid)
-                    && get_total_htlc_count(channel) <= config.max_htlc_count.value
-                {
-                    candidatelist.push(scid);
-                }
+                    job,
+                    graph,
+                    tempbans,
+                    config,
+                    &peer_htlc_counts,
+                    &peer_htlc_value_usage,
+                    depleteuptopercent,
+                    depleteuptoamount,
+                )
+            };
+            match reason {
+                Some(r) => excluded.push(CandidateExclusion {
+                    short_channel_id: scid,
+                    reason: r.to_string(),
+                }),
+                None => candidatelist.push(scid),
             }
         }
     }
 
-    candidatelist
+    (candidatelist, excluded)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn candidate_exclusion_reason(
+    channel: &ListpeerchannelsChannels,
+    scid: ShortChannelId,
+    job: &Job,
+    graph: &LnGraph,
+    tempbans: &HashMap<ShortChannelId, u64>,
+    config: &Config,
+    peer_htlc_counts: &HashMap<PublicKey, u64>,
+    peer_htlc_value_usage: &HashMap<PublicKey, f64>,
+    depleteuptopercent: f64,
+    depleteuptoamount: u64,
+) -> Option<&'static str> {
+    let chan_in_ppm =
+        match get_remote_feeppm_effective(channel, graph, scid, job.amount_msat, &config.version) {
+            Ok(o) => o,
+            Err(_) => return Some("no remote fee in graph"),
+        };
+
+    let to_us_msat = Amount::msat(&channel.to_us_msat.unwrap());
+    let total_msat = Amount::msat(&channel.total_msat.unwrap());
+    let chan_out_ppm = feeppm_effective(
+        channel.fee_proportional_millionths.unwrap(),
+        Amount::msat(&channel.fee_base_msat.unwrap()) as u32,
+        job.amount_msat,
+    );
+    let depletable_msat = match job.sat_direction {
+        SatDirection::Pull => to_us_msat,
+        SatDirection::Push => total_msat - to_us_msat,
+    };
+
+    if depletable_msat
+        <= max(
+            job.amount_msat + 10_000_000,
+            min(
+                (depleteuptopercent * total_msat as f64) as u64,
+                depleteuptoamount,
+            ),
+        )
+    {
+        return Some("not enough liquidity");
+    }
+    if let Some(out) = job.outppm {
+        match job.sat_direction {
+            SatDirection::Pull if chan_out_ppm > out => return Some("fee above outppm"),
+            SatDirection::Push if chan_out_ppm < out => return Some("fee below outppm"),
+            _ => (),
+        }
+    }
+    if job.sat_direction == SatDirection::Push
+        && chan_in_ppm > job.maxppm_for(SatDirection::Push) as u64
+    {
+        return Some("remote fee above maxppm");
+    }
+    if tempbans.contains_key(&scid) {
+        return Some("tempbanned");
+    }
+    if job.peer == Some(channel.peer_id) {
+        return Some("peer of the job");
+    }
+    if is_htlc_capped(channel, config.max_htlc_count.value) {
+        return Some("too many pending htlcs");
+    }
+    if config.max_peer_htlc_count.value > 0
+        && peer_htlc_counts.get(&channel.peer_id).copied().unwrap_or(0)
+            > config.max_peer_htlc_count.value
+    {
+        return Some("too many pending htlcs with peer");
+    }
+    if config.max_peer_htlc_value.value > 0
+        && peer_htlc_value_usage
+            .get(&channel.peer_id)
+            .copied()
+            .unwrap_or(0.0)
+            * 100.0
+            >= config.max_peer_htlc_value.value as f64
+    {
+        return Some("too much pending htlc value with peer");
+    }
+    None
+}
+
+// bounds the work of one iteration on nodes with lots of channels, the chosen
+// candidates keep their order from the candidatelist
+pub fn cap_candidates(
+    candidatelist: &mut Vec<ShortChannelId>,
+    max_candidates: u64,
+    strategy: &str,
+    sat_direction: SatDirection,
+    peer_channels: &HashMap<ShortChannelId, ListpeerchannelsChannels>,
+    candidate_feeppms: &HashMap<ShortChannelId, u64>,
+    rotation: &mut usize,
+) {
+    let max_candidates = max_candidates as usize;
+    if max_candidates == 0 || candidatelist.len() <= max_candidates {
+        return;
+    }
+    let mut ranked = candidatelist.clone();
+    let chosen: HashSet<ShortChannelId> = if strategy == CANDIDATES_CAP_MOST_IMBALANCED {
+        sort_candidates_most_overfull(&mut ranked, peer_channels);
+        if sat_direction == SatDirection::Push {
+            ranked.reverse();
+        }
+        ranked.into_iter().take(max_candidates).collect()
+    } else if strategy == CANDIDATES_CAP_CHEAPEST_HISTORY {
+        ranked.sort_by_key(|scid| candidate_feeppms.get(scid).copied().unwrap_or(u64::MAX));
+        ranked.into_iter().take(max_candidates).collect()
+    } else {
+        ranked.sort();
+        let start = *rotation % ranked.len();
+        *rotation = (start + max_candidates) % ranked.len();
+        ranked
+            .into_iter()
+            .cycle()
+            .skip(start)
+            .take(max_candidates)
+            .collect()
+    };
+    candidatelist.retain(|c| chosen.contains(c));
+}
+
+pub fn sort_candidates_largest_first(
+    candidatelist: &mut [ShortChannelId],
+    peer_channels: &HashMap<ShortChannelId, ListpeerchannelsChannels>,
+) {
+    candidatelist.sort_by_key(|scid| {
+        std::cmp::Reverse(
+            peer_channels
+                .get(scid)
+                .and_then(|c| c.to_us_msat)
+                .map(|a| Amount::msat(&a))
+                .unwrap_or(0),
+        )
+    });
+}
+
+pub fn sort_candidates_most_overfull(
+    candidatelist: &mut [ShortChannelId],
+    peer_channels: &HashMap<ShortChannelId, ListpeerchannelsChannels>,
+) {
+    let fill = |scid: &ShortChannelId| {
+        peer_channels
+            .get(scid)
+            .filter(|c| has_balance_fields(c) && Amount::msat(&c.total_msat.unwrap()) > 0)
+            .map(|c| {
+                Amount::msat(&c.to_us_msat.unwrap()) as f64
+                    / Amount::msat(&c.total_msat.unwrap()) as f64
+            })
+            .unwrap_or(0.0)
+    };
+    candidatelist.sort_by(|a, b| fill(b).total_cmp(&fill(a)));
+}
+
+// how far each candidate is past the balance it should keep, using the target
+// of its own job if it has one, most over target first
+pub fn sort_candidates_most_over_target(
+    candidatelist: &mut [ShortChannelId],
+    sat_direction: SatDirection,
+    peer_channels: &HashMap<ShortChannelId, ListpeerchannelsChannels>,
+    jobs: &BTreeMap<ShortChannelId, Job>,
+) {
+    let over_target = |scid: &ShortChannelId| {
+        let our_target = match jobs.get(scid) {
+            Some(j) => match j.sat_direction {
+                SatDirection::Pull => j.target.unwrap_or(0.5),
+                SatDirection::Push => 1.0 - j.target.unwrap_or(0.5),
+            },
+            None => 0.5,
+        };
+        peer_channels
+            .get(scid)
+            .filter(|c| has_balance_fields(c))
+            .map(|c| {
+                let our_ratio = Amount::msat(&c.to_us_msat.unwrap()) as f64
+                    / Amount::msat(&c.total_msat.unwrap()) as f64;
+                match sat_direction {
+                    SatDirection::Pull => our_ratio - our_target,
+                    SatDirection::Push => our_target - our_ratio,
+                }
+            })
+            .unwrap_or(f64::MIN)
+    };
+    candidatelist.sort_by(|a, b| over_target(b).total_cmp(&over_target(a)));
+}
+
+pub fn sourceable_liquidity(
+    peer_channels: &HashMap<ShortChannelId, ListpeerchannelsChannels>,
+    candidatelist: &[ShortChannelId],
+    job: &Job,
+    config: &Config,
+) -> Vec<(ShortChannelId, u64)> {
+    let effective = EffectiveJobConfig::new(job, config);
+    let mut sourceable = candidatelist
+        .iter()
+        .filter_map(|scid| peer_channels.get(scid).map(|c| (scid, c)))
+        .map(|(scid, channel)| {
+            let to_us_msat = Amount::msat(&channel.to_us_msat.unwrap());
+            let total_msat = Amount::msat(&channel.total_msat.unwrap());
+            let floor_msat = min(
+                (effective.depleteuptopercent * total_msat as f64) as u64,
+                effective.depleteuptoamount,
+            );
+            let msat = match job.sat_direction {
+                SatDirection::Pull => min(
+                    channel
+                        .spendable_msat
+                        .map(|a| Amount::msat(&a))
+                        .unwrap_or(0),
+                    to_us_msat.saturating_sub(floor_msat),
+                ),
+                SatDirection::Push => min(
+                    channel
+                        .receivable_msat
+                        .map(|a| Amount::msat(&a))
+                        .unwrap_or(0),
+                    (total_msat - to_us_msat).saturating_sub(floor_msat),
+                ),
+            };
+            (*scid, msat)
+        })
+        .collect::<Vec<_>>();
+    sourceable.sort_by_key(|(_, msat)| std::cmp::Reverse(*msat));
+    sourceable
 }
@@ -1,58 +1,474 @@
 use std::cmp::max;
 use std::time::{SystemTime, UNIX_EPOCH};
-use std::{collections::HashMap, path::Path, str::FromStr};
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
 
 use anyhow::{anyhow, Error};
 use chrono::Local;
 use chrono::TimeZone;
+use chrono::Utc;
 use cln_plugin::Plugin;
 
-use cln_rpc::model::responses::ListpeerchannelsChannels;
-use cln_rpc::primitives::{PublicKey, ShortChannelId};
-use log::{debug, info};
+use cln_rpc::model::requests::{ListforwardsRequest, ListforwardsStatus};
+use cln_rpc::model::responses::{
+    ListforwardsForwards, ListforwardsForwardsStatus, ListpeerchannelsChannels,
+};
+use cln_rpc::primitives::{Amount, PublicKey, ShortChannelId};
+use cln_rpc::ClnRpc;
+use log::{debug, info, warn};
 use num_format::{Locale, ToFormattedString};
+use parking_lot::Mutex;
 use serde_json::json;
 use sling::{
-    ChannelPartnerStats, FailureReasonCount, FailuresInTimeWindow, PeerPartnerStats, SlingStats,
-    SuccessesInTimeWindow,
+    has_balance_fields, ChannelPartnerStats, ChannelRoi, ExceptImpact, FailureReasonCount,
+    FailuresInTimeWindow, FeeComparison, Job, JobStateStats, MaxppmSuggestion, PeerPartnerStats,
+    RollupStats, Rollups, SatDirection, SlingStats, StatsRollup, SuccessesInTimeWindow,
 };
 use tabled::Table;
 
-use crate::model::{FailureReb, SuccessReb};
-use crate::model::{JobState, PluginState, StatSummary, NO_ALIAS_SET, PLUGIN_NAME};
-use crate::util::{get_all_normal_channels_from_listpeerchannels, refresh_joblists};
+use crate::model::{FailureReb, LnGraph, StatsCache, SuccessReb};
+use crate::model::{
+    JobState, PluginState, StatSummary, FAILURES_SUFFIX, NO_ALIAS_SET, PLUGIN_NAME, ROLLUP_SUFFIX,
+    SUCCESSES_SUFFIX,
+};
+use crate::parse::parse_scid;
+use crate::rpc_sling::{slinggo, slingstop};
+use crate::util::{
+    get_all_normal_channels_from_listpeerchannels, get_job_channels, read_jobs, refresh_joblists,
+};
+
+const OVERPAYING_FACTOR: f64 = 2.0;
 
-pub async fn slingstats(
+type StatsArgs = (Option<ShortChannelId>, Option<String>, Option<String>);
+const SUGGEST_MAXPPM_PERCENTILE: f64 = 0.75;
+const SUGGEST_MAXPPM_MARGIN_PERCENT: u64 = 10;
+
+pub async fn slingclearstats(
     plugin: Plugin<PluginState>,
     args: serde_json::Value,
 ) -> Result<serde_json::Value, Error> {
     let sling_dir = Path::new(&plugin.configuration().lightning_dir).join(PLUGIN_NAME);
 
-    let input_array = match args {
-        serde_json::Value::Array(a) => a,
-        e => {
+    let scid = match args {
+        serde_json::Value::Array(a) if a.len() == 1 => match a.first().unwrap() {
+            serde_json::Value::String(s) if s.eq("all") => None,
+            serde_json::Value::String(s) => Some(parse_scid(s)?),
+            _ => return Err(anyhow!("invalid short_channel_id")),
+        },
+        _ => {
             return Err(anyhow!(
-                "sling-stats: invalid arguments, expected array, got: {}",
-                e
+                "Please provide exactly one short_channel_id or `all`"
             ))
         }
     };
-    if input_array.len() > 1 {
-        return Err(anyhow!(
-            "Please provide exactly one short_channel_id or nothing for a summary"
-        ));
-    }
 
-    let stats_delete_successes_age = plugin
+    let running_jobs = plugin
         .state()
-        .config
+        .job_state
         .lock()
-        .stats_delete_successes_age
-        .value;
-    let stats_delete_failures_age = plugin.state().config.lock().stats_delete_failures_age.value;
+        .iter()
+        .filter(|(chan_id, jobstates)| {
+            (scid.is_none() || scid == Some(**chan_id)) && jobstates.iter().any(|j| j.is_active())
+        })
+        .map(|(chan_id, _)| *chan_id)
+        .collect::<Vec<ShortChannelId>>();
+    for chan_id in &running_jobs {
+        slingstop(plugin.clone(), json!([chan_id.to_string()])).await?;
+    }
+
+    let stats_dirs = match scid {
+        Some(s) => vec![plugin.state().stats_dir(&sling_dir, &s)],
+        None => {
+            let mut dirs = plugin
+                .state()
+                .loaded_jobs
+                .lock()
+                .values()
+                .map(|j| j.stats_dir(&sling_dir))
+                .collect::<Vec<PathBuf>>();
+            dirs.push(sling_dir.clone());
+            dirs.sort();
+            dirs.dedup();
+            dirs
+        }
+    };
+    let mut removed_files = 0;
+    for stats_dir in &stats_dirs {
+        removed_files += remove_stats_files(stats_dir, scid.as_ref()).await?;
+    }
+    info!(
+        "Cleared stats for {}: removed {} files",
+        scid.map_or("all channels".to_string(), |s| s.to_string()),
+        removed_files
+    );
+
+    for chan_id in &running_jobs {
+        slinggo(plugin.clone(), json!([chan_id.to_string()])).await?;
+    }
+
+    Ok(json!({ "removed_files": removed_files, "restarted_jobs": running_jobs.len() }))
+}
+
+// rebalances exported from another rebalancer as a json array, json lines or csv with a header
+pub fn parse_imported_rebalances(contents: &str) -> (Vec<(ShortChannelId, SuccessReb)>, usize) {
+    let rows: Vec<Result<serde_json::Map<String, serde_json::Value>, String>> =
+        match contents.trim_start().chars().next() {
+            Some('[') => match serde_json::from_str::<Vec<serde_json::Value>>(contents) {
+                Ok(values) => values
+                    .into_iter()
+                    .map(|v| match v {
+                        serde_json::Value::Object(o) => Ok(o),
+                        other => Err(format!("not an object: {}", other)),
+                    })
+                    .collect(),
+                Err(e) => vec![Err(e.to_string())],
+            },
+            Some('{') => contents
+                .lines()
+                .filter(|l| !l.trim().is_empty())
+                .map(|l| serde_json::from_str(l).map_err(|e| e.to_string()))
+                .collect(),
+            _ => {
+                let mut lines = contents.lines().filter(|l| !l.trim().is_empty());
+                let header = lines
+                    .next()
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|h| h.trim().to_string())
+                    .collect::<Vec<_>>();
+                lines
+                    .map(|l| {
+                        let fields = l.split(',').map(|f| f.trim()).collect::<Vec<_>>();
+                        if fields.len() != header.len() {
+                            return Err(format!("expected {} columns: {}", header.len(), l));
+                        }
+                        Ok(header
+                            .iter()
+                            .zip(fields)
+                            .map(|(h, f)| (h.clone(), json!(f)))
+                            .collect())
+                    })
+                    .collect()
+            }
+        };
+
+    let mut records = Vec::new();
+    let mut skipped = 0;
+    for (i, row) in rows.into_iter().enumerate() {
+        match row.and_then(|r| imported_rebalance(&r)) {
+            Ok(o) => records.push(o),
+            Err(e) => {
+                warn!("import: skipping row {}: {}", i + 1, e);
+                skipped += 1;
+            }
+        }
+    }
+    (records, skipped)
+}
+
+fn imported_rebalance(
+    row: &serde_json::Map<String, serde_json::Value>,
+) -> Result<(ShortChannelId, SuccessReb), String> {
+    let field = |name: &str| -> Result<String, String> {
+        match row.get(name) {
+            Some(serde_json::Value::String(s)) if !s.is_empty() => Ok(s.clone()),
+            Some(serde_json::Value::Number(n)) => Ok(n.to_string()),
+            _ => Err(format!("missing {}", name)),
+        }
+    };
+    let number = |name: &str| -> Result<u64, String> {
+        field(name)?
+            .parse::<u64>()
+            .map_err(|e| format!("invalid {}: {}", name, e))
+    };
+    let scid = parse_scid(&field("scid")?).map_err(|e| e.to_string())?;
+    let channel_partner = match field("partner") {
+        Ok(partner) => Some(parse_scid(&partner).map_err(|e| e.to_string())?),
+        Err(_) => None,
+    };
+    let amount_msat = number("amount_msat")?;
+    let fee_msat = number("fee_msat")?;
+    let completed_at = number("timestamp")?;
+    let hops = match row.get("hops") {
+        Some(_) => u8::try_from(number("hops")?).map_err(|e| format!("invalid hops: {}", e))?,
+        None => 2,
+    };
+    if amount_msat == 0 || completed_at == 0 || hops < 2 {
+        return Err("amount_msat and timestamp must be > 0, hops >= 2".to_string());
+    }
+    let fee_ppm = u32::try_from(fee_msat as u128 * 1_000_000 / amount_msat as u128)
+        .map_err(|_| "fee_msat too high for amount_msat".to_string())?;
+    Ok((
+        scid,
+        SuccessReb {
+            amount_msat,
+            fee_ppm,
+            fee_msat,
+            channel_partner,
+            hops,
+            completed_at,
+        },
+    ))
+}
+
+// appends the records to the per-channel success files, records already there are skipped
+pub async fn import_rebalances(
+    sling_dir: &Path,
+    records: Vec<(ShortChannelId, SuccessReb)>,
+) -> Result<(usize, usize), Error> {
+    let mut existing: HashMap<ShortChannelId, Vec<SuccessReb>> = HashMap::new();
+    let mut imported = 0;
+    let mut duplicates = 0;
+    for (scid, record) in records {
+        let chan_successes = match existing.entry(scid) {
+            Entry::Occupied(o) => o.into_mut(),
+            Entry::Vacant(v) => v.insert(
+                SuccessReb::read_from_file(sling_dir, &scid)
+                    .await
+                    .unwrap_or_default(),
+            ),
+        };
+        if chan_successes.iter().any(|s| {
+            s.completed_at == record.completed_at
+                && s.amount_msat == record.amount_msat
+                && s.channel_partner == record.channel_partner
+        }) {
+            duplicates += 1;
+            continue;
+        }
+        record.write_to_file(scid, sling_dir).await?;
+        chan_successes.push(record);
+        imported += 1;
+    }
+    Ok((imported, duplicates))
+}
+
+pub async fn slingimporthistory(
+    plugin: Plugin<PluginState>,
+    args: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let path = match args {
+        serde_json::Value::Array(a) if a.len() == 1 => match a.first().unwrap() {
+            serde_json::Value::String(s) => s.clone(),
+            o => return Err(anyhow!("not a valid path: {}", o)),
+        },
+        _ => return Err(anyhow!("Please provide the path of the file to import")),
+    };
+    let sling_dir = Path::new(&plugin.configuration().lightning_dir).join(PLUGIN_NAME);
+    let contents = tokio::fs::read_to_string(&path).await?;
+    let (records, malformed) = parse_imported_rebalances(&contents);
+    let mut records_by_dir: HashMap<PathBuf, Vec<(ShortChannelId, SuccessReb)>> = HashMap::new();
+    for (scid, record) in records {
+        records_by_dir
+            .entry(plugin.state().stats_dir(&sling_dir, &scid))
+            .or_default()
+            .push((scid, record));
+    }
+    let mut imported = 0;
+    let mut duplicates = 0;
+    for (stats_dir, records) in records_by_dir {
+        let (i, d) = import_rebalances(&stats_dir, records).await?;
+        imported += i;
+        duplicates += d;
+    }
+    info!(
+        "import: {} rebalances imported from {}, {} malformed and {} duplicates skipped",
+        imported, path, malformed, duplicates
+    );
+    Ok(json!({
+        "imported": imported,
+        "skipped_malformed": malformed,
+        "skipped_duplicates": duplicates,
+    }))
+}
+
+pub async fn remove_stats_files(
+    sling_dir: &Path,
+    scid: Option<&ShortChannelId>,
+) -> Result<u64, Error> {
+    let mut stats_files = Vec::new();
+    match scid {
+        Some(s) => {
+            stats_files.push(sling_dir.join(s.to_string() + SUCCESSES_SUFFIX));
+            stats_files.push(sling_dir.join(s.to_string() + FAILURES_SUFFIX));
+            stats_files.push(sling_dir.join(s.to_string() + ROLLUP_SUFFIX));
+        }
+        None => {
+            let mut entries = tokio::fs::read_dir(sling_dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let file_name = entry.file_name().to_string_lossy().to_string();
+                if file_name.ends_with(SUCCESSES_SUFFIX)
+                    || file_name.ends_with(FAILURES_SUFFIX)
+                    || file_name.ends_with(ROLLUP_SUFFIX)
+                {
+                    stats_files.push(entry.path());
+                }
+            }
+        }
+    }
+
+    let mut removed_files = 0;
+    for file in stats_files {
+        match tokio::fs::remove_file(&file).await {
+            Ok(()) => removed_files += 1,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => return Err(anyhow!("Could not remove {}: {}", file.display(), e)),
+        }
+    }
+    Ok(removed_files)
+}
+
+// returns the records to keep and the ones pruned by age and then by size
+pub fn prune_records<T>(
+    records: Vec<T>,
+    timestamp: impl Fn(&T) -> u64,
+    min_timestamp: Option<u64>,
+    max_size: u64,
+) -> (Vec<T>, Vec<T>) {
+    let (mut kept, mut pruned): (Vec<T>, Vec<T>) = records
+        .into_iter()
+        .partition(|r| min_timestamp.is_none_or(|min| timestamp(r) >= min));
+    if max_size > 0 && kept.len() as u64 > max_size {
+        let rest = kept.split_off(kept.len() - max_size as usize);
+        pruned.append(&mut kept);
+        kept = rest;
+    }
+    (kept, pruned)
+}
+
+pub fn retain_min_amount<T>(records: &mut Vec<T>, amount: impl Fn(&T) -> u64, min_msat: u64) {
+    if min_msat > 0 {
+        records.retain(|r| amount(r) >= min_msat);
+    }
+}
+
+pub fn add_to_rollup(rollup: &mut StatsRollup, successes: &[SuccessReb], failures: &[FailureReb]) {
+    for success in successes {
+        rollup.add_success(success.amount_msat, success.fee_msat);
+    }
+    for failure in failures {
+        rollup.add_failure(failure.amount_msat);
+    }
+}
+
+pub async fn read_rollup(sling_dir: &Path, scid: &ShortChannelId) -> Result<StatsRollup, Error> {
+    let rollup_file = sling_dir.join(scid.to_string() + ROLLUP_SUFFIX);
+    match tokio::fs::read_to_string(&rollup_file).await {
+        Ok(content) => Ok(serde_json::from_str(&content)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(StatsRollup::default()),
+        Err(e) => Err(anyhow!("Could not read {}: {}", rollup_file.display(), e)),
+    }
+}
+
+// everything ever rebalanced by the job, pruned stats included
+pub async fn lifetime_rebalanced_msat(
+    sling_dir: &Path,
+    scid: &ShortChannelId,
+) -> Result<u64, Error> {
+    let successes = match SuccessReb::read_from_file(sling_dir, scid).await {
+        Ok(o) => o,
+        Err(e) => {
+            debug!("{}: Could not get any successes: {}", scid, e);
+            Vec::new()
+        }
+    };
+    Ok(read_rollup(sling_dir, scid).await?.total_amount_msat
+        + successes.iter().map(|s| s.amount_msat).sum::<u64>())
+}
+
+async fn file_fingerprint(path: &Path) -> Option<(u64, SystemTime)> {
+    let metadata = tokio::fs::metadata(path).await.ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+// lifetime_rebalanced_msat, but the stats files are only read again if
+// they changed or the channel was dropped from the cache
+pub async fn cached_lifetime_rebalanced_msat(
+    cache: &Mutex<StatsCache>,
+    max_entries: usize,
+    sling_dir: &Path,
+    scid: &ShortChannelId,
+) -> Result<u64, Error> {
+    let key = sling_dir.join(scid.to_string() + SUCCESSES_SUFFIX);
+    let fingerprint = [
+        file_fingerprint(&key).await,
+        file_fingerprint(&sling_dir.join(scid.to_string() + ROLLUP_SUFFIX)).await,
+    ];
+    if let Some(lifetime_msat) = cache.lock().get(&key, &fingerprint) {
+        return Ok(lifetime_msat);
+    }
+    let lifetime_msat = lifetime_rebalanced_msat(sling_dir, scid).await?;
+    cache
+        .lock()
+        .insert(key, fingerprint, lifetime_msat, max_entries);
+    Ok(lifetime_msat)
+}
+
+pub async fn write_rollup(
+    sling_dir: &Path,
+    scid: &ShortChannelId,
+    rollup: &StatsRollup,
+) -> Result<(), Error> {
+    tokio::fs::write(
+        sling_dir.join(scid.to_string() + ROLLUP_SUFFIX),
+        serde_json::to_string(rollup)?,
+    )
+    .await?;
+    Ok(())
+}
+
+pub async fn slingstats(
+    plugin: Plugin<PluginState>,
+    args: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let sling_dir = Path::new(&plugin.configuration().lightning_dir).join(PLUGIN_NAME);
+
+    if let serde_json::Value::Array(a) = &args {
+        if a.first().and_then(|f| f.as_str()) == Some("rollup") {
+            return stats_rollups(plugin, &sling_dir).await;
+        }
+    }
+
+    let (scid, sort, filter, min_amount_sat) = match args {
+        serde_json::Value::Array(a) if a.len() <= 2 => {
+            let (scid, sort, filter) = parse_stats_array_args(&a)?;
+            (scid, sort, filter, None)
+        }
+        serde_json::Value::Object(o) => (
+            match o.get("scid").and_then(|s| s.as_str()) {
+                Some(s) => Some(parse_scid(s)?),
+                None => None,
+            },
+            o.get("sort")
+                .and_then(|s| s.as_str())
+                .map(|s| s.to_string()),
+            o.get("filter")
+                .and_then(|f| f.as_str())
+                .map(|f| f.to_string()),
+            match o.get("min_amount_sat") {
+                Some(m) => Some(
+                    m.as_u64()
+                        .ok_or(anyhow!("min_amount_sat must be a positive integer"))?,
+                ),
+                None => None,
+            },
+        ),
+        _ => {
+            return Err(anyhow!(
+                "Please provide exactly one short_channel_id or nothing for a summary, \
+                optionally with a sort key and a filter"
+            ))
+        }
+    };
+
+    let min_amount_msat = min_amount_sat.unwrap_or(0).saturating_mul(1_000);
+    let recent_window_days = plugin.state().config.lock().stats_recent_window_days.value;
+    let min_funded_msat = plugin.state().config.lock().min_funded_sats.value * 1_000;
     let peer_channels = plugin.state().peer_channels.lock().clone();
 
-    if input_array.is_empty() {
+    if scid.is_none() {
         let mut successes = HashMap::new();
         let mut failures = HashMap::new();
         refresh_joblists(plugin.clone()).await?;
@@ -78,15 +494,18 @@ pub async fn slingstats(
         }
         all_jobs.retain(|c| normal_channels_alias.contains_key(c));
         for scid in &all_jobs {
-            match SuccessReb::read_from_file(&sling_dir, scid).await {
-                Ok(o) => {
+            let stats_dir = plugin.state().stats_dir(&sling_dir, scid);
+            match SuccessReb::read_from_file(&stats_dir, scid).await {
+                Ok(mut o) => {
+                    retain_min_amount(&mut o, |s| s.amount_msat, min_amount_msat);
                     successes.insert(scid, o);
                 }
                 Err(e) => debug!("probably no success stats yet: {:?}", e),
             };
 
-            match FailureReb::read_from_file(&sling_dir, scid).await {
-                Ok(o) => {
+            match FailureReb::read_from_file(&stats_dir, scid).await {
+                Ok(mut o) => {
+                    retain_min_amount(&mut o, |f| f.amount_msat, min_amount_msat);
                     failures.insert(scid, o);
                 }
                 Err(e) => debug!("probably no failure stats yet: {:?}", e),
@@ -98,26 +517,35 @@ pub async fn slingstats(
             .unwrap()
             .as_secs();
         let mut table = Vec::new();
-        let jobstates = plugin.state().job_state.lock().clone();
+        let jobs = read_jobs(&sling_dir, &plugin).await?;
+        // all rows are built from this one copy, job tasks keep updating the
+        // live state while we format
+        let jobstates = plugin.state().job_states_snapshot();
 
         for job in &all_jobs {
             let mut total_amount_msat = 0;
             let mut most_recent_completed_at = 0;
             let mut weighted_fee_ppm = 0;
-            let jobstate: Vec<String> = jobstates
+            let mut hops = Vec::new();
+            let (status, active, stuck) = job_status(jobstates.get(job));
+            let tags = jobs
                 .get(job)
-                .unwrap_or(&vec![JobState::missing()])
-                .iter()
-                .map(|jt| jt.id().to_string() + ":" + &jt.state().to_string())
-                .collect();
+                .and_then(|j| j.tags.clone())
+                .unwrap_or_default();
+            let imbalance = match jobs.get(job) {
+                Some(j) => job_imbalance(
+                    j,
+                    &get_job_channels(&peer_channels, job, j, min_funded_msat),
+                ),
+                None => 0.0,
+            };
             for success_reb in successes.get(&job).unwrap_or(&Vec::new()) {
-                if stats_delete_successes_age == 0
-                    || success_reb.completed_at >= now - stats_delete_successes_age * 24 * 60 * 60
-                {
+                if is_recent(success_reb.completed_at, now, recent_window_days) {
                     total_amount_msat += success_reb.amount_msat;
                     weighted_fee_ppm += success_reb.fee_ppm as u64 * success_reb.amount_msat;
                     most_recent_completed_at =
                         std::cmp::max(most_recent_completed_at, success_reb.completed_at);
+                    hops.push(success_reb.hops);
                 }
             }
             if total_amount_msat > 0 {
@@ -161,11 +589,20 @@ pub async fn slingstats(
                     .replace(|c: char| !c.is_ascii(), "?"),
                 scid: *job,
                 pubkey: *scid_peer_map.get(&job.clone()).unwrap(),
-                status: jobstate.join("\n"),
+                status,
                 rebamount: (total_amount_msat / 1_000).to_formatted_string(&Locale::en),
                 w_feeppm: weighted_fee_ppm,
+                hops: match hop_summary(&hops) {
+                    Some((avg, min, max)) => format!("{:.1} ({}-{})", avg, min, max),
+                    None => "-".to_string(),
+                },
                 last_route_taken,
                 last_success_reb,
+                active,
+                stuck,
+                imbalance,
+                last_success: last_route_success,
+                tags,
             })
         }
         table.sort_by_key(|x| {
@@ -175,49 +612,586 @@ pub async fn slingstats(
                 .collect::<String>()
                 .to_ascii_lowercase()
         });
+        sort_and_filter_stat_summaries(&mut table, sort.as_deref(), filter.as_deref())?;
         let tabled = Table::new(table);
         Ok(json!({"format-hint":"simple","result":format!("{}", tabled,)}))
     } else {
-        let scid = match input_array.first().unwrap() {
-            serde_json::Value::String(i) => ShortChannelId::from_str(i)?,
-            _ => return Err(anyhow!("invalid short_channel_id")),
+        let scid = match scid {
+            Some(s) => s,
+            None => return Err(anyhow!("invalid short_channel_id")),
         };
-        let successes = match SuccessReb::read_from_file(&sling_dir, &scid).await {
+        let stats_dir = plugin.state().stats_dir(&sling_dir, &scid);
+        let mut successes = match SuccessReb::read_from_file(&stats_dir, &scid).await {
             Ok(o) => o,
             Err(e) => {
                 info!("Could not get any successes: {}", e);
                 Vec::new()
             }
         };
-        let failures = match FailureReb::read_from_file(&sling_dir, &scid).await {
+        let mut failures = match FailureReb::read_from_file(&stats_dir, &scid).await {
             Ok(o) => o,
             Err(e) => {
                 info!("Could not get any failures: {}", e);
                 Vec::new()
             }
         };
+        let mut lifetime = read_rollup(&stats_dir, &scid).await?;
+        add_to_rollup(&mut lifetime, &successes, &failures);
+        retain_min_amount(&mut successes, |s| s.amount_msat, min_amount_msat);
+        retain_min_amount(&mut failures, |f| f.amount_msat, min_amount_msat);
         let alias_map = plugin.state().alias_peer_map.lock().clone();
 
+        let my_pubkey = plugin.state().config.lock().pubkey;
+        let fee_comparison = match peer_channels.get(&scid) {
+            Some(channel) => fee_comparison(
+                &successes,
+                recent_window_days,
+                &plugin.state().graph.lock(),
+                &channel.peer_id,
+                &my_pubkey,
+            ),
+            None => None,
+        };
+
+        let job_states = plugin
+            .state()
+            .job_states_snapshot()
+            .get(&scid)
+            .map(|jts| {
+                jts.iter()
+                    .map(|jt| JobStateStats {
+                        task_id: jt.id(),
+                        state: jt.state().to_string(),
+                        code: jt.state().code(),
+                        stuck_htlc: jt.stuck_htlc().cloned(),
+                        success_ratio: jt.success_ratio(),
+                        too_expensive: jt.too_expensive(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
         let sling_stats = SlingStats {
             successes_in_time_window: success_stats(
                 successes,
-                stats_delete_successes_age,
+                recent_window_days,
                 &alias_map,
                 &peer_channels,
             ),
             failures_in_time_window: failure_stats(
                 failures,
-                stats_delete_failures_age,
+                recent_window_days,
                 &alias_map,
                 &peer_channels,
             ),
+            fee_comparison,
+            job_states,
+            lifetime: if lifetime == StatsRollup::default() {
+                None
+            } else {
+                Some(lifetime)
+            },
+            note: plugin
+                .state()
+                .loaded_jobs
+                .lock()
+                .get(&scid)
+                .and_then(|j| j.note.clone()),
         };
 
         Ok(json!(sling_stats))
     }
 }
 
-fn success_stats(
+async fn stats_rollups(
+    plugin: Plugin<PluginState>,
+    sling_dir: &Path,
+) -> Result<serde_json::Value, Error> {
+    refresh_joblists(plugin.clone()).await?;
+    let pull_jobs = plugin.state().pull_jobs.lock().clone();
+    let push_jobs = plugin.state().push_jobs.lock().clone();
+    let mut successes = Vec::new();
+    let mut failures = Vec::new();
+    for scid in pull_jobs.iter().chain(push_jobs.iter()) {
+        let stats_dir = plugin.state().stats_dir(sling_dir, scid);
+        match SuccessReb::read_from_file(&stats_dir, scid).await {
+            Ok(o) => successes.extend(o),
+            Err(e) => debug!("probably no success stats yet: {:?}", e),
+        };
+        match FailureReb::read_from_file(&stats_dir, scid).await {
+            Ok(o) => failures.extend(o),
+            Err(e) => debug!("probably no failure stats yet: {:?}", e),
+        };
+    }
+    Ok(json!(rollups(&successes, &failures)))
+}
+
+pub fn rollups(successes: &[SuccessReb], failures: &[FailureReb]) -> Rollups {
+    Rollups {
+        weekly: rollup_by(successes, failures, "%G-W%V"),
+        monthly: rollup_by(successes, failures, "%Y-%m"),
+    }
+}
+
+fn rollup_by(successes: &[SuccessReb], failures: &[FailureReb], format: &str) -> Vec<RollupStats> {
+    let period = |timestamp: u64| {
+        Utc.timestamp_opt(timestamp as i64, 0)
+            .unwrap()
+            .format(format)
+            .to_string()
+    };
+    // amount_msat, fee_msat, successes, failures
+    let mut buckets: BTreeMap<String, (u64, u64, u64, u64)> = BTreeMap::new();
+    for success in successes {
+        let bucket = buckets.entry(period(success.completed_at)).or_default();
+        bucket.0 += success.amount_msat;
+        bucket.1 += success.fee_msat;
+        bucket.2 += 1;
+    }
+    for failure in failures {
+        buckets.entry(period(failure.created_at)).or_default().3 += 1;
+    }
+    buckets
+        .into_iter()
+        .map(
+            |(period, (amount_msat, fee_msat, succeeded, failed))| RollupStats {
+                period,
+                total_amount_sats: amount_msat / 1_000,
+                total_spent_sats: fee_msat / 1_000,
+                total_amount_msat: amount_msat,
+                total_spent_msat: fee_msat,
+                total_rebalances: succeeded,
+                total_rebalances_failed: failed,
+                success_rate: succeeded as f64 / (succeeded + failed) as f64,
+            },
+        )
+        .collect()
+}
+
+pub fn hop_summary(hops: &[u8]) -> Option<(f64, u8, u8)> {
+    if hops.is_empty() {
+        return None;
+    }
+    let avg = hops.iter().map(|h| *h as f64).sum::<f64>() / hops.len() as f64;
+    Some((
+        avg,
+        *hops.iter().min().unwrap(),
+        *hops.iter().max().unwrap(),
+    ))
+}
+
+pub fn job_status(job_states: Option<&Vec<JobState>>) -> (String, bool, bool) {
+    let missing = vec![JobState::missing()];
+    let job_states = job_states.unwrap_or(&missing);
+    let status = job_states
+        .iter()
+        .map(|jt| jt.id().to_string() + ":" + &jt.state().to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let active_states = job_states
+        .iter()
+        .filter(|jt| jt.is_active())
+        .map(|jt| jt.state())
+        .collect::<Vec<_>>();
+    let stuck = !active_states.is_empty() && active_states.iter().all(|state| state.is_stuck());
+    (status, !active_states.is_empty(), stuck)
+}
+
+pub fn is_recent(timestamp: u64, now: u64, window_days: u64) -> bool {
+    window_days == 0 || timestamp >= now.saturating_sub(window_days * 24 * 60 * 60)
+}
+
+pub fn fee_comparison(
+    successes: &[SuccessReb],
+    time_window: u64,
+    graph: &LnGraph,
+    peer: &PublicKey,
+    my_pubkey: &PublicKey,
+) -> Option<FeeComparison> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let successes = successes
+        .iter()
+        .filter(|s| is_recent(s.completed_at, now, time_window))
+        .collect::<Vec<_>>();
+    let total_amount_msat = successes.iter().map(|s| s.amount_msat).sum::<u64>();
+    if total_amount_msat == 0 {
+        return None;
+    }
+    let feeppm_weighted_avg = successes
+        .iter()
+        .map(|s| s.fee_ppm as u64 * s.amount_msat)
+        .sum::<u64>()
+        / total_amount_msat;
+    let (hops_avg, _, _) = hop_summary(&successes.iter().map(|s| s.hops).collect::<Vec<_>>())?;
+
+    let mut hop_feeppms = graph
+        .graph
+        .values()
+        .flat_map(|c| c.values())
+        .filter(|c| {
+            c.active
+                && (c.source == *peer || c.destination == *peer)
+                && c.source != *my_pubkey
+                && c.destination != *my_pubkey
+        })
+        .map(|c| c.fee_per_millionth)
+        .collect::<Vec<_>>();
+    if hop_feeppms.is_empty() {
+        return None;
+    }
+    hop_feeppms.sort();
+    let network_median_hop_feeppm = hop_feeppms[hop_feeppms.len() / 2];
+    let network_expected_feeppm = (network_median_hop_feeppm as f64 * hops_avg).round() as u64;
+
+    Some(FeeComparison {
+        feeppm_weighted_avg,
+        hops_avg,
+        network_median_hop_feeppm,
+        network_expected_feeppm,
+        overpaying: feeppm_weighted_avg as f64 > network_expected_feeppm as f64 * OVERPAYING_FACTOR,
+    })
+}
+
+pub async fn settled_forwards(rpc_path: &Path) -> Result<Vec<ListforwardsForwards>, Error> {
+    let mut rpc = ClnRpc::new(rpc_path).await?;
+    Ok(rpc
+        .call_typed(&ListforwardsRequest {
+            status: Some(ListforwardsStatus::SETTLED),
+            in_channel: None,
+            out_channel: None,
+            index: None,
+            start: None,
+            limit: None,
+        })
+        .await?
+        .forwards)
+}
+
+// msat forwarded in or out of each channel
+pub fn forward_volumes(
+    forwards: &[ListforwardsForwards],
+    now: u64,
+    window_days: u64,
+) -> HashMap<ShortChannelId, u64> {
+    let mut volumes: HashMap<ShortChannelId, u64> = HashMap::new();
+    for forward in forwards.iter().filter(|f| {
+        f.status == ListforwardsForwardsStatus::SETTLED
+            && is_recent(
+                f.resolved_time.unwrap_or(f.received_time) as u64,
+                now,
+                window_days,
+            )
+    }) {
+        *volumes.entry(forward.in_channel).or_default() += Amount::msat(&forward.in_msat);
+        if let (Some(out_channel), Some(out_msat)) = (forward.out_channel, forward.out_msat) {
+            *volumes.entry(out_channel).or_default() += Amount::msat(&out_msat);
+        }
+    }
+    volumes
+}
+
+pub fn is_low_utilization(
+    volumes: &HashMap<ShortChannelId, u64>,
+    job_channels: &[ListpeerchannelsChannels],
+    min_volume_msat: u64,
+) -> bool {
+    job_channels
+        .iter()
+        .filter_map(|c| c.short_channel_id)
+        .map(|scid| volumes.get(&scid).copied().unwrap_or(0))
+        .sum::<u64>()
+        < min_volume_msat
+}
+
+// forwarding fees are earned on the outgoing channel, rebalance fees are
+// paid by the job channel the rebalance was for. Worst channels first.
+pub fn channel_roi(
+    forwards: &[ListforwardsForwards],
+    successes: &HashMap<ShortChannelId, Vec<SuccessReb>>,
+    now: u64,
+    window_days: u64,
+) -> Vec<ChannelRoi> {
+    let mut fees: BTreeMap<ShortChannelId, (u64, u64)> = BTreeMap::new();
+    for forward in forwards {
+        if forward.status != ListforwardsForwardsStatus::SETTLED
+            || !is_recent(
+                forward.resolved_time.unwrap_or(forward.received_time) as u64,
+                now,
+                window_days,
+            )
+        {
+            continue;
+        }
+        if let (Some(out_channel), Some(fee_msat)) = (forward.out_channel, forward.fee_msat) {
+            fees.entry(out_channel).or_default().0 += Amount::msat(&fee_msat);
+        }
+    }
+    for (scid, chan_successes) in successes {
+        let rebalance_fees_msat = chan_successes
+            .iter()
+            .filter(|s| is_recent(s.completed_at, now, window_days))
+            .map(|s| s.fee_msat)
+            .sum::<u64>();
+        if rebalance_fees_msat > 0 {
+            fees.entry(*scid).or_default().1 += rebalance_fees_msat;
+        }
+    }
+    let mut result = fees
+        .into_iter()
+        .map(
+            |(short_channel_id, (forward_fees_msat, rebalance_fees_msat))| ChannelRoi {
+                short_channel_id,
+                forward_fees_msat,
+                rebalance_fees_msat,
+                net_msat: forward_fees_msat as i64 - rebalance_fees_msat as i64,
+            },
+        )
+        .collect::<Vec<_>>();
+    result.sort_by_key(|r| r.net_msat);
+    result
+}
+
+// the running jobs that would lose scid as a candidate and how much of their
+// recent rebalancing went through it
+pub fn except_chan_impact(
+    scid: &ShortChannelId,
+    jobs: &BTreeMap<ShortChannelId, Job>,
+    current_candidates: &HashMap<ShortChannelId, Vec<ShortChannelId>>,
+    successes: &HashMap<ShortChannelId, Vec<SuccessReb>>,
+    now: u64,
+    window_days: u64,
+) -> Vec<ExceptImpact> {
+    let mut impacts = Vec::new();
+    for (job_scid, job) in jobs {
+        let candidates = match current_candidates.get(job_scid) {
+            Some(c) => c,
+            None => continue,
+        };
+        let listed_candidate = job.candidatelist.iter().any(|c| c.contains(scid))
+            || job.candidatelist_backup.iter().any(|c| c.contains(scid));
+        let current_candidate = candidates.contains(scid);
+        let recent = successes
+            .get(job_scid)
+            .map(|s| {
+                s.iter()
+                    .filter(|s| {
+                        s.channel_partner == Some(*scid)
+                            && is_recent(s.completed_at, now, window_days)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        if !listed_candidate && !current_candidate && recent.is_empty() {
+            continue;
+        }
+        impacts.push(ExceptImpact {
+            short_channel_id: *job_scid,
+            direction: job.sat_direction,
+            listed_candidate,
+            current_candidate,
+            recent_successes: recent.len() as u64,
+            recent_success_msat: recent.iter().map(|s| s.amount_msat).sum(),
+        });
+    }
+    impacts
+}
+
+pub async fn roi_by_channel(plugin: &Plugin<PluginState>) -> Result<Vec<ChannelRoi>, Error> {
+    let sling_dir = Path::new(&plugin.configuration().lightning_dir).join(PLUGIN_NAME);
+    let (rpc_path, window_days) = {
+        let config = plugin.state().config.lock();
+        (
+            config.rpc_path.clone(),
+            config.stats_recent_window_days.value,
+        )
+    };
+    let forwards = settled_forwards(&rpc_path).await?;
+    let scids = plugin
+        .state()
+        .peer_channels
+        .lock()
+        .keys()
+        .copied()
+        .collect::<Vec<_>>();
+    let mut successes = HashMap::new();
+    for scid in scids {
+        let stats_dir = plugin.state().stats_dir(&sling_dir, &scid);
+        if let Ok(o) = SuccessReb::read_from_file(&stats_dir, &scid).await {
+            successes.insert(scid, o);
+        }
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    Ok(channel_roi(&forwards, &successes, now, window_days))
+}
+
+pub async fn slingroi(
+    plugin: Plugin<PluginState>,
+    _args: serde_json::Value,
+) -> Result<serde_json::Value, Error> {
+    let rois = roi_by_channel(&plugin).await?;
+    let peer_channels = plugin.state().peer_channels.lock().clone();
+    let alias_map = plugin.state().alias_peer_map.lock().clone();
+    Ok(json!({
+        "channels": rois
+            .iter()
+            .map(|r| {
+                let mut roi = serde_json::to_value(r).unwrap();
+                let alias = peer_channels
+                    .get(&r.short_channel_id)
+                    .and_then(|c| alias_map.get(&c.peer_id))
+                    .cloned()
+                    .unwrap_or(NO_ALIAS_SET.to_string());
+                roi["alias"] = json!(alias);
+                roi["negative"] = json!(r.net_msat < 0);
+                roi
+            })
+            .collect::<Vec<serde_json::Value>>(),
+    }))
+}
+
+pub fn suggest_maxppm(
+    successes: &[SuccessReb],
+    graph: &LnGraph,
+    peer: &PublicKey,
+    my_pubkey: &PublicKey,
+) -> Option<MaxppmSuggestion> {
+    let mut feeppms = successes.iter().map(|s| s.fee_ppm).collect::<Vec<_>>();
+    feeppms.sort();
+    let history_p75_feeppm = if feeppms.is_empty() {
+        None
+    } else {
+        let rank = (feeppms.len() as f64 * SUGGEST_MAXPPM_PERCENTILE).ceil() as usize;
+        Some(feeppms[rank.max(1) - 1])
+    };
+
+    let mut network_feeppms = graph
+        .graph
+        .values()
+        .flat_map(|c| c.values())
+        .filter(|c| c.active && c.destination == *peer && c.source != *my_pubkey)
+        .map(|c| c.fee_per_millionth)
+        .collect::<Vec<_>>();
+    network_feeppms.sort();
+    let network_median_feeppm = network_feeppms.get(network_feeppms.len() / 2).copied();
+
+    let base = max(history_p75_feeppm, network_median_feeppm)?;
+    Some(MaxppmSuggestion {
+        samples: feeppms.len(),
+        history_p75_feeppm,
+        network_median_feeppm,
+        suggested_maxppm: (base as u64 * (100 + SUGGEST_MAXPPM_MARGIN_PERCENT)).div_ceil(100)
+            as u32,
+    })
+}
+
+pub fn job_imbalance(job: &Job, job_channels: &[ListpeerchannelsChannels]) -> f64 {
+    let total_msat = job_channels
+        .iter()
+        .filter(|c| has_balance_fields(c))
+        .map(|c| Amount::msat(&c.total_msat.unwrap()))
+        .sum::<u64>();
+    if total_msat == 0 {
+        return 0.0;
+    }
+    job_target_room(job, job_channels) as f64 / total_msat as f64
+}
+
+pub fn job_target_room(job: &Job, job_channels: &[ListpeerchannelsChannels]) -> u64 {
+    let total_msat = job_channels
+        .iter()
+        .filter(|c| has_balance_fields(c))
+        .map(|c| Amount::msat(&c.total_msat.unwrap()))
+        .sum::<u64>();
+    let to_us_msat = job_channels
+        .iter()
+        .filter(|c| has_balance_fields(c))
+        .map(|c| Amount::msat(&c.to_us_msat.unwrap()))
+        .sum::<u64>();
+    let current_msat = match job.sat_direction {
+        SatDirection::Pull => to_us_msat,
+        SatDirection::Push => total_msat - to_us_msat,
+    };
+    job.target_cap(job_channels).saturating_sub(current_msat)
+}
+
+fn is_stats_filter(arg: &str) -> bool {
+    matches!(arg, "all" | "active" | "stuck") || arg.starts_with("tag:")
+}
+
+// positional form: [scid], [sort, filter?] or [filter, sort?]
+pub fn parse_stats_array_args(args: &[serde_json::Value]) -> Result<StatsArgs, Error> {
+    let second = args.get(1).and_then(|f| f.as_str()).map(|f| f.to_string());
+    match args.first() {
+        None => Ok((None, None, None)),
+        Some(serde_json::Value::String(first)) if is_stats_filter(first) => {
+            Ok((None, second, Some(first.clone())))
+        }
+        Some(serde_json::Value::String(first)) => match parse_scid(first) {
+            Ok(scid) if args.len() == 1 => Ok((Some(scid), None, None)),
+            _ => Ok((None, Some(first.clone()), second)),
+        },
+        Some(o) => Err(anyhow!("invalid argument: {}", o)),
+    }
+}
+
+pub fn sort_and_filter_stat_summaries(
+    table: &mut Vec<StatSummary>,
+    sort: Option<&str>,
+    filter: Option<&str>,
+) -> Result<(), Error> {
+    match filter {
+        None | Some("all") => (),
+        Some("active") => table.retain(|row| row.active),
+        Some("stuck") => table.retain(|row| row.stuck),
+        Some(f) if f.starts_with("tag:") => {
+            let tag = &f["tag:".len()..];
+            table.retain(|row| row.tags.iter().any(|t| t == tag))
+        }
+        Some(f) => {
+            return Err(anyhow!(
+                "Unknown filter `{}`, use `all`, `active`, `stuck` or `tag:<tag>`",
+                f
+            ))
+        }
+    }
+    let (key, ascending) = match sort {
+        None => return Ok(()),
+        Some(s) => match s.split_once(':') {
+            Some((key, "asc")) => (key, Some(true)),
+            Some((key, "desc")) => (key, Some(false)),
+            Some(_) => return Err(anyhow!("Sort order must be `asc` or `desc`")),
+            None => (s, None),
+        },
+    };
+    match key {
+        "alias" => {
+            if ascending == Some(false) {
+                table.reverse()
+            }
+        }
+        "imbalance" => table.sort_by(|a, b| a.imbalance.total_cmp(&b.imbalance)),
+        "w_feeppm" => table.sort_by_key(|row| row.w_feeppm),
+        "last_success" => table.sort_by_key(|row| row.last_success),
+        k => {
+            return Err(anyhow!(
+                "Unknown sort key `{}`, use `alias`, `imbalance`, `w_feeppm` or `last_success`",
+                k
+            ))
+        }
+    }
+    if key != "alias" && !ascending.unwrap_or(false) {
+        table.reverse()
+    }
+    Ok(())
+}
+
+pub fn success_stats(
     successes: Vec<SuccessReb>,
     time_window: u64,
     alias_map: &HashMap<PublicKey, String>,
@@ -227,6 +1201,7 @@ fn success_stats(
         return None;
     }
     let mut total_amount_msat = 0;
+    let mut total_spent_msat = 0;
     let mut channel_partner_counts = HashMap::new();
     let mut hop_counts = HashMap::new();
     let mut most_recent_completed_at = 0;
@@ -239,12 +1214,14 @@ fn success_stats(
         .as_secs();
 
     for success_reb in successes {
-        if time_window == 0 || success_reb.completed_at >= now - time_window * 24 * 60 * 60 {
+        if is_recent(success_reb.completed_at, now, time_window) {
             total_amount_msat += success_reb.amount_msat;
+            total_spent_msat += success_reb.fee_msat;
             weighted_fee_ppm += success_reb.fee_ppm as u64 * success_reb.amount_msat;
-            *channel_partner_counts
-                .entry(success_reb.channel_partner)
-                .or_insert(0) += success_reb.amount_msat / 1_000;
+            if let Some(partner) = success_reb.channel_partner {
+                *channel_partner_counts.entry(partner).or_insert(0) +=
+                    success_reb.amount_msat / 1_000;
+            }
             *hop_counts.entry(success_reb.hops).or_insert(0) += 1;
             most_recent_completed_at =
                 std::cmp::max(most_recent_completed_at, success_reb.completed_at);
@@ -302,13 +1279,14 @@ fn success_stats(
         most_common_hop_count,
         time_of_last_rebalance,
         total_rebalances: total_transactions,
-        total_spent_sats: (weighted_fee_ppm as f64 * 0.000001 * total_amount_msat as f64) as u64
-            / 1000,
+        total_spent_sats: total_spent_msat / 1_000,
+        total_amount_msat,
+        total_spent_msat,
     };
     Some(successes_in_time_window)
 }
 
-fn failure_stats(
+pub fn failure_stats(
     failures: Vec<FailureReb>,
     time_window: u64,
     alias_map: &HashMap<PublicKey, String>,
@@ -330,7 +1308,7 @@ fn failure_stats(
         .as_secs();
 
     for fail_reb in failures {
-        if time_window == 0 || fail_reb.created_at >= now - time_window * 24 * 60 * 60 {
+        if is_recent(fail_reb.created_at, now, time_window) {
             total_amount_msat += fail_reb.amount_msat;
             *channel_partner_counts
                 .entry(fail_reb.channel_partner)
@@ -390,6 +1368,7 @@ fn failure_stats(
             time_window.to_string()
         },
         total_amount_tried_sats: total_amount_msat / 1_000,
+        total_amount_tried_msat: total_amount_msat,
         top_5_failure_reasons: top_5_failure_reasons
             .iter()
             .map(|(reason, count)| FailureReasonCount {
@@ -15,7 +15,7 @@ use cln_rpc::{
     ClnRpc,
 };
 
-use log::{debug, info};
+use log::{debug, info, warn};
 
 use sling::DirectedChannel;
 use tokio::{
@@ -24,7 +24,15 @@ use tokio::{
     time::{self, Instant},
 };
 
-use crate::{gossip::read_gossip_store, model::*, util::*};
+use crate::{
+    gossip::{update_graph, GossipStoreSource, ListchannelsSource},
+    model::*,
+    stats::{
+        add_to_rollup, forward_volumes, is_recent, prune_records, read_rollup, roi_by_channel,
+        settled_forwards, write_rollup,
+    },
+    util::*,
+};
 
 pub async fn refresh_aliasmap(plugin: Plugin<PluginState>) -> Result<(), Error> {
     let rpc_path;
@@ -34,22 +42,26 @@ pub async fn refresh_aliasmap(plugin: Plugin<PluginState>) -> Result<(), Error>
         rpc_path = config.rpc_path.clone();
         interval = config.refresh_aliasmap_interval.value;
     }
-    let mut rpc = ClnRpc::new(&rpc_path).await?;
+    let mut backoff = RefreshBackoff::new("refresh_aliasmap", interval);
 
     loop {
         let now = Instant::now();
-        {
+        let result: Result<(), Error> = async {
+            let mut rpc = ClnRpc::new(&rpc_path).await?;
             let nodes = rpc.call_typed(&ListnodesRequest { id: None }).await?.nodes;
             *plugin.state().alias_peer_map.lock() = nodes
                 .into_iter()
                 .filter_map(|node| node.alias.map(|alias| (node.nodeid, alias)))
                 .collect();
+            plugin.state().alias_blocklist.lock().clear();
+            info!(
+                "Refreshing alias map done in {}ms!",
+                now.elapsed().as_millis().to_string()
+            );
+            Ok(())
         }
-        info!(
-            "Refreshing alias map done in {}ms!",
-            now.elapsed().as_millis().to_string()
-        );
-        time::sleep(Duration::from_secs(interval)).await;
+        .await;
+        time::sleep(Duration::from_secs(backoff.next_interval(&result))).await;
     }
 }
 
@@ -60,11 +72,11 @@ pub async fn refresh_listpeerchannels_loop(plugin: Plugin<PluginState>) -> Resul
         interval = config.refresh_peers_interval.value;
     }
 
+    let mut backoff = RefreshBackoff::new("refresh_listpeerchannels", interval);
+
     loop {
-        {
-            refresh_listpeerchannels(&plugin).await?;
-        }
-        time::sleep(Duration::from_secs(interval)).await;
+        let result = refresh_listpeerchannels(&plugin).await;
+        time::sleep(Duration::from_secs(backoff.next_interval(&result))).await;
     }
 }
 
@@ -74,16 +86,20 @@ pub async fn refresh_listpeerchannels(plugin: &Plugin<PluginState>) -> Result<()
         let config = plugin.state().config.lock();
         rpc_path = config.rpc_path.clone();
     }
-    let mut rpc = ClnRpc::new(&rpc_path).await?;
+    let mut rpc = plugin.state().rpc_pool.get_cln(&rpc_path).await?;
 
     let now = Instant::now();
-    *plugin.state().peer_channels.lock() = rpc
-        .call_typed(&ListpeerchannelsRequest { id: None })
-        .await?
+    let result = rpc.call_typed(&ListpeerchannelsRequest { id: None }).await;
+    rpc.discard_on_transport_error(&result);
+    *plugin.state().peer_channels.lock() = result?
         .channels
         .into_iter()
         .filter_map(|channel| channel.short_channel_id.map(|id| (id, channel)))
         .collect();
+    *plugin.state().peers_refreshed_at.lock() = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
     debug!(
         "Peerchannels refreshed in {}ms",
         now.elapsed().as_millis().to_string()
@@ -96,7 +112,10 @@ pub async fn refresh_graph(plugin: Plugin<PluginState>) -> Result<(), Error> {
     let interval;
     let my_pubkey;
     let sling_dir;
-    let mut offset = 0;
+    let mut gossip_store = GossipStoreSource::new(
+        Path::new(&plugin.configuration().lightning_dir).join("gossip_store"),
+    );
+    let mut listchannels = ListchannelsSource;
     {
         let config = plugin.state().config.lock();
         // rpc_path = config.rpc_path.clone();
@@ -104,11 +123,25 @@ pub async fn refresh_graph(plugin: Plugin<PluginState>) -> Result<(), Error> {
         my_pubkey = config.pubkey;
         sling_dir = config.sling_dir.clone();
     }
+    if plugin.state().config.lock().flow_priors.value {
+        let pull_jobs = plugin.state().pull_jobs.lock().clone();
+        let push_jobs = plugin.state().push_jobs.lock().clone();
+        let mut successes = HashMap::new();
+        for scid in pull_jobs.iter().chain(push_jobs.iter()) {
+            let stats_dir = plugin.state().stats_dir(&sling_dir, scid);
+            if let Ok(o) = SuccessReb::read_from_file(&stats_dir, scid).await {
+                successes.insert(*scid, o);
+            }
+        }
+        *plugin.state().historical_flows.lock() = historical_flows(&successes, &pull_jobs);
+    }
     *plugin.state().graph.lock() = read_graph(&sling_dir).await?;
     // let mut rpc = ClnRpc::new(&rpc_path).await?;
+    let mut backoff = RefreshBackoff::new("refresh_graph", interval);
+    let mut graph_written_at = Instant::now();
 
     loop {
-        {
+        let result: Result<(), Error> = async {
             let now = Instant::now();
             // let jobs = read_jobs(
             //     &Path::new(&plugin.configuration().lightning_dir).join(PLUGIN_NAME),
@@ -130,10 +163,17 @@ pub async fn refresh_graph(plugin: Plugin<PluginState>) -> Result<(), Error> {
             //     .as_secs()
             //     - 1_209_600) as u32;
             {
-                debug!("Getting all channels in gossip_store...");
-                read_gossip_store(plugin.clone(), &mut offset).await?;
+                let graph_source = plugin.state().config.lock().graph_source.value.clone();
+                debug!("Getting all channels from {}...", graph_source);
+                if graph_source == GRAPH_SOURCE_LISTCHANNELS {
+                    update_graph(&plugin, &mut listchannels).await?;
+                } else {
+                    update_graph(&plugin, &mut gossip_store).await?;
+                }
+                *plugin.state().graph_refreshed.lock() = true;
                 debug!(
-                    "Reading gossip store done after {}ms!",
+                    "Reading {} done after {}ms!",
+                    graph_source,
                     now.elapsed().as_millis().to_string()
                 );
 
@@ -222,6 +262,7 @@ pub async fn refresh_graph(plugin: Plugin<PluginState>) -> Result<(), Error> {
                                 last_update: timestamp as u32,
                                 liquidity: chan.spendable_msat.unwrap().msat(),
                                 liquidity_age: timestamp,
+                                confidence_age: 0,
                             },
                         );
                         lngraph.graph.entry(chan.peer_id).or_default().insert(
@@ -244,6 +285,7 @@ pub async fn refresh_graph(plugin: Plugin<PluginState>) -> Result<(), Error> {
                                 last_update: timestamp as u32,
                                 liquidity: chan.receivable_msat.unwrap().msat(),
                                 liquidity_age: timestamp,
+                                confidence_age: 0,
                             },
                         );
                     }
@@ -273,17 +315,36 @@ pub async fn refresh_graph(plugin: Plugin<PluginState>) -> Result<(), Error> {
                 );
 
                 lngraph.graph.retain(|_, v| !v.is_empty());
+
+                let graph_max_channels = plugin.state().config.lock().graph_max_channels.value;
+                if graph_max_channels > 0 {
+                    let pruned = lngraph.prune(&my_pubkey, graph_max_channels as usize);
+                    if pruned > 0 {
+                        info!(
+                            "Pruned {} channels from sling graph to stay below {} channels",
+                            pruned, graph_max_channels
+                        );
+                    }
+                }
             }
-            // match write_graph(plugin.clone()).await {
-            //     Ok(_) => (),
-            //     Err(e) => return Err(anyhow!("Too dumb to write....{}", e)),
-            // };
             info!(
                 "Refreshed graph in {}ms!",
                 now.elapsed().as_millis().to_string()
             );
+            let graph_write_interval = plugin.state().config.lock().graph_write_interval.value;
+            if graph_write_interval > 0
+                && graph_written_at.elapsed() >= Duration::from_secs(graph_write_interval * 60)
+            {
+                // a failed write is counted, the in-memory graph is still good
+                if let Err(e) = write_graph(plugin.clone()).await {
+                    warn!("Error writing graph: {}", e);
+                }
+                graph_written_at = Instant::now();
+            }
+            Ok(())
         }
-        time::sleep(Duration::from_secs(interval)).await;
+        .await;
+        time::sleep(Duration::from_secs(backoff.next_interval(&result))).await;
     }
 }
 
@@ -298,7 +359,12 @@ pub async fn refresh_liquidity(plugin: Plugin<PluginState>) -> Result<(), Error>
     loop {
         {
             let now = Instant::now();
-            plugin.state().graph.lock().refresh_liquidity(interval);
+            let pinned = plugin.state().pinned_liquidity.lock().clone();
+            plugin
+                .state()
+                .graph
+                .lock()
+                .refresh_liquidity(interval, &pinned);
             info!(
                 "Refreshed Liquidity in {}ms!",
                 now.elapsed().as_millis().to_string()
@@ -308,6 +374,98 @@ pub async fn refresh_liquidity(plugin: Plugin<PluginState>) -> Result<(), Error>
     }
 }
 
+pub async fn refresh_failure_rates(plugin: Plugin<PluginState>) -> Result<(), Error> {
+    let sling_dir = Path::new(&plugin.configuration().lightning_dir).join(PLUGIN_NAME);
+    loop {
+        {
+            let now = Instant::now();
+            let pull_jobs = plugin.state().pull_jobs.lock().clone();
+            let push_jobs = plugin.state().push_jobs.lock().clone();
+            let recent_window_days = plugin.state().config.lock().stats_recent_window_days.value;
+            let sys_time_now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            let mut successes = Vec::new();
+            let mut failures = Vec::new();
+            for scid in pull_jobs.iter().chain(push_jobs.iter()) {
+                let stats_dir = plugin.state().stats_dir(&sling_dir, scid);
+                if let Ok(o) = SuccessReb::read_from_file(&stats_dir, scid).await {
+                    successes.extend(
+                        o.into_iter().filter(|s| {
+                            is_recent(s.completed_at, sys_time_now, recent_window_days)
+                        }),
+                    );
+                }
+                if let Ok(o) = FailureReb::read_from_file(&stats_dir, scid).await {
+                    failures.extend(
+                        o.into_iter()
+                            .filter(|f| is_recent(f.created_at, sys_time_now, recent_window_days)),
+                    );
+                }
+            }
+            *plugin.state().failure_rates.lock() = failure_rates(&successes, &failures);
+            *plugin.state().candidate_feeppms.lock() = candidate_feeppms(&successes);
+            debug!(
+                "Refreshed failure rates in {}ms!",
+                now.elapsed().as_millis().to_string()
+            );
+        }
+        time::sleep(Duration::from_secs(600)).await;
+    }
+}
+
+pub async fn warn_negative_roi(plugin: Plugin<PluginState>) -> Result<(), Error> {
+    loop {
+        if plugin.state().config.lock().roi_warn.value {
+            match roi_by_channel(&plugin).await {
+                Ok(rois) => {
+                    for roi in rois.iter().filter(|r| r.net_msat < 0) {
+                        warn!(
+                            "{}: negative ROI, spent {}msat rebalancing but earned {}msat forwarding",
+                            roi.short_channel_id, roi.rebalance_fees_msat, roi.forward_fees_msat
+                        );
+                    }
+                }
+                Err(e) => warn!("Could not compute ROI: {}", e),
+            }
+        }
+        time::sleep(Duration::from_secs(21_600)).await;
+    }
+}
+
+pub async fn refresh_forward_volumes(plugin: Plugin<PluginState>) -> Result<(), Error> {
+    loop {
+        let (rpc_path, min_forward_volume, window_days) = {
+            let config = plugin.state().config.lock();
+            (
+                config.rpc_path.clone(),
+                config.min_forward_volume.value,
+                config.stats_recent_window_days.value,
+            )
+        };
+        if min_forward_volume > 0 {
+            let now = Instant::now();
+            match settled_forwards(&rpc_path).await {
+                Ok(forwards) => {
+                    let sys_time_now = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap()
+                        .as_secs();
+                    *plugin.state().forward_volumes.lock() =
+                        Some(forward_volumes(&forwards, sys_time_now, window_days));
+                    debug!(
+                        "Refreshed forward volumes in {}ms!",
+                        now.elapsed().as_millis().to_string()
+                    );
+                }
+                Err(e) => warn!("Could not refresh forward volumes: {}", e),
+            }
+        }
+        time::sleep(Duration::from_secs(3_600)).await;
+    }
+}
+
 pub async fn clear_tempbans(plugin: Plugin<PluginState>) -> Result<(), Error> {
     loop {
         {
@@ -319,10 +477,61 @@ pub async fn clear_tempbans(plugin: Plugin<PluginState>) -> Result<(), Error> {
                     - 600
             })
         }
+        {
+            let node_ban_window = plugin.state().config.lock().node_ban_window.value;
+            for node in plugin.state().node_bans.lock().reinstate_expired(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs(),
+                node_ban_window * 60,
+            ) {
+                info!("{}: no longer avoiding node after failures", node);
+            }
+        }
         time::sleep(Duration::from_secs(100)).await;
     }
 }
 
+pub async fn sweep_pays(plugin: Plugin<PluginState>) -> Result<(), Error> {
+    loop {
+        let interval = plugin.state().config.lock().pays_sweep_interval.value;
+        if interval > 0 {
+            let swept = plugin.state().pays.write().sweep(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs(),
+                PAYS_ORPHAN_AGE_SECS,
+            );
+            if swept > 0 {
+                info!("Swept {} payments that never resolved", swept);
+            }
+        }
+        time::sleep(Duration::from_secs(interval.max(1) * 60)).await;
+    }
+}
+
+// so a crash doesn't lose the routes learned since startup
+pub async fn persist_route_cache(plugin: Plugin<PluginState>) -> Result<(), Error> {
+    let mut written = plugin.state().route_cache.lock().clone();
+    loop {
+        time::sleep(Duration::from_secs(600)).await;
+        let (sling_dir, route_cache_max_age) = {
+            let config = plugin.state().config.lock();
+            (config.sling_dir.clone(), config.route_cache_max_age.value)
+        };
+        let route_cache = plugin.state().route_cache.lock().clone();
+        if route_cache_max_age == 0 || route_cache == written {
+            continue;
+        }
+        match route_cache.write_to_file(&sling_dir).await {
+            Ok(()) => written = route_cache,
+            Err(e) => warn!("Error writing route cache: {}", e),
+        }
+    }
+}
+
 pub async fn clear_stats(plugin: Plugin<PluginState>) -> Result<(), Error> {
     let sling_dir = Path::new(&plugin.configuration().lightning_dir).join(PLUGIN_NAME);
     loop {
@@ -344,14 +553,15 @@ pub async fn clear_stats(plugin: Plugin<PluginState>) -> Result<(), Error> {
 
             all_jobs.retain(|c| scid_peer_map.contains_key(c));
             for scid in &all_jobs {
-                match SuccessReb::read_from_file(&sling_dir, scid).await {
+                let stats_dir = plugin.state().stats_dir(&sling_dir, scid);
+                match SuccessReb::read_from_file(&stats_dir, scid).await {
                     Ok(o) => {
                         successes.insert(scid, o);
                     }
                     Err(e) => debug!("{}: probably no success stats yet: {:?}", scid, e),
                 };
 
-                match FailureReb::read_from_file(&sling_dir, scid).await {
+                match FailureReb::read_from_file(&stats_dir, scid).await {
                     Ok(o) => {
                         failures.insert(scid, o);
                     }
@@ -382,40 +592,31 @@ pub async fn clear_stats(plugin: Plugin<PluginState>) -> Result<(), Error> {
                 .duration_since(UNIX_EPOCH)
                 .unwrap()
                 .as_secs();
-            let succ_age = sys_time_now - stats_delete_successes_age * 24 * 60 * 60;
-            let fail_age = sys_time_now - stats_delete_failures_age * 24 * 60 * 60;
+            let succ_age = (stats_delete_successes_age > 0)
+                .then(|| sys_time_now - stats_delete_successes_age * 24 * 60 * 60);
+            let fail_age = (stats_delete_failures_age > 0)
+                .then(|| sys_time_now - stats_delete_failures_age * 24 * 60 * 60);
+            let stats_rollup = plugin.state().config.lock().stats_rollup.value;
             for (chan_id, rebs) in successes {
-                let rebs_len = rebs.len();
-                let filtered_rebs = if stats_delete_successes_age > 0 {
-                    rebs.into_iter()
-                        .filter(|c| c.completed_at >= succ_age)
-                        .collect::<Vec<SuccessReb>>()
-                } else {
-                    rebs
-                };
-                let filtered_rebs_len = filtered_rebs.len();
-                debug!(
-                    "{}: filtered {} success entries because of age",
-                    chan_id,
-                    rebs_len - filtered_rebs_len
+                let (kept_rebs, pruned_rebs) = prune_records(
+                    rebs,
+                    |r| r.completed_at,
+                    succ_age,
+                    stats_delete_successes_size,
                 );
-                let pruned_rebs = if stats_delete_successes_size > 0
-                    && filtered_rebs_len as u64 > stats_delete_successes_size
-                {
-                    filtered_rebs
-                        .into_iter()
-                        .skip(filtered_rebs_len - stats_delete_successes_size as usize)
-                        .collect::<Vec<SuccessReb>>()
-                } else {
-                    filtered_rebs
-                };
                 debug!(
-                    "{}: filtered {} success entries because of size",
+                    "{}: pruned {} success entries because of age or size",
                     chan_id,
-                    filtered_rebs_len - pruned_rebs.len()
+                    pruned_rebs.len()
                 );
+                let stats_dir = plugin.state().stats_dir(&sling_dir, chan_id);
+                if stats_rollup && !pruned_rebs.is_empty() {
+                    let mut rollup = read_rollup(&stats_dir, chan_id).await?;
+                    add_to_rollup(&mut rollup, &pruned_rebs, &[]);
+                    write_rollup(&stats_dir, chan_id, &rollup).await?;
+                }
                 let mut content: Vec<u8> = vec![];
-                for reb in &pruned_rebs {
+                for reb in &kept_rebs {
                     let serialized = serde_json::to_string(&reb)?;
                     content.extend(format!("{}\n", serialized).as_bytes());
                 }
@@ -424,42 +625,26 @@ pub async fn clear_stats(plugin: Plugin<PluginState>) -> Result<(), Error> {
                     .write(true)
                     .create(true)
                     .truncate(true)
-                    .open(sling_dir.join(chan_id.to_string() + SUCCESSES_SUFFIX))
+                    .open(stats_dir.join(chan_id.to_string() + SUCCESSES_SUFFIX))
                     .await?;
                 file.write_all(&content).await?;
             }
             for (chan_id, rebs) in failures {
-                let rebs_len = rebs.len();
-                let filtered_rebs = if stats_delete_failures_age > 0 {
-                    rebs.into_iter()
-                        .filter(|c| c.created_at >= fail_age)
-                        .collect::<Vec<FailureReb>>()
-                } else {
-                    rebs
-                };
-                let filtered_rebs_len = filtered_rebs.len();
+                let (kept_rebs, pruned_rebs) =
+                    prune_records(rebs, |r| r.created_at, fail_age, stats_delete_failures_size);
                 debug!(
-                    "{}: filtered {} failure entries because of age",
+                    "{}: pruned {} failure entries because of age or size",
                     chan_id,
-                    rebs_len - filtered_rebs_len
-                );
-                let pruned_rebs = if stats_delete_failures_size > 0
-                    && filtered_rebs_len as u64 > stats_delete_failures_size
-                {
-                    filtered_rebs
-                        .into_iter()
-                        .skip(filtered_rebs_len - stats_delete_failures_size as usize)
-                        .collect::<Vec<FailureReb>>()
-                } else {
-                    filtered_rebs
-                };
-                debug!(
-                    "{}: filtered {} failure entries because of size",
-                    chan_id,
-                    filtered_rebs_len - pruned_rebs.len()
+                    pruned_rebs.len()
                 );
+                let stats_dir = plugin.state().stats_dir(&sling_dir, chan_id);
+                if stats_rollup && !pruned_rebs.is_empty() {
+                    let mut rollup = read_rollup(&stats_dir, chan_id).await?;
+                    add_to_rollup(&mut rollup, &[], &pruned_rebs);
+                    write_rollup(&stats_dir, chan_id, &rollup).await?;
+                }
                 let mut content: Vec<u8> = vec![];
-                for reb in &pruned_rebs {
+                for reb in &kept_rebs {
                     let serialized = serde_json::to_string(&reb)?;
                     content.extend(format!("{}\n", serialized).as_bytes());
                 }
@@ -468,7 +653,7 @@ pub async fn clear_stats(plugin: Plugin<PluginState>) -> Result<(), Error> {
                     .write(true)
                     .create(true)
                     .truncate(true)
-                    .open(sling_dir.join(chan_id.to_string() + FAILURES_SUFFIX))
+                    .open(stats_dir.join(chan_id.to_string() + FAILURES_SUFFIX))
                     .await?;
                 file.write_all(&content).await?;
             }
@@ -151,3 +151,6544 @@ fn test_feeppm_effective_from_amts() {
     let result1 = std::panic::catch_unwind(|| feeppm_effective_from_amts(1_000, 2_000));
     assert!(result1.is_err());
 }
+
+#[test]
+fn test_check_candidatelist() {
+    use crate::util::check_candidatelist;
+    use cln_rpc::primitives::ShortChannelId;
+    use sling::Job;
+    use std::collections::HashSet;
+    use std::str::FromStr;
+
+    let chan_id = ShortChannelId::from_str("100x1x0").unwrap();
+    let own_channels: HashSet<ShortChannelId> = ["100x1x0", "101x1x0", "102x1x0"]
+        .iter()
+        .map(|s| ShortChannelId::from_str(s).unwrap())
+        .collect();
+
+    let mut job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 100,
+        "candidatelist": ["101x1x0", "102x1x0", "101x1x0"]
+    }))
+    .unwrap();
+    assert!(check_candidatelist(&chan_id, &mut job, &own_channels).is_ok());
+    assert_eq!(
+        job.candidatelist.unwrap(),
+        vec![
+            ShortChannelId::from_str("101x1x0").unwrap(),
+            ShortChannelId::from_str("102x1x0").unwrap()
+        ]
+    );
+
+    let mut job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 100,
+        "candidatelist": ["101x1x0", "999x1x0"]
+    }))
+    .unwrap();
+    assert!(check_candidatelist(&chan_id, &mut job, &own_channels).is_err());
+}
+
+#[test]
+fn test_hop_summary() {
+    use crate::model::SuccessReb;
+    use crate::stats::hop_summary;
+    use cln_rpc::primitives::ShortChannelId;
+    use std::str::FromStr;
+
+    assert_eq!(hop_summary(&[]), None);
+
+    let successes = [2, 3, 5, 6]
+        .iter()
+        .map(|h| SuccessReb {
+            amount_msat: 100_000_000,
+            fee_ppm: 100,
+            fee_msat: 10_000,
+            channel_partner: Some(ShortChannelId::from_str("101x1x0").unwrap()),
+            hops: *h,
+            completed_at: 0,
+        })
+        .collect::<Vec<SuccessReb>>();
+    let hops = successes.iter().map(|s| s.hops).collect::<Vec<u8>>();
+    assert_eq!(hop_summary(&hops), Some((4.0, 2, 6)));
+    assert_eq!(hop_summary(&[3]), Some((3.0, 3, 3)));
+}
+
+fn test_peer_channel(
+    scid: &str,
+    peer_id: &str,
+    total_msat: u64,
+    to_us_msat: u64,
+    htlc_count: u64,
+) -> cln_rpc::model::responses::ListpeerchannelsChannels {
+    let htlcs = (0..htlc_count)
+        .map(|id| {
+            serde_json::json!({
+                "direction": "out",
+                "id": id,
+                "amount_msat": 1_000_000,
+                "expiry": 800_000,
+                "payment_hash": "0000000000000000000000000000000000000000000000000000000000000000",
+                "state": "SENT_ADD_HTLC"
+            })
+        })
+        .collect::<Vec<serde_json::Value>>();
+    serde_json::from_value(serde_json::json!({
+        "peer_id": peer_id,
+        "peer_connected": true,
+        "state": "CHANNELD_NORMAL",
+        "opener": "local",
+        "features": [],
+        "short_channel_id": scid,
+        "direction": 0,
+        "private": false,
+        "total_msat": total_msat,
+        "to_us_msat": to_us_msat,
+        "our_reserve_msat": total_msat / 100,
+        "their_reserve_msat": total_msat / 100,
+        "spendable_msat": to_us_msat.saturating_sub(total_msat / 100),
+        "receivable_msat": (total_msat - to_us_msat).saturating_sub(total_msat / 100),
+        "fee_base_msat": 0,
+        "fee_proportional_millionths": 100,
+        "htlcs": htlcs,
+        "updates": {
+            "local": {
+                "htlc_minimum_msat": 1,
+                "htlc_maximum_msat": total_msat,
+                "cltv_expiry_delta": 144,
+                "fee_base_msat": 0,
+                "fee_proportional_millionths": 100
+            },
+            "remote": {
+                "htlc_minimum_msat": 1,
+                "htlc_maximum_msat": total_msat,
+                "cltv_expiry_delta": 144,
+                "fee_base_msat": 0,
+                "fee_proportional_millionths": 100
+            }
+        }
+    }))
+    .unwrap()
+}
+
+fn test_config() -> crate::model::Config {
+    use cln_rpc::primitives::PublicKey;
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    crate::model::Config::new(
+        PublicKey::from_str(TEST_PEER_A).unwrap(),
+        PathBuf::from("lightning-rpc"),
+        PathBuf::from("sling"),
+        PathBuf::from("."),
+        "v24.05".to_string(),
+    )
+}
+
+const TEST_PEER_A: &str = "0279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798";
+const TEST_PEER_B: &str = "02C6047F9441ED7D6D3045406E95C07CD85C778E4B8CEF3CA7ABAC09B95C709EE5";
+
+#[test]
+fn test_get_peer_htlc_counts() {
+    use crate::util::get_peer_htlc_counts;
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    let peer_channels = [
+        test_peer_channel("101x1x0", TEST_PEER_A, 1_000_000_000, 500_000_000, 3),
+        test_peer_channel("102x1x0", TEST_PEER_A, 1_000_000_000, 500_000_000, 4),
+        test_peer_channel("103x1x0", TEST_PEER_B, 1_000_000_000, 500_000_000, 1),
+    ]
+    .into_iter()
+    .map(|c| (c.short_channel_id.unwrap(), c))
+    .collect::<HashMap<ShortChannelId, _>>();
+
+    let counts = get_peer_htlc_counts(&peer_channels);
+    assert_eq!(counts.len(), 2);
+    assert_eq!(counts[&PublicKey::from_str(TEST_PEER_A).unwrap()], 7);
+    assert_eq!(counts[&PublicKey::from_str(TEST_PEER_B).unwrap()], 1);
+}
+
+#[test]
+fn test_build_candidatelist_max_peer_htlc_count() {
+    use crate::model::LnGraph;
+    use crate::slings::build_candidatelist;
+    use cln_rpc::primitives::ShortChannelId;
+    use sling::Job;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    let peer_channels = [
+        test_peer_channel("101x1x0", TEST_PEER_A, 1_000_000_000, 800_000_000, 3),
+        test_peer_channel("102x1x0", TEST_PEER_A, 1_000_000_000, 800_000_000, 3),
+        test_peer_channel("103x1x0", TEST_PEER_B, 1_000_000_000, 800_000_000, 0),
+    ]
+    .into_iter()
+    .map(|c| (c.short_channel_id.unwrap(), c))
+    .collect::<HashMap<ShortChannelId, _>>();
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 100
+    }))
+    .unwrap();
+    let mut config = test_config();
+
+    let candidates = build_candidatelist(
+        &peer_channels,
+        &job,
+        &LnGraph::new(),
+        &HashMap::new(),
+        &config,
+        None,
+        1_000,
+    );
+    assert_eq!(candidates.len(), 3);
+
+    config.max_peer_htlc_count.value = 5;
+    let candidates = build_candidatelist(
+        &peer_channels,
+        &job,
+        &LnGraph::new(),
+        &HashMap::new(),
+        &config,
+        None,
+        1_000,
+    );
+    assert_eq!(
+        candidates,
+        vec![ShortChannelId::from_str("103x1x0").unwrap()]
+    );
+}
+
+#[test]
+fn test_parse_job_dryrun() {
+    use crate::parse::parse_job;
+    use sling::Job;
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let args = serde_json::json!({
+        "scid": "100x1x0",
+        "direction": "pull",
+        "amount": 100_000,
+        "maxppm": 100,
+        "outppm": 0
+    });
+    let (_, job) = rt.block_on(parse_job(args.clone())).unwrap();
+    assert!(!job.dryrun);
+    assert!(serde_json::to_value(&job).unwrap().get("dryrun").is_none());
+
+    let mut dryrun_args = args.clone();
+    dryrun_args["dryrun"] = serde_json::json!(true);
+    let (_, job) = rt.block_on(parse_job(dryrun_args)).unwrap();
+    assert!(job.dryrun);
+    let serialized = serde_json::to_value(&job).unwrap();
+    assert_eq!(serialized["dryrun"], true);
+    assert_eq!(serde_json::from_value::<Job>(serialized).unwrap(), job);
+
+    let mut bad_args = args;
+    bad_args["dryrun"] = serde_json::json!("yes");
+    assert!(rt.block_on(parse_job(bad_args)).is_err());
+}
+
+#[test]
+fn test_dryrun_job_does_not_send() {
+    use crate::model::{channel_jobstate_update, JobMessage, JobState, Task};
+    use crate::util::dryrun_route;
+    use cln_rpc::model::requests::SendpayRoute;
+    use cln_rpc::primitives::{Amount, PublicKey, ShortChannelId};
+    use parking_lot::Mutex;
+    use sling::Job;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    let sling_dir = std::env::temp_dir().join(format!("sling-test-dryrun-{}", std::process::id()));
+    std::fs::create_dir_all(&sling_dir).unwrap();
+    let task = Task {
+        chan_id: ShortChannelId::from_str("100x1x0").unwrap(),
+        task_id: 1,
+    };
+    let job_states = Arc::new(Mutex::new(HashMap::from([(
+        task.chan_id,
+        vec![JobState::new(JobMessage::Rebalancing, 1)],
+    )])));
+    let route = vec![
+        SendpayRoute {
+            amount_msat: Amount::from_msat(100_010_000),
+            id: PublicKey::from_str(TEST_PEER_A).unwrap(),
+            delay: 288,
+            channel: ShortChannelId::from_str("101x1x0").unwrap(),
+        },
+        SendpayRoute {
+            amount_msat: Amount::from_msat(100_000_000),
+            id: PublicKey::from_str(TEST_PEER_B).unwrap(),
+            delay: 144,
+            channel: task.chan_id,
+        },
+    ];
+    let mut job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 200,
+        "outppm": 0,
+        "dryrun": true
+    }))
+    .unwrap();
+
+    // the loop only sends and records the outcome when the route is not a dryrun
+    assert!(dryrun_route(job_states.clone(), &task, &job, &route, 100).unwrap());
+    let state = job_states.lock().get(&task.chan_id).unwrap()[0].clone();
+    assert!(matches!(state.state(), JobMessage::DryRun));
+    assert!(state.is_active());
+    assert_eq!(
+        std::fs::read_dir(job.stats_dir(&sling_dir))
+            .unwrap()
+            .count(),
+        0
+    );
+
+    job.dryrun = false;
+    channel_jobstate_update(
+        job_states.clone(),
+        &task,
+        &JobMessage::Rebalancing,
+        true,
+        false,
+    )
+    .unwrap();
+    assert!(!dryrun_route(job_states.clone(), &task, &job, &route, 100).unwrap());
+    assert!(matches!(
+        job_states.lock().get(&task.chan_id).unwrap()[0].state(),
+        JobMessage::Rebalancing
+    ));
+
+    std::fs::remove_dir_all(&sling_dir).unwrap();
+}
+
+const TEST_PEER_C: &str = "02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9";
+const TEST_PEER_D: &str = "02E493DBF1C10D80F3581E4904930B1404CC6C13900EE0758474FA94ABE8C4CD13";
+
+fn test_graph_edge(
+    graph: &mut crate::model::LnGraph,
+    scid: &str,
+    source: &str,
+    destination: &str,
+    fee_per_millionth: u32,
+) {
+    use crate::model::DirectedChannelState;
+    use cln_rpc::primitives::{Amount, PublicKey, ShortChannelId};
+    use sling::DirectedChannel;
+    use std::str::FromStr;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let source = PublicKey::from_str(source).unwrap();
+    let destination = PublicKey::from_str(destination).unwrap();
+    graph.graph.entry(source).or_default().insert(
+        DirectedChannel {
+            short_channel_id: ShortChannelId::from_str(scid).unwrap(),
+            direction: if source < destination { 0 } else { 1 },
+        },
+        DirectedChannelState {
+            source,
+            destination,
+            active: true,
+            scid_alias: None,
+            fee_per_millionth,
+            base_fee_millisatoshi: 0,
+            htlc_maximum_msat: Amount::from_msat(1_000_000_000),
+            htlc_minimum_msat: Amount::from_msat(1),
+            amount_msat: Amount::from_msat(1_000_000_000),
+            delay: 144,
+            last_update: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as u32,
+            liquidity: 1_000_000_000,
+            liquidity_age: 0,
+            confidence_age: 0,
+        },
+    );
+}
+
+#[test]
+fn test_dijkstra_reliability_weight() {
+    use crate::dijkstra::dijkstra;
+    use crate::model::{DijkstraNode, ExcludeGraph, LnGraph};
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use sling::Job;
+    use std::collections::{HashMap, HashSet};
+    use std::str::FromStr;
+
+    let me = PublicKey::from_str(TEST_PEER_A).unwrap();
+    let other = PublicKey::from_str(TEST_PEER_B).unwrap();
+    let flaky = ShortChannelId::from_str("101x1x0").unwrap();
+    let reliable = ShortChannelId::from_str("102x1x0").unwrap();
+
+    let mut graph = LnGraph::new();
+    test_graph_edge(&mut graph, "100x1x0", TEST_PEER_B, TEST_PEER_A, 0);
+    test_graph_edge(&mut graph, "101x1x0", TEST_PEER_A, TEST_PEER_C, 0);
+    test_graph_edge(&mut graph, "102x1x0", TEST_PEER_A, TEST_PEER_D, 0);
+    test_graph_edge(&mut graph, "103x1x0", TEST_PEER_C, TEST_PEER_B, 10);
+    test_graph_edge(&mut graph, "104x1x0", TEST_PEER_D, TEST_PEER_B, 50);
+
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 100,
+        "candidatelist": ["101x1x0", "102x1x0"]
+    }))
+    .unwrap();
+    let slingchan_inc = graph.get_channel(&other, &ShortChannelId::from_str("100x1x0").unwrap());
+    let slingchan = DijkstraNode {
+        score: 0,
+        destination: me,
+        channel_state: slingchan_inc.unwrap(),
+        hops: 0,
+        short_channel_id: ShortChannelId::from_str("100x1x0").unwrap(),
+    };
+    let failure_rates = HashMap::from([(flaky, 0.9), (reliable, 0.0)]);
+    let route_with_weight = |weight: f64| {
+        dijkstra(
+            &me,
+            &graph,
+            &me,
+            &other,
+            &slingchan,
+            &job,
+            &[flaky, reliable],
+            9,
+            &ExcludeGraph {
+                exclude_chans: HashSet::new(),
+                exclude_peers: HashSet::new(),
+            },
+            144,
+            &HashMap::new(),
+            &[],
+            &failure_rates,
+            weight,
+            0,
+            0,
+            None,
+            0,
+            &HashMap::new(),
+        )
+        .unwrap()
+    };
+
+    let route = route_with_weight(0.0);
+    assert_eq!(route.first().unwrap().channel, flaky);
+
+    let route = route_with_weight(1.0);
+    assert_eq!(route.first().unwrap().channel, reliable);
+}
+
+#[test]
+fn test_failure_rates() {
+    use crate::model::{FailureReb, SuccessReb};
+    use crate::util::{failure_rates, reliability_penalty};
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use std::str::FromStr;
+
+    let flaky = ShortChannelId::from_str("101x1x0").unwrap();
+    let reliable = ShortChannelId::from_str("102x1x0").unwrap();
+    let successes = [flaky, reliable, reliable]
+        .iter()
+        .map(|scid| SuccessReb {
+            amount_msat: 100_000_000,
+            fee_ppm: 100,
+            fee_msat: 10_000,
+            channel_partner: Some(*scid),
+            hops: 3,
+            completed_at: 0,
+        })
+        .collect::<Vec<SuccessReb>>();
+    let failures = [flaky, flaky, flaky]
+        .iter()
+        .map(|scid| FailureReb {
+            amount_msat: 100_000_000,
+            failure_reason: "WIRE_TEMPORARY_CHANNEL_FAILURE".to_string(),
+            failure_node: PublicKey::from_str(TEST_PEER_C).unwrap(),
+            channel_partner: *scid,
+            hops: 3,
+            created_at: 0,
+        })
+        .collect::<Vec<FailureReb>>();
+
+    let rates = failure_rates(&successes, &failures);
+    assert_eq!(rates[&flaky], 0.75);
+    assert_eq!(rates[&reliable], 0.0);
+
+    assert_eq!(reliability_penalty(0.75, 0.0, 100_000_000), 0);
+    assert_eq!(reliability_penalty(0.75, 1.0, 100_000_000), 75_000);
+    assert_eq!(reliability_penalty(0.0, 1.0, 100_000_000), 0);
+}
+
+#[test]
+fn test_remove_stats_files() {
+    use crate::model::{FailureReb, SuccessReb};
+    use crate::stats::remove_stats_files;
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use std::str::FromStr;
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let sling_dir = std::env::temp_dir().join(format!("sling-test-clear-{}", std::process::id()));
+    std::fs::create_dir_all(&sling_dir).unwrap();
+    let scid = ShortChannelId::from_str("100x1x0").unwrap();
+    let other_scid = ShortChannelId::from_str("200x1x0").unwrap();
+    let success = SuccessReb {
+        amount_msat: 100_000_000,
+        fee_ppm: 100,
+        fee_msat: 10_000,
+        channel_partner: Some(ShortChannelId::from_str("101x1x0").unwrap()),
+        hops: 3,
+        completed_at: 0,
+    };
+    let failure = FailureReb {
+        amount_msat: 100_000_000,
+        failure_reason: "WIRE_TEMPORARY_CHANNEL_FAILURE".to_string(),
+        failure_node: PublicKey::from_str(TEST_PEER_C).unwrap(),
+        channel_partner: ShortChannelId::from_str("101x1x0").unwrap(),
+        hops: 3,
+        created_at: 0,
+    };
+
+    rt.block_on(async {
+        for chan_id in [scid, other_scid] {
+            success.write_to_file(chan_id, &sling_dir).await.unwrap();
+            failure.write_to_file(chan_id, &sling_dir).await.unwrap();
+        }
+        assert_eq!(
+            SuccessReb::read_from_file(&sling_dir, &scid)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        assert_eq!(
+            remove_stats_files(&sling_dir, Some(&scid)).await.unwrap(),
+            2
+        );
+        assert!(SuccessReb::read_from_file(&sling_dir, &scid)
+            .await
+            .unwrap_or_default()
+            .is_empty());
+        assert!(FailureReb::read_from_file(&sling_dir, &scid)
+            .await
+            .unwrap_or_default()
+            .is_empty());
+        assert_eq!(
+            FailureReb::read_from_file(&sling_dir, &other_scid)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            remove_stats_files(&sling_dir, Some(&scid)).await.unwrap(),
+            0
+        );
+
+        assert_eq!(remove_stats_files(&sling_dir, None).await.unwrap(), 2);
+        assert!(SuccessReb::read_from_file(&sling_dir, &other_scid)
+            .await
+            .unwrap_or_default()
+            .is_empty());
+    });
+    std::fs::remove_dir_all(&sling_dir).unwrap();
+}
+
+#[test]
+fn test_liquidity_belief_max_age() {
+    use crate::model::{ExcludeGraph, LnGraph, PublicKeyPair};
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use sling::{DirectedChannel, Job};
+    use std::collections::{HashMap, HashSet};
+    use std::str::FromStr;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let mut graph = LnGraph::new();
+    test_graph_edge(&mut graph, "103x1x0", TEST_PEER_C, TEST_PEER_B, 10);
+    let source = PublicKey::from_str(TEST_PEER_C).unwrap();
+    let dir_chan = DirectedChannel {
+        short_channel_id: ShortChannelId::from_str("103x1x0").unwrap(),
+        direction: 1,
+    };
+    let channel_state = graph
+        .graph
+        .get_mut(&source)
+        .unwrap()
+        .get_mut(&dir_chan)
+        .unwrap();
+    channel_state.liquidity = 500_000_000;
+    channel_state.liquidity_age = now - 2 * 60 * 60;
+
+    assert_eq!(channel_state.liquidity_belief(now, 0), 500_000_000);
+    assert_eq!(channel_state.liquidity_belief(now, 180), 500_000_000);
+    assert_eq!(channel_state.liquidity_belief(now, 60), 250_000_000);
+
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 300_000_000,
+        "maxppm": 100,
+        "outppm": 0
+    }))
+    .unwrap();
+    let edges = |liquidity_max_age: u64| {
+        graph
+            .edges(
+                &PublicKeyPair {
+                    my_pubkey: PublicKey::from_str(TEST_PEER_A).unwrap(),
+                    other_pubkey: source,
+                },
+                &ExcludeGraph {
+                    exclude_chans: HashSet::new(),
+                    exclude_peers: HashSet::new(),
+                },
+                &job,
+                &[],
+                &HashMap::new(),
+                &[],
+                liquidity_max_age,
+                0,
+            )
+            .len()
+    };
+    assert_eq!(edges(0), 1);
+    assert_eq!(edges(60), 0);
+}
+
+#[test]
+fn test_candidatelist_backup() {
+    use crate::model::LnGraph;
+    use crate::slings::build_candidatelist;
+    use cln_rpc::primitives::ShortChannelId;
+    use sling::Job;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    let primary = ShortChannelId::from_str("101x1x0").unwrap();
+    let backup = ShortChannelId::from_str("102x1x0").unwrap();
+    let mut peer_channels = [
+        test_peer_channel("101x1x0", TEST_PEER_A, 1_000_000_000, 800_000_000, 0),
+        test_peer_channel("102x1x0", TEST_PEER_B, 1_000_000_000, 800_000_000, 0),
+    ]
+    .into_iter()
+    .map(|c| (c.short_channel_id.unwrap(), c))
+    .collect::<HashMap<ShortChannelId, _>>();
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 100,
+        "candidatelist": ["101x1x0"],
+        "candidatelist_backup": ["102x1x0"]
+    }))
+    .unwrap();
+    let config = test_config();
+    let candidates = |job: &Job, peer_channels: &HashMap<ShortChannelId, _>| {
+        build_candidatelist(
+            peer_channels,
+            job,
+            &LnGraph::new(),
+            &HashMap::new(),
+            &config,
+            job.candidatelist.as_ref(),
+            1_000,
+        )
+    };
+
+    assert_eq!(candidates(&job, &peer_channels), vec![primary]);
+
+    peer_channels.insert(
+        primary,
+        test_peer_channel("101x1x0", TEST_PEER_A, 1_000_000_000, 100_000_000, 0),
+    );
+    assert!(candidates(&job, &peer_channels).is_empty());
+    let backup_job = job.backup_job().unwrap();
+    assert_eq!(backup_job.candidatelist_backup, None);
+    assert_eq!(candidates(&backup_job, &peer_channels), vec![backup]);
+    assert_eq!(
+        job.to_json()["candidates_backup"],
+        serde_json::json!("102x1x0")
+    );
+
+    let job_without_backup = Job {
+        candidatelist_backup: None,
+        ..job
+    };
+    assert!(job_without_backup.backup_job().is_none());
+}
+
+#[test]
+fn test_graph_prune() {
+    use crate::model::LnGraph;
+    use cln_rpc::primitives::{Amount, PublicKey, ShortChannelId};
+    use std::collections::HashSet;
+    use std::str::FromStr;
+
+    let test_peer_e = "022F8BDE4D1A07209355B4A7250A5C5128E88B84BDDC619AB7CBA8D569B240EFE4";
+    let me = PublicKey::from_str(TEST_PEER_A).unwrap();
+    let mut graph = LnGraph::new();
+    test_graph_edge(&mut graph, "101x1x0", TEST_PEER_A, TEST_PEER_C, 0);
+    test_graph_edge(&mut graph, "101x1x0", TEST_PEER_C, TEST_PEER_A, 0);
+    test_graph_edge(&mut graph, "102x1x0", TEST_PEER_A, TEST_PEER_D, 0);
+    test_graph_edge(&mut graph, "103x1x0", TEST_PEER_C, TEST_PEER_B, 10);
+    test_graph_edge(&mut graph, "104x1x0", TEST_PEER_D, TEST_PEER_B, 50);
+    test_graph_edge(&mut graph, "105x1x0", TEST_PEER_B, test_peer_e, 50);
+    for channel_state in graph
+        .graph
+        .get_mut(&PublicKey::from_str(TEST_PEER_D).unwrap())
+        .unwrap()
+        .values_mut()
+    {
+        channel_state.amount_msat = Amount::from_msat(2_000_000_000);
+    }
+    let scids = |graph: &LnGraph| {
+        graph
+            .graph
+            .values()
+            .flat_map(|c| c.keys().map(|d| d.short_channel_id.to_string()))
+            .collect::<HashSet<String>>()
+    };
+
+    let mut unlimited = LnGraph {
+        graph: graph.graph.clone(),
+    };
+    assert_eq!(unlimited.prune(&me, 10), 0);
+    assert_eq!(scids(&unlimited).len(), 5);
+
+    let mut limited = LnGraph {
+        graph: graph.graph.clone(),
+    };
+    assert_eq!(limited.prune(&me, 3), 2);
+    assert_eq!(
+        scids(&limited),
+        HashSet::from([
+            "101x1x0".to_string(),
+            "102x1x0".to_string(),
+            "104x1x0".to_string()
+        ])
+    );
+    assert!(limited
+        .get_channel(
+            &PublicKey::from_str(TEST_PEER_C).unwrap(),
+            &ShortChannelId::from_str("101x1x0").unwrap()
+        )
+        .is_ok());
+
+    assert_eq!(graph.prune(&me, 1), 3);
+    assert_eq!(
+        scids(&graph),
+        HashSet::from(["101x1x0".to_string(), "102x1x0".to_string()])
+    );
+}
+
+#[test]
+fn test_route_attempts() {
+    use crate::model::RouteAttempts;
+
+    let mut unlimited = RouteAttempts::default();
+    assert!((0..100).all(|_| !unlimited.record(false, 0)));
+
+    let mut attempts = RouteAttempts::default();
+    assert!(!attempts.record(false, 3));
+    assert!(!attempts.record(false, 3));
+    assert!(attempts.record(false, 3));
+    assert!(!attempts.record(false, 3));
+
+    assert!(!attempts.record(true, 3));
+    assert!(!attempts.record(false, 3));
+    assert!(!attempts.record(false, 3));
+    assert!(attempts.record(false, 3));
+
+    assert!(!attempts.record(false, 3));
+    attempts.reset();
+    assert!(!attempts.record(false, 3));
+    assert!(!attempts.record(false, 3));
+    assert!(attempts.record(false, 3));
+}
+
+#[test]
+fn test_maintenance_keeps_refreshing() {
+    use crate::model::{JobMessage, PluginState};
+    use cln_rpc::primitives::PublicKey;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    let state = PluginState::new(
+        PublicKey::from_str(TEST_PEER_A).unwrap(),
+        PathBuf::from("lightning-rpc"),
+        PathBuf::from("sling"),
+        PathBuf::from("."),
+        "v24.05".to_string(),
+    );
+    let config = state.config.lock().clone();
+    assert!(state.dispatch_paused(&config).is_none());
+    *state.maintenance.lock() = true;
+    // sling() checks this right before building a route, so nothing is sent
+    assert!(matches!(
+        state.dispatch_paused(&config),
+        Some(JobMessage::Maintenance)
+    ));
+
+    // the refresh tasks don't look at the flag and keep the graph up to date
+    test_graph_edge(
+        &mut state.graph.lock(),
+        "103x1x0",
+        TEST_PEER_C,
+        TEST_PEER_B,
+        10,
+    );
+    for channel_state in state
+        .graph
+        .lock()
+        .graph
+        .values_mut()
+        .flat_map(|c| c.values_mut())
+    {
+        channel_state.liquidity = 0;
+    }
+    state.graph.lock().refresh_liquidity(0, &HashMap::new());
+    assert!(state
+        .graph
+        .lock()
+        .graph
+        .values()
+        .flat_map(|c| c.values())
+        .all(|c| c.liquidity == 500_000_000));
+    assert!(matches!(
+        state.dispatch_paused(&config),
+        Some(JobMessage::Maintenance)
+    ));
+
+    *state.maintenance.lock() = false;
+    assert!(state.dispatch_paused(&config).is_none());
+}
+
+#[test]
+fn test_flow_liquidity_priors() {
+    use crate::model::SuccessReb;
+    use crate::util::{historical_flows, liquidity_prior};
+    use cln_rpc::primitives::ShortChannelId;
+    use std::collections::{HashMap, HashSet};
+    use std::str::FromStr;
+
+    let pull_job = ShortChannelId::from_str("100x1x0").unwrap();
+    let outbound = ShortChannelId::from_str("101x1x0").unwrap();
+    let unknown = ShortChannelId::from_str("102x1x0").unwrap();
+    let successes = HashMap::from([(
+        pull_job,
+        (0..4)
+            .map(|_| SuccessReb {
+                amount_msat: 100_000_000,
+                fee_ppm: 100,
+                fee_msat: 10_000,
+                channel_partner: Some(outbound),
+                hops: 3,
+                completed_at: 0,
+            })
+            .collect::<Vec<SuccessReb>>(),
+    )]);
+    let flows = historical_flows(&successes, &HashSet::from([pull_job]));
+    assert_eq!(flows[&outbound], (400_000_000, 0));
+    assert_eq!(flows[&pull_job], (0, 400_000_000));
+
+    let htlc_max = 1_000_000_000;
+    assert!(liquidity_prior(htlc_max, flows.get(&outbound), false) < htlc_max / 2);
+    assert!(liquidity_prior(htlc_max, flows.get(&outbound), true) > htlc_max / 2);
+    assert_eq!(
+        liquidity_prior(htlc_max, flows.get(&outbound), false),
+        100_000_000
+    );
+    assert!(liquidity_prior(htlc_max, flows.get(&pull_job), false) > htlc_max / 2);
+    assert_eq!(
+        liquidity_prior(htlc_max, flows.get(&unknown), false),
+        htlc_max / 2
+    );
+}
+
+#[test]
+fn test_effective_job_config() {
+    use crate::model::EffectiveJobConfig;
+    use sling::Job;
+
+    let mut config = test_config();
+    config.maxhops.value = 6;
+    config.paralleljobs.value = 2;
+    config.timeoutpay.value = 90;
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 100,
+        "maxhops": 4,
+        "depleteuptoamount": 1_000_000_000_u64
+    }))
+    .unwrap();
+
+    let effective = EffectiveJobConfig::new(&job, &config);
+    assert_eq!(effective.maxhops, 4);
+    assert_eq!(effective.depleteuptoamount, 1_000_000_000);
+    assert_eq!(effective.paralleljobs, 2);
+    assert_eq!(effective.timeoutpay, 90);
+    assert_eq!(
+        effective.depleteuptopercent,
+        config.depleteuptopercent.value
+    );
+    assert_eq!(effective.target, 0.5);
+    assert!(!effective.dryrun);
+}
+
+#[test]
+fn test_peer_job_aggregates_channels() {
+    use crate::model::LnGraph;
+    use crate::slings::build_candidatelist;
+    use crate::util::{get_job_channels, pick_job_channel, resolve_job_chan_id};
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use sling::Job;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    let peer_b = PublicKey::from_str(TEST_PEER_B).unwrap();
+    let peer_channels = [
+        test_peer_channel("101x1x0", TEST_PEER_B, 1_000_000_000, 800_000_000, 0),
+        test_peer_channel("102x1x0", TEST_PEER_B, 1_000_000_000, 100_000_000, 0),
+        test_peer_channel("103x1x0", TEST_PEER_A, 1_000_000_000, 800_000_000, 0),
+    ]
+    .into_iter()
+    .map(|c| (c.short_channel_id.unwrap(), c))
+    .collect::<HashMap<ShortChannelId, _>>();
+    let chan_job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 100
+    }))
+    .unwrap();
+    let peer_job = Job {
+        peer: Some(peer_b),
+        ..chan_job.clone()
+    };
+
+    let anchor = resolve_job_chan_id(&peer_channels, None, Some(peer_b)).unwrap();
+    assert_eq!(anchor, ShortChannelId::from_str("101x1x0").unwrap());
+    assert!(resolve_job_chan_id(
+        &peer_channels,
+        Some(ShortChannelId::from_str("103x1x0").unwrap()),
+        Some(peer_b)
+    )
+    .is_err());
+
+    let chan_channels = get_job_channels(&peer_channels, &anchor, &chan_job, 0);
+    assert_eq!(chan_channels.len(), 1);
+    assert!(chan_job.is_balanced(&chan_channels, &anchor));
+
+    let peer_job_channels = get_job_channels(&peer_channels, &anchor, &peer_job, 0);
+    assert_eq!(peer_job_channels.len(), 2);
+    assert_eq!(
+        peer_job.target_cap(&peer_job_channels),
+        2 * peer_job.target_cap(&chan_channels)
+    );
+    assert!(!peer_job.is_balanced(&peer_job_channels, &anchor));
+    assert_eq!(
+        pick_job_channel(&peer_job, &peer_job_channels, 30),
+        Some(ShortChannelId::from_str("102x1x0").unwrap())
+    );
+
+    let candidates = build_candidatelist(
+        &peer_channels,
+        &peer_job,
+        &LnGraph::new(),
+        &HashMap::new(),
+        &test_config(),
+        None,
+        1_000,
+    );
+    assert_eq!(
+        candidates,
+        vec![ShortChannelId::from_str("103x1x0").unwrap()]
+    );
+}
+
+#[test]
+fn test_last_route_after_failure() {
+    use crate::model::{
+        channel_last_route_update, get_last_route, FailureReb, JobMessage, JobState, LastRoute,
+        Task,
+    };
+    use cln_rpc::model::requests::SendpayRoute;
+    use cln_rpc::primitives::{Amount, PublicKey, ShortChannelId};
+    use parking_lot::Mutex;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    let chan_id = ShortChannelId::from_str("100x1x0").unwrap();
+    let task = Task {
+        chan_id,
+        task_id: 1,
+    };
+    let job_states = Arc::new(Mutex::new(HashMap::from([(
+        chan_id,
+        vec![JobState::new(JobMessage::Rebalancing, 1)],
+    )])));
+    assert!(get_last_route(&job_states.lock(), &chan_id).is_none());
+
+    let peer_b = PublicKey::from_str(TEST_PEER_B).unwrap();
+    let peer_c = PublicKey::from_str(TEST_PEER_C).unwrap();
+    let route = vec![
+        SendpayRoute {
+            amount_msat: Amount::from_msat(100_010_000),
+            id: peer_b,
+            delay: 288,
+            channel: ShortChannelId::from_str("101x1x0").unwrap(),
+        },
+        SendpayRoute {
+            amount_msat: Amount::from_msat(100_000_000),
+            id: peer_c,
+            delay: 144,
+            channel: ShortChannelId::from_str("102x1x0").unwrap(),
+        },
+    ];
+    let failure = FailureReb {
+        amount_msat: 100_000_000,
+        failure_reason: "WIRE_TEMPORARY_CHANNEL_FAILURE".to_string(),
+        failure_node: peer_b,
+        channel_partner: ShortChannelId::from_str("101x1x0").unwrap(),
+        hops: 1,
+        created_at: 0,
+    };
+    channel_last_route_update(
+        job_states.clone(),
+        &task,
+        LastRoute::new(task.task_id, &route, Some(1), Some(failure)),
+    );
+
+    let last_route = get_last_route(&job_states.lock(), &chan_id).unwrap();
+    assert_eq!(last_route.task_id, 1);
+    assert_eq!(last_route.route.len(), 2);
+    assert_eq!(last_route.route[0].peer_id, peer_b);
+    assert_eq!(last_route.route[0].fee_msat, 10_000);
+    assert_eq!(last_route.route[1].fee_msat, 0);
+    assert_eq!(last_route.failure_index, Some(1));
+    assert_eq!(last_route.failure.unwrap().failure_node, peer_b);
+}
+
+#[test]
+fn test_split_amount_routes_partial() {
+    use crate::dijkstra::dijkstra;
+    use crate::model::{DijkstraNode, ExcludeGraph, LnGraph};
+    use cln_rpc::primitives::{Amount, PublicKey, ShortChannelId};
+    use sling::Job;
+    use std::collections::{HashMap, HashSet};
+    use std::str::FromStr;
+
+    let me = PublicKey::from_str(TEST_PEER_A).unwrap();
+    let other = PublicKey::from_str(TEST_PEER_B).unwrap();
+    let candidate = ShortChannelId::from_str("101x1x0").unwrap();
+
+    let mut graph = LnGraph::new();
+    test_graph_edge(&mut graph, "100x1x0", TEST_PEER_B, TEST_PEER_A, 0);
+    test_graph_edge(&mut graph, "101x1x0", TEST_PEER_A, TEST_PEER_C, 0);
+    test_graph_edge(&mut graph, "102x1x0", TEST_PEER_C, TEST_PEER_B, 10);
+    for channel_state in graph
+        .graph
+        .get_mut(&PublicKey::from_str(TEST_PEER_C).unwrap())
+        .unwrap()
+        .values_mut()
+    {
+        channel_state.liquidity = 60_000_000;
+    }
+
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "min_amount_msat": 40_000_000,
+        "maxppm": 100,
+        "candidatelist": ["101x1x0"]
+    }))
+    .unwrap();
+    assert_eq!(job.split_amount(100_000_000), Some(50_000_000));
+    assert_eq!(job.split_amount(50_000_000), Some(40_000_000));
+    assert_eq!(job.split_amount(40_000_000), None);
+    assert_eq!(job.with_amount(1_000).split_amount(1_000), None);
+
+    let slingchan = DijkstraNode {
+        score: 0,
+        destination: me,
+        channel_state: graph
+            .get_channel(&other, &ShortChannelId::from_str("100x1x0").unwrap())
+            .unwrap(),
+        hops: 0,
+        short_channel_id: ShortChannelId::from_str("100x1x0").unwrap(),
+    };
+    let route_for = |job: &Job| {
+        dijkstra(
+            &me,
+            &graph,
+            &me,
+            &other,
+            &slingchan,
+            job,
+            &[candidate],
+            9,
+            &ExcludeGraph {
+                exclude_chans: HashSet::new(),
+                exclude_peers: HashSet::new(),
+            },
+            144,
+            &HashMap::new(),
+            &[],
+            &HashMap::new(),
+            0.0,
+            0,
+            0,
+            None,
+            0,
+            &HashMap::new(),
+        )
+        .unwrap()
+    };
+
+    assert!(route_for(&job).is_empty());
+    let half = job.with_amount(job.split_amount(job.amount_msat).unwrap());
+    let route = route_for(&half);
+    assert_eq!(route.first().unwrap().channel, candidate);
+    assert_eq!(Amount::msat(&route.last().unwrap().amount_msat), 50_000_000);
+}
+
+#[test]
+fn test_fee_comparison() {
+    use crate::model::{LnGraph, SuccessReb};
+    use crate::stats::fee_comparison;
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use std::str::FromStr;
+
+    let me = PublicKey::from_str(TEST_PEER_A).unwrap();
+    let peer = PublicKey::from_str(TEST_PEER_B).unwrap();
+    let mut graph = LnGraph::new();
+    test_graph_edge(&mut graph, "100x1x0", TEST_PEER_B, TEST_PEER_A, 5_000);
+    test_graph_edge(&mut graph, "101x1x0", TEST_PEER_C, TEST_PEER_B, 100);
+    test_graph_edge(&mut graph, "102x1x0", TEST_PEER_D, TEST_PEER_B, 200);
+    test_graph_edge(&mut graph, "103x1x0", TEST_PEER_B, TEST_PEER_C, 300);
+
+    let successes = |fee_ppm: u32| {
+        (0..2)
+            .map(|_| SuccessReb {
+                amount_msat: 100_000_000,
+                fee_ppm,
+                fee_msat: 100_000_000 * fee_ppm as u64 / 1_000_000,
+                channel_partner: Some(ShortChannelId::from_str("104x1x0").unwrap()),
+                hops: 2,
+                completed_at: 0,
+            })
+            .collect::<Vec<SuccessReb>>()
+    };
+
+    let comparison = fee_comparison(&successes(350), 0, &graph, &peer, &me).unwrap();
+    assert_eq!(comparison.network_median_hop_feeppm, 200);
+    assert_eq!(comparison.network_expected_feeppm, 400);
+    assert_eq!(comparison.feeppm_weighted_avg, 350);
+    assert!(!comparison.overpaying);
+
+    let comparison = fee_comparison(&successes(1_000), 0, &graph, &peer, &me).unwrap();
+    assert!(comparison.overpaying);
+
+    assert!(fee_comparison(&[], 0, &graph, &peer, &me).is_none());
+}
+
+#[test]
+fn test_pinned_liquidity_survives_reset() {
+    use crate::model::LnGraph;
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use sling::DirectedChannel;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    let mut graph = LnGraph::new();
+    test_graph_edge(&mut graph, "101x1x0", TEST_PEER_B, TEST_PEER_C, 10);
+    test_graph_edge(&mut graph, "102x1x0", TEST_PEER_C, TEST_PEER_D, 10);
+    let pinned_chan = DirectedChannel {
+        short_channel_id: ShortChannelId::from_str("101x1x0").unwrap(),
+        direction: 0,
+    };
+    graph.pin_liquidity(&pinned_chan, 123_000_000).unwrap();
+    let pinned = HashMap::from([(pinned_chan, 123_000_000)]);
+    assert!(graph
+        .pin_liquidity(
+            &DirectedChannel {
+                short_channel_id: ShortChannelId::from_str("103x1x0").unwrap(),
+                direction: 0,
+            },
+            1
+        )
+        .is_err());
+
+    for _ in 0..2 {
+        graph.refresh_liquidity(0, &pinned);
+        let liquidity = |node: &str, scid: &str| {
+            graph.graph[&PublicKey::from_str(node).unwrap()]
+                .iter()
+                .find(|(dc, _)| dc.short_channel_id == ShortChannelId::from_str(scid).unwrap())
+                .unwrap()
+                .1
+                .liquidity
+        };
+        assert_eq!(liquidity(TEST_PEER_B, "101x1x0"), 123_000_000);
+        assert_eq!(liquidity(TEST_PEER_C, "102x1x0"), 500_000_000);
+    }
+
+    graph.refresh_liquidity(0, &HashMap::new());
+    assert!(graph
+        .graph
+        .values()
+        .flat_map(|c| c.values())
+        .all(|c| c.liquidity == 500_000_000));
+}
+
+#[test]
+fn test_post_success_command_args() {
+    use crate::model::SuccessReb;
+    use crate::util::post_success_command;
+    use cln_rpc::primitives::ShortChannelId;
+    use std::ffi::OsStr;
+    use std::str::FromStr;
+
+    let chan_id = ShortChannelId::from_str("100x1x0").unwrap();
+    let success_reb = SuccessReb {
+        amount_msat: 100_000_000,
+        fee_ppm: 250,
+        fee_msat: 25_000,
+        channel_partner: Some(ShortChannelId::from_str("101x1x0").unwrap()),
+        hops: 3,
+        completed_at: 0,
+    };
+    assert!(post_success_command("", &chan_id, &success_reb).is_none());
+
+    let cmd = post_success_command(
+        "/usr/local/bin/fee-script; rm -rf /",
+        &chan_id,
+        &success_reb,
+    )
+    .unwrap();
+    assert_eq!(
+        cmd.get_program(),
+        OsStr::new("/usr/local/bin/fee-script; rm -rf /")
+    );
+    assert_eq!(
+        cmd.get_args().collect::<Vec<_>>(),
+        vec!["100x1x0", "100000000", "250"]
+    );
+    let envs = cmd.get_envs().collect::<Vec<_>>();
+    assert!(envs.contains(&(OsStr::new("SLING_SCID"), Some(OsStr::new("100x1x0")))));
+    assert!(envs.contains(&(
+        OsStr::new("SLING_AMOUNT_MSAT"),
+        Some(OsStr::new("100000000"))
+    )));
+    assert!(envs.contains(&(OsStr::new("SLING_FEE_PPM"), Some(OsStr::new("250")))));
+}
+
+#[test]
+fn test_sort_and_filter_stat_summaries() {
+    use crate::model::StatSummary;
+    use crate::stats::sort_and_filter_stat_summaries;
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use std::str::FromStr;
+
+    let row = |alias: &str, w_feeppm: u64, active: bool| StatSummary {
+        alias: alias.to_string(),
+        scid: ShortChannelId::from_str("100x1x0").unwrap(),
+        pubkey: PublicKey::from_str(TEST_PEER_B).unwrap(),
+        status: String::new(),
+        rebamount: "0".to_string(),
+        w_feeppm,
+        hops: "-".to_string(),
+        last_route_taken: "Never".to_string(),
+        last_success_reb: "Never".to_string(),
+        active,
+        stuck: false,
+        imbalance: 0.0,
+        last_success: 0,
+        tags: Vec::new(),
+    };
+    let rows = || {
+        vec![
+            row("alice", 100, true),
+            row("bob", 500, false),
+            row("carol", 300, true),
+            row("dave", 200, true),
+        ]
+    };
+
+    let mut table = rows();
+    sort_and_filter_stat_summaries(&mut table, Some("w_feeppm"), Some("active")).unwrap();
+    assert_eq!(
+        table.iter().map(|r| r.alias.as_str()).collect::<Vec<_>>(),
+        vec!["carol", "dave", "alice"]
+    );
+
+    let mut table = rows();
+    sort_and_filter_stat_summaries(&mut table, Some("w_feeppm:asc"), None).unwrap();
+    assert_eq!(
+        table.iter().map(|r| r.w_feeppm).collect::<Vec<_>>(),
+        vec![100, 200, 300, 500]
+    );
+
+    let mut table = rows();
+    sort_and_filter_stat_summaries(&mut table, None, Some("stuck")).unwrap();
+    assert!(table.is_empty());
+
+    assert!(sort_and_filter_stat_summaries(&mut rows(), Some("fees"), None).is_err());
+    assert!(sort_and_filter_stat_summaries(&mut rows(), None, Some("broken")).is_err());
+}
+
+#[test]
+fn test_parse_stats_array_args() {
+    use crate::stats::parse_stats_array_args;
+    use cln_rpc::primitives::ShortChannelId;
+    use serde_json::json;
+    use std::str::FromStr;
+
+    let scid = ShortChannelId::from_str("123x1x0").unwrap();
+    assert_eq!(
+        parse_stats_array_args(&[json!("123x1x0")]).unwrap(),
+        (Some(scid), None, None)
+    );
+    assert_eq!(
+        parse_stats_array_args(&[json!("active")]).unwrap(),
+        (None, None, Some("active".to_string()))
+    );
+    assert_eq!(
+        parse_stats_array_args(&[json!("tag:sales"), json!("w_feeppm")]).unwrap(),
+        (
+            None,
+            Some("w_feeppm".to_string()),
+            Some("tag:sales".to_string())
+        )
+    );
+    assert_eq!(
+        parse_stats_array_args(&[json!("imbalance"), json!("stuck")]).unwrap(),
+        (
+            None,
+            Some("imbalance".to_string()),
+            Some("stuck".to_string())
+        )
+    );
+    assert!(parse_stats_array_args(&[json!(1)]).is_err());
+}
+
+#[test]
+fn test_job_message_codes() {
+    use crate::model::JobMessage;
+    use std::collections::HashSet;
+
+    let documented = [
+        (JobMessage::Starting, 1),
+        (JobMessage::Rebalancing, 2),
+        (JobMessage::Balanced, 3),
+        (JobMessage::NoCandidates, 4),
+        (JobMessage::HTLCcapped, 5),
+        (JobMessage::Disconnected, 6),
+        (JobMessage::PeerNotFound, 7),
+        (JobMessage::PeerNotReady, 8),
+        (JobMessage::ChanNotNormal, 9),
+        (JobMessage::GraphEmpty, 10),
+        (JobMessage::ChanNotInGraph, 11),
+        (JobMessage::NoRoute, 12),
+        (JobMessage::TooExp, 13),
+        (JobMessage::MaxAttempts, 14),
+        (JobMessage::DryRun, 15),
+        (JobMessage::Maintenance, 16),
+        (JobMessage::Stopping, 17),
+        (JobMessage::Stopped, 18),
+        (JobMessage::Error, 19),
+        (JobMessage::NoJob, 20),
+        (JobMessage::ChanClosing, 21),
+        (JobMessage::CircuitOpen, 22),
+        (JobMessage::ChanNotFunded, 23),
+        (JobMessage::ChanDisabled, 24),
+        (JobMessage::HtlcStuck, 25),
+        (JobMessage::Follower, 26),
+        (JobMessage::TotalCapReached, 27),
+        (JobMessage::Throttled, 28),
+        (JobMessage::LowUtilization, 29),
+        (JobMessage::StaleData, 30),
+        (JobMessage::GroupWaiting, 31),
+        (JobMessage::OutsideSchedule, 32),
+    ];
+    for (message, code) in &documented {
+        assert_eq!(message.code(), *code, "{}", message);
+    }
+    let codes = documented
+        .iter()
+        .map(|(message, _)| message.code())
+        .collect::<HashSet<u16>>();
+    assert_eq!(codes.len(), documented.len());
+}
+
+#[test]
+fn test_closing_channel_stops_job() {
+    use crate::channel_jobstate_update;
+    use crate::model::{JobMessage, JobState, Task};
+    use crate::util::channel_stop_reason;
+    use cln_rpc::model::responses::ListpeerchannelsChannelsState;
+    use cln_rpc::primitives::ShortChannelId;
+    use parking_lot::Mutex;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    let chan_id = ShortChannelId::from_str("101x1x0").unwrap();
+    let mut peer_channels = [test_peer_channel(
+        "101x1x0",
+        TEST_PEER_A,
+        1_000_000_000,
+        500_000_000,
+        0,
+    )]
+    .into_iter()
+    .map(|c| (c.short_channel_id.unwrap(), c))
+    .collect::<HashMap<ShortChannelId, _>>();
+    assert!(channel_stop_reason(&peer_channels, &chan_id).is_none());
+
+    peer_channels.get_mut(&chan_id).unwrap().state =
+        ListpeerchannelsChannelsState::CHANNELD_SHUTTING_DOWN;
+    let reason = channel_stop_reason(&peer_channels, &chan_id).unwrap();
+    assert!(matches!(reason, JobMessage::ChanClosing));
+
+    let task = Task {
+        chan_id,
+        task_id: 1,
+    };
+    let job_states = Arc::new(Mutex::new(HashMap::from([(
+        chan_id,
+        vec![JobState::new(JobMessage::Rebalancing, 1)],
+    )])));
+    channel_jobstate_update(job_states.clone(), &task, &reason, false, true).unwrap();
+    let state = job_states.lock().get(&chan_id).unwrap()[0].clone();
+    assert!(state.should_stop());
+    assert!(!state.is_active());
+    assert!(matches!(state.state(), JobMessage::ChanClosing));
+
+    peer_channels.get_mut(&chan_id).unwrap().state = ListpeerchannelsChannelsState::ONCHAIN;
+    assert!(matches!(
+        channel_stop_reason(&peer_channels, &chan_id),
+        Some(JobMessage::ChanClosing)
+    ));
+    peer_channels.get_mut(&chan_id).unwrap().state =
+        ListpeerchannelsChannelsState::CHANNELD_AWAITING_LOCKIN;
+    assert!(matches!(
+        channel_stop_reason(&peer_channels, &chan_id),
+        Some(JobMessage::ChanNotNormal)
+    ));
+    assert!(matches!(
+        channel_stop_reason(
+            &peer_channels,
+            &ShortChannelId::from_str("999x1x0").unwrap()
+        ),
+        Some(JobMessage::ChanNotNormal)
+    ));
+}
+
+#[test]
+fn test_graph_sources_are_equivalent() {
+    use crate::gossip::{listchannels_graph_data, GossipStoreSource};
+    use crate::model::LnGraph;
+    use cln_rpc::model::responses::ListchannelsChannels;
+    use cln_rpc::primitives::PublicKey;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    fn record(msg_type: u16, payload: &[u8]) -> Vec<u8> {
+        let mut rec = Vec::new();
+        rec.extend_from_slice(&0u16.to_be_bytes());
+        rec.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+        rec.extend_from_slice(&[0u8; 8]);
+        rec.extend_from_slice(&msg_type.to_be_bytes());
+        rec.extend_from_slice(payload);
+        rec
+    }
+    fn scid_bytes(block: u32) -> Vec<u8> {
+        let mut scid = block.to_be_bytes()[1..].to_vec();
+        scid.extend_from_slice(&[0, 0, 1, 0, 0]);
+        scid
+    }
+
+    let node_a = PublicKey::from_str(TEST_PEER_A).unwrap();
+    let node_b = PublicKey::from_str(TEST_PEER_B).unwrap();
+    let (node_1, node_2) = if node_a < node_b {
+        (node_a, node_b)
+    } else {
+        (node_b, node_a)
+    };
+
+    let mut store = vec![12u8];
+    let mut ann = vec![0u8; 258 + 32];
+    ann.extend(scid_bytes(101));
+    ann.extend_from_slice(&node_1.serialize());
+    ann.extend_from_slice(&node_2.serialize());
+    ann.extend_from_slice(&[0u8; 66]);
+    store.extend(record(256, &ann));
+    store.extend(record(4101, &1_000_000u64.to_be_bytes()));
+    let mut listchannels = Vec::new();
+    for (direction, ppm) in [(0u8, 100u32), (1u8, 250u32)] {
+        let mut update = vec![0u8; 96];
+        update.extend(scid_bytes(101));
+        update.extend_from_slice(&1_700_000_000u32.to_be_bytes());
+        update.push(1);
+        update.push(direction);
+        update.extend_from_slice(&144u16.to_be_bytes());
+        update.extend_from_slice(&1_000u64.to_be_bytes());
+        update.extend_from_slice(&1_000u32.to_be_bytes());
+        update.extend_from_slice(&ppm.to_be_bytes());
+        update.extend_from_slice(&990_000_000u64.to_be_bytes());
+        store.extend(record(258, &update));
+
+        let (source, destination) = if direction == 0 {
+            (node_1, node_2)
+        } else {
+            (node_2, node_1)
+        };
+        listchannels.push(
+            serde_json::from_value::<ListchannelsChannels>(serde_json::json!({
+                "source": source.to_string(),
+                "destination": destination.to_string(),
+                "short_channel_id": "101x1x0",
+                "direction": direction,
+                "public": true,
+                "amount_msat": 1_000_000_000,
+                "message_flags": 1,
+                "channel_flags": direction,
+                "active": true,
+                "last_update": 1_700_000_000,
+                "base_fee_millisatoshi": 1_000,
+                "fee_per_millionth": ppm,
+                "delay": 144,
+                "htlc_minimum_msat": 1_000,
+                "htlc_maximum_msat": 990_000_000,
+                "features": ""
+            }))
+            .unwrap(),
+        );
+    }
+    let store_path =
+        std::env::temp_dir().join(format!("sling-test-gossip-store-{}", std::process::id()));
+    std::fs::write(&store_path, store).unwrap();
+
+    let my_pubkey = node_a;
+    let flows = HashMap::new();
+    let mut gossip_store_graph = LnGraph::new();
+    let gossip_store_data = GossipStoreSource::new(store_path.clone())
+        .read_store()
+        .unwrap();
+    gossip_store_graph
+        .update(&gossip_store_data, &my_pubkey, &flows, 1_700_000_000)
+        .unwrap();
+    std::fs::remove_file(&store_path).unwrap();
+
+    let mut listchannels_graph = LnGraph::new();
+    listchannels_graph
+        .update(
+            &listchannels_graph_data(&listchannels),
+            &my_pubkey,
+            &flows,
+            1_700_000_000,
+        )
+        .unwrap();
+
+    assert_eq!(gossip_store_graph.graph.values().flatten().count(), 2);
+    assert_eq!(
+        serde_json::to_value(&gossip_store_graph).unwrap(),
+        serde_json::to_value(&listchannels_graph).unwrap()
+    );
+}
+
+#[test]
+fn test_unsupported_gossip_store_version() {
+    use crate::gossip::{is_supported_store_version, GossipStoreSource};
+
+    let store_path = std::env::temp_dir().join(format!(
+        "sling-test-gossip-store-version-{}",
+        std::process::id()
+    ));
+    std::fs::write(&store_path, [0x2c]).unwrap();
+    let mut source = GossipStoreSource::new(store_path.clone());
+    let version = source.store_version().unwrap();
+    assert!(!is_supported_store_version(version));
+    assert!(source.read_store().is_err());
+
+    std::fs::write(&store_path, [0x0c]).unwrap();
+    let mut source = GossipStoreSource::new(store_path.clone());
+    assert!(is_supported_store_version(source.store_version().unwrap()));
+    assert!(source.read_store().unwrap().updates.is_empty());
+    std::fs::remove_file(&store_path).unwrap();
+}
+
+#[test]
+fn test_circuit_breaker() {
+    use crate::model::{channel_attempt_update, JobMessage, JobState, Task};
+    use cln_rpc::primitives::ShortChannelId;
+    use parking_lot::Mutex;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    let chan_id = ShortChannelId::from_str("100x1x0").unwrap();
+    let task = Task {
+        chan_id,
+        task_id: 1,
+    };
+    let job_states = Arc::new(Mutex::new(HashMap::from([(
+        chan_id,
+        vec![JobState::new(JobMessage::Rebalancing, 1)],
+    )])));
+
+    for _ in 0..2 {
+        assert!(!channel_attempt_update(job_states.clone(), &task, false, 3).unwrap());
+    }
+    assert!(!channel_attempt_update(job_states.clone(), &task, true, 3).unwrap());
+    assert_eq!(job_states.lock()[&chan_id][0].consecutive_failures(), 0);
+
+    for _ in 0..2 {
+        assert!(!channel_attempt_update(job_states.clone(), &task, false, 3).unwrap());
+    }
+    assert!(channel_attempt_update(job_states.clone(), &task, false, 3).unwrap());
+    assert!(job_states.lock()[&chan_id][0].is_circuit_open());
+
+    let mut jobstate = job_states.lock()[&chan_id][0].clone();
+    jobstate.reset_circuit();
+    assert!(!jobstate.is_circuit_open());
+    assert_eq!(jobstate.consecutive_failures(), 0);
+    assert!(matches!(jobstate.state(), JobMessage::Stopped));
+
+    let mut disabled = JobState::new(JobMessage::Rebalancing, 2);
+    for _ in 0..100 {
+        assert!(!disabled.record_attempt(false, 0));
+    }
+}
+
+#[test]
+fn test_amount_fraction_scales_with_candidate() {
+    use crate::dijkstra::dijkstra;
+    use crate::model::{DijkstraNode, ExcludeGraph, LnGraph};
+    use cln_rpc::primitives::{Amount, PublicKey, ShortChannelId};
+    use sling::Job;
+    use std::collections::{HashMap, HashSet};
+    use std::str::FromStr;
+
+    let me = PublicKey::from_str(TEST_PEER_A).unwrap();
+    let other = PublicKey::from_str(TEST_PEER_B).unwrap();
+    let small = ShortChannelId::from_str("101x1x0").unwrap();
+    let big = ShortChannelId::from_str("102x1x0").unwrap();
+
+    let mut graph = LnGraph::new();
+    test_graph_edge(&mut graph, "100x1x0", TEST_PEER_B, TEST_PEER_A, 0);
+    test_graph_edge(&mut graph, "101x1x0", TEST_PEER_A, TEST_PEER_C, 0);
+    test_graph_edge(&mut graph, "102x1x0", TEST_PEER_A, TEST_PEER_D, 0);
+    test_graph_edge(&mut graph, "103x1x0", TEST_PEER_C, TEST_PEER_B, 0);
+    test_graph_edge(&mut graph, "104x1x0", TEST_PEER_D, TEST_PEER_B, 0);
+    for (dir_chan, state) in graph.graph.get_mut(&me).unwrap().iter_mut() {
+        if dir_chan.short_channel_id == big {
+            state.amount_msat = Amount::from_msat(4_000_000_000);
+        }
+    }
+
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 500_000_000,
+        "amount_fraction": 0.1,
+        "maxppm": 100,
+        "candidatelist": ["101x1x0", "102x1x0"]
+    }))
+    .unwrap();
+    let slingchan = DijkstraNode {
+        score: 0,
+        destination: me,
+        channel_state: graph
+            .get_channel(&other, &ShortChannelId::from_str("100x1x0").unwrap())
+            .unwrap(),
+        hops: 0,
+        short_channel_id: ShortChannelId::from_str("100x1x0").unwrap(),
+    };
+    let route_amount = |job: &Job, candidate: ShortChannelId| {
+        let route = dijkstra(
+            &me,
+            &graph,
+            &me,
+            &other,
+            &slingchan,
+            job,
+            &[candidate],
+            9,
+            &ExcludeGraph {
+                exclude_chans: HashSet::new(),
+                exclude_peers: HashSet::new(),
+            },
+            144,
+            &HashMap::new(),
+            &[],
+            &HashMap::new(),
+            0.0,
+            0,
+            0,
+            None,
+            0,
+            &HashMap::new(),
+        )
+        .unwrap();
+        assert_eq!(route.first().unwrap().channel, candidate);
+        Amount::msat(&route.last().unwrap().amount_msat)
+    };
+
+    assert_eq!(route_amount(&job, small), 100_000_000);
+    assert_eq!(route_amount(&job, big), 400_000_000);
+
+    let capped = Job {
+        amount_fraction: Some(0.5),
+        ..job.clone()
+    };
+    assert_eq!(route_amount(&capped, big), 500_000_000);
+
+    let fixed = Job {
+        amount_fraction: None,
+        ..job
+    };
+    assert_eq!(route_amount(&fixed, small), 500_000_000);
+    assert_eq!(route_amount(&fixed, big), 500_000_000);
+}
+
+#[test]
+fn test_diagnostics_redaction() {
+    use crate::model::{PluginState, REDACTED};
+    use crate::util::diagnostics;
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use sling::Job;
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    let pubkey = PublicKey::from_str(TEST_PEER_A).unwrap();
+    let state = PluginState::new(
+        pubkey,
+        PathBuf::from("/tmp/lightning-rpc"),
+        PathBuf::from("/tmp/sling"),
+        PathBuf::from("/tmp"),
+        "v24.02".to_string(),
+    );
+    state
+        .alias_peer_map
+        .lock()
+        .insert(pubkey, "my-secret-alias".to_string());
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 100
+    }))
+    .unwrap();
+    let jobs = BTreeMap::from([(ShortChannelId::from_str("100x1x0").unwrap(), job)]);
+
+    let plain = diagnostics(&state, &jobs, false);
+    assert_eq!(plain["pubkey"], serde_json::json!(pubkey.to_string()));
+    assert_eq!(plain["alias"], serde_json::json!("my-secret-alias"));
+    assert_eq!(plain["jobs"], serde_json::json!(1));
+    assert_eq!(plain["cln_version"], serde_json::json!("v24.02"));
+
+    let redacted = diagnostics(&state, &jobs, true);
+    assert_eq!(redacted["pubkey"], serde_json::json!(REDACTED));
+    assert_eq!(redacted["alias"], serde_json::json!(REDACTED));
+    let redacted = redacted.to_string();
+    assert!(!redacted.contains(&pubkey.to_string()));
+    assert!(!redacted.contains("my-secret-alias"));
+    assert!(!redacted.contains("/tmp/lightning-rpc"));
+}
+
+#[test]
+fn test_candidate_order_largest_first() {
+    use crate::model::{LnGraph, CANDIDATE_ORDER_LARGEST_FIRST};
+    use crate::slings::build_candidatelist;
+    use cln_rpc::primitives::ShortChannelId;
+    use sling::Job;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    let peer_channels = [
+        test_peer_channel("101x1x0", TEST_PEER_A, 1_000_000_000, 300_000_000, 0),
+        test_peer_channel("102x1x0", TEST_PEER_A, 5_000_000_000, 4_000_000_000, 0),
+        test_peer_channel("103x1x0", TEST_PEER_B, 2_000_000_000, 900_000_000, 0),
+    ]
+    .into_iter()
+    .map(|c| (c.short_channel_id.unwrap(), c))
+    .collect::<HashMap<ShortChannelId, _>>();
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 100
+    }))
+    .unwrap();
+    let mut config = test_config();
+    config.candidate_order.value = CANDIDATE_ORDER_LARGEST_FIRST.to_string();
+
+    let candidates = build_candidatelist(
+        &peer_channels,
+        &job,
+        &LnGraph::new(),
+        &HashMap::new(),
+        &config,
+        None,
+        1_000,
+    );
+    assert_eq!(
+        candidates,
+        vec![
+            ShortChannelId::from_str("102x1x0").unwrap(),
+            ShortChannelId::from_str("103x1x0").unwrap(),
+            ShortChannelId::from_str("101x1x0").unwrap(),
+        ]
+    );
+}
+
+#[test]
+fn test_candidate_order_most_overfull() {
+    use crate::model::{LnGraph, CANDIDATE_ORDER_MOST_OVERFULL};
+    use crate::slings::build_candidatelist;
+    use cln_rpc::primitives::ShortChannelId;
+    use sling::Job;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    let peer_channels = [
+        test_peer_channel("101x1x0", TEST_PEER_A, 1_000_000_000, 300_000_000, 0),
+        test_peer_channel("102x1x0", TEST_PEER_A, 5_000_000_000, 4_000_000_000, 0),
+        test_peer_channel("103x1x0", TEST_PEER_B, 500_000_000, 450_000_000, 0),
+        // fullest of all but not eligible with too many pending htlcs
+        test_peer_channel("104x1x0", TEST_PEER_B, 500_000_000, 490_000_000, 10),
+    ]
+    .into_iter()
+    .map(|c| (c.short_channel_id.unwrap(), c))
+    .collect::<HashMap<ShortChannelId, _>>();
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 100
+    }))
+    .unwrap();
+    let mut config = test_config();
+    config.candidate_order.value = CANDIDATE_ORDER_MOST_OVERFULL.to_string();
+
+    let candidates = build_candidatelist(
+        &peer_channels,
+        &job,
+        &LnGraph::new(),
+        &HashMap::new(),
+        &config,
+        None,
+        1_000,
+    );
+    assert_eq!(
+        candidates,
+        vec![
+            ShortChannelId::from_str("103x1x0").unwrap(),
+            ShortChannelId::from_str("102x1x0").unwrap(),
+            ShortChannelId::from_str("101x1x0").unwrap(),
+        ]
+    );
+
+    // channels without balance fields or capacity sort last instead of panicking
+    let unfunded = test_peer_channel("105x1x0", TEST_PEER_C, 0, 0, 0);
+    let mut opening = test_peer_channel("106x1x0", TEST_PEER_C, 1_000_000_000, 900_000_000, 0);
+    opening.to_us_msat = None;
+    let peer_channels = [
+        test_peer_channel("101x1x0", TEST_PEER_A, 1_000_000_000, 300_000_000, 0),
+        unfunded,
+        opening,
+    ]
+    .into_iter()
+    .map(|c| (c.short_channel_id.unwrap(), c))
+    .collect::<HashMap<ShortChannelId, _>>();
+    let mut candidates = ["105x1x0", "106x1x0", "101x1x0"]
+        .map(|s| ShortChannelId::from_str(s).unwrap())
+        .to_vec();
+    crate::slings::sort_candidates_most_overfull(&mut candidates, &peer_channels);
+    assert_eq!(candidates[0], ShortChannelId::from_str("101x1x0").unwrap());
+}
+
+#[test]
+fn test_dijkstra_htlc_minimum_after_fees() {
+    use crate::dijkstra::dijkstra;
+    use crate::model::{DijkstraNode, ExcludeGraph, LnGraph};
+    use cln_rpc::primitives::{Amount, PublicKey, ShortChannelId};
+    use sling::{DirectedChannel, Job};
+    use std::collections::{HashMap, HashSet};
+    use std::str::FromStr;
+
+    let me = PublicKey::from_str(TEST_PEER_A).unwrap();
+    let other = PublicKey::from_str(TEST_PEER_B).unwrap();
+    let candidate = ShortChannelId::from_str("101x1x0").unwrap();
+
+    let mut graph = LnGraph::new();
+    test_graph_edge(&mut graph, "100x1x0", TEST_PEER_B, TEST_PEER_A, 0);
+    test_graph_edge(&mut graph, "101x1x0", TEST_PEER_A, TEST_PEER_C, 0);
+    test_graph_edge(&mut graph, "102x1x0", TEST_PEER_C, TEST_PEER_D, 0);
+    test_graph_edge(&mut graph, "103x1x0", TEST_PEER_D, TEST_PEER_B, 10_000);
+
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 20_000
+    }))
+    .unwrap();
+    let mut route_with_minimum = |scid: &str, source: &str, destination: &str, minimum: u64| {
+        let source = PublicKey::from_str(source).unwrap();
+        let destination = PublicKey::from_str(destination).unwrap();
+        graph
+            .graph
+            .get_mut(&source)
+            .unwrap()
+            .get_mut(&DirectedChannel {
+                short_channel_id: ShortChannelId::from_str(scid).unwrap(),
+                direction: if source < destination { 0 } else { 1 },
+            })
+            .unwrap()
+            .htlc_minimum_msat = Amount::from_msat(minimum);
+        let slingchan = DijkstraNode {
+            score: 0,
+            destination: me,
+            channel_state: graph
+                .get_channel(&other, &ShortChannelId::from_str("100x1x0").unwrap())
+                .unwrap(),
+            hops: 0,
+            short_channel_id: ShortChannelId::from_str("100x1x0").unwrap(),
+        };
+        dijkstra(
+            &me,
+            &graph,
+            &me,
+            &other,
+            &slingchan,
+            &job,
+            &[candidate],
+            9,
+            &ExcludeGraph {
+                exclude_chans: HashSet::new(),
+                exclude_peers: HashSet::new(),
+            },
+            144,
+            &HashMap::new(),
+            &[],
+            &HashMap::new(),
+            0.0,
+            0,
+            0,
+            None,
+            0,
+            &HashMap::new(),
+        )
+        .unwrap()
+    };
+
+    // 102x1x0 carries the amount plus the 1% fee of 103x1x0
+    let route = route_with_minimum("102x1x0", TEST_PEER_C, TEST_PEER_D, 100_500_000);
+    assert_eq!(route.len(), 4);
+    assert_eq!(route.first().unwrap().channel, candidate);
+    assert_eq!(Amount::msat(&route[1].amount_msat), 101_000_000);
+
+    assert!(route_with_minimum("102x1x0", TEST_PEER_C, TEST_PEER_D, 101_500_000).is_empty());
+
+    // 103x1x0 is the last hop before the sling channel and only carries the amount
+    route_with_minimum("102x1x0", TEST_PEER_C, TEST_PEER_D, 1);
+    assert!(route_with_minimum("103x1x0", TEST_PEER_D, TEST_PEER_B, 100_500_000).is_empty());
+}
+
+#[test]
+fn test_channel_missing_balance_fields() {
+    use crate::util::get_job_channels;
+    use cln_rpc::primitives::ShortChannelId;
+    use sling::{has_balance_fields, Job};
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    let complete = test_peer_channel("101x1x0", TEST_PEER_A, 1_000_000_000, 200_000_000, 0);
+    let mut incomplete = test_peer_channel("102x1x0", TEST_PEER_A, 1_000_000_000, 200_000_000, 0);
+    incomplete.our_reserve_msat = None;
+    incomplete.their_reserve_msat = None;
+    assert!(has_balance_fields(&complete));
+    assert!(!has_balance_fields(&incomplete));
+
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 100
+    }))
+    .unwrap();
+    let incomplete_scid = ShortChannelId::from_str("102x1x0").unwrap();
+    assert_eq!(
+        job.target_cap(&[complete.clone(), incomplete.clone()]),
+        job.target_cap(std::slice::from_ref(&complete))
+    );
+    assert_eq!(
+        job.is_balanced(&[complete.clone(), incomplete.clone()], &incomplete_scid),
+        job.is_balanced(std::slice::from_ref(&complete), &incomplete_scid)
+    );
+
+    let peer_channels = [complete, incomplete]
+        .into_iter()
+        .map(|c| (c.short_channel_id.unwrap(), c))
+        .collect::<HashMap<ShortChannelId, _>>();
+    assert!(get_job_channels(&peer_channels, &incomplete_scid, &job, 0).is_empty());
+
+    let mut peer_job = job.clone();
+    peer_job.peer = Some(cln_rpc::primitives::PublicKey::from_str(TEST_PEER_A).unwrap());
+    let job_channels = get_job_channels(&peer_channels, &incomplete_scid, &peer_job, 0);
+    assert_eq!(job_channels.len(), 1);
+    assert_eq!(
+        job_channels[0].short_channel_id,
+        Some(ShortChannelId::from_str("101x1x0").unwrap())
+    );
+}
+
+#[test]
+fn test_tempban_exempt() {
+    use crate::model::PluginState;
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    let state = PluginState::new(
+        PublicKey::from_str(TEST_PEER_A).unwrap(),
+        PathBuf::from("lightning-rpc"),
+        PathBuf::from("sling"),
+        PathBuf::from("."),
+        "v24.05".to_string(),
+    );
+    let hub = ShortChannelId::from_str("101x1x0").unwrap();
+    let flaky = ShortChannelId::from_str("102x1x0").unwrap();
+    state.tempban_exempts.lock().insert(hub);
+
+    assert!(!state.tempban(hub));
+    assert!(state.tempban(flaky));
+    let tempbans = state.tempbans.lock();
+    assert!(!tempbans.contains_key(&hub));
+    assert!(tempbans.contains_key(&flaky));
+}
+
+#[test]
+fn test_stats_rollups() {
+    use crate::model::{FailureReb, SuccessReb};
+    use crate::stats::rollups;
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use std::str::FromStr;
+
+    // 2024-01-01 00:00 UTC, a Monday and the start of ISO week 2024-W01
+    const JAN_1_2024: u64 = 1_704_067_200;
+    const DAY: u64 = 24 * 60 * 60;
+    let scid = ShortChannelId::from_str("101x1x0").unwrap();
+    let success = |completed_at: u64, amount_msat: u64, fee_ppm: u32| SuccessReb {
+        amount_msat,
+        fee_ppm,
+        fee_msat: amount_msat * fee_ppm as u64 / 1_000_000,
+        channel_partner: Some(scid),
+        hops: 3,
+        completed_at,
+    };
+    let successes = vec![
+        success(JAN_1_2024 - DAY + 3600, 100_000_000, 100),
+        success(JAN_1_2024 + 3600, 100_000_000, 100),
+        success(JAN_1_2024 + 2 * DAY, 200_000_000, 200),
+        success(JAN_1_2024 + 8 * DAY, 300_000_000, 100),
+        success(JAN_1_2024 + 31 * DAY, 400_000_000, 100),
+    ];
+    let failures = vec![FailureReb {
+        amount_msat: 100_000_000,
+        failure_reason: "WIRE_TEMPORARY_CHANNEL_FAILURE".to_string(),
+        failure_node: PublicKey::from_str(TEST_PEER_C).unwrap(),
+        channel_partner: scid,
+        hops: 3,
+        created_at: JAN_1_2024 + 9 * DAY,
+    }];
+
+    let rollups = rollups(&successes, &failures);
+    assert_eq!(
+        rollups
+            .weekly
+            .iter()
+            .map(|r| (
+                r.period.as_str(),
+                r.total_rebalances,
+                r.total_rebalances_failed
+            ))
+            .collect::<Vec<_>>(),
+        vec![
+            ("2023-W52", 1, 0),
+            ("2024-W01", 2, 0),
+            ("2024-W02", 1, 1),
+            ("2024-W05", 1, 0),
+        ]
+    );
+    let week_1 = &rollups.weekly[1];
+    assert_eq!(week_1.total_amount_sats, 300_000);
+    assert_eq!(week_1.total_spent_sats, 50);
+    assert_eq!(rollups.weekly[2].success_rate, 0.5);
+
+    assert_eq!(
+        rollups
+            .monthly
+            .iter()
+            .map(|r| (r.period.as_str(), r.total_amount_sats))
+            .collect::<Vec<_>>(),
+        vec![
+            ("2023-12", 100_000),
+            ("2024-01", 600_000),
+            ("2024-02", 400_000),
+        ]
+    );
+    assert_eq!(rollups.monthly[1].success_rate, 0.75);
+}
+
+#[test]
+fn test_reload_jobs_diff() {
+    use crate::model::{JobsDiff, JOB_FILE_NAME};
+    use crate::util::{diff_jobs, read_jobs_file};
+    use cln_rpc::primitives::ShortChannelId;
+    use std::collections::HashSet;
+    use std::str::FromStr;
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let sling_dir = std::env::temp_dir().join(format!("sling-test-reload-{}", std::process::id()));
+    std::fs::create_dir_all(&sling_dir).unwrap();
+    let jobfile = sling_dir.join(JOB_FILE_NAME);
+    let own_channels = ["100x1x0", "101x1x0", "102x1x0", "103x1x0"]
+        .iter()
+        .map(|s| ShortChannelId::from_str(s).unwrap())
+        .collect::<HashSet<_>>();
+    let job = |amount_msat: u64| {
+        serde_json::json!({
+            "sat_direction": "Pull",
+            "amount_msat": amount_msat,
+            "maxppm": 100,
+            "outppm": 0
+        })
+    };
+
+    std::fs::write(
+        &jobfile,
+        serde_json::json!({
+            "100x1x0": job(100_000_000),
+            "101x1x0": job(100_000_000),
+            "102x1x0": job(100_000_000),
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let old = rt
+        .block_on(read_jobs_file(&jobfile, &own_channels))
+        .unwrap();
+
+    std::fs::write(
+        &jobfile,
+        serde_json::json!({
+            "100x1x0": job(100_000_000),
+            "101x1x0": job(200_000_000),
+            "103x1x0": job(100_000_000),
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let new = rt
+        .block_on(read_jobs_file(&jobfile, &own_channels))
+        .unwrap();
+    assert_eq!(
+        diff_jobs(&old, &new),
+        JobsDiff {
+            added: vec![ShortChannelId::from_str("103x1x0").unwrap()],
+            removed: vec![ShortChannelId::from_str("102x1x0").unwrap()],
+            changed: vec![ShortChannelId::from_str("101x1x0").unwrap()],
+            unchanged: vec![ShortChannelId::from_str("100x1x0").unwrap()],
+        }
+    );
+
+    // candidates that are gone are dropped like on startup
+    let mut with_candidates = job(100_000_000);
+    with_candidates["candidatelist"] = serde_json::json!(["101x1x0", "999x1x0"]);
+    std::fs::write(
+        &jobfile,
+        serde_json::json!({ "100x1x0": with_candidates }).to_string(),
+    )
+    .unwrap();
+    let reloaded = rt
+        .block_on(read_jobs_file(&jobfile, &own_channels))
+        .unwrap();
+    assert_eq!(
+        reloaded[&ShortChannelId::from_str("100x1x0").unwrap()].candidatelist,
+        Some(vec![ShortChannelId::from_str("101x1x0").unwrap()])
+    );
+
+    std::fs::write(
+        &jobfile,
+        serde_json::json!({ "100x1x0": job(0) }).to_string(),
+    )
+    .unwrap();
+    assert!(rt
+        .block_on(read_jobs_file(&jobfile, &own_channels))
+        .is_err());
+    std::fs::write(&jobfile, "{ not json").unwrap();
+    assert!(rt
+        .block_on(read_jobs_file(&jobfile, &own_channels))
+        .is_err());
+
+    std::fs::remove_dir_all(&sling_dir).unwrap();
+}
+
+#[test]
+fn test_unbounded_maxhops_uses_fee_budget() {
+    use crate::dijkstra::dijkstra;
+    use crate::model::{DijkstraNode, EffectiveJobConfig, ExcludeGraph, LnGraph};
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use sling::Job;
+    use std::collections::{HashMap, HashSet};
+    use std::str::FromStr;
+
+    let secp = Secp256k1::new();
+    let hops = (1..=10u8)
+        .map(|i| {
+            PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[i; 32]).unwrap()).to_string()
+        })
+        .collect::<Vec<String>>();
+    let me = PublicKey::from_str(TEST_PEER_A).unwrap();
+    let other = PublicKey::from_str(TEST_PEER_B).unwrap();
+    let candidate = ShortChannelId::from_str("101x1x0").unwrap();
+    // us -> hop 1 -> ... -> hop 10 -> other -> us
+    let chain = |fee_per_millionth: u32| {
+        let mut graph = LnGraph::new();
+        test_graph_edge(&mut graph, "100x1x0", TEST_PEER_B, TEST_PEER_A, 0);
+        test_graph_edge(&mut graph, "101x1x0", TEST_PEER_A, &hops[0], 0);
+        for (i, pair) in hops.windows(2).enumerate() {
+            let scid = format!("{}x1x0", 102 + i);
+            test_graph_edge(&mut graph, &scid, &pair[0], &pair[1], fee_per_millionth);
+        }
+        test_graph_edge(
+            &mut graph,
+            "200x1x0",
+            &hops[9],
+            TEST_PEER_B,
+            fee_per_millionth,
+        );
+        graph
+    };
+
+    let mut config = test_config();
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 100
+    }))
+    .unwrap();
+    let route = |graph: &LnGraph, config: &crate::model::Config| {
+        let (max_hops, fee_budget_msat) = EffectiveJobConfig::new(&job, config).route_limits(&job);
+        dijkstra(
+            &me,
+            graph,
+            &me,
+            &other,
+            &DijkstraNode {
+                score: 0,
+                destination: me,
+                channel_state: graph
+                    .get_channel(&other, &ShortChannelId::from_str("100x1x0").unwrap())
+                    .unwrap(),
+                hops: 0,
+                short_channel_id: ShortChannelId::from_str("100x1x0").unwrap(),
+            },
+            &job,
+            &[candidate],
+            max_hops,
+            &ExcludeGraph {
+                exclude_chans: HashSet::new(),
+                exclude_peers: HashSet::new(),
+            },
+            144,
+            &HashMap::new(),
+            &[],
+            &HashMap::new(),
+            0.0,
+            0,
+            0,
+            fee_budget_msat,
+            0,
+            &HashMap::new(),
+        )
+        .unwrap()
+    };
+
+    let cheap = chain(5);
+    let expensive = chain(20);
+    assert!(route(&cheap, &config).is_empty());
+
+    config.maxhops.value = 0;
+    let cheap_route = route(&cheap, &config);
+    assert_eq!(cheap_route.len(), 12);
+    assert_eq!(cheap_route.first().unwrap().channel, candidate);
+    assert!(route(&expensive, &config).is_empty());
+}
+
+#[test]
+fn test_find_conflicting_jobs() {
+    use crate::util::find_conflicting_jobs;
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use sling::Job;
+    use std::collections::{BTreeMap, HashMap};
+    use std::str::FromStr;
+
+    let peer_channels = [
+        test_peer_channel("101x1x0", TEST_PEER_A, 1_000_000_000, 500_000_000, 0),
+        test_peer_channel("102x1x0", TEST_PEER_A, 1_000_000_000, 500_000_000, 0),
+        test_peer_channel("103x1x0", TEST_PEER_B, 1_000_000_000, 500_000_000, 0),
+    ]
+    .into_iter()
+    .map(|c| (c.short_channel_id.unwrap(), c))
+    .collect::<HashMap<ShortChannelId, _>>();
+    let job = |direction: &str, target: f64| -> Job {
+        serde_json::from_value(serde_json::json!({
+            "sat_direction": direction,
+            "amount_msat": 100_000_000,
+            "maxppm": 100,
+            "outppm": 0,
+            "target": target
+        }))
+        .unwrap()
+    };
+    let scid_101 = ShortChannelId::from_str("101x1x0").unwrap();
+    let scid_102 = ShortChannelId::from_str("102x1x0").unwrap();
+    let scid_103 = ShortChannelId::from_str("103x1x0").unwrap();
+
+    let mut peer_push = job("push", 0.5);
+    peer_push.peer = Some(PublicKey::from_str(TEST_PEER_A).unwrap());
+    let mut jobs = BTreeMap::from([
+        (scid_101, job("pull", 0.8)),
+        (scid_102, peer_push),
+        (scid_103, job("push", 0.8)),
+    ]);
+    assert_eq!(
+        find_conflicting_jobs(&jobs, &peer_channels),
+        vec![(scid_101, scid_102)]
+    );
+
+    jobs.insert(scid_101, job("pull", 0.5));
+    assert!(find_conflicting_jobs(&jobs, &peer_channels).is_empty());
+}
+
+#[test]
+fn test_success_reb_fee_msat() {
+    use crate::model::SuccessReb;
+    use cln_rpc::primitives::ShortChannelId;
+    use std::str::FromStr;
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let sling_dir =
+        std::env::temp_dir().join(format!("sling-test-fee-msat-{}", std::process::id()));
+    std::fs::create_dir_all(&sling_dir).unwrap();
+    let scid = ShortChannelId::from_str("100x1x0").unwrap();
+    let success = SuccessReb {
+        amount_msat: 100_000_000,
+        fee_ppm: 123,
+        fee_msat: 12_345,
+        channel_partner: Some(ShortChannelId::from_str("101x1x0").unwrap()),
+        hops: 3,
+        completed_at: 0,
+    };
+    let read = rt.block_on(async {
+        success.write_to_file(scid, &sling_dir).await.unwrap();
+        SuccessReb::read_from_file(&sling_dir, &scid).await.unwrap()
+    });
+    assert_eq!(read.len(), 1);
+    assert_eq!(read[0].fee_msat, 12_345);
+    std::fs::remove_dir_all(&sling_dir).unwrap();
+
+    let old: SuccessReb = serde_json::from_str(
+        r#"{"amount_msat":100000000,"fee_ppm":250,"channel_partner":"101x1x0","hops":3,"completed_at":0}"#,
+    )
+    .unwrap();
+    assert_eq!(old.fee_msat, 25_000);
+}
+
+#[test]
+fn test_node_bans() {
+    use crate::model::NodeBans;
+    use cln_rpc::primitives::PublicKey;
+    use std::str::FromStr;
+
+    let flaky = PublicKey::from_str(TEST_PEER_C).unwrap();
+    let other = PublicKey::from_str(TEST_PEER_D).unwrap();
+    let window = 3600;
+    let mut bans = NodeBans::default();
+
+    // failures outside of the window don't add up
+    assert!(!bans.record_failure(flaky, 1_000, 3, window));
+    assert!(!bans.record_failure(flaky, 5_000, 3, window));
+    assert!(!bans.record_failure(flaky, 5_100, 3, window));
+    assert!(bans.banned().is_empty());
+
+    assert!(bans.record_failure(flaky, 5_200, 3, window));
+    assert!(!bans.record_failure(other, 5_200, 3, window));
+    assert_eq!(bans.banned(), [flaky].into());
+
+    assert!(bans
+        .reinstate_expired(5_200 + window - 1, window)
+        .is_empty());
+    assert_eq!(bans.banned(), [flaky].into());
+    assert_eq!(bans.reinstate_expired(5_200 + window, window), vec![flaky]);
+    assert!(bans.banned().is_empty());
+
+    // disabled with a threshold of 0
+    assert!(!bans.record_failure(flaky, 10_000, 0, window));
+}
+
+#[test]
+fn test_startup_delay() {
+    use crate::model::PluginState;
+    use cln_rpc::primitives::PublicKey;
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    let state = PluginState::new(
+        PublicKey::from_str(TEST_PEER_A).unwrap(),
+        PathBuf::from("lightning-rpc"),
+        PathBuf::from("sling"),
+        PathBuf::from("."),
+        "v24.05".to_string(),
+    );
+    let started = state.started_at;
+
+    // no delay configured: jobs dispatch right away like before
+    assert!(state.startup_ready(started, 0));
+
+    // graph not refreshed yet
+    assert!(!state.startup_ready(started + 600, 60));
+
+    *state.graph_refreshed.lock() = true;
+    assert!(!state.startup_ready(started + 59, 60));
+    assert!(state.startup_ready(started + 60, 60));
+}
+
+#[test]
+fn test_parse_scid() {
+    use crate::parse::parse_scid;
+
+    let canonical = parse_scid("773x1x0").unwrap();
+    assert_eq!(canonical.to_string(), "773x1x0");
+    for input in [
+        "773x1x0",
+        " 773x1x0 ",
+        "773X1X0",
+        "773:1:0",
+        "849922488336384",
+    ] {
+        assert_eq!(parse_scid(input).unwrap(), canonical, "{}", input);
+    }
+
+    for input in [
+        "",
+        "773x1",
+        "773x1x0x2",
+        "773xx0",
+        "773x-1x0",
+        "773x1:0",
+        "0x773",
+        "abc",
+        "16777216x1x0",
+        "773x1x65536",
+    ] {
+        assert!(parse_scid(input).is_err(), "{}", input);
+    }
+}
+
+#[test]
+fn test_unfunded_channel_waits() {
+    use crate::util::{channel_funding_pending, channel_stop_reason, get_job_channels};
+    use cln_rpc::model::responses::ListpeerchannelsChannelsState;
+    use cln_rpc::primitives::ShortChannelId;
+    use sling::Job;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    let funded_scid = ShortChannelId::from_str("101x1x0").unwrap();
+    let unfunded_scid = ShortChannelId::from_str("102x1x0").unwrap();
+    let mut unfunded = test_peer_channel("102x1x0", TEST_PEER_B, 0, 0, 0);
+    unfunded.state = ListpeerchannelsChannelsState::CHANNELD_AWAITING_LOCKIN;
+    let mut peer_channels = [
+        test_peer_channel("101x1x0", TEST_PEER_A, 1_000_000_000, 500_000_000, 0),
+        unfunded,
+    ]
+    .into_iter()
+    .map(|c| (c.short_channel_id.unwrap(), c))
+    .collect::<HashMap<ShortChannelId, _>>();
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 100
+    }))
+    .unwrap();
+
+    // still opening: held instead of stopped
+    assert!(channel_funding_pending(&peer_channels, &unfunded_scid, 0));
+    assert!(get_job_channels(&peer_channels, &unfunded_scid, &job, 0).is_empty());
+
+    // normal but still empty: no bogus target from a zero total
+    peer_channels.get_mut(&unfunded_scid).unwrap().state =
+        ListpeerchannelsChannelsState::CHANNELD_NORMAL;
+    assert!(channel_stop_reason(&peer_channels, &unfunded_scid).is_none());
+    assert!(channel_funding_pending(&peer_channels, &unfunded_scid, 0));
+    assert!(get_job_channels(&peer_channels, &unfunded_scid, &job, 0).is_empty());
+
+    // funded channels go ahead unless they are below the configured minimum
+    assert!(!channel_funding_pending(&peer_channels, &funded_scid, 0));
+    assert_eq!(
+        get_job_channels(&peer_channels, &funded_scid, &job, 0).len(),
+        1
+    );
+    assert!(channel_funding_pending(
+        &peer_channels,
+        &funded_scid,
+        2_000_000_000
+    ));
+    assert!(get_job_channels(&peer_channels, &funded_scid, &job, 2_000_000_000).is_empty());
+
+    // once funded the job picks the channel up on its next check
+    *peer_channels.get_mut(&unfunded_scid).unwrap() =
+        test_peer_channel("102x1x0", TEST_PEER_B, 1_000_000_000, 0, 0);
+    assert!(!channel_funding_pending(&peer_channels, &unfunded_scid, 0));
+    assert_eq!(
+        get_job_channels(&peer_channels, &unfunded_scid, &job, 0).len(),
+        1
+    );
+}
+
+#[test]
+fn test_stats_recent_window() {
+    use crate::model::{FailureReb, SuccessReb};
+    use crate::stats::is_recent;
+    use crate::util::failure_rates;
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use std::str::FromStr;
+
+    let day = 24 * 60 * 60;
+    let now = 100 * day;
+    let partner = ShortChannelId::from_str("101x1x0").unwrap();
+    let successes = [now - day, now - 10 * day, now - 40 * day]
+        .iter()
+        .map(|completed_at| SuccessReb {
+            amount_msat: 100_000_000,
+            fee_ppm: 100,
+            fee_msat: 10_000,
+            channel_partner: Some(partner),
+            hops: 3,
+            completed_at: *completed_at,
+        })
+        .collect::<Vec<SuccessReb>>();
+    let failures = [now - 5 * day, now - 20 * day]
+        .iter()
+        .map(|created_at| FailureReb {
+            amount_msat: 100_000_000,
+            failure_reason: "WIRE_TEMPORARY_CHANNEL_FAILURE".to_string(),
+            failure_node: PublicKey::from_str(TEST_PEER_C).unwrap(),
+            channel_partner: partner,
+            hops: 3,
+            created_at: *created_at,
+        })
+        .collect::<Vec<FailureReb>>();
+    let recent = |window_days: u64| {
+        (
+            successes
+                .iter()
+                .filter(|s| is_recent(s.completed_at, now, window_days))
+                .cloned()
+                .collect::<Vec<_>>(),
+            failures
+                .iter()
+                .filter(|f| is_recent(f.created_at, now, window_days))
+                .cloned()
+                .collect::<Vec<_>>(),
+        )
+    };
+
+    let (succ, fail) = recent(7);
+    assert_eq!((succ.len(), fail.len()), (1, 1));
+    assert_eq!(failure_rates(&succ, &fail)[&partner], 0.5);
+
+    let (succ, fail) = recent(30);
+    assert_eq!((succ.len(), fail.len()), (2, 2));
+    assert_eq!(failure_rates(&succ, &fail)[&partner], 0.5);
+
+    let (succ, fail) = recent(0);
+    assert_eq!((succ.len(), fail.len()), (3, 2));
+    assert_eq!(failure_rates(&succ, &fail)[&partner], 0.4);
+
+    // windows larger than the clock don't underflow
+    assert!(is_recent(0, day, 30));
+}
+
+#[test]
+fn test_dijkstra_via_node() {
+    use crate::dijkstra::dijkstra;
+    use crate::model::{DijkstraNode, ExcludeGraph, LnGraph};
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use sling::Job;
+    use std::collections::{HashMap, HashSet};
+    use std::str::FromStr;
+
+    let test_peer_e = "022F8BDE4D1A07209355B4A7250A5C5128E88B84BDDC619AB7CBA8D569B240EFE4";
+    let me = PublicKey::from_str(TEST_PEER_A).unwrap();
+    let other = PublicKey::from_str(TEST_PEER_B).unwrap();
+    let peer_c = PublicKey::from_str(TEST_PEER_C).unwrap();
+    let peer_d = PublicKey::from_str(TEST_PEER_D).unwrap();
+    let candidates = [
+        ShortChannelId::from_str("101x1x0").unwrap(),
+        ShortChannelId::from_str("104x1x0").unwrap(),
+    ];
+
+    let mut graph = LnGraph::new();
+    test_graph_edge(&mut graph, "100x1x0", TEST_PEER_B, TEST_PEER_A, 0);
+    // cheap way to the job peer over C, expensive one over D
+    test_graph_edge(&mut graph, "101x1x0", TEST_PEER_A, TEST_PEER_C, 0);
+    test_graph_edge(&mut graph, "102x1x0", TEST_PEER_C, TEST_PEER_B, 0);
+    test_graph_edge(&mut graph, "104x1x0", TEST_PEER_A, TEST_PEER_D, 0);
+    test_graph_edge(&mut graph, "105x1x0", TEST_PEER_D, TEST_PEER_B, 1_000);
+    // E hangs off C only, passing it would need C twice
+    test_graph_edge(&mut graph, "106x1x0", TEST_PEER_C, test_peer_e, 0);
+    test_graph_edge(&mut graph, "106x1x0", test_peer_e, TEST_PEER_C, 0);
+
+    let route_via = |via_node: Option<&str>| {
+        let mut job: Job = serde_json::from_value(serde_json::json!({
+            "sat_direction": "pull",
+            "amount_msat": 100_000_000,
+            "maxppm": 2_000
+        }))
+        .unwrap();
+        job.via_node = via_node.map(|v| PublicKey::from_str(v).unwrap());
+        let slingchan = DijkstraNode {
+            score: 0,
+            destination: me,
+            channel_state: graph
+                .get_channel(&other, &ShortChannelId::from_str("100x1x0").unwrap())
+                .unwrap(),
+            hops: 0,
+            short_channel_id: ShortChannelId::from_str("100x1x0").unwrap(),
+        };
+        dijkstra(
+            &me,
+            &graph,
+            &me,
+            &other,
+            &slingchan,
+            &job,
+            &candidates,
+            9,
+            &ExcludeGraph {
+                exclude_chans: HashSet::new(),
+                exclude_peers: HashSet::new(),
+            },
+            144,
+            &HashMap::new(),
+            &[],
+            &HashMap::new(),
+            0.0,
+            0,
+            0,
+            None,
+            0,
+            &HashMap::new(),
+        )
+        .unwrap()
+        .iter()
+        .map(|hop| hop.id)
+        .collect::<Vec<_>>()
+    };
+
+    assert_eq!(route_via(None), vec![peer_c, other, me]);
+    assert_eq!(route_via(Some(TEST_PEER_D)), vec![peer_d, other, me]);
+    assert_eq!(route_via(Some(TEST_PEER_C)), vec![peer_c, other, me]);
+    assert!(route_via(Some(test_peer_e)).is_empty());
+
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 2_000,
+        "via_node": TEST_PEER_D
+    }))
+    .unwrap();
+    assert_eq!(job.via_node, Some(peer_d));
+    assert_eq!(
+        job.to_json()["via_node"],
+        serde_json::json!(peer_d.to_string())
+    );
+}
+
+#[test]
+fn test_job_states_snapshot_consistent() {
+    use crate::model::{channel_jobstate_update, JobMessage, JobState, PluginState, Task};
+    use crate::stats::job_status;
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    let state = PluginState::new(
+        PublicKey::from_str(TEST_PEER_A).unwrap(),
+        PathBuf::from("lightning-rpc"),
+        PathBuf::from("sling"),
+        PathBuf::from("."),
+        "v24.05".to_string(),
+    );
+    let chan_id = ShortChannelId::from_str("101x1x0").unwrap();
+    state.job_state.lock().insert(
+        chan_id,
+        vec![
+            JobState::new(JobMessage::Rebalancing, 1),
+            JobState::new(JobMessage::Rebalancing, 2),
+        ],
+    );
+
+    let writer = {
+        let job_state = state.job_state.clone();
+        std::thread::spawn(move || {
+            for i in 0..2_000 {
+                let message = if i % 2 == 0 {
+                    JobMessage::NoRoute
+                } else {
+                    JobMessage::Rebalancing
+                };
+                for task_id in [1, 2] {
+                    channel_jobstate_update(
+                        job_state.clone(),
+                        &Task { chan_id, task_id },
+                        &message,
+                        true,
+                        false,
+                    )
+                    .unwrap();
+                }
+            }
+        })
+    };
+
+    while !writer.is_finished() {
+        let snapshot = state.job_states_snapshot();
+        let tasks = snapshot.get(&chan_id).unwrap();
+        assert_eq!(tasks.len(), 2);
+        let (status, active, stuck) = job_status(Some(tasks));
+        // the row only ever reflects the copy it was built from
+        assert!(active);
+        assert_eq!(stuck, tasks.iter().all(|jt| jt.state().is_stuck()));
+        assert_eq!(
+            status,
+            tasks
+                .iter()
+                .map(|jt| format!("{}:{}", jt.id(), jt.state()))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+    }
+    writer.join().unwrap();
+
+    let (status, active, stuck) = job_status(None);
+    assert_eq!(status, "0:NoJob");
+    assert!(!active);
+    assert!(!stuck);
+}
+
+#[test]
+fn test_liquidity_confidence_widens() {
+    use crate::model::{DirectedChannelState, ExcludeGraph, LnGraph, PublicKeyPair};
+    use cln_rpc::model::requests::SendpayRoute;
+    use cln_rpc::primitives::{Amount, PublicKey, ShortChannelId};
+    use sling::{DirectedChannel, Job};
+    use std::collections::{HashMap, HashSet};
+    use std::str::FromStr;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let me = PublicKey::from_str(TEST_PEER_A).unwrap();
+    let source = PublicKey::from_str(TEST_PEER_C).unwrap();
+    let mut graph = LnGraph::new();
+    test_graph_edge(&mut graph, "103x1x0", TEST_PEER_C, TEST_PEER_B, 10);
+    let dir_chan = DirectedChannel {
+        short_channel_id: ShortChannelId::from_str("103x1x0").unwrap(),
+        direction: 1,
+    };
+    let set_belief = |graph: &mut LnGraph, liquidity: u64, age: u64| {
+        let channel_state = graph
+            .graph
+            .get_mut(&source)
+            .unwrap()
+            .get_mut(&dir_chan)
+            .unwrap();
+        channel_state.liquidity = liquidity;
+        channel_state.liquidity_age = now - age;
+        channel_state.confidence_age = now - age;
+    };
+
+    // freshly probed: tight bound
+    set_belief(&mut graph, 500_000_000, 0);
+    let channel_state = *graph.graph[&source].get(&dir_chan).unwrap();
+    assert_eq!(
+        channel_state.liquidity_bounds(now, 0, 60),
+        (500_000_000, 500_000_000)
+    );
+    // aged: widens with time up to the whole channel
+    assert_eq!(
+        channel_state.liquidity_bounds(now + 30 * 60, 0, 60),
+        (250_000_000, 750_000_000)
+    );
+    assert_eq!(
+        channel_state.liquidity_bounds(now + 5 * 60 * 60, 0, 60),
+        (0, 1_000_000_000)
+    );
+    // off by default
+    assert_eq!(
+        channel_state.liquidity_bounds(now + 5 * 60 * 60, 0, 0),
+        (500_000_000, 500_000_000)
+    );
+    // never probed: nothing to lose confidence in
+    let unprobed = DirectedChannelState {
+        confidence_age: 0,
+        ..channel_state
+    };
+    assert_eq!(
+        unprobed.liquidity_bounds(now + 5 * 60 * 60, 0, 60),
+        (500_000_000, 500_000_000)
+    );
+
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 300_000_000,
+        "maxppm": 100,
+        "outppm": 0
+    }))
+    .unwrap();
+    let edges = |graph: &LnGraph, confidence_window: u64| {
+        graph
+            .edges(
+                &PublicKeyPair {
+                    my_pubkey: me,
+                    other_pubkey: source,
+                },
+                &ExcludeGraph {
+                    exclude_chans: HashSet::new(),
+                    exclude_peers: HashSet::new(),
+                },
+                &job,
+                &[],
+                &HashMap::new(),
+                &[],
+                0,
+                confidence_window,
+            )
+            .len()
+    };
+    // admission uses the lower bound
+    set_belief(&mut graph, 500_000_000, 30 * 60);
+    assert_eq!(edges(&graph, 0), 1);
+    assert_eq!(edges(&graph, 60), 0);
+
+    // a success through the channel narrows the bound to what is left
+    set_belief(&mut graph, 500_000_000, 30 * 60);
+    let route = [
+        SendpayRoute {
+            amount_msat: Amount::from_msat(300_000_000),
+            id: source,
+            delay: 144,
+            channel: ShortChannelId::from_str("101x1x0").unwrap(),
+        },
+        SendpayRoute {
+            amount_msat: Amount::from_msat(300_000_000),
+            id: PublicKey::from_str(TEST_PEER_B).unwrap(),
+            delay: 144,
+            channel: dir_chan.short_channel_id,
+        },
+    ];
+    graph.record_success(&route, &me, now, 0);
+    let channel_state = graph.graph[&source].get(&dir_chan).unwrap();
+    assert_eq!(channel_state.liquidity, 500_000_000);
+    assert_eq!(channel_state.confidence_age, now - 30 * 60);
+    graph.record_success(&route, &me, now, 60);
+    let channel_state = graph.graph[&source].get(&dir_chan).unwrap();
+    assert_eq!(channel_state.liquidity, 200_000_000);
+    assert_eq!(channel_state.liquidity_age, now - 30 * 60);
+    assert_eq!(
+        channel_state.liquidity_bounds(now, 0, 60),
+        (200_000_000, 200_000_000)
+    );
+}
+
+#[test]
+fn test_sourceable_liquidity() {
+    use crate::slings::sourceable_liquidity;
+    use crate::stats::job_target_room;
+    use cln_rpc::primitives::ShortChannelId;
+    use sling::Job;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    let peer_channels = [
+        test_peer_channel("101x1x0", TEST_PEER_A, 1_000_000_000, 300_000_000, 0),
+        test_peer_channel("102x1x0", TEST_PEER_B, 5_000_000_000, 4_000_000_000, 0),
+        test_peer_channel("103x1x0", TEST_PEER_C, 1_000_000_000, 100_000_000, 0),
+    ]
+    .into_iter()
+    .map(|c| (c.short_channel_id.unwrap(), c))
+    .collect::<HashMap<ShortChannelId, _>>();
+    let candidates = [
+        ShortChannelId::from_str("101x1x0").unwrap(),
+        ShortChannelId::from_str("102x1x0").unwrap(),
+        ShortChannelId::from_str("103x1x0").unwrap(),
+    ];
+    let job = |direction: &str| -> Job {
+        serde_json::from_value(serde_json::json!({
+            "sat_direction": direction,
+            "amount_msat": 100_000_000,
+            "maxppm": 100,
+            "depleteuptopercent": 0.2,
+            "depleteuptoamount": 150_000_000
+        }))
+        .unwrap()
+    };
+
+    // 103x1x0 holds less than its deplete floor and sources nothing
+    assert_eq!(
+        sourceable_liquidity(&peer_channels, &candidates, &job("pull"), &test_config()),
+        vec![
+            (candidates[1], 3_850_000_000),
+            (candidates[0], 150_000_000),
+            (candidates[2], 0),
+        ]
+    );
+    assert_eq!(
+        sourceable_liquidity(&peer_channels, &candidates, &job("push"), &test_config()),
+        vec![
+            (candidates[1], 850_000_000),
+            (candidates[2], 750_000_000),
+            (candidates[0], 550_000_000),
+        ]
+    );
+
+    let target = [test_peer_channel(
+        "104x1x0",
+        TEST_PEER_D,
+        1_000_000_000,
+        200_000_000,
+        0,
+    )];
+    assert_eq!(job_target_room(&job("pull"), &target), 300_000_000);
+    assert_eq!(job_target_room(&job("push"), &target), 0);
+}
+
+#[test]
+fn test_resolve_job_template() {
+    use crate::parse::resolve_job_template;
+    use sling::SatDirection;
+    use std::collections::BTreeMap;
+
+    let templates: BTreeMap<String, serde_json::Value> =
+        serde_json::from_value(serde_json::json!({
+            "sink": {
+                "sat_direction": "pull",
+                "amount_msat": 100_000_000,
+                "maxppm": 500,
+                "outppm": 100,
+                "target": 0.7,
+                "depleteuptopercent": 0.3
+            }
+        }))
+        .unwrap();
+
+    let job = resolve_job_template(
+        serde_json::json!({"template": "sink", "sat_direction": "push"}),
+        &templates,
+    )
+    .unwrap();
+    assert_eq!(job.sat_direction, SatDirection::Push);
+    assert_eq!(job.amount_msat, 100_000_000);
+    assert_eq!(job.maxppm, 500);
+    assert_eq!(job.target, Some(0.7));
+    assert_eq!(job.depleteuptopercent, Some(0.3));
+
+    let job = resolve_job_template(
+        serde_json::json!({"template": "sink", "maxppm": 800, "target": 0.4}),
+        &templates,
+    )
+    .unwrap();
+    assert_eq!(job.sat_direction, SatDirection::Pull);
+    assert_eq!(job.maxppm, 800);
+    assert_eq!(job.target, Some(0.4));
+    assert_eq!(job.outppm, Some(100));
+
+    let job = resolve_job_template(
+        serde_json::json!({"sat_direction": "pull", "amount_msat": 1_000, "maxppm": 10}),
+        &templates,
+    )
+    .unwrap();
+    assert_eq!(job.target, None);
+
+    assert!(resolve_job_template(
+        serde_json::json!({"template": "source", "sat_direction": "push"}),
+        &templates,
+    )
+    .is_err());
+}
+
+#[test]
+fn test_jobs_keep_template_references() {
+    use crate::model::{JOB_FILE_NAME, TEMPLATES_FILE_NAME};
+    use crate::util::{read_raw_jobs, read_templates, resolve_jobs};
+    use cln_rpc::primitives::ShortChannelId;
+    use std::str::FromStr;
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let sling_dir =
+        std::env::temp_dir().join(format!("sling-test-templates-{}", std::process::id()));
+    std::fs::create_dir_all(&sling_dir).unwrap();
+    let chan_id = ShortChannelId::from_str("100x1x0").unwrap();
+    let write_template = |maxppm: u32| {
+        std::fs::write(
+            sling_dir.join(TEMPLATES_FILE_NAME),
+            serde_json::json!({
+                "sink": {"sat_direction": "pull", "amount_msat": 100_000_000, "maxppm": maxppm}
+            })
+            .to_string(),
+        )
+        .unwrap();
+    };
+    write_template(500);
+    std::fs::write(
+        sling_dir.join(JOB_FILE_NAME),
+        serde_json::json!({"100x1x0": {"template": "sink", "sat_direction": "push"}}).to_string(),
+    )
+    .unwrap();
+
+    let raw_jobs = rt.block_on(read_raw_jobs(&sling_dir)).unwrap();
+    assert_eq!(raw_jobs[&chan_id]["template"], "sink");
+    let templates = rt.block_on(read_templates(&sling_dir)).unwrap();
+    assert_eq!(
+        resolve_jobs(raw_jobs.clone(), &templates).unwrap()[&chan_id].maxppm,
+        500
+    );
+
+    // an edited template applies to the jobs referencing it
+    write_template(800);
+    let templates = rt.block_on(read_templates(&sling_dir)).unwrap();
+    assert_eq!(
+        resolve_jobs(raw_jobs.clone(), &templates).unwrap()[&chan_id].maxppm,
+        800
+    );
+
+    // a job whose template is gone is an error, not silently dropped
+    std::fs::write(sling_dir.join(TEMPLATES_FILE_NAME), "{}").unwrap();
+    let templates = rt.block_on(read_templates(&sling_dir)).unwrap();
+    assert!(resolve_jobs(raw_jobs, &templates).is_err());
+
+    std::fs::remove_dir_all(&sling_dir).unwrap();
+}
+
+#[test]
+fn test_locally_disabled_channel_pauses() {
+    use crate::model::{JobMessage, LnGraph};
+    use crate::util::channel_locally_disabled;
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use std::str::FromStr;
+
+    let me = PublicKey::from_str(TEST_PEER_A).unwrap();
+    let scid = ShortChannelId::from_str("101x1x0").unwrap();
+    let mut graph = LnGraph::new();
+    test_graph_edge(&mut graph, "101x1x0", TEST_PEER_A, TEST_PEER_B, 100);
+    test_graph_edge(&mut graph, "101x1x0", TEST_PEER_B, TEST_PEER_A, 100);
+    assert!(!channel_locally_disabled(&graph, &me, &scid));
+
+    // only our own direction counts
+    for state in graph
+        .graph
+        .get_mut(&PublicKey::from_str(TEST_PEER_B).unwrap())
+        .unwrap()
+        .values_mut()
+    {
+        state.active = false;
+    }
+    assert!(!channel_locally_disabled(&graph, &me, &scid));
+
+    for state in graph.graph.get_mut(&me).unwrap().values_mut() {
+        state.active = false;
+    }
+    assert!(channel_locally_disabled(&graph, &me, &scid));
+    assert!(!channel_locally_disabled(
+        &graph,
+        &me,
+        &ShortChannelId::from_str("102x1x0").unwrap()
+    ));
+
+    assert_eq!(JobMessage::ChanDisabled.code(), 24);
+    assert!(!JobMessage::ChanDisabled.is_stuck());
+}
+
+#[test]
+fn test_hop_results_one_record_per_hop() {
+    use crate::model::{HopResult, HOP_RESULTS_FILE_NAME};
+    use cln_rpc::model::requests::SendpayRoute;
+    use cln_rpc::primitives::{Amount, PublicKey, ShortChannelId};
+    use std::str::FromStr;
+
+    let me = PublicKey::from_str(TEST_PEER_A).unwrap();
+    let hop = |amount_msat: u64, peer: &str, scid: &str| SendpayRoute {
+        amount_msat: Amount::from_msat(amount_msat),
+        id: PublicKey::from_str(peer).unwrap(),
+        delay: 144,
+        channel: ShortChannelId::from_str(scid).unwrap(),
+    };
+    let route = vec![
+        hop(100_300_000, TEST_PEER_B, "101x1x0"),
+        hop(100_200_000, TEST_PEER_C, "102x1x0"),
+        hop(100_000_000, TEST_PEER_A, "103x1x0"),
+    ];
+
+    let success = HopResult::from_route(&route, &me, None, 1_700_000_000);
+    assert_eq!(success.len(), 3);
+    assert!(success.iter().all(|h| h.success));
+    assert_eq!(
+        success
+            .iter()
+            .map(|h| (h.short_channel_id.to_string(), h.amount_msat))
+            .collect::<Vec<_>>(),
+        vec![
+            ("101x1x0".to_string(), 100_300_000),
+            ("102x1x0".to_string(), 100_200_000),
+            ("103x1x0".to_string(), 100_000_000),
+        ]
+    );
+    // direction 0 goes from the lower to the higher node id
+    assert_eq!(
+        success.iter().map(|h| h.direction).collect::<Vec<_>>(),
+        vec![0, 0, 1]
+    );
+
+    // the node at index 1 could not forward, the last hop was never tried
+    let failure = HopResult::from_route(&route, &me, Some(1), 1_700_000_000);
+    assert_eq!(
+        failure.iter().map(|h| h.success).collect::<Vec<_>>(),
+        vec![true, false]
+    );
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let sling_dir =
+        std::env::temp_dir().join(format!("sling-test-hop-results-{}", std::process::id()));
+    std::fs::create_dir_all(&sling_dir).unwrap();
+    rt.block_on(HopResult::write_to_file(&success, &sling_dir))
+        .unwrap();
+    rt.block_on(HopResult::write_to_file(&failure, &sling_dir))
+        .unwrap();
+    let written = std::fs::read_to_string(sling_dir.join(HOP_RESULTS_FILE_NAME))
+        .unwrap()
+        .lines()
+        .map(|l| serde_json::from_str::<HopResult>(l).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(written, [success, failure].concat());
+
+    std::fs::remove_dir_all(&sling_dir).unwrap();
+}
+
+#[test]
+fn test_negotiated_htlc_limit() {
+    use crate::model::LnGraph;
+    use crate::slings::build_candidatelist;
+    use crate::util::{is_htlc_capped, pick_job_channel};
+    use cln_rpc::primitives::ShortChannelId;
+    use sling::Job;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    let mut config = test_config();
+    config.max_htlc_count.value = 5;
+    let mut small = test_peer_channel("101x1x0", TEST_PEER_A, 1_000_000_000, 800_000_000, 3);
+    small.max_accepted_htlcs = Some(3);
+    let mut roomy = test_peer_channel("102x1x0", TEST_PEER_B, 1_000_000_000, 800_000_000, 3);
+    roomy.max_accepted_htlcs = Some(30);
+    let unknown = test_peer_channel("103x1x0", TEST_PEER_C, 1_000_000_000, 800_000_000, 3);
+
+    assert!(is_htlc_capped(&small, config.max_htlc_count.value));
+    assert!(!is_htlc_capped(&roomy, config.max_htlc_count.value));
+    assert!(!is_htlc_capped(&unknown, config.max_htlc_count.value));
+    // the global limit still applies to channels that negotiated more
+    assert!(is_htlc_capped(&roomy, 2));
+
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 100
+    }))
+    .unwrap();
+    let peer_channels = [small.clone(), roomy, unknown]
+        .into_iter()
+        .map(|c| (c.short_channel_id.unwrap(), c))
+        .collect::<HashMap<ShortChannelId, _>>();
+    let mut candidates = build_candidatelist(
+        &peer_channels,
+        &job,
+        &LnGraph::new(),
+        &HashMap::new(),
+        &config,
+        None,
+        1_000,
+    );
+    candidates.sort();
+    assert_eq!(
+        candidates,
+        vec![
+            ShortChannelId::from_str("102x1x0").unwrap(),
+            ShortChannelId::from_str("103x1x0").unwrap(),
+        ]
+    );
+
+    assert_eq!(
+        pick_job_channel(&job, &[small], config.max_htlc_count.value),
+        None
+    );
+}
+
+#[test]
+fn test_prune_stats_into_rollup() {
+    use crate::model::{FailureReb, SuccessReb};
+    use crate::stats::{add_to_rollup, prune_records, read_rollup, write_rollup};
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use sling::StatsRollup;
+    use std::str::FromStr;
+
+    const DAY: u64 = 24 * 60 * 60;
+    let now = 1_704_067_200;
+    let scid = ShortChannelId::from_str("101x1x0").unwrap();
+    let success = |completed_at: u64, amount_msat: u64, fee_ppm: u32| SuccessReb {
+        amount_msat,
+        fee_ppm,
+        fee_msat: amount_msat * fee_ppm as u64 / 1_000_000,
+        channel_partner: Some(scid),
+        hops: 3,
+        completed_at,
+    };
+    let failure = |created_at: u64| FailureReb {
+        amount_msat: 100_000_000,
+        failure_reason: "WIRE_TEMPORARY_CHANNEL_FAILURE".to_string(),
+        failure_node: PublicKey::from_str(TEST_PEER_C).unwrap(),
+        channel_partner: scid,
+        hops: 3,
+        created_at,
+    };
+    let successes = vec![
+        success(now - 40 * DAY, 100_000_000, 100),
+        success(now - 3 * DAY, 200_000_000, 300),
+        success(now - 2 * DAY, 300_000_000, 200),
+        success(now - DAY, 400_000_000, 100),
+    ];
+    let failures = vec![failure(now - 50 * DAY), failure(now - DAY)];
+    let mut original = StatsRollup::default();
+    add_to_rollup(&mut original, &successes, &failures);
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let sling_dir = std::env::temp_dir().join(format!("sling-test-rollup-{}", std::process::id()));
+    std::fs::create_dir_all(&sling_dir).unwrap();
+
+    // too old for 30 days and more than 2 entries
+    let (kept_successes, pruned_successes) =
+        prune_records(successes, |r| r.completed_at, Some(now - 30 * DAY), 2);
+    assert_eq!(pruned_successes.len(), 2);
+    assert_eq!(
+        kept_successes
+            .iter()
+            .map(|s| s.completed_at)
+            .collect::<Vec<_>>(),
+        vec![now - 2 * DAY, now - DAY]
+    );
+    let (kept_failures, pruned_failures) =
+        prune_records(failures, |r| r.created_at, Some(now - 30 * DAY), 0);
+    assert_eq!(pruned_failures.len(), 1);
+
+    // two prune runs add up in the same rollup file
+    let mut rollup = rt.block_on(read_rollup(&sling_dir, &scid)).unwrap();
+    assert_eq!(rollup, StatsRollup::default());
+    add_to_rollup(&mut rollup, &pruned_successes, &[]);
+    rt.block_on(write_rollup(&sling_dir, &scid, &rollup))
+        .unwrap();
+    let mut rollup = rt.block_on(read_rollup(&sling_dir, &scid)).unwrap();
+    add_to_rollup(&mut rollup, &[], &pruned_failures);
+    rt.block_on(write_rollup(&sling_dir, &scid, &rollup))
+        .unwrap();
+
+    let mut combined = rt.block_on(read_rollup(&sling_dir, &scid)).unwrap();
+    add_to_rollup(&mut combined, &kept_successes, &kept_failures);
+    assert_eq!(combined, original);
+    assert_eq!(combined.total_rebalances, 4);
+    assert_eq!(combined.total_spent_msat, 170_000);
+    assert_eq!(combined.feeppm_weighted_avg, 170);
+
+    std::fs::remove_dir_all(&sling_dir).unwrap();
+}
+
+#[test]
+fn test_dijkstra_shortest_objective() {
+    use crate::dijkstra::dijkstra;
+    use crate::model::{DijkstraNode, ExcludeGraph, LnGraph};
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use sling::{Job, RouteObjective};
+    use std::collections::{HashMap, HashSet};
+    use std::str::FromStr;
+
+    let test_peer_e = "022F8BDE4D1A07209355B4A7250A5C5128E88B84BDDC619AB7CBA8D569B240EFE4";
+    let test_peer_f = "02FFF97BD5755EEEA420453A14355235D382F6472F8568A18B2F057A1460297556";
+    let me = PublicKey::from_str(TEST_PEER_A).unwrap();
+    let other = PublicKey::from_str(TEST_PEER_B).unwrap();
+    let candidates = [
+        ShortChannelId::from_str("101x1x0").unwrap(),
+        ShortChannelId::from_str("104x1x0").unwrap(),
+    ];
+
+    let mut graph = LnGraph::new();
+    test_graph_edge(&mut graph, "100x1x0", TEST_PEER_B, TEST_PEER_A, 0);
+    // 4 hops over C, E and F for 30ppm
+    test_graph_edge(&mut graph, "101x1x0", TEST_PEER_A, TEST_PEER_C, 0);
+    test_graph_edge(&mut graph, "102x1x0", TEST_PEER_C, test_peer_e, 10);
+    test_graph_edge(&mut graph, "103x1x0", test_peer_e, test_peer_f, 10);
+    test_graph_edge(&mut graph, "106x1x0", test_peer_f, TEST_PEER_B, 10);
+    // 2 hops over D for 500ppm
+    test_graph_edge(&mut graph, "104x1x0", TEST_PEER_A, TEST_PEER_D, 0);
+    test_graph_edge(&mut graph, "105x1x0", TEST_PEER_D, TEST_PEER_B, 500);
+
+    let route_hops = |objective: Option<RouteObjective>| {
+        let mut job: Job = serde_json::from_value(serde_json::json!({
+            "sat_direction": "pull",
+            "amount_msat": 100_000_000,
+            "maxppm": 2_000
+        }))
+        .unwrap();
+        job.objective = objective;
+        dijkstra(
+            &me,
+            &graph,
+            &me,
+            &other,
+            &DijkstraNode {
+                score: 0,
+                destination: me,
+                channel_state: graph
+                    .get_channel(&other, &ShortChannelId::from_str("100x1x0").unwrap())
+                    .unwrap(),
+                hops: 0,
+                short_channel_id: ShortChannelId::from_str("100x1x0").unwrap(),
+            },
+            &job,
+            &candidates,
+            9,
+            &ExcludeGraph {
+                exclude_chans: HashSet::new(),
+                exclude_peers: HashSet::new(),
+            },
+            144,
+            &HashMap::new(),
+            &[],
+            &HashMap::new(),
+            0.0,
+            0,
+            0,
+            None,
+            0,
+            &HashMap::new(),
+        )
+        .unwrap()
+        .len()
+            - 1
+    };
+
+    assert_eq!(route_hops(None), 4);
+    assert_eq!(route_hops(Some(RouteObjective::Cheapest)), 4);
+    assert_eq!(route_hops(Some(RouteObjective::Shortest)), 2);
+
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 2_000,
+        "objective": "shortest"
+    }))
+    .unwrap();
+    assert_eq!(job.objective, Some(RouteObjective::Shortest));
+}
+
+#[test]
+fn test_update_excepts() {
+    use crate::parse::parse_scid;
+    use crate::util::{sorted_excepts, update_excepts};
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use std::collections::HashSet;
+    use std::str::FromStr;
+
+    let mut excepts: HashSet<ShortChannelId> = HashSet::new();
+    update_excepts(&mut excepts, "add", parse_scid("102x1x0").unwrap()).unwrap();
+    update_excepts(&mut excepts, "add", parse_scid("101:1:0").unwrap()).unwrap();
+    assert_eq!(sorted_excepts(&excepts), vec!["101x1x0", "102x1x0"]);
+
+    assert!(update_excepts(&mut excepts, "add", parse_scid("101x1x0").unwrap()).is_err());
+    assert!(update_excepts(&mut excepts, "delete", parse_scid("101x1x0").unwrap()).is_err());
+    assert!(parse_scid("101x1").is_err());
+    assert!(parse_scid("abc").is_err());
+
+    update_excepts(&mut excepts, "remove", parse_scid("101x1x0").unwrap()).unwrap();
+    assert!(update_excepts(&mut excepts, "remove", parse_scid("101x1x0").unwrap()).is_err());
+    assert_eq!(sorted_excepts(&excepts), vec!["102x1x0"]);
+
+    let mut excepts_peers: HashSet<PublicKey> = HashSet::new();
+    update_excepts(
+        &mut excepts_peers,
+        "add",
+        PublicKey::from_str(TEST_PEER_B).unwrap(),
+    )
+    .unwrap();
+    assert!(PublicKey::from_str("02abcd").is_err());
+    assert_eq!(
+        sorted_excepts(&excepts_peers),
+        vec![TEST_PEER_B.to_lowercase()]
+    );
+    update_excepts(
+        &mut excepts_peers,
+        "remove",
+        PublicKey::from_str(TEST_PEER_B).unwrap(),
+    )
+    .unwrap();
+    assert!(sorted_excepts(&excepts_peers).is_empty());
+}
+
+#[test]
+fn test_refresh_backoff() {
+    use crate::model::{RefreshBackoff, REFRESH_BACKOFF_MAX_SECS};
+    use anyhow::anyhow;
+
+    let failing_rpc = || -> Result<(), anyhow::Error> { Err(anyhow!("rpc unavailable")) };
+    let mut backoff = RefreshBackoff::new("test", 60);
+
+    assert_eq!(backoff.next_interval(&Ok(())), 60);
+    assert_eq!(backoff.next_interval(&failing_rpc()), 120);
+    assert_eq!(backoff.next_interval(&failing_rpc()), 240);
+    assert_eq!(backoff.next_interval(&failing_rpc()), 480);
+    for _ in 0..100 {
+        assert!(backoff.next_interval(&failing_rpc()) <= REFRESH_BACKOFF_MAX_SECS);
+    }
+    assert_eq!(
+        backoff.next_interval(&failing_rpc()),
+        REFRESH_BACKOFF_MAX_SECS
+    );
+    assert_eq!(backoff.next_interval(&Ok(())), 60);
+    assert_eq!(backoff.next_interval(&failing_rpc()), 120);
+
+    let mut slow = RefreshBackoff::new("slow", 7_200);
+    assert_eq!(slow.next_interval(&failing_rpc()), 7_200);
+}
+
+#[test]
+fn test_filter_stat_summaries_by_tag() {
+    use crate::model::StatSummary;
+    use crate::parse::parse_job;
+    use crate::stats::sort_and_filter_stat_summaries;
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use std::str::FromStr;
+
+    let (_, job) = tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(parse_job(serde_json::json!({
+            "scid": "100x1x0",
+            "direction": "pull",
+            "amount": 100_000,
+            "maxppm": 300,
+            "outppm": 0,
+            "tags": ["inbound-for-sales", "drain-for-close"]
+        })))
+        .unwrap();
+    assert_eq!(
+        job.tags,
+        Some(vec![
+            "inbound-for-sales".to_string(),
+            "drain-for-close".to_string()
+        ])
+    );
+    let saved: sling::Job = serde_json::from_str(&serde_json::to_string(&job).unwrap()).unwrap();
+    assert_eq!(saved.tags, job.tags);
+
+    let row = |alias: &str, tags: &[&str]| StatSummary {
+        alias: alias.to_string(),
+        scid: ShortChannelId::from_str("100x1x0").unwrap(),
+        pubkey: PublicKey::from_str(TEST_PEER_B).unwrap(),
+        status: String::new(),
+        rebamount: "0".to_string(),
+        w_feeppm: 0,
+        hops: "-".to_string(),
+        last_route_taken: "Never".to_string(),
+        last_success_reb: "Never".to_string(),
+        active: true,
+        stuck: false,
+        imbalance: 0.0,
+        last_success: 0,
+        tags: tags.iter().map(|t| t.to_string()).collect(),
+    };
+    let mut table = vec![
+        row("alice", &["inbound-for-sales"]),
+        row("bob", &[]),
+        row("carol", &["drain-for-close", "inbound-for-sales"]),
+        row("dave", &["drain-for-close"]),
+    ];
+    sort_and_filter_stat_summaries(&mut table, None, Some("tag:inbound-for-sales")).unwrap();
+    assert_eq!(
+        table.iter().map(|r| r.alias.as_str()).collect::<Vec<_>>(),
+        vec!["alice", "carol"]
+    );
+}
+
+#[test]
+fn test_check_candidatelist_drops_target() {
+    use crate::util::check_candidatelist;
+    use cln_rpc::primitives::ShortChannelId;
+    use sling::Job;
+    use std::collections::HashSet;
+    use std::str::FromStr;
+
+    let chan_id = ShortChannelId::from_str("100x1x0").unwrap();
+    let own_channels: HashSet<ShortChannelId> = ["100x1x0", "101x1x0", "102x1x0"]
+        .iter()
+        .map(|s| ShortChannelId::from_str(s).unwrap())
+        .collect();
+
+    let mut job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 100,
+        "candidatelist": ["101x1x0", "100x1x0"],
+        "candidatelist_backup": ["100x1x0", "102x1x0"]
+    }))
+    .unwrap();
+    assert!(check_candidatelist(&chan_id, &mut job, &own_channels).is_ok());
+    assert_eq!(
+        job.candidatelist.clone().unwrap(),
+        vec![ShortChannelId::from_str("101x1x0").unwrap()]
+    );
+    assert_eq!(
+        job.candidatelist_backup.clone().unwrap(),
+        vec![ShortChannelId::from_str("102x1x0").unwrap()]
+    );
+    assert!(!job.drop_target_candidate(&chan_id));
+}
+
+#[test]
+fn test_retain_min_amount() {
+    use crate::model::{FailureReb, SuccessReb};
+    use crate::stats::retain_min_amount;
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use std::str::FromStr;
+
+    let success = |amount_msat: u64| SuccessReb {
+        amount_msat,
+        fee_ppm: 100,
+        fee_msat: amount_msat / 10_000,
+        channel_partner: Some(ShortChannelId::from_str("101x1x0").unwrap()),
+        hops: 3,
+        completed_at: 1_700_000_000,
+    };
+    let failure = |amount_msat: u64| FailureReb {
+        amount_msat,
+        failure_reason: "WIRE_TEMPORARY_CHANNEL_FAILURE".to_string(),
+        failure_node: PublicKey::from_str(TEST_PEER_C).unwrap(),
+        channel_partner: ShortChannelId::from_str("101x1x0").unwrap(),
+        hops: 3,
+        created_at: 1_700_000_000,
+    };
+
+    let mut successes = vec![success(1_000), success(100_000_000), success(9_999_999)];
+    let mut failures = vec![failure(50_000), failure(10_000_000)];
+    retain_min_amount(&mut successes, |s| s.amount_msat, 0);
+    assert_eq!(successes.len(), 3);
+
+    retain_min_amount(&mut successes, |s| s.amount_msat, 10_000 * 1_000);
+    retain_min_amount(&mut failures, |f| f.amount_msat, 10_000 * 1_000);
+    assert_eq!(
+        successes.iter().map(|s| s.amount_msat).collect::<Vec<_>>(),
+        vec![100_000_000]
+    );
+    assert_eq!(
+        failures.iter().map(|f| f.amount_msat).collect::<Vec<_>>(),
+        vec![10_000_000]
+    );
+}
+
+#[test]
+fn test_validate_circular_route() {
+    use crate::util::validate_circular_route;
+    use cln_rpc::model::requests::SendpayRoute;
+    use cln_rpc::primitives::{Amount, PublicKey, ShortChannelId};
+    use std::str::FromStr;
+
+    let me = PublicKey::from_str(TEST_PEER_A).unwrap();
+    let hop = |peer: &str, scid: &str| SendpayRoute {
+        amount_msat: Amount::from_msat(100_000_000),
+        id: PublicKey::from_str(peer).unwrap(),
+        delay: 144,
+        channel: ShortChannelId::from_str(scid).unwrap(),
+    };
+
+    let valid = vec![
+        hop(TEST_PEER_B, "101x1x0"),
+        hop(TEST_PEER_C, "102x1x0"),
+        hop(TEST_PEER_A, "103x1x0"),
+    ];
+    assert!(validate_circular_route(&valid, &me).is_ok());
+
+    let same_channel_both_legs = vec![
+        hop(TEST_PEER_B, "101x1x0"),
+        hop(TEST_PEER_C, "102x1x0"),
+        hop(TEST_PEER_A, "101x1x0"),
+    ];
+    assert!(validate_circular_route(&same_channel_both_legs, &me).is_err());
+
+    let me_intermediate = vec![
+        hop(TEST_PEER_B, "101x1x0"),
+        hop(TEST_PEER_A, "102x1x0"),
+        hop(TEST_PEER_C, "104x1x0"),
+        hop(TEST_PEER_A, "103x1x0"),
+    ];
+    assert!(validate_circular_route(&me_intermediate, &me).is_err());
+
+    let not_back_home = vec![hop(TEST_PEER_B, "101x1x0"), hop(TEST_PEER_C, "102x1x0")];
+    assert!(validate_circular_route(&not_back_home, &me).is_err());
+    assert!(validate_circular_route(&[hop(TEST_PEER_A, "101x1x0")], &me).is_err());
+}
+
+#[test]
+fn test_stats_msat_fields() {
+    use crate::model::{FailureReb, SuccessReb};
+    use crate::stats::{failure_stats, rollups, success_stats};
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use sling::Job;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    let scid = ShortChannelId::from_str("101x1x0").unwrap();
+    let successes = vec![
+        SuccessReb {
+            amount_msat: 100_000_500,
+            fee_ppm: 105,
+            fee_msat: 10_500,
+            channel_partner: Some(scid),
+            hops: 3,
+            completed_at: 1_700_000_000,
+        },
+        SuccessReb {
+            amount_msat: 50_000_250,
+            fee_ppm: 10,
+            fee_msat: 500,
+            channel_partner: Some(scid),
+            hops: 2,
+            completed_at: 1_700_000_100,
+        },
+    ];
+    let failures = vec![FailureReb {
+        amount_msat: 20_000_999,
+        failure_reason: "WIRE_TEMPORARY_CHANNEL_FAILURE".to_string(),
+        failure_node: PublicKey::from_str(TEST_PEER_C).unwrap(),
+        channel_partner: scid,
+        hops: 3,
+        created_at: 1_700_000_200,
+    }];
+
+    let s = success_stats(successes.clone(), 0, &HashMap::new(), &HashMap::new()).unwrap();
+    assert_eq!(s.total_amount_msat, 150_000_750);
+    assert_eq!(s.total_amount_sats, s.total_amount_msat / 1_000);
+    assert_eq!(s.total_spent_msat, 11_000);
+    assert_eq!(s.total_spent_sats, s.total_spent_msat / 1_000);
+
+    let f = failure_stats(failures.clone(), 0, &HashMap::new(), &HashMap::new()).unwrap();
+    assert_eq!(f.total_amount_tried_msat, 20_000_999);
+    assert_eq!(f.total_amount_tried_sats, 20_000);
+
+    let weekly = &rollups(&successes, &failures).weekly[0];
+    assert_eq!(weekly.total_amount_msat, 150_000_750);
+    assert_eq!(weekly.total_amount_sats, 150_000);
+    assert_eq!(weekly.total_spent_msat, 11_000);
+    assert_eq!(weekly.total_spent_sats, 11);
+
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_500,
+        "min_amount_msat": 10_000_250,
+        "maxppm": 100,
+        "depleteuptoamount": 2_000_000_999
+    }))
+    .unwrap();
+    let json = job.to_json();
+    assert_eq!(json["amount"], "100000");
+    assert_eq!(json["amount_msat"], "100000500");
+    assert_eq!(json["min_amount"], "10000");
+    assert_eq!(json["min_amount_msat"], "10000250");
+    assert_eq!(json["depleteuptoamount"], "2000000");
+    assert_eq!(json["depleteuptoamount_msat"], "2000000999");
+}
+
+#[test]
+fn test_check_sling_dir_writable() {
+    use crate::util::check_sling_dir_writable;
+
+    let dir = std::env::temp_dir().join(format!("sling-test-writable-{}", std::process::id()));
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(check_sling_dir_writable(&dir)).unwrap();
+    assert!(dir.is_dir());
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // a file where the sling folder should be can't be written into, even as root
+    std::fs::write(&dir, "not a folder").unwrap();
+    let err = rt.block_on(check_sling_dir_writable(&dir)).unwrap_err();
+    assert!(err.to_string().contains("is not writable"), "{}", err);
+    std::fs::remove_file(&dir).unwrap();
+}
+
+#[test]
+fn test_peer_job_aggregate_inbound_target() {
+    use crate::util::{get_job_channels, pick_job_channel};
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use sling::Job;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    // push job: we want 60% of the total capacity to the peer on their side
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "push",
+        "amount_msat": 50_000_000,
+        "maxppm": 100,
+        "target": 0.6,
+        "peer": TEST_PEER_B
+    }))
+    .unwrap();
+    let channels = |to_us_big: u64, to_us_small: u64| {
+        [
+            test_peer_channel("101x1x0", TEST_PEER_B, 4_000_000_000, to_us_big, 0),
+            test_peer_channel("102x1x0", TEST_PEER_B, 1_000_000_000, to_us_small, 0),
+        ]
+        .into_iter()
+        .map(|c| (c.short_channel_id.unwrap(), c))
+        .collect::<HashMap<ShortChannelId, _>>()
+    };
+    let anchor = ShortChannelId::from_str("101x1x0").unwrap();
+    assert_eq!(job.peer, Some(PublicKey::from_str(TEST_PEER_B).unwrap()));
+
+    // the big channel has more to push but already holds its share of inbound,
+    // the small one is furthest below its share of the aggregate target
+    let peer_channels = channels(1_700_000_000, 900_000_000);
+    let job_channels = get_job_channels(&peer_channels, &anchor, &job, 0);
+    assert!(!job.is_balanced(&job_channels, &anchor));
+    assert_eq!(
+        pick_job_channel(&job, &job_channels, 30),
+        Some(ShortChannelId::from_str("102x1x0").unwrap())
+    );
+
+    // once the small channel reached its share the big one gets filled
+    let peer_channels = channels(2_000_000_000, 400_000_000);
+    let job_channels = get_job_channels(&peer_channels, &anchor, &job, 0);
+    assert!(!job.is_balanced(&job_channels, &anchor));
+    assert_eq!(
+        pick_job_channel(&job, &job_channels, 30),
+        Some(ShortChannelId::from_str("101x1x0").unwrap())
+    );
+
+    // aggregate inbound of 3_000_000_000 reaches 60% of 5_000_000_000
+    let peer_channels = channels(1_600_000_000, 400_000_000);
+    let job_channels = get_job_channels(&peer_channels, &anchor, &job, 0);
+    assert!(job.is_balanced(&job_channels, &anchor));
+}
+
+#[test]
+fn test_timeout_success_from_balance_change() {
+    use crate::model::SuccessReb;
+    use cln_rpc::model::requests::SendpayRoute;
+    use cln_rpc::primitives::{Amount, PublicKey, ShortChannelId};
+    use sling::SatDirection;
+    use std::str::FromStr;
+
+    let hop = |amount_msat: u64, peer: &str, scid: &str| SendpayRoute {
+        amount_msat: Amount::from_msat(amount_msat),
+        id: PublicKey::from_str(peer).unwrap(),
+        delay: 144,
+        channel: ShortChannelId::from_str(scid).unwrap(),
+    };
+    let route = vec![
+        hop(100_020_000, TEST_PEER_B, "101x1x0"),
+        hop(100_000_000, TEST_PEER_C, "102x1x0"),
+        hop(100_000_000, TEST_PEER_A, "103x1x0"),
+    ];
+
+    // pull: the balance of our side of 103x1x0 went up by the amount
+    let success = SuccessReb::from_balance_change(
+        &route,
+        SatDirection::Pull,
+        400_000_000,
+        500_000_000,
+        1_700_000_000,
+    )
+    .unwrap();
+    assert_eq!(success.amount_msat, 100_000_000);
+    assert_eq!(success.fee_msat, 20_000);
+    assert_eq!(success.fee_ppm, 200);
+    assert_eq!(
+        success.channel_partner,
+        Some(ShortChannelId::from_str("101x1x0").unwrap())
+    );
+    assert_eq!(success.hops, 2);
+
+    // nothing moved, it stays a failure
+    assert!(SuccessReb::from_balance_change(
+        &route,
+        SatDirection::Pull,
+        400_000_000,
+        400_000_000,
+        1_700_000_000
+    )
+    .is_none());
+    // unrelated forwards that moved less than the amount don't count either
+    assert!(SuccessReb::from_balance_change(
+        &route,
+        SatDirection::Pull,
+        400_000_000,
+        450_000_000,
+        1_700_000_000
+    )
+    .is_none());
+
+    // push: our side of the first channel went down
+    let success = SuccessReb::from_balance_change(
+        &route,
+        SatDirection::Push,
+        500_000_000,
+        399_980_000,
+        1_700_000_000,
+    )
+    .unwrap();
+    assert_eq!(
+        success.channel_partner,
+        Some(ShortChannelId::from_str("103x1x0").unwrap())
+    );
+}
+
+#[test]
+fn test_cap_candidates() {
+    use crate::model::{
+        CANDIDATES_CAP_CHEAPEST_HISTORY, CANDIDATES_CAP_MOST_IMBALANCED, CANDIDATES_CAP_ROUND_ROBIN,
+    };
+    use crate::slings::cap_candidates;
+    use cln_rpc::primitives::ShortChannelId;
+    use sling::SatDirection;
+    use std::collections::{HashMap, HashSet};
+    use std::str::FromStr;
+
+    let peer_channels = [
+        test_peer_channel("101x1x0", TEST_PEER_B, 1_000_000_000, 900_000_000, 0),
+        test_peer_channel("102x1x0", TEST_PEER_C, 1_000_000_000, 500_000_000, 0),
+        test_peer_channel("103x1x0", TEST_PEER_D, 1_000_000_000, 700_000_000, 0),
+        test_peer_channel("104x1x0", TEST_PEER_B, 1_000_000_000, 100_000_000, 0),
+        test_peer_channel("105x1x0", TEST_PEER_C, 1_000_000_000, 300_000_000, 0),
+    ]
+    .into_iter()
+    .map(|c| (c.short_channel_id.unwrap(), c))
+    .collect::<HashMap<ShortChannelId, _>>();
+    let scid = |s: &str| ShortChannelId::from_str(s).unwrap();
+    let all = vec![
+        scid("101x1x0"),
+        scid("102x1x0"),
+        scid("103x1x0"),
+        scid("104x1x0"),
+        scid("105x1x0"),
+    ];
+    let cap = |max: u64, strategy: &str, direction: SatDirection, rotation: &mut usize| {
+        let mut candidates = all.clone();
+        cap_candidates(
+            &mut candidates,
+            max,
+            strategy,
+            direction,
+            &peer_channels,
+            &HashMap::from([(scid("105x1x0"), 10), (scid("102x1x0"), 50)]),
+            rotation,
+        );
+        candidates
+    };
+
+    let mut rotation = 0;
+    assert_eq!(
+        cap(
+            0,
+            CANDIDATES_CAP_ROUND_ROBIN,
+            SatDirection::Pull,
+            &mut rotation
+        ),
+        all
+    );
+
+    // round-robin evaluates 2 per iteration and covers all candidates after 3 iterations
+    let mut seen = HashSet::new();
+    for _ in 0..3 {
+        let candidates = cap(
+            2,
+            CANDIDATES_CAP_ROUND_ROBIN,
+            SatDirection::Pull,
+            &mut rotation,
+        );
+        assert_eq!(candidates.len(), 2);
+        seen.extend(candidates);
+    }
+    assert_eq!(seen.len(), all.len());
+
+    let mut rotation = 0;
+    assert_eq!(
+        cap(
+            2,
+            CANDIDATES_CAP_MOST_IMBALANCED,
+            SatDirection::Pull,
+            &mut rotation
+        ),
+        vec![scid("101x1x0"), scid("103x1x0")]
+    );
+    assert_eq!(
+        cap(
+            2,
+            CANDIDATES_CAP_MOST_IMBALANCED,
+            SatDirection::Push,
+            &mut rotation
+        ),
+        vec![scid("104x1x0"), scid("105x1x0")]
+    );
+    assert_eq!(
+        cap(
+            2,
+            CANDIDATES_CAP_CHEAPEST_HISTORY,
+            SatDirection::Pull,
+            &mut rotation
+        ),
+        vec![scid("102x1x0"), scid("105x1x0")]
+    );
+    assert_eq!(rotation, 0);
+}
+
+#[test]
+fn test_force_stop_jobs() {
+    use crate::model::{JobMessage, JobState, PluginState, Task};
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use std::path::PathBuf;
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    let state = PluginState::new(
+        PublicKey::from_str(TEST_PEER_A).unwrap(),
+        PathBuf::from("lightning-rpc"),
+        PathBuf::from("sling"),
+        PathBuf::from("."),
+        "v24.05".to_string(),
+    );
+    let scid = ShortChannelId::from_str("101x1x0").unwrap();
+    let other = ShortChannelId::from_str("102x1x0").unwrap();
+    state
+        .job_state
+        .lock()
+        .insert(scid, vec![JobState::new(JobMessage::Rebalancing, 1)]);
+    state
+        .job_state
+        .lock()
+        .insert(other, vec![JobState::new(JobMessage::Rebalancing, 1)]);
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let dispatched = Arc::new(AtomicU64::new(0));
+        let counter = dispatched.clone();
+        let handle = tokio::spawn(async move {
+            loop {
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            }
+        });
+        state.task_handles.lock().insert(
+            Task {
+                chan_id: scid,
+                task_id: 1,
+            },
+            handle.abort_handle(),
+        );
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        assert_eq!(state.force_stop_jobs(Some(scid)), 1);
+        assert!(handle.await.unwrap_err().is_cancelled());
+        let after_stop = dispatched.load(Ordering::SeqCst);
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        assert_eq!(dispatched.load(Ordering::SeqCst), after_stop);
+    });
+
+    let job_states = state.job_state.lock().clone();
+    let stopped = job_states.get(&scid).unwrap().first().unwrap();
+    assert!(!stopped.is_active());
+    assert!(stopped.should_stop());
+    assert!(matches!(stopped.state(), JobMessage::Stopped));
+    assert!(job_states.get(&other).unwrap().first().unwrap().is_active());
+    assert!(state.task_handles.lock().is_empty());
+
+    assert_eq!(state.force_stop_jobs(None), 2);
+    assert!(!state.job_state.lock().get(&other).unwrap()[0].is_active());
+}
+
+#[test]
+fn test_task_finished_removes_handle() {
+    use crate::model::{channel_jobstate_update, JobMessage, JobState, PluginState, Task};
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    let state = PluginState::new(
+        PublicKey::from_str(TEST_PEER_A).unwrap(),
+        PathBuf::from("lightning-rpc"),
+        PathBuf::from("sling"),
+        PathBuf::from("."),
+        "v24.05".to_string(),
+    );
+    let task = Task {
+        chan_id: ShortChannelId::from_str("101x1x0").unwrap(),
+        task_id: 1,
+    };
+    state.job_state.lock().insert(
+        task.chan_id,
+        vec![JobState::new(JobMessage::Rebalancing, 1)],
+    );
+    channel_jobstate_update(
+        state.job_state.clone(),
+        &task,
+        &JobMessage::Stopped,
+        false,
+        true,
+    )
+    .unwrap();
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let handle = tokio::spawn(async {});
+        state
+            .task_handles
+            .lock()
+            .insert(task, handle.abort_handle());
+        handle.await.unwrap();
+    });
+    state.task_finished(&task);
+    assert!(state.task_handles.lock().is_empty());
+
+    // the slot was restarted before the old task got to clean up
+    rt.block_on(async {
+        let handle = tokio::spawn(async {});
+        state
+            .task_handles
+            .lock()
+            .insert(task, handle.abort_handle());
+    });
+    state.job_state.lock().get_mut(&task.chan_id).unwrap()[0] =
+        JobState::new(JobMessage::Starting, 1);
+    state.task_finished(&task);
+    assert!(state.task_handles.lock().contains_key(&task));
+}
+
+#[test]
+fn test_candidate_order_most_over_target() {
+    use crate::slings::sort_candidates_most_over_target;
+    use cln_rpc::primitives::ShortChannelId;
+    use sling::{Job, SatDirection};
+    use std::collections::{BTreeMap, HashMap};
+    use std::str::FromStr;
+
+    let scid = |s: &str| ShortChannelId::from_str(s).unwrap();
+    let peer_channels = [
+        test_peer_channel("101x1x0", TEST_PEER_A, 1_000_000_000, 600_000_000, 0),
+        test_peer_channel("102x1x0", TEST_PEER_B, 1_000_000_000, 700_000_000, 0),
+        test_peer_channel("103x1x0", TEST_PEER_C, 1_000_000_000, 200_000_000, 0),
+    ]
+    .into_iter()
+    .map(|c| (c.short_channel_id.unwrap(), c))
+    .collect::<HashMap<ShortChannelId, _>>();
+    // 101x1x0 wants 80% on their side, so it is 40% over its target on our side
+    let mut jobs = BTreeMap::new();
+    jobs.insert(
+        scid("101x1x0"),
+        serde_json::from_value::<Job>(serde_json::json!({
+            "sat_direction": "push",
+            "amount_msat": 100_000_000,
+            "maxppm": 100,
+            "target": 0.8
+        }))
+        .unwrap(),
+    );
+
+    let mut candidates = vec![scid("103x1x0"), scid("102x1x0"), scid("101x1x0")];
+    sort_candidates_most_over_target(&mut candidates, SatDirection::Pull, &peer_channels, &jobs);
+    assert_eq!(
+        candidates,
+        vec![scid("101x1x0"), scid("102x1x0"), scid("103x1x0")]
+    );
+
+    sort_candidates_most_over_target(&mut candidates, SatDirection::Push, &peer_channels, &jobs);
+    assert_eq!(candidates.first(), Some(&scid("103x1x0")));
+    assert_eq!(candidates.last(), Some(&scid("101x1x0")));
+}
+
+#[test]
+fn test_trace_records_exclusions_and_route() {
+    use crate::model::{LnGraph, TraceRecord, TRACE_FILE_NAME};
+    use crate::slings::candidate_exclusions;
+    use cln_rpc::primitives::ShortChannelId;
+    use sling::Job;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    let scid = |s: &str| ShortChannelId::from_str(s).unwrap();
+    let peer_channels = [
+        test_peer_channel("101x1x0", TEST_PEER_A, 1_000_000_000, 800_000_000, 0),
+        test_peer_channel("102x1x0", TEST_PEER_B, 1_000_000_000, 50_000_000, 0),
+        test_peer_channel("103x1x0", TEST_PEER_C, 1_000_000_000, 800_000_000, 0),
+    ]
+    .into_iter()
+    .map(|c| (c.short_channel_id.unwrap(), c))
+    .collect::<HashMap<ShortChannelId, _>>();
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 100
+    }))
+    .unwrap();
+    let mut tempbans = HashMap::new();
+    tempbans.insert(scid("103x1x0"), 0);
+
+    let (candidates, excluded) = candidate_exclusions(
+        &peer_channels,
+        &job,
+        &LnGraph::new(),
+        &tempbans,
+        &test_config(),
+        None,
+        1_000,
+    );
+    assert_eq!(candidates, vec![scid("101x1x0")]);
+    let mut reasons = excluded
+        .iter()
+        .map(|e| (e.short_channel_id.to_string(), e.reason.as_str()))
+        .collect::<Vec<_>>();
+    reasons.sort();
+    assert_eq!(
+        reasons,
+        vec![
+            ("102x1x0".to_string(), "not enough liquidity"),
+            ("103x1x0".to_string(), "tempbanned"),
+        ]
+    );
+
+    let record = TraceRecord {
+        chan_id: scid("100x1x0"),
+        task_id: 1,
+        amount_msat: job.amount_msat,
+        candidates,
+        excluded,
+        route: vec![scid("101x1x0"), scid("104x1x0"), scid("100x1x0")],
+        fee_ppm: Some(42),
+        outcome: "success".to_string(),
+        created_at: 1_700_000_000,
+    };
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let sling_dir = std::env::temp_dir().join(format!("sling-test-trace-{}", std::process::id()));
+    std::fs::create_dir_all(&sling_dir).unwrap();
+    rt.block_on(record.write_to_file(&sling_dir)).unwrap();
+    let written = std::fs::read_to_string(sling_dir.join(TRACE_FILE_NAME)).unwrap();
+    assert_eq!(written.lines().count(), 1);
+    let line: serde_json::Value = serde_json::from_str(written.trim()).unwrap();
+    assert_eq!(
+        line["route"],
+        serde_json::json!(["101x1x0", "104x1x0", "100x1x0"])
+    );
+    assert!(line["excluded"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|e| e["reason"] == "tempbanned"));
+    assert_eq!(
+        serde_json::from_str::<TraceRecord>(written.trim()).unwrap(),
+        record
+    );
+
+    std::fs::remove_dir_all(&sling_dir).unwrap();
+}
+
+#[test]
+fn test_pending_payment_marks_htlc_stuck() {
+    use crate::model::{JobMessage, JobState, Task};
+    use crate::util::track_pending_payment;
+    use anyhow::anyhow;
+    use cln_rpc::model::responses::ListsendpaysPaymentsStatus;
+    use cln_rpc::primitives::ShortChannelId;
+    use parking_lot::Mutex;
+    use sling::StuckHtlc;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let task = Task {
+        chan_id: ShortChannelId::from_str("101x1x0").unwrap(),
+        task_id: 1,
+    };
+    let job_states = Arc::new(Mutex::new(HashMap::new()));
+    job_states.lock().insert(
+        task.chan_id,
+        vec![JobState::new(JobMessage::Rebalancing, 1)],
+    );
+    let stuck_htlc = StuckHtlc {
+        payment_hash: "00".repeat(32),
+        short_channel_id: task.chan_id,
+        amount_msat: 100_000_000,
+        sent_at: 1_700_000_000,
+    };
+    let state_of = |job_states: &Arc<Mutex<HashMap<ShortChannelId, Vec<JobState>>>>| {
+        job_states.lock().get(&task.chan_id).unwrap()[0].clone()
+    };
+    // replays the statuses listsendpays would return and what the task looked like
+    // at every poll
+    let track = |stuck_at: u64, statuses: Vec<Result<Option<ListsendpaysPaymentsStatus>, _>>| {
+        let observed = Arc::new(Mutex::new(Vec::new()));
+        let mut statuses = statuses.into_iter();
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(track_pending_payment(
+                job_states.clone(),
+                task,
+                stuck_htlc.clone(),
+                stuck_at,
+                Duration::from_millis(1),
+                || {
+                    observed.lock().push(state_of(&job_states));
+                    let status = statuses.next().unwrap();
+                    async move { status }
+                },
+            ));
+        let observed = observed.lock().clone();
+        observed
+    };
+
+    // a payment that resolves before it is overdue is never reported
+    let observed = track(
+        u64::MAX,
+        vec![
+            Ok(Some(ListsendpaysPaymentsStatus::PENDING)),
+            Ok(Some(ListsendpaysPaymentsStatus::COMPLETE)),
+        ],
+    );
+    assert!(observed
+        .iter()
+        .all(|js| matches!(js.state(), JobMessage::Rebalancing) && js.stuck_htlc().is_none()));
+
+    // still pending past sling-timeoutpay: stuck until listsendpays reports it resolved
+    let observed = track(
+        0,
+        vec![
+            Ok(Some(ListsendpaysPaymentsStatus::PENDING)),
+            Err(anyhow!("lightningd busy")),
+            Ok(Some(ListsendpaysPaymentsStatus::PENDING)),
+            Ok(Some(ListsendpaysPaymentsStatus::FAILED)),
+        ],
+    );
+    assert_eq!(observed.len(), 4);
+    for js in &observed[1..] {
+        assert!(matches!(js.state(), JobMessage::HtlcStuck));
+        assert_eq!(js.stuck_htlc(), Some(&stuck_htlc));
+    }
+    assert!(matches!(
+        state_of(&job_states).state(),
+        JobMessage::Rebalancing
+    ));
+    assert!(state_of(&job_states).stuck_htlc().is_none());
+}
+
+#[test]
+fn test_follower_mode_refreshes_without_dispatch() {
+    use crate::model::{JobMessage, PluginState, MODE_ACTIVE, MODE_FOLLOWER};
+    use cln_rpc::primitives::PublicKey;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    let state = PluginState::new(
+        PublicKey::from_str(TEST_PEER_A).unwrap(),
+        PathBuf::from("lightning-rpc"),
+        PathBuf::from("sling"),
+        PathBuf::from("."),
+        "v24.05".to_string(),
+    );
+    assert!(state.dispatch_paused(&state.config.lock()).is_none());
+    state.config.lock().mode.value = MODE_FOLLOWER.to_string();
+    assert!(matches!(
+        state.dispatch_paused(&state.config.lock()),
+        Some(JobMessage::Follower)
+    ));
+
+    test_graph_edge(
+        &mut state.graph.lock(),
+        "103x1x0",
+        TEST_PEER_C,
+        TEST_PEER_B,
+        10,
+    );
+    for channel_state in state
+        .graph
+        .lock()
+        .graph
+        .values_mut()
+        .flat_map(|c| c.values_mut())
+    {
+        channel_state.liquidity = 0;
+    }
+    state.graph.lock().refresh_liquidity(0, &HashMap::new());
+    assert!(state
+        .graph
+        .lock()
+        .graph
+        .values()
+        .flat_map(|c| c.values())
+        .all(|c| c.liquidity == 500_000_000));
+    assert!(matches!(
+        state.dispatch_paused(&state.config.lock()),
+        Some(JobMessage::Follower)
+    ));
+
+    // maintenance wins while both are on
+    *state.maintenance.lock() = true;
+    assert!(matches!(
+        state.dispatch_paused(&state.config.lock()),
+        Some(JobMessage::Maintenance)
+    ));
+    *state.maintenance.lock() = false;
+
+    state.config.lock().mode.value = MODE_ACTIVE.to_string();
+    assert!(state.dispatch_paused(&state.config.lock()).is_none());
+}
+
+#[test]
+fn test_alias_blocklist() {
+    use crate::model::AliasBlocklist;
+    use cln_rpc::primitives::PublicKey;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    let spammer = PublicKey::from_str(TEST_PEER_B).unwrap();
+    let friend = PublicKey::from_str(TEST_PEER_C).unwrap();
+    let anonymous = PublicKey::from_str(TEST_PEER_D).unwrap();
+    let mut alias_map = HashMap::from([
+        (spammer, "Free-SPAM-node".to_string()),
+        (friend, "friendly".to_string()),
+    ]);
+
+    let mut blocklist = AliasBlocklist::default();
+    assert!(!blocklist.is_blocked("", &spammer, &alias_map));
+    assert!(blocklist.is_blocked("spam, junk", &spammer, &alias_map));
+    assert!(!blocklist.is_blocked("spam, junk", &friend, &alias_map));
+    assert!(!blocklist.is_blocked("spam, junk", &anonymous, &alias_map));
+
+    // cached until the aliases are refreshed
+    alias_map.insert(friend, "junk router".to_string());
+    assert!(!blocklist.is_blocked("spam, junk", &friend, &alias_map));
+    blocklist.clear();
+    assert!(blocklist.is_blocked("spam, junk", &friend, &alias_map));
+
+    // new patterns are matched again
+    assert!(!blocklist.is_blocked("other", &spammer, &alias_map));
+}
+
+#[test]
+fn test_route_cache_reload() {
+    use crate::model::{LnGraph, RouteCache};
+    use cln_rpc::model::requests::SendpayRoute;
+    use cln_rpc::primitives::{Amount, PublicKey, ShortChannelId};
+    use std::str::FromStr;
+
+    let me = PublicKey::from_str(TEST_PEER_A).unwrap();
+    let scid = |s: &str| ShortChannelId::from_str(s).unwrap();
+    let hop = |amount_msat: u64, peer: &str, chan: &str| SendpayRoute {
+        amount_msat: Amount::from_msat(amount_msat),
+        id: PublicKey::from_str(peer).unwrap(),
+        delay: 144,
+        channel: scid(chan),
+    };
+    let mut graph = LnGraph::new();
+    test_graph_edge(&mut graph, "101x1x0", TEST_PEER_A, TEST_PEER_B, 0);
+    test_graph_edge(&mut graph, "102x1x0", TEST_PEER_B, TEST_PEER_C, 0);
+    test_graph_edge(&mut graph, "103x1x0", TEST_PEER_C, TEST_PEER_A, 0);
+    let fresh = vec![
+        hop(100_020_000, TEST_PEER_B, "101x1x0"),
+        hop(100_000_000, TEST_PEER_C, "102x1x0"),
+        hop(100_000_000, TEST_PEER_A, "103x1x0"),
+    ];
+    // 104x1x0 closed since the route was cached
+    let gone = vec![
+        hop(100_000_000, TEST_PEER_B, "101x1x0"),
+        hop(100_000_000, TEST_PEER_A, "104x1x0"),
+    ];
+
+    let mut route_cache = RouteCache::default();
+    route_cache.insert(scid("103x1x0"), &fresh, 10_000);
+    route_cache.insert(scid("104x1x0"), &gone, 10_000);
+    route_cache.insert(scid("105x1x0"), &fresh, 1_000);
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let sling_dir =
+        std::env::temp_dir().join(format!("sling-test-route-cache-{}", std::process::id()));
+    std::fs::create_dir_all(&sling_dir).unwrap();
+    rt.block_on(route_cache.write_to_file(&sling_dir)).unwrap();
+    let mut reloaded = rt.block_on(RouteCache::read_from_file(&sling_dir)).unwrap();
+    assert_eq!(reloaded, route_cache);
+
+    assert_eq!(reloaded.drop_expired(10_600, 3_600), 1);
+    assert!(reloaded
+        .get_valid(&scid("105x1x0"), 100_000_000, 1_000, &graph, &me)
+        .is_none());
+    assert!(reloaded
+        .get_valid(&scid("104x1x0"), 100_000_000, 1_000, &graph, &me)
+        .is_none());
+    // only reused for the same amount
+    assert!(reloaded
+        .get_valid(&scid("103x1x0"), 50_000_000, 1_000, &graph, &me)
+        .is_none());
+    // 200ppm is above the job's maxppm now
+    assert!(reloaded
+        .get_valid(&scid("103x1x0"), 100_000_000, 100, &graph, &me)
+        .is_none());
+    let warm = reloaded
+        .get_valid(&scid("103x1x0"), 100_000_000, 1_000, &graph, &me)
+        .unwrap();
+    assert_eq!(
+        warm.iter()
+            .map(|h| (h.channel, Amount::msat(&h.amount_msat), h.id))
+            .collect::<Vec<_>>(),
+        fresh
+            .iter()
+            .map(|h| (h.channel, Amount::msat(&h.amount_msat), h.id))
+            .collect::<Vec<_>>()
+    );
+
+    // C raised its fee on 103x1x0 above what the cached route pays
+    test_graph_edge(&mut graph, "103x1x0", TEST_PEER_C, TEST_PEER_A, 100);
+    assert!(reloaded
+        .get_valid(&scid("103x1x0"), 100_000_000, 1_000, &graph, &me)
+        .is_none());
+
+    std::fs::remove_dir_all(&sling_dir).unwrap();
+    assert_eq!(
+        rt.block_on(RouteCache::read_from_file(&sling_dir)).unwrap(),
+        RouteCache::default()
+    );
+}
+
+#[test]
+fn test_max_total_sat_cap() {
+    use crate::model::SuccessReb;
+    use crate::parse::parse_job;
+    use crate::stats::{lifetime_rebalanced_msat, write_rollup};
+    use cln_rpc::primitives::ShortChannelId;
+    use sling::{Job, StatsRollup};
+    use std::str::FromStr;
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let (chan_id, job) = rt
+        .block_on(parse_job(serde_json::json!({
+            "scid": "100x1x0",
+            "direction": "pull",
+            "amount": 50,
+            "maxppm": 100,
+            "outppm": 0,
+            "max_total_sat": 300
+        })))
+        .unwrap();
+    let chan_id = chan_id.unwrap();
+    assert_eq!(job.max_total_sat, Some(300));
+    let serialized = serde_json::to_value(&job).unwrap();
+    assert_eq!(serialized["max_total_sat"], 300);
+    assert_eq!(serde_json::from_value::<Job>(serialized).unwrap(), job);
+    assert!(rt
+        .block_on(parse_job(serde_json::json!({
+            "scid": "100x1x0",
+            "direction": "pull",
+            "amount": 50,
+            "maxppm": 100,
+            "outppm": 0,
+            "max_total_sat": 0
+        })))
+        .is_err());
+
+    let sling_dir =
+        std::env::temp_dir().join(format!("sling-test-max-total-{}", std::process::id()));
+    std::fs::create_dir_all(&sling_dir).unwrap();
+    assert_eq!(
+        rt.block_on(lifetime_rebalanced_msat(&sling_dir, &chan_id))
+            .unwrap(),
+        0
+    );
+
+    // pruned history from earlier sessions
+    let mut rollup = StatsRollup::default();
+    rollup.add_success(200_000, 20);
+    rt.block_on(write_rollup(&sling_dir, &chan_id, &rollup))
+        .unwrap();
+    let session_success = SuccessReb {
+        amount_msat: 50_000,
+        fee_ppm: 100,
+        fee_msat: 5,
+        channel_partner: Some(ShortChannelId::from_str("101x1x0").unwrap()),
+        hops: 2,
+        completed_at: 1_700_000_000,
+    };
+    rt.block_on(session_success.write_to_file(chan_id, &sling_dir))
+        .unwrap();
+    let total = rt
+        .block_on(lifetime_rebalanced_msat(&sling_dir, &chan_id))
+        .unwrap();
+    assert_eq!(total, 250_000);
+    assert!(!job.total_cap_reached(total));
+
+    rt.block_on(session_success.write_to_file(chan_id, &sling_dir))
+        .unwrap();
+    let total = rt
+        .block_on(lifetime_rebalanced_msat(&sling_dir, &chan_id))
+        .unwrap();
+    assert_eq!(total, 300_000);
+    assert!(job.total_cap_reached(total));
+
+    let uncapped = Job {
+        max_total_sat: Some(u64::MAX),
+        ..job.clone()
+    };
+    assert!(!uncapped.total_cap_reached(total));
+
+    std::fs::remove_dir_all(&sling_dir).unwrap();
+}
+
+#[test]
+fn test_read_skips_corrupt_lines() {
+    use crate::model::{FailureReb, SuccessReb};
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use std::str::FromStr;
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let sling_dir = std::env::temp_dir().join(format!("sling-test-corrupt-{}", std::process::id()));
+    std::fs::create_dir_all(&sling_dir).unwrap();
+    let scid = ShortChannelId::from_str("100x1x0").unwrap();
+    let success = SuccessReb {
+        amount_msat: 100_000_000,
+        fee_ppm: 123,
+        fee_msat: 12_300,
+        channel_partner: Some(ShortChannelId::from_str("101x1x0").unwrap()),
+        hops: 3,
+        completed_at: 1,
+    };
+    let failure = FailureReb {
+        amount_msat: 100_000_000,
+        failure_reason: "WIRE_TEMPORARY_CHANNEL_FAILURE".to_string(),
+        failure_node: PublicKey::from_str(TEST_PEER_A).unwrap(),
+        channel_partner: ShortChannelId::from_str("101x1x0").unwrap(),
+        hops: 3,
+        created_at: 1,
+    };
+    let (successes, failures) = rt.block_on(async {
+        success.write_to_file(scid, &sling_dir).await.unwrap();
+        failure.write_to_file(scid, &sling_dir).await.unwrap();
+        for suffix in ["_successes.json", "_failures.json"] {
+            let path = sling_dir.join(scid.to_string() + suffix);
+            let mut contents = tokio::fs::read_to_string(&path).await.unwrap();
+            contents.push_str("{\"amount_msat\":1000,\"fee_pp\n");
+            tokio::fs::write(&path, contents).await.unwrap();
+        }
+        success.write_to_file(scid, &sling_dir).await.unwrap();
+        failure.write_to_file(scid, &sling_dir).await.unwrap();
+        (
+            SuccessReb::read_from_file(&sling_dir, &scid).await.unwrap(),
+            FailureReb::read_from_file(&sling_dir, &scid).await.unwrap(),
+        )
+    });
+    assert_eq!(successes.len(), 2);
+    assert!(successes.iter().all(|s| s.fee_ppm == 123));
+    assert_eq!(failures.len(), 2);
+    assert!(failures.iter().all(|f| f.created_at == 1));
+    std::fs::remove_dir_all(&sling_dir).unwrap();
+}
+
+#[test]
+fn test_suggest_maxppm() {
+    use crate::model::{LnGraph, SuccessReb};
+    use crate::stats::suggest_maxppm;
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use std::str::FromStr;
+
+    let me = PublicKey::from_str(TEST_PEER_A).unwrap();
+    let peer = PublicKey::from_str(TEST_PEER_B).unwrap();
+    let successes = |fee_ppms: &[u32]| {
+        fee_ppms
+            .iter()
+            .map(|fee_ppm| SuccessReb {
+                amount_msat: 100_000_000,
+                fee_ppm: *fee_ppm,
+                fee_msat: 100_000_000 * *fee_ppm as u64 / 1_000_000,
+                channel_partner: Some(ShortChannelId::from_str("104x1x0").unwrap()),
+                hops: 2,
+                completed_at: 0,
+            })
+            .collect::<Vec<SuccessReb>>()
+    };
+    let history = successes(&[400, 100, 300, 200, 500, 600, 700, 800]);
+
+    let empty_graph = LnGraph::new();
+    assert!(suggest_maxppm(&[], &empty_graph, &peer, &me).is_none());
+    let suggestion = suggest_maxppm(&history, &empty_graph, &peer, &me).unwrap();
+    assert_eq!(suggestion.samples, 8);
+    assert_eq!(suggestion.history_p75_feeppm, Some(600));
+    assert_eq!(suggestion.network_median_feeppm, None);
+    assert_eq!(suggestion.suggested_maxppm, 660);
+
+    let mut graph = LnGraph::new();
+    // our own channel toward the peer is not a network fee
+    test_graph_edge(&mut graph, "100x1x0", TEST_PEER_A, TEST_PEER_B, 5_000);
+    test_graph_edge(&mut graph, "101x1x0", TEST_PEER_C, TEST_PEER_B, 700);
+    test_graph_edge(&mut graph, "102x1x0", TEST_PEER_D, TEST_PEER_B, 900);
+    test_graph_edge(&mut graph, "103x1x0", TEST_PEER_B, TEST_PEER_C, 10_000);
+    let suggestion = suggest_maxppm(&history, &graph, &peer, &me).unwrap();
+    assert_eq!(suggestion.history_p75_feeppm, Some(600));
+    assert_eq!(suggestion.network_median_feeppm, Some(900));
+    assert_eq!(suggestion.suggested_maxppm, 990);
+
+    let suggestion = suggest_maxppm(&[], &graph, &peer, &me).unwrap();
+    assert_eq!(suggestion.samples, 0);
+    assert_eq!(suggestion.history_p75_feeppm, None);
+    assert_eq!(suggestion.suggested_maxppm, 990);
+}
+
+#[test]
+fn test_min_rebalance_sat() {
+    use crate::parse::parse_job;
+    use cln_rpc::primitives::ShortChannelId;
+    use sling::Job;
+    use std::str::FromStr;
+
+    let (_, job) = tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(parse_job(serde_json::json!({
+            "scid": "100x1x0",
+            "direction": "pull",
+            "amount": 100_000,
+            "maxppm": 100,
+            "outppm": 0,
+            "target": 0.5,
+            "min_rebalance_sat": 50_000
+        })))
+        .unwrap();
+    assert_eq!(job.min_rebalance_sat, Some(50_000));
+    let serialized = serde_json::to_value(&job).unwrap();
+    assert_eq!(serialized["min_rebalance_sat"], 50_000);
+    assert_eq!(serde_json::from_value::<Job>(serialized).unwrap(), job);
+
+    let scid = ShortChannelId::from_str("100x1x0").unwrap();
+    // target is 500_000 sats on our side
+    let below_min = [test_peer_channel(
+        "100x1x0",
+        TEST_PEER_B,
+        1_000_000_000,
+        460_000_000,
+        0,
+    )];
+    assert!(job.is_balanced(&below_min, &scid));
+    let above_min = [test_peer_channel(
+        "100x1x0",
+        TEST_PEER_B,
+        1_000_000_000,
+        440_000_000,
+        0,
+    )];
+    assert!(!job.is_balanced(&above_min, &scid));
+
+    let huge_min = Job {
+        min_rebalance_sat: Some(u64::MAX),
+        ..job.clone()
+    };
+    assert!(huge_min.is_balanced(&above_min, &scid));
+
+    let without_min = Job {
+        min_rebalance_sat: None,
+        ..job
+    };
+    assert!(!without_min.is_balanced(&below_min, &scid));
+}
+
+#[test]
+fn test_channel_roi() {
+    use crate::model::SuccessReb;
+    use crate::stats::channel_roi;
+    use cln_rpc::model::responses::ListforwardsForwards;
+    use cln_rpc::primitives::ShortChannelId;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    let now = 1_700_000_000;
+    let forward = |out_channel: &str, fee_msat: u64, status: &str, resolved_time: u64| {
+        serde_json::from_value::<ListforwardsForwards>(serde_json::json!({
+            "in_channel": "200x1x0",
+            "in_msat": 100_000_000 + fee_msat,
+            "status": status,
+            "received_time": resolved_time as f64 - 1.0,
+            "resolved_time": resolved_time as f64,
+            "out_channel": out_channel,
+            "out_msat": 100_000_000,
+            "fee_msat": fee_msat,
+        }))
+        .unwrap()
+    };
+    let forwards = vec![
+        forward("100x1x0", 3_000, "settled", now - 100),
+        forward("100x1x0", 2_000, "settled", now - 100),
+        forward("101x1x0", 1_000, "settled", now - 100),
+        // failed and old forwards earn nothing
+        forward("101x1x0", 50_000, "failed", now - 100),
+        forward("101x1x0", 50_000, "settled", now - 40 * 86_400),
+    ];
+    let success = |fee_msat: u64, completed_at: u64| SuccessReb {
+        amount_msat: 100_000_000,
+        fee_ppm: (fee_msat / 100) as u32,
+        fee_msat,
+        channel_partner: Some(ShortChannelId::from_str("102x1x0").unwrap()),
+        hops: 2,
+        completed_at,
+    };
+    let mut successes = HashMap::new();
+    successes.insert(
+        ShortChannelId::from_str("100x1x0").unwrap(),
+        vec![success(4_000, now - 100)],
+    );
+    successes.insert(
+        ShortChannelId::from_str("101x1x0").unwrap(),
+        vec![
+            success(5_000, now - 100),
+            success(90_000, now - 40 * 86_400),
+        ],
+    );
+
+    let rois = channel_roi(&forwards, &successes, now, 30);
+    assert_eq!(rois.len(), 2);
+    // worst channel first
+    assert_eq!(rois[0].short_channel_id.to_string(), "101x1x0");
+    assert_eq!(rois[0].forward_fees_msat, 1_000);
+    assert_eq!(rois[0].rebalance_fees_msat, 5_000);
+    assert_eq!(rois[0].net_msat, -4_000);
+    assert_eq!(rois[1].short_channel_id.to_string(), "100x1x0");
+    assert_eq!(rois[1].forward_fees_msat, 5_000);
+    assert_eq!(rois[1].rebalance_fees_msat, 4_000);
+    assert_eq!(rois[1].net_msat, 1_000);
+    let negative = rois.iter().filter(|r| r.net_msat < 0).collect::<Vec<_>>();
+    assert_eq!(negative.len(), 1);
+}
+
+#[test]
+fn test_parallelism_auto_tune() {
+    use crate::model::{tune_parallelism, JobMessage, JobState, PluginState, PARALLEL_TUNE_WINDOW};
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use sling::Job;
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 100,
+        "outppm": 0,
+        "paralleljobs": 4,
+        "paralleljobs_min": 1
+    }))
+    .unwrap();
+    let state = PluginState::new(
+        PublicKey::from_str(TEST_PEER_A).unwrap(),
+        PathBuf::from("rpc"),
+        PathBuf::from("sling"),
+        PathBuf::from("."),
+        "v24.05".to_string(),
+    );
+    let config = state.config.lock().clone();
+    let scid = ShortChannelId::from_str("100x1x0").unwrap();
+    state.job_state.lock().insert(
+        scid,
+        (1..=4)
+            .map(|i| JobState::new(JobMessage::Rebalancing, i))
+            .collect(),
+    );
+    let attempts = |successes: u64| {
+        let mut job_states = state.job_state.lock();
+        let tasks = job_states.get_mut(&scid).unwrap();
+        for i in 0..PARALLEL_TUNE_WINDOW {
+            let task_count = tasks.len();
+            tasks[i as usize % task_count].record_attempt(i < successes, 0);
+        }
+    };
+    assert_eq!(state.effective_parallelism(&scid, &job, &config), 1);
+
+    // high success rate grows parallelism up to paralleljobs
+    let mut seen = vec![];
+    for _ in 0..5 {
+        attempts(PARALLEL_TUNE_WINDOW);
+        state.retune_parallelism(&scid, &job, &config);
+        seen.push(state.effective_parallelism(&scid, &job, &config));
+    }
+    assert_eq!(seen, vec![2, 3, 4, 4, 4]);
+    assert_eq!(
+        state.job_state.lock().get(&scid).unwrap()[0].success_ratio(),
+        None
+    );
+
+    // a middling rate keeps it where it is
+    attempts(PARALLEL_TUNE_WINDOW * 7 / 10);
+    assert_eq!(state.retune_parallelism(&scid, &job, &config), None);
+    assert_eq!(state.effective_parallelism(&scid, &job, &config), 4);
+
+    // rising failures back off down to paralleljobs_min
+    let mut seen = vec![];
+    for _ in 0..5 {
+        attempts(PARALLEL_TUNE_WINDOW / 5);
+        state.retune_parallelism(&scid, &job, &config);
+        seen.push(state.effective_parallelism(&scid, &job, &config));
+    }
+    assert_eq!(seen, vec![3, 2, 1, 1, 1]);
+
+    // nothing happens before a full window of attempts
+    let mut job_states = vec![JobState::new(JobMessage::Rebalancing, 1)];
+    job_states[0].record_attempt(true, 0);
+    assert_eq!(job_states[0].success_ratio(), Some(1.0));
+    assert_eq!(tune_parallelism(&mut job_states, 2, 1, 4), 2);
+
+    // without paralleljobs_min all tasks dispatch
+    let fixed = Job {
+        paralleljobs_min: None,
+        ..job
+    };
+    assert_eq!(state.effective_parallelism(&scid, &fixed, &config), 4);
+}
+
+#[test]
+fn test_unmanaged_channels() {
+    use crate::util::{channel_imbalance, unmanaged_channels};
+    use cln_rpc::primitives::ShortChannelId;
+    use sling::Job;
+    use std::collections::{BTreeMap, HashMap, HashSet};
+    use std::str::FromStr;
+
+    let peer_channels = [
+        test_peer_channel("100x1x0", TEST_PEER_B, 1_000_000_000, 500_000_000, 0),
+        test_peer_channel("101x1x0", TEST_PEER_B, 1_000_000_000, 500_000_000, 0),
+        test_peer_channel("102x1x0", TEST_PEER_C, 1_000_000_000, 400_000_000, 0),
+        test_peer_channel("103x1x0", TEST_PEER_C, 1_000_000_000, 100_000_000, 0),
+        test_peer_channel("104x1x0", TEST_PEER_D, 1_000_000_000, 900_000_000, 0),
+    ]
+    .into_iter()
+    .map(|c| (c.short_channel_id.unwrap(), c))
+    .collect::<HashMap<ShortChannelId, _>>();
+    let scid = |s: &str| ShortChannelId::from_str(s).unwrap();
+
+    // a peer job on 100x1x0 covers all channels with TEST_PEER_B
+    let peer_job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "push",
+        "amount_msat": 100_000_000,
+        "maxppm": 100,
+        "outppm": 0,
+        "peer": TEST_PEER_B
+    }))
+    .unwrap();
+    let chan_job = Job {
+        peer: None,
+        ..peer_job.clone()
+    };
+    let mut jobs = BTreeMap::new();
+    jobs.insert(scid("100x1x0"), peer_job);
+    jobs.insert(scid("102x1x0"), chan_job);
+    let pull_jobs = HashSet::from([scid("102x1x0")]);
+    let push_jobs = HashSet::from([scid("100x1x0")]);
+
+    let unmanaged = unmanaged_channels(&peer_channels, &pull_jobs, &push_jobs, &jobs)
+        .into_iter()
+        .map(|c| c.short_channel_id.unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(unmanaged, vec![scid("103x1x0"), scid("104x1x0")]);
+    assert!((channel_imbalance(&peer_channels[&scid("103x1x0")]) + 0.4).abs() < 1e-9);
+    assert!((channel_imbalance(&peer_channels[&scid("104x1x0")]) - 0.4).abs() < 1e-9);
+
+    let unmanaged = unmanaged_channels(&peer_channels, &HashSet::new(), &HashSet::new(), &jobs);
+    assert_eq!(unmanaged.len(), 5);
+}
+
+#[test]
+fn test_maxppm_per_direction() {
+    use crate::model::{EffectiveJobConfig, PluginState};
+    use crate::parse::parse_job;
+    use cln_rpc::primitives::PublicKey;
+    use sling::{Job, SatDirection};
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    let (_, job) = tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(parse_job(serde_json::json!({
+            "scid": "100x1x0",
+            "direction": "push",
+            "amount": 100_000,
+            "maxppm": 300,
+            "maxppm_push": 100,
+            "outppm": 0
+        })))
+        .unwrap();
+    assert_eq!(job.maxppm_for(SatDirection::Push), 100);
+    assert_eq!(job.maxppm_for(SatDirection::Pull), 300);
+    let serialized = serde_json::to_value(&job).unwrap();
+    assert_eq!(serialized["maxppm_push"], 100);
+    assert!(serialized.get("maxppm_pull").is_none());
+    assert_eq!(serde_json::from_value::<Job>(serialized).unwrap(), job);
+
+    let pull = Job {
+        sat_direction: SatDirection::Pull,
+        maxppm_pull: Some(500),
+        ..job.clone()
+    };
+    assert_eq!(pull.maxppm_for(SatDirection::Pull), 500);
+    assert_eq!(pull.maxppm_for(SatDirection::Push), 100);
+
+    // the ceiling of the job's direction bounds unlimited-hop routes
+    let state = PluginState::new(
+        PublicKey::from_str(TEST_PEER_A).unwrap(),
+        PathBuf::from("rpc"),
+        PathBuf::from("sling"),
+        PathBuf::from("."),
+        "v24.05".to_string(),
+    );
+    let mut config = state.config.lock().clone();
+    config.maxhops.value = 0;
+    let (_, max_fee) = EffectiveJobConfig::new(&job, &config).route_limits(&job);
+    assert_eq!(max_fee, Some(10_000));
+    let (_, max_fee) = EffectiveJobConfig::new(&pull, &config).route_limits(&pull);
+    assert_eq!(max_fee, Some(50_000));
+}
+
+#[test]
+fn test_import_history() {
+    use crate::model::SuccessReb;
+    use crate::stats::{import_rebalances, parse_imported_rebalances};
+    use cln_rpc::primitives::ShortChannelId;
+    use std::str::FromStr;
+
+    let scid = |s: &str| ShortChannelId::from_str(s).unwrap();
+    let csv = "scid,partner,direction,amount_msat,fee_msat,timestamp\n\
+        100x1x0,101x1x0,pull,100000000,25000,1700000000\n\
+        100x1x0,101x1x0,pull,1,18446744073709551615,1700000100\n\
+        100x1x0,101x1x0,pull,lots,25000,1700000200\n\
+        100x1x0,101x1x0,pull,100000000\n\
+        102x1x0,,,50000000,5000,1700000300\n";
+    let (records, malformed) = parse_imported_rebalances(csv);
+    assert_eq!(malformed, 3);
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].0, scid("100x1x0"));
+    assert_eq!(records[0].1.fee_ppm, 250);
+    assert_eq!(records[0].1.hops, 2);
+    assert_eq!(records[1].0, scid("102x1x0"));
+    assert_eq!(records[1].1.fee_ppm, 100);
+    assert_eq!(records[1].1.channel_partner, None);
+
+    let json = serde_json::json!([
+        {"scid": "100x1x0", "partner": "103x1x0", "direction": "pull",
+         "amount_msat": 200_000_000, "fee_msat": 10_000, "timestamp": 1_700_000_400, "hops": 4},
+        {"scid": "100x1x0", "partner": "103x1x0", "direction": "pull",
+         "amount_msat": 0, "fee_msat": 10_000, "timestamp": 1_700_000_500},
+        "garbage"
+    ])
+    .to_string();
+    let (json_records, malformed) = parse_imported_rebalances(&json);
+    assert_eq!(malformed, 2);
+    assert_eq!(json_records.len(), 1);
+    assert_eq!(json_records[0].1.hops, 4);
+    let jsonl = "{\"scid\":\"100x1x0\",\"partner\":\"103x1x0\",\"direction\":\"pull\",\"amount_msat\":200000000,\"fee_msat\":10000,\"timestamp\":1700000400,\"hops\":4}\n{\"scid\":";
+    let (jsonl_records, malformed) = parse_imported_rebalances(jsonl);
+    assert_eq!(malformed, 1);
+    assert_eq!(jsonl_records.len(), 1);
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let sling_dir = std::env::temp_dir().join(format!("sling-test-import-{}", std::process::id()));
+    std::fs::create_dir_all(&sling_dir).unwrap();
+    let (imported, duplicates) = rt
+        .block_on(import_rebalances(
+            &sling_dir,
+            records.into_iter().chain(json_records).collect(),
+        ))
+        .unwrap();
+    assert_eq!((imported, duplicates), (3, 0));
+    // importing the same file again adds nothing
+    let (imported, duplicates) = rt
+        .block_on(import_rebalances(&sling_dir, jsonl_records))
+        .unwrap();
+    assert_eq!((imported, duplicates), (0, 1));
+
+    let read = rt
+        .block_on(SuccessReb::read_from_file(&sling_dir, &scid("100x1x0")))
+        .unwrap();
+    assert_eq!(read.len(), 2);
+    assert_eq!(read[0].amount_msat, 100_000_000);
+    assert_eq!(read[0].fee_msat, 25_000);
+    assert_eq!(read[0].channel_partner, Some(scid("101x1x0")));
+    assert_eq!(read[0].completed_at, 1_700_000_000);
+    assert_eq!(read[1].channel_partner, Some(scid("103x1x0")));
+    let read = rt
+        .block_on(SuccessReb::read_from_file(&sling_dir, &scid("102x1x0")))
+        .unwrap();
+    assert_eq!(read.len(), 1);
+    assert_eq!(read[0].channel_partner, None);
+    std::fs::remove_dir_all(&sling_dir).unwrap();
+}
+
+#[test]
+fn test_low_utilization() {
+    use crate::model::JobMessage;
+    use crate::stats::{forward_volumes, is_low_utilization};
+    use cln_rpc::model::responses::ListforwardsForwards;
+    use cln_rpc::primitives::ShortChannelId;
+    use std::str::FromStr;
+
+    let now = 1_700_000_000;
+    let forward = |in_channel: &str, out_channel: &str, status: &str, resolved_time: u64| {
+        serde_json::from_value::<ListforwardsForwards>(serde_json::json!({
+            "in_channel": in_channel,
+            "in_msat": 100_001_000,
+            "status": status,
+            "received_time": resolved_time as f64 - 1.0,
+            "resolved_time": resolved_time as f64,
+            "out_channel": out_channel,
+            "out_msat": 100_000_000,
+            "fee_msat": 1_000,
+        }))
+        .unwrap()
+    };
+    let forwards = vec![
+        forward("100x1x0", "101x1x0", "settled", now - 100),
+        forward("100x1x0", "101x1x0", "settled", now - 100),
+        // neither failed nor old forwards count
+        forward("102x1x0", "101x1x0", "failed", now - 100),
+        forward("102x1x0", "101x1x0", "settled", now - 40 * 86_400),
+    ];
+    let volumes = forward_volumes(&forwards, now, 30);
+    let scid = |s: &str| ShortChannelId::from_str(s).unwrap();
+    assert_eq!(volumes.get(&scid("100x1x0")), Some(&200_002_000));
+    assert_eq!(volumes.get(&scid("101x1x0")), Some(&200_000_000));
+    assert_eq!(volumes.get(&scid("102x1x0")), None);
+
+    let idle = [test_peer_channel(
+        "102x1x0",
+        TEST_PEER_B,
+        1_000_000_000,
+        500_000_000,
+        0,
+    )];
+    let active = [test_peer_channel(
+        "100x1x0",
+        TEST_PEER_C,
+        1_000_000_000,
+        500_000_000,
+        0,
+    )];
+    assert!(is_low_utilization(&volumes, &idle, 100_000_000));
+    assert!(!is_low_utilization(&volumes, &active, 100_000_000));
+    assert!(is_low_utilization(&volumes, &active, 300_000_000));
+    // a peer job counts the volume of all its channels
+    let peer_channels = [active[0].clone(), idle[0].clone()];
+    assert!(!is_low_utilization(&volumes, &peer_channels, 200_000_000));
+    assert_eq!(JobMessage::LowUtilization.code(), 29);
+}
+
+#[test]
+fn test_too_expensive_surfaces_cheapest_route() {
+    use crate::model::{
+        channel_jobstate_update, channel_too_expensive, JobMessage, JobState, Task,
+    };
+    use crate::slings::cheaper_feeppm;
+    use cln_rpc::primitives::ShortChannelId;
+    use parking_lot::Mutex;
+    use sling::TooExpensive;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    // rejected routes while splitting the amount
+    let cheapest = [1_500, 1_200, 1_300]
+        .into_iter()
+        .fold(None, cheaper_feeppm)
+        .unwrap();
+    assert_eq!(cheapest, 1_200);
+
+    let task = Task {
+        chan_id: ShortChannelId::from_str("100x1x0").unwrap(),
+        task_id: 1,
+    };
+    let job_states = Arc::new(Mutex::new(HashMap::from([(
+        task.chan_id,
+        vec![JobState::new(JobMessage::Rebalancing, 1)],
+    )])));
+    channel_too_expensive(
+        job_states.clone(),
+        &task,
+        TooExpensive {
+            cheapest_feeppm: cheapest,
+            maxppm: 1_000,
+        },
+    )
+    .unwrap();
+    {
+        let states = job_states.lock();
+        let state = &states.get(&task.chan_id).unwrap()[0];
+        assert!(matches!(state.state(), JobMessage::TooExp));
+        assert_eq!(
+            state.too_expensive(),
+            Some(TooExpensive {
+                cheapest_feeppm: 1_200,
+                maxppm: 1_000
+            })
+        );
+    }
+    let serialized = serde_json::to_value(TooExpensive {
+        cheapest_feeppm: 1_200,
+        maxppm: 1_000,
+    })
+    .unwrap();
+    assert_eq!(serialized["cheapest_feeppm"], 1_200);
+
+    // cleared once the task moves on
+    channel_jobstate_update(
+        job_states.clone(),
+        &task,
+        &JobMessage::Rebalancing,
+        true,
+        false,
+    )
+    .unwrap();
+    assert_eq!(
+        job_states.lock().get(&task.chan_id).unwrap()[0].too_expensive(),
+        None
+    );
+
+    let missing = Task {
+        chan_id: task.chan_id,
+        task_id: 2,
+    };
+    assert!(channel_too_expensive(
+        job_states,
+        &missing,
+        TooExpensive {
+            cheapest_feeppm: 1_200,
+            maxppm: 1_000
+        }
+    )
+    .is_err());
+}
+
+#[test]
+fn test_job_subdir_stats() {
+    use crate::model::{PluginState, SuccessReb};
+    use crate::parse::parse_job;
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let job_json = |subdir: &str| {
+        serde_json::json!({
+            "scid": "100x1x0",
+            "direction": "pull",
+            "amount": 100_000,
+            "maxppm": 100,
+            "outppm": 0,
+            "subdir": subdir
+        })
+    };
+    for invalid in ["", "..", "a/b"] {
+        assert!(rt.block_on(parse_job(job_json(invalid))).is_err());
+    }
+    let (chan_id, job) = rt.block_on(parse_job(job_json("hot"))).unwrap();
+    let chan_id = chan_id.unwrap();
+    assert_eq!(job.to_json()["subdir"], "hot");
+
+    let sling_dir = std::env::temp_dir().join(format!("sling-test-subdir-{}", std::process::id()));
+    let stats_dir = job.stats_dir(&sling_dir);
+    assert_eq!(stats_dir, sling_dir.join("hot"));
+    std::fs::create_dir_all(&stats_dir).unwrap();
+
+    let plugin_state = PluginState::new(
+        PublicKey::from_str(TEST_PEER_A).unwrap(),
+        PathBuf::from("lightning-rpc"),
+        PathBuf::from("sling"),
+        PathBuf::from("."),
+        "v24.05".to_string(),
+    );
+    let other_chan = ShortChannelId::from_str("101x1x0").unwrap();
+    assert_eq!(plugin_state.stats_dir(&sling_dir, &chan_id), sling_dir);
+    plugin_state.loaded_jobs.lock().insert(chan_id, job);
+    assert_eq!(plugin_state.stats_dir(&sling_dir, &chan_id), stats_dir);
+    assert_eq!(plugin_state.stats_dir(&sling_dir, &other_chan), sling_dir);
+
+    let success = SuccessReb {
+        amount_msat: 100_000_000,
+        fee_ppm: 123,
+        fee_msat: 12_300,
+        channel_partner: Some(other_chan),
+        hops: 3,
+        completed_at: 1,
+    };
+    let (nested, flat) = rt.block_on(async {
+        success.write_to_file(chan_id, &stats_dir).await.unwrap();
+        (
+            SuccessReb::read_from_file(&stats_dir, &chan_id)
+                .await
+                .unwrap(),
+            SuccessReb::read_from_file(&sling_dir, &chan_id).await,
+        )
+    });
+    assert_eq!(nested.len(), 1);
+    assert_eq!(nested[0].fee_ppm, 123);
+    assert!(flat.is_err());
+    std::fs::remove_dir_all(&sling_dir).unwrap();
+}
+
+#[test]
+fn test_balance_moved() {
+    use crate::model::balance_moved;
+    use sling::SatDirection;
+
+    // a success that left our balance untouched is flagged
+    assert!(!balance_moved(
+        SatDirection::Pull,
+        400_000_000,
+        400_000_000,
+        100_000_000
+    ));
+    assert!(!balance_moved(
+        SatDirection::Push,
+        400_000_000,
+        400_000_000,
+        100_000_000
+    ));
+    // moving the wrong way is no better
+    assert!(!balance_moved(
+        SatDirection::Pull,
+        400_000_000,
+        300_000_000,
+        100_000_000
+    ));
+
+    assert!(balance_moved(
+        SatDirection::Pull,
+        400_000_000,
+        500_000_000,
+        100_000_000
+    ));
+    assert!(balance_moved(
+        SatDirection::Push,
+        500_000_000,
+        400_000_000,
+        100_000_000
+    ));
+    // a forward settling in the meantime is tolerated
+    assert!(balance_moved(
+        SatDirection::Pull,
+        400_000_000,
+        495_000_000,
+        100_000_000
+    ));
+    assert!(!balance_moved(
+        SatDirection::Pull,
+        400_000_000,
+        450_000_000,
+        100_000_000
+    ));
+}
+
+#[test]
+fn test_except_chan_impact() {
+    use crate::model::SuccessReb;
+    use crate::stats::except_chan_impact;
+    use cln_rpc::primitives::ShortChannelId;
+    use sling::{Job, SatDirection};
+    use std::collections::{BTreeMap, HashMap};
+    use std::str::FromStr;
+
+    let scid = |s: &str| ShortChannelId::from_str(s).unwrap();
+    let job = |candidates: serde_json::Value| -> Job {
+        serde_json::from_value(serde_json::json!({
+            "sat_direction": "pull",
+            "amount_msat": 100_000_000,
+            "maxppm": 100,
+            "candidatelist": candidates,
+        }))
+        .unwrap()
+    };
+    let excepted = scid("200x1x0");
+    let now = 1_700_000_000;
+    let mut jobs = BTreeMap::new();
+    // lists it explicitly
+    jobs.insert(scid("100x1x0"), job(serde_json::json!(["200x1x0"])));
+    // uses it because it is currently eligible
+    jobs.insert(scid("101x1x0"), job(serde_json::Value::Null));
+    // not a candidate anymore but recently rebalanced through it
+    jobs.insert(scid("102x1x0"), job(serde_json::Value::Null));
+    // unrelated
+    jobs.insert(scid("103x1x0"), job(serde_json::json!(["201x1x0"])));
+    // lists it but is not running
+    jobs.insert(scid("104x1x0"), job(serde_json::json!(["200x1x0"])));
+
+    let current_candidates = HashMap::from([
+        (scid("100x1x0"), vec![excepted]),
+        (scid("101x1x0"), vec![excepted, scid("201x1x0")]),
+        (scid("102x1x0"), vec![scid("201x1x0")]),
+        (scid("103x1x0"), vec![scid("201x1x0")]),
+    ]);
+    let success = |partner: ShortChannelId, completed_at: u64| SuccessReb {
+        amount_msat: 50_000_000,
+        fee_ppm: 100,
+        fee_msat: 5_000,
+        channel_partner: Some(partner),
+        hops: 2,
+        completed_at,
+    };
+    let successes = HashMap::from([
+        (
+            scid("102x1x0"),
+            vec![
+                success(excepted, now - 60),
+                success(excepted, now - 120),
+                // outside of the window
+                success(excepted, now - 30 * 24 * 60 * 60),
+                success(scid("201x1x0"), now - 60),
+            ],
+        ),
+        (scid("104x1x0"), vec![success(excepted, now - 60)]),
+    ]);
+
+    let impacts = except_chan_impact(&excepted, &jobs, &current_candidates, &successes, now, 7);
+    assert_eq!(
+        impacts
+            .iter()
+            .map(|i| i.short_channel_id)
+            .collect::<Vec<_>>(),
+        vec![scid("100x1x0"), scid("101x1x0"), scid("102x1x0")]
+    );
+    assert!(impacts[0].listed_candidate && impacts[0].current_candidate);
+    assert!(!impacts[1].listed_candidate && impacts[1].current_candidate);
+    assert!(!impacts[2].listed_candidate && !impacts[2].current_candidate);
+    assert_eq!(impacts[2].recent_successes, 2);
+    assert_eq!(impacts[2].recent_success_msat, 100_000_000);
+    assert_eq!(impacts[0].direction, SatDirection::Pull);
+}
+
+#[test]
+fn test_wait_for_graph() {
+    use crate::model::{JobMessage, JobState, LnGraph, Task};
+    use crate::util::wait_for_graph;
+    use cln_rpc::primitives::ShortChannelId;
+    use parking_lot::Mutex;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    let chan_id = ShortChannelId::from_str("100x1x0").unwrap();
+    let task = Task {
+        chan_id,
+        task_id: 1,
+    };
+    let job_states = Arc::new(Mutex::new(HashMap::from([(
+        chan_id,
+        vec![JobState::new(JobMessage::Starting, 1)],
+    )])));
+    let graph = Arc::new(Mutex::new(LnGraph::new()));
+
+    let filler = {
+        let graph = graph.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(1_500));
+            test_graph_edge(&mut graph.lock(), "101x1x0", TEST_PEER_A, TEST_PEER_B, 100);
+        })
+    };
+    let started = Instant::now();
+    tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(wait_for_graph(&graph, job_states.clone(), &task, 1))
+        .unwrap();
+    filler.join().unwrap();
+    // waited for the graph instead of failing right away
+    assert!(started.elapsed() >= Duration::from_millis(1_500));
+    assert!(!graph.lock().graph.is_empty());
+    assert!(matches!(
+        job_states.lock().get(&chan_id).unwrap()[0].state(),
+        JobMessage::GraphEmpty
+    ));
+
+    // a filled graph doesn't wait at all
+    let started = Instant::now();
+    tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(wait_for_graph(&graph, job_states, &task, 1))
+        .unwrap();
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn test_job_note_round_trip() {
+    use crate::parse::parse_job;
+    use sling::Job;
+    use std::collections::BTreeMap;
+
+    let (chan_id, job) = tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(parse_job(serde_json::json!({
+            "scid": "100x1x0",
+            "direction": "pull",
+            "amount": 100_000,
+            "maxppm": 100,
+            "outppm": 0,
+            "note": "drains every weekend, keep it full"
+        })))
+        .unwrap();
+    assert_eq!(
+        job.note.as_deref(),
+        Some("drains every weekend, keep it full")
+    );
+
+    // jobs.json and the sling-jobsettings listing are both the serialized jobs
+    let jobs = BTreeMap::from([(chan_id.unwrap(), job.clone())]);
+    let persisted = serde_json::to_string(&jobs).unwrap();
+    let reloaded: BTreeMap<_, Job> = serde_json::from_str(&persisted).unwrap();
+    assert_eq!(reloaded, jobs);
+    assert_eq!(
+        serde_json::to_value(&jobs).unwrap()["100x1x0"]["note"],
+        "drains every weekend, keep it full"
+    );
+    assert_eq!(job.to_json()["note"], "drains every weekend, keep it full");
+
+    let without_note = Job { note: None, ..job };
+    assert!(serde_json::to_value(&without_note)
+        .unwrap()
+        .get("note")
+        .is_none());
+}
+
+#[test]
+fn test_stale_peer_data_holds_dispatch() {
+    use crate::model::{JobMessage, PluginState};
+    use cln_rpc::primitives::PublicKey;
+    use std::path::PathBuf;
+    use std::str::FromStr;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let state = PluginState::new(
+        PublicKey::from_str(TEST_PEER_A).unwrap(),
+        PathBuf::from("lightning-rpc"),
+        PathBuf::from("sling"),
+        PathBuf::from("."),
+        "v24.05".to_string(),
+    );
+    let mut config = test_config();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    // off by default, even if the channels were never refreshed
+    assert!(state.dispatch_paused(&config).is_none());
+
+    config.max_peer_data_age.value = 60;
+    *state.peers_refreshed_at.lock() = now - 600;
+    assert!(matches!(
+        state.dispatch_paused(&config),
+        Some(JobMessage::StaleData)
+    ));
+    assert_eq!(JobMessage::StaleData.code(), 30);
+
+    // a fresh refresh lets the jobs dispatch again
+    *state.peers_refreshed_at.lock() = now;
+    assert!(state.dispatch_paused(&config).is_none());
+    assert!(!state.peer_data_stale(now + 60, 60));
+    assert!(state.peer_data_stale(now + 61, 60));
+}
+
+#[test]
+fn test_dijkstra_capacity_preference() {
+    use crate::dijkstra::dijkstra;
+    use crate::model::{DijkstraNode, ExcludeGraph, LnGraph};
+    use crate::util::capacity_tier_penalty;
+    use cln_rpc::primitives::{Amount, PublicKey, ShortChannelId};
+    use sling::Job;
+    use std::collections::{HashMap, HashSet};
+    use std::str::FromStr;
+
+    let me = PublicKey::from_str(TEST_PEER_A).unwrap();
+    let other = PublicKey::from_str(TEST_PEER_B).unwrap();
+    let to_large = ShortChannelId::from_str("101x1x0").unwrap();
+    let to_small = ShortChannelId::from_str("102x1x0").unwrap();
+
+    let mut graph = LnGraph::new();
+    test_graph_edge(&mut graph, "100x1x0", TEST_PEER_B, TEST_PEER_A, 0);
+    test_graph_edge(&mut graph, "101x1x0", TEST_PEER_A, TEST_PEER_C, 0);
+    test_graph_edge(&mut graph, "102x1x0", TEST_PEER_A, TEST_PEER_D, 0);
+    test_graph_edge(&mut graph, "103x1x0", TEST_PEER_C, TEST_PEER_B, 10);
+    test_graph_edge(&mut graph, "104x1x0", TEST_PEER_D, TEST_PEER_B, 10);
+    for (peer, capacity_msat) in [(TEST_PEER_C, 20_000_000_000), (TEST_PEER_D, 500_000_000)] {
+        for state in graph
+            .graph
+            .get_mut(&PublicKey::from_str(peer).unwrap())
+            .unwrap()
+            .values_mut()
+        {
+            state.amount_msat = Amount::from_msat(capacity_msat);
+        }
+    }
+
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 100,
+        "candidatelist": ["101x1x0", "102x1x0"]
+    }))
+    .unwrap();
+    let slingchan = DijkstraNode {
+        score: 0,
+        destination: me,
+        channel_state: graph
+            .get_channel(&other, &ShortChannelId::from_str("100x1x0").unwrap())
+            .unwrap(),
+        hops: 0,
+        short_channel_id: ShortChannelId::from_str("100x1x0").unwrap(),
+    };
+    let route_with_preference = |capacity_preference: i64| {
+        dijkstra(
+            &me,
+            &graph,
+            &me,
+            &other,
+            &slingchan,
+            &job,
+            &[to_large, to_small],
+            9,
+            &ExcludeGraph {
+                exclude_chans: HashSet::new(),
+                exclude_peers: HashSet::new(),
+            },
+            144,
+            &HashMap::new(),
+            &[],
+            &HashMap::new(),
+            0.0,
+            0,
+            0,
+            None,
+            capacity_preference,
+            &HashMap::new(),
+        )
+        .unwrap()
+    };
+
+    assert_eq!(route_with_preference(5).first().unwrap().channel, to_large);
+    assert_eq!(route_with_preference(-5).first().unwrap().channel, to_small);
+
+    assert_eq!(capacity_tier_penalty(500_000_000, 0, 100_000_000), 0);
+    assert_eq!(capacity_tier_penalty(500_000_000, 5, 100_000_000), 1_000);
+    assert_eq!(capacity_tier_penalty(2_000_000_000, 5, 100_000_000), 500);
+    assert_eq!(capacity_tier_penalty(20_000_000_000, 5, 100_000_000), 0);
+    assert_eq!(
+        capacity_tier_penalty(20_000_000_000, -5, 100_000_000),
+        1_000
+    );
+}
+
+#[test]
+fn test_rpc_pool_reuses_connections() {
+    use crate::model::RpcPool;
+    use cln_rpc::RpcError;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+
+    struct MockConn {
+        id: u32,
+    }
+    let connects = AtomicU32::new(0);
+    let connect = || async {
+        Ok::<_, anyhow::Error>(MockConn {
+            id: connects.fetch_add(1, Ordering::SeqCst),
+        })
+    };
+    let pool = RpcPool::new(1);
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let first = pool.get(connect).await.unwrap().id;
+        let second = pool.get(connect).await.unwrap().id;
+        assert_eq!(first, second);
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+
+        // an rpc error keeps the connection, a transport error replaces it
+        let mut conn = pool.get(connect).await.unwrap();
+        conn.discard_on_transport_error(&Err::<(), _>(RpcError {
+            code: Some(204),
+            message: "WIRE_TEMPORARY_CHANNEL_FAILURE".to_string(),
+            data: None,
+        }));
+        drop(conn);
+        let mut conn = pool.get(connect).await.unwrap();
+        assert_eq!(conn.id, first);
+        conn.discard_on_transport_error(&Err::<(), _>(RpcError {
+            code: None,
+            message: "Error passing request to lightningd".to_string(),
+            data: None,
+        }));
+        drop(conn);
+        assert_ne!(pool.get(connect).await.unwrap().id, first);
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+
+        // no more than the capacity is handed out at once
+        let held = pool.get(connect).await.unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), pool.get(connect))
+                .await
+                .is_err()
+        );
+        drop(held);
+        pool.set_capacity(2);
+        let _a = pool.get(connect).await.unwrap();
+        let _b = pool.get(connect).await.unwrap();
+        assert_eq!(connects.load(Ordering::SeqCst), 3);
+    });
+}
+
+#[test]
+fn test_node_penalties_decay() {
+    use crate::dijkstra::dijkstra;
+    use crate::model::{DijkstraNode, ExcludeGraph, LnGraph, NodePenalties};
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use sling::Job;
+    use std::collections::{HashMap, HashSet};
+    use std::str::FromStr;
+
+    let me = PublicKey::from_str(TEST_PEER_A).unwrap();
+    let other = PublicKey::from_str(TEST_PEER_B).unwrap();
+    let flaky = PublicKey::from_str(TEST_PEER_C).unwrap();
+    let via_flaky = ShortChannelId::from_str("101x1x0").unwrap();
+    let via_steady = ShortChannelId::from_str("102x1x0").unwrap();
+
+    let mut graph = LnGraph::new();
+    test_graph_edge(&mut graph, "100x1x0", TEST_PEER_B, TEST_PEER_A, 0);
+    test_graph_edge(&mut graph, "101x1x0", TEST_PEER_A, TEST_PEER_C, 0);
+    test_graph_edge(&mut graph, "102x1x0", TEST_PEER_A, TEST_PEER_D, 0);
+    test_graph_edge(&mut graph, "103x1x0", TEST_PEER_C, TEST_PEER_B, 10);
+    test_graph_edge(&mut graph, "104x1x0", TEST_PEER_D, TEST_PEER_B, 11);
+
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 100,
+        "candidatelist": ["101x1x0", "102x1x0"]
+    }))
+    .unwrap();
+    let slingchan = DijkstraNode {
+        score: 0,
+        destination: me,
+        channel_state: graph
+            .get_channel(&other, &ShortChannelId::from_str("100x1x0").unwrap())
+            .unwrap(),
+        hops: 0,
+        short_channel_id: ShortChannelId::from_str("100x1x0").unwrap(),
+    };
+    let route_with_penalties = |node_penalties: &HashMap<PublicKey, u64>| {
+        dijkstra(
+            &me,
+            &graph,
+            &me,
+            &other,
+            &slingchan,
+            &job,
+            &[via_flaky, via_steady],
+            9,
+            &ExcludeGraph {
+                exclude_chans: HashSet::new(),
+                exclude_peers: HashSet::new(),
+            },
+            144,
+            &HashMap::new(),
+            &[],
+            &HashMap::new(),
+            0.0,
+            0,
+            0,
+            None,
+            0,
+            node_penalties,
+        )
+        .unwrap()
+    };
+
+    let half_life_secs = 3_600;
+    let mut penalties = NodePenalties::default();
+    assert_eq!(
+        route_with_penalties(&penalties.penalties_ppm(1_000, 20, half_life_secs))
+            .first()
+            .unwrap()
+            .channel,
+        via_flaky
+    );
+
+    penalties.record_failure(flaky, 1_000, half_life_secs);
+    penalties.record_failure(flaky, 1_000, half_life_secs);
+    let fresh = penalties.penalties_ppm(1_000, 20, half_life_secs);
+    assert_eq!(fresh.get(&flaky), Some(&20));
+    assert_eq!(
+        route_with_penalties(&fresh).first().unwrap().channel,
+        via_steady
+    );
+    assert!(penalties.penalties_ppm(1_000, 0, half_life_secs).is_empty());
+
+    let decayed = penalties.penalties_ppm(1_000 + half_life_secs, 20, half_life_secs);
+    assert_eq!(decayed.get(&flaky), Some(&10));
+    let faded = penalties.penalties_ppm(1_000 + 8 * half_life_secs, 20, half_life_secs);
+    assert!(faded.is_empty());
+    assert_eq!(
+        route_with_penalties(&faded).first().unwrap().channel,
+        via_flaky
+    );
+
+    let huge = HashMap::from([(flaky, u64::MAX)]);
+    assert_eq!(
+        route_with_penalties(&huge).first().unwrap().channel,
+        via_steady
+    );
+}
+
+#[test]
+fn test_graph_export() {
+    use crate::model::{GraphExportFormat, LnGraph};
+    use cln_rpc::primitives::PublicKey;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    let me = PublicKey::from_str(TEST_PEER_A).unwrap();
+    let [a, b, c, d] =
+        [TEST_PEER_A, TEST_PEER_B, TEST_PEER_C, TEST_PEER_D].map(|p| p.to_lowercase());
+    let mut graph = LnGraph::new();
+    test_graph_edge(&mut graph, "100x1x0", &a, &b, 10);
+    test_graph_edge(&mut graph, "100x1x0", &b, &a, 20);
+    test_graph_edge(&mut graph, "101x1x0", &b, &c, 30);
+    test_graph_edge(&mut graph, "102x1x0", &c, &d, 40);
+    let alias_map = HashMap::from([
+        (me, "me".to_string()),
+        (PublicKey::from_str(&b).unwrap(), "bob \"b\"".to_string()),
+    ]);
+
+    let (dot, nodes, edges) = graph.export(None, &alias_map, GraphExportFormat::Dot, 0, 0);
+    assert_eq!((nodes, edges), (4, 4));
+    assert!(dot.starts_with("digraph sling {\n"));
+    assert!(dot.contains(&format!("  \"{}\" [label=\"me\"];\n", &a)));
+    assert!(dot.contains(&format!("  \"{}\" [label=\"bob \\\"b\\\"\"];\n", &b)));
+    assert!(dot.contains(&format!("  \"{}\" [label=\"{}\"];\n", &d, &d)));
+    assert!(dot.contains(&format!(
+        "  \"{}\" -> \"{}\" [label=\"101x1x0 0+30ppm 1000000000msat\"];\n",
+        &b, &c
+    )));
+
+    let neighborhood = graph.neighborhood(&me, 1);
+    assert_eq!(neighborhood.len(), 2);
+    let (edgelist, nodes, edges) = graph.export(
+        Some(&neighborhood),
+        &alias_map,
+        GraphExportFormat::EdgeList,
+        0,
+        0,
+    );
+    assert_eq!((nodes, edges), (2, 2));
+    assert_eq!(edgelist.lines().count(), 3);
+    assert!(edgelist.contains(&format!(
+        "{}\tme\t{}\tbob \"b\"\t100x1x0\t0\t10\t1000000000\n",
+        &a, &b
+    )));
+    assert!(!edgelist.contains("101x1x0"));
+
+    let (graphml, _, edges) = graph.export(
+        Some(&graph.neighborhood(&me, 2)),
+        &alias_map,
+        GraphExportFormat::GraphMl,
+        0,
+        0,
+    );
+    assert_eq!(edges, 3);
+    assert!(graphml.contains("<data key=\"alias\">bob &quot;b&quot;</data>"));
+    assert!(graphml.contains("<data key=\"scid\">101x1x0</data>"));
+    assert!(!graphml.contains(&d));
+    assert!(graphml.trim_end().ends_with("</graphml>"));
+    assert!(GraphExportFormat::from_str("png").is_err());
+}
+
+#[test]
+fn test_stats_write_failure_logged_and_counted() {
+    use crate::model::{
+        PluginState, SuccessReb, WriteError, SUCCESSES_SUFFIX, WRITE_FAILURE_WARN_COUNT,
+    };
+    use cln_rpc::primitives::{PublicKey, ShortChannelId};
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    let state = PluginState::new(
+        PublicKey::from_str(TEST_PEER_A).unwrap(),
+        PathBuf::from("lightning-rpc"),
+        PathBuf::from("sling"),
+        PathBuf::from("."),
+        "v24.05".to_string(),
+    );
+    let missing_dir = std::env::temp_dir().join(format!(
+        "sling-test-write-failure-{}/missing",
+        std::process::id()
+    ));
+    let chan_id = ShortChannelId::from_str("100x1x0").unwrap();
+    let success = SuccessReb {
+        amount_msat: 1_000_000,
+        fee_ppm: 10,
+        fee_msat: 10,
+        channel_partner: Some(ShortChannelId::from_str("101x1x0").unwrap()),
+        hops: 2,
+        completed_at: 1_000,
+    };
+    let rt = tokio::runtime::Runtime::new().unwrap();
+
+    for expected in 1..=WRITE_FAILURE_WARN_COUNT {
+        let err = state
+            .track_write(rt.block_on(success.write_to_file(chan_id, &missing_dir)))
+            .unwrap_err();
+        let write_error = err.downcast_ref::<WriteError>().unwrap();
+        assert_eq!(
+            write_error.path,
+            missing_dir.join(format!("{}{}", chan_id, SUCCESSES_SUFFIX))
+        );
+        assert!(err.to_string().contains(&missing_dir.display().to_string()));
+        assert_eq!(state.write_failures.lock().total(), expected);
+    }
+
+    assert!(state.track_write(Ok::<_, anyhow::Error>(())).is_ok());
+    assert!(state
+        .track_write::<()>(Err(anyhow::anyhow!("not a write")))
+        .is_err());
+    assert_eq!(
+        state.write_failures.lock().total(),
+        WRITE_FAILURE_WARN_COUNT
+    );
+}
+
+#[test]
+fn test_job_group_works_on_most_imbalanced() {
+    use crate::slings::group_member_to_work;
+    use crate::util::check_group_target;
+    use cln_rpc::primitives::ShortChannelId;
+    use sling::Job;
+    use std::collections::{BTreeMap, HashMap, HashSet};
+    use std::str::FromStr;
+
+    let member = |direction: &str, target: f64, group: &str| -> Job {
+        serde_json::from_value(serde_json::json!({
+            "sat_direction": direction,
+            "amount_msat": 100_000_000,
+            "maxppm": 100,
+            "outppm": 50,
+            "target": target,
+            "group": group
+        }))
+        .unwrap()
+    };
+    let [slightly_low, very_low, balanced, other_group] =
+        ["100x1x0", "101x1x0", "102x1x0", "103x1x0"].map(|s| ShortChannelId::from_str(s).unwrap());
+    let peer_channels = HashMap::from([
+        (
+            slightly_low,
+            test_peer_channel("100x1x0", TEST_PEER_A, 10_000_000_000, 4_000_000_000, 0),
+        ),
+        (
+            very_low,
+            test_peer_channel("101x1x0", TEST_PEER_B, 4_000_000_000, 400_000_000, 0),
+        ),
+        (
+            balanced,
+            test_peer_channel("102x1x0", TEST_PEER_C, 10_000_000_000, 6_000_000_000, 0),
+        ),
+        (
+            other_group,
+            test_peer_channel("103x1x0", TEST_PEER_D, 10_000_000_000, 0, 0),
+        ),
+    ]);
+    let mut jobs = BTreeMap::from([
+        (slightly_low, member("pull", 0.5, "equal")),
+        (very_low, member("pull", 0.5, "equal")),
+        (balanced, member("pull", 0.5, "equal")),
+        (other_group, member("pull", 0.5, "other")),
+    ]);
+    let mut running = jobs.keys().copied().collect::<HashSet<_>>();
+
+    assert_eq!(
+        group_member_to_work("equal", &jobs, &peer_channels, &running, 0),
+        Some(very_low)
+    );
+    assert_eq!(
+        group_member_to_work("other", &jobs, &peer_channels, &running, 0),
+        Some(other_group)
+    );
+
+    // once the most imbalanced member is balanced or stopped the next one gets its turn
+    running.remove(&very_low);
+    assert_eq!(
+        group_member_to_work("equal", &jobs, &peer_channels, &running, 0),
+        Some(slightly_low)
+    );
+    jobs.get_mut(&slightly_low).unwrap().target = Some(0.4);
+    assert_eq!(
+        group_member_to_work("equal", &jobs, &peer_channels, &running, 0),
+        None
+    );
+
+    let jobs = BTreeMap::from([(slightly_low, member("pull", 0.5, "equal"))]);
+    assert!(check_group_target(&jobs, &very_low, &member("push", 0.5, "equal")).is_ok());
+    assert!(check_group_target(&jobs, &very_low, &member("pull", 0.6, "other")).is_ok());
+    assert!(check_group_target(&jobs, &slightly_low, &member("pull", 0.6, "equal")).is_ok());
+    assert!(check_group_target(&jobs, &very_low, &member("push", 0.3, "equal")).is_err());
+}
+
+#[test]
+fn test_job_group_skips_failing_member() {
+    use crate::model::{JobMessage, JobState};
+    use crate::slings::{group_member_to_work, group_members_ready};
+    use crate::util::retain_group_targets;
+    use cln_rpc::primitives::ShortChannelId;
+    use sling::Job;
+    use std::collections::{BTreeMap, HashMap};
+    use std::str::FromStr;
+
+    let member = |target: f64| -> Job {
+        serde_json::from_value(serde_json::json!({
+            "sat_direction": "pull",
+            "amount_msat": 100_000_000,
+            "maxppm": 100,
+            "outppm": 50,
+            "target": target,
+            "group": "equal"
+        }))
+        .unwrap()
+    };
+    let [slightly_low, very_low] =
+        ["100x1x0", "101x1x0"].map(|s| ShortChannelId::from_str(s).unwrap());
+    let peer_channels = HashMap::from([
+        (
+            slightly_low,
+            test_peer_channel("100x1x0", TEST_PEER_A, 10_000_000_000, 4_000_000_000, 0),
+        ),
+        (
+            very_low,
+            test_peer_channel("101x1x0", TEST_PEER_B, 4_000_000_000, 400_000_000, 0),
+        ),
+    ]);
+    let jobs = BTreeMap::from([(slightly_low, member(0.5)), (very_low, member(0.5))]);
+    let mut job_states = HashMap::from([
+        (
+            slightly_low,
+            vec![JobState::new(JobMessage::GroupWaiting, 0)],
+        ),
+        (very_low, vec![JobState::new(JobMessage::Rebalancing, 0)]),
+    ]);
+
+    let ready = group_members_ready(&job_states);
+    assert_eq!(
+        group_member_to_work("equal", &jobs, &peer_channels, &ready, 0),
+        Some(very_low)
+    );
+
+    // the most imbalanced member finds no route, it must not hold the group turn
+    for stuck in [
+        JobMessage::NoRoute,
+        JobMessage::TooExp,
+        JobMessage::LowUtilization,
+    ] {
+        job_states.insert(very_low, vec![JobState::new(stuck, 0)]);
+        let ready = group_members_ready(&job_states);
+        assert_eq!(
+            group_member_to_work("equal", &jobs, &peer_channels, &ready, 0),
+            Some(slightly_low)
+        );
+    }
+
+    // back to rebalancing it gets the turn again
+    job_states.insert(
+        very_low,
+        vec![
+            JobState::new(JobMessage::NoRoute, 0),
+            JobState::new(JobMessage::Starting, 1),
+        ],
+    );
+    let ready = group_members_ready(&job_states);
+    assert_eq!(
+        group_member_to_work("equal", &jobs, &peer_channels, &ready, 0),
+        Some(very_low)
+    );
+
+    // jobs.json edited by hand with a mismatching target keeps the first member only
+    let mut jobs = BTreeMap::from([(slightly_low, member(0.5)), (very_low, member(0.3))]);
+    retain_group_targets(&mut jobs);
+    assert_eq!(jobs.keys().copied().collect::<Vec<_>>(), vec![slightly_low]);
+}
+
+#[test]
+fn test_orphaned_pays_are_swept() {
+    use crate::model::{Pays, PAYS_ORPHAN_AGE_SECS};
+
+    let mut pays = Pays::default();
+    pays.insert("resolved".to_string(), "preimage1".to_string(), 1_000);
+    pays.insert("orphaned".to_string(), "preimage2".to_string(), 1_000);
+    pays.insert("recent".to_string(), "preimage3".to_string(), 5_000);
+    assert_eq!(pays.remove("resolved"), Some("preimage1".to_string()));
+    assert_eq!(pays.remove("resolved"), None);
+    assert_eq!(pays.len(), 2);
+
+    assert_eq!(
+        pays.sweep(1_000 + PAYS_ORPHAN_AGE_SECS - 1, PAYS_ORPHAN_AGE_SECS),
+        0
+    );
+    assert_eq!(
+        pays.sweep(1_000 + PAYS_ORPHAN_AGE_SECS, PAYS_ORPHAN_AGE_SECS),
+        1
+    );
+    assert_eq!(pays.len(), 1);
+    assert_eq!(pays.remove("orphaned"), None);
+    assert_eq!(pays.remove("recent"), Some("preimage3".to_string()));
+}
+
+#[test]
+fn test_build_candidatelist_max_peer_htlc_value() {
+    use crate::model::LnGraph;
+    use crate::slings::build_candidatelist;
+    use crate::util::get_peer_htlc_value_usage;
+    use cln_rpc::primitives::{Amount, PublicKey, ShortChannelId};
+    use sling::{Job, SatDirection};
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    // five pending 100_000sat outgoing htlcs take half of the 1_000_000sat channel with peer A
+    let mut saturated = test_peer_channel("101x1x0", TEST_PEER_A, 1_000_000_000, 800_000_000, 5);
+    for htlc in saturated.htlcs.iter_mut().flatten() {
+        htlc.amount_msat = Amount::from_msat(100_000_000);
+    }
+    let peer_channels = [
+        saturated,
+        test_peer_channel("103x1x0", TEST_PEER_B, 1_000_000_000, 800_000_000, 0),
+    ]
+    .into_iter()
+    .map(|c| (c.short_channel_id.unwrap(), c))
+    .collect::<HashMap<ShortChannelId, _>>();
+    let usage = get_peer_htlc_value_usage(&peer_channels, SatDirection::Pull);
+    assert_eq!(usage[&PublicKey::from_str(TEST_PEER_A).unwrap()], 0.5);
+    assert_eq!(usage[&PublicKey::from_str(TEST_PEER_B).unwrap()], 0.0);
+    assert_eq!(
+        get_peer_htlc_value_usage(&peer_channels, SatDirection::Push)
+            [&PublicKey::from_str(TEST_PEER_A).unwrap()],
+        0.0
+    );
+
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 1_000,
+        "maxppm": 100
+    }))
+    .unwrap();
+    let mut config = test_config();
+    config.max_htlc_count.value = 10;
+    let candidates = |config: &crate::model::Config| {
+        let mut c = build_candidatelist(
+            &peer_channels,
+            &job,
+            &LnGraph::new(),
+            &HashMap::new(),
+            config,
+            None,
+            1_000,
+        );
+        c.sort();
+        c
+    };
+    assert_eq!(candidates(&config).len(), 2);
+
+    config.max_peer_htlc_value.value = 60;
+    assert_eq!(candidates(&config).len(), 2);
+    config.max_peer_htlc_value.value = 40;
+    assert_eq!(
+        candidates(&config),
+        vec![ShortChannelId::from_str("103x1x0").unwrap()]
+    );
+}
+
+#[test]
+fn test_failing_candidate_rotation() {
+    use crate::model::CandidateFailures;
+    use cln_rpc::primitives::ShortChannelId;
+    use std::str::FromStr;
+
+    let [cheap, alternate] = ["101x1x0", "102x1x0"].map(|s| ShortChannelId::from_str(s).unwrap());
+    let candidates = vec![cheap, alternate];
+    let mut failures = CandidateFailures::default();
+
+    assert!(!failures.record(cheap, false, 0));
+    assert!(!failures.record(cheap, false, 0));
+    let mut list = candidates.clone();
+    assert!(failures.rotate(&mut list).is_empty());
+    assert_eq!(list, candidates);
+
+    // a success in between breaks the streak
+    assert!(!failures.record(cheap, false, 2));
+    assert!(!failures.record(cheap, true, 2));
+    assert!(!failures.record(cheap, false, 2));
+    assert!(failures.record(cheap, false, 2));
+    let mut list = candidates.clone();
+    assert_eq!(failures.rotate(&mut list), vec![cheap]);
+    assert_eq!(list, vec![alternate]);
+
+    // once the alternate keeps failing too, all candidates get another turn
+    assert!(!failures.record(alternate, false, 2));
+    assert!(failures.record(alternate, false, 2));
+    let mut list = candidates.clone();
+    assert!(failures.rotate(&mut list).is_empty());
+    assert_eq!(list, candidates);
+    let mut list = candidates.clone();
+    assert!(failures.rotate(&mut list).is_empty());
+    assert_eq!(list, candidates);
+
+    assert!(failures.record(cheap, false, 1));
+    failures.reset();
+    let mut list = candidates.clone();
+    assert!(failures.rotate(&mut list).is_empty());
+    assert_eq!(list, candidates);
+}
+
+#[test]
+fn test_schedule_windows() {
+    use chrono::{TimeZone, Utc};
+    use sling::{Job, Schedule};
+    use std::str::FromStr;
+
+    let at = |y, mo, d, h, mi| Utc.with_ymd_and_hms(y, mo, d, h, mi, 0).unwrap();
+
+    let utc = Schedule::from_str("22:00-06:00").unwrap();
+    assert!(utc.contains(at(2024, 6, 1, 23, 0)));
+    assert!(utc.contains(at(2024, 6, 1, 5, 59)));
+    assert!(!utc.contains(at(2024, 6, 1, 6, 0)));
+    assert!(!utc.contains(at(2024, 6, 1, 12, 0)));
+
+    let offset = Schedule::from_str("09:00-17:00 +02:00").unwrap();
+    assert!(!offset.contains(at(2024, 6, 1, 6, 59)));
+    assert!(offset.contains(at(2024, 6, 1, 7, 0)));
+    assert!(offset.contains(at(2024, 6, 1, 14, 59)));
+    assert!(!offset.contains(at(2024, 6, 1, 15, 0)));
+
+    // DST starts in New York on 2024-03-10 at 07:00 UTC, the window moves
+    // from 03:00-11:00 UTC to 02:00-10:00 UTC
+    let new_york = Schedule::from_str("22:00-06:00 America/New_York").unwrap();
+    assert!(!new_york.contains(at(2024, 3, 9, 2, 30)));
+    assert!(new_york.contains(at(2024, 3, 9, 10, 30)));
+    assert!(new_york.contains(at(2024, 3, 10, 3, 0)));
+    assert!(new_york.contains(at(2024, 3, 10, 9, 59)));
+    assert!(!new_york.contains(at(2024, 3, 10, 10, 30)));
+    assert!(!new_york.contains(at(2024, 3, 11, 1, 59)));
+    assert!(new_york.contains(at(2024, 3, 11, 2, 30)));
+
+    for invalid in [
+        "",
+        "22:00",
+        "25:00-06:00",
+        "22:00-22:00",
+        "22:00-06:00 Mars/Olympus",
+        "22:00-06:00 +25:00",
+        "22:00-06:00 UTC extra",
+    ] {
+        assert!(Schedule::from_str(invalid).is_err(), "{}", invalid);
+    }
+
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 100,
+        "outppm": 50,
+        "schedule": "09:00-17:00 +02:00"
+    }))
+    .unwrap();
+    assert!(job.outside_schedule(at(2024, 6, 1, 20, 0)));
+    assert!(!job.outside_schedule(at(2024, 6, 1, 8, 0)));
+    let unscheduled = Job {
+        schedule: None,
+        ..job
+    };
+    assert!(!unscheduled.outside_schedule(at(2024, 6, 1, 20, 0)));
+}
+
+#[test]
+fn test_timed_out_sendpay_status() {
+    use crate::util::sendpay_status;
+    use cln_rpc::model::responses::{ListsendpaysPayments, ListsendpaysPaymentsStatus};
+
+    let part = |status: &str| {
+        serde_json::from_value::<ListsendpaysPayments>(serde_json::json!({
+            "id": 1,
+            "groupid": 1,
+            "payment_hash": "0000000000000000000000000000000000000000000000000000000000000000",
+            "status": status,
+            "amount_sent_msat": 100_020_000,
+            "created_at": 1_700_000_000
+        }))
+        .unwrap()
+    };
+    assert_eq!(sendpay_status(&[]), None);
+    assert_eq!(
+        sendpay_status(&[part("failed")]),
+        Some(ListsendpaysPaymentsStatus::FAILED)
+    );
+    // a retry of a failed part is still in flight
+    assert_eq!(
+        sendpay_status(&[part("failed"), part("pending")]),
+        Some(ListsendpaysPaymentsStatus::PENDING)
+    );
+    assert_eq!(
+        sendpay_status(&[part("pending"), part("complete")]),
+        Some(ListsendpaysPaymentsStatus::COMPLETE)
+    );
+}
+
+#[test]
+fn test_foreign_balance_change_not_own_payment() {
+    use crate::model::{is_own_payment, ArrivedPay, Pays, SuccessReb};
+    use cln_rpc::model::requests::SendpayRoute;
+    use cln_rpc::primitives::{Amount, PublicKey, ShortChannelId};
+    use sling::SatDirection;
+    use std::str::FromStr;
+
+    let hop = |amount_msat: u64, peer: &str, scid: &str| SendpayRoute {
+        amount_msat: Amount::from_msat(amount_msat),
+        id: PublicKey::from_str(peer).unwrap(),
+        delay: 144,
+        channel: ShortChannelId::from_str(scid).unwrap(),
+    };
+    let route = vec![
+        hop(100_050_000, TEST_PEER_A, "101x1x0"),
+        hop(100_000_000, TEST_PEER_B, "102x1x0"),
+    ];
+    let job_chan = ShortChannelId::from_str("102x1x0").unwrap();
+    let other_chan = ShortChannelId::from_str("103x1x0").unwrap();
+
+    // another rebalancer pulled the same amount into the job's channel while
+    // our payment timed out, the balance alone looks like our success
+    assert!(SuccessReb::from_balance_change(
+        &route,
+        SatDirection::Pull,
+        1_000_000_000,
+        1_100_000_000,
+        1_000
+    )
+    .is_some());
+
+    let mut pays = Pays::default();
+    pays.insert("ours".to_string(), "preimage".to_string(), 1_000);
+    assert_eq!(pays.take_arrived("ours"), None);
+    assert!(!is_own_payment(pays.take_arrived("ours").as_ref(), &route));
+
+    // our htlc came back over another channel or with less than we sent
+    for arrived in [
+        ArrivedPay {
+            short_channel_id: other_chan,
+            amount_msat: 100_000_000,
+        },
+        ArrivedPay {
+            short_channel_id: job_chan,
+            amount_msat: 99_999_999,
+        },
+    ] {
+        assert!(!is_own_payment(Some(&arrived), &route));
+    }
+
+    let arrived = ArrivedPay {
+        short_channel_id: job_chan,
+        amount_msat: 100_000_000,
+    };
+    assert_eq!(
+        pays.resolve("ours", Some(arrived), 1_010),
+        Some("preimage".to_string())
+    );
+    assert_eq!(pays.len(), 0);
+    assert_eq!(pays.resolve("ours", Some(arrived), 1_010), None);
+    let taken = pays.take_arrived("ours");
+    assert!(is_own_payment(taken.as_ref(), &route));
+    assert_eq!(pays.take_arrived("ours"), None);
+
+    // arrivals that nobody picked up are swept with the orphaned pays
+    pays.insert("late".to_string(), "preimage".to_string(), 1_000);
+    pays.resolve("late", Some(arrived), 1_000);
+    pays.sweep(2_000, 500);
+    assert_eq!(pays.take_arrived("late"), None);
+}
+
+#[test]
+fn test_stats_cache_evicts_least_recently_used() {
+    use crate::model::{StatsCache, SuccessReb, SUCCESSES_SUFFIX};
+    use crate::stats::{cached_lifetime_rebalanced_msat, lifetime_rebalanced_msat};
+    use cln_rpc::primitives::ShortChannelId;
+    use parking_lot::Mutex;
+    use std::str::FromStr;
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let sling_dir =
+        std::env::temp_dir().join(format!("sling-test-stats-cache-{}", std::process::id()));
+    std::fs::create_dir_all(&sling_dir).unwrap();
+    let success = |amount_msat: u64| SuccessReb {
+        amount_msat,
+        fee_ppm: 100,
+        fee_msat: amount_msat / 10_000,
+        channel_partner: Some(ShortChannelId::from_str("200x1x0").unwrap()),
+        hops: 2,
+        completed_at: 1_700_000_000,
+    };
+    let chans = ["100x1x0", "101x1x0", "102x1x0"].map(|s| ShortChannelId::from_str(s).unwrap());
+    for (i, chan) in chans.iter().enumerate() {
+        rt.block_on(success((i as u64 + 1) * 100_000).write_to_file(*chan, &sling_dir))
+            .unwrap();
+    }
+    let key = |chan: &ShortChannelId| sling_dir.join(chan.to_string() + SUCCESSES_SUFFIX);
+    let cache = Mutex::new(StatsCache::default());
+    let cached = |chan: &ShortChannelId| {
+        rt.block_on(cached_lifetime_rebalanced_msat(&cache, 2, &sling_dir, chan))
+            .unwrap()
+    };
+    let fingerprint = |chan: &ShortChannelId| {
+        let metadata = std::fs::metadata(key(chan)).unwrap();
+        [Some((metadata.len(), metadata.modified().unwrap())), None]
+    };
+
+    assert_eq!(cached(&chans[0]), 100_000);
+    assert_eq!(cached(&chans[1]), 200_000);
+    // using the first channel again makes the second the least recently used
+    assert_eq!(cached(&chans[0]), 100_000);
+    assert_eq!(cached(&chans[2]), 300_000);
+    assert_eq!(cache.lock().len(), 2);
+    assert_eq!(
+        cache.lock().get(&key(&chans[0]), &fingerprint(&chans[0])),
+        Some(100_000)
+    );
+    assert_eq!(
+        cache.lock().get(&key(&chans[1]), &fingerprint(&chans[1])),
+        None
+    );
+
+    // the evicted channel is read again, and a changed stats file is not served from the cache
+    assert_eq!(cached(&chans[1]), 200_000);
+    rt.block_on(success(50_000).write_to_file(chans[1], &sling_dir))
+        .unwrap();
+    assert_eq!(cached(&chans[1]), 250_000);
+    for chan in &chans {
+        assert_eq!(
+            cached(chan),
+            rt.block_on(lifetime_rebalanced_msat(&sling_dir, chan))
+                .unwrap()
+        );
+    }
+    assert_eq!(cache.lock().len(), 2);
+
+    let disabled = Mutex::new(StatsCache::default());
+    assert_eq!(
+        rt.block_on(cached_lifetime_rebalanced_msat(
+            &disabled, 0, &sling_dir, &chans[0]
+        ))
+        .unwrap(),
+        100_000
+    );
+    assert_eq!(disabled.lock().len(), 0);
+
+    std::fs::remove_dir_all(&sling_dir).unwrap();
+}
+
+#[test]
+fn test_own_payment_verified_without_verify_balance() {
+    use crate::model::{ArrivedPay, Task};
+    use crate::response::own_payment_verified;
+    use cln_rpc::model::requests::SendpayRoute;
+    use cln_rpc::primitives::{Amount, PublicKey, ShortChannelId};
+    use std::str::FromStr;
+
+    let job_chan = ShortChannelId::from_str("102x1x0").unwrap();
+    let route = vec![
+        SendpayRoute {
+            amount_msat: Amount::from_msat(100_050_000),
+            id: PublicKey::from_str(TEST_PEER_A).unwrap(),
+            delay: 144,
+            channel: ShortChannelId::from_str("101x1x0").unwrap(),
+        },
+        SendpayRoute {
+            amount_msat: Amount::from_msat(100_000_000),
+            id: PublicKey::from_str(TEST_PEER_B).unwrap(),
+            delay: 144,
+            channel: job_chan,
+        },
+    ];
+    let task = Task {
+        chan_id: job_chan,
+        task_id: 1,
+    };
+    let arrived = ArrivedPay {
+        short_channel_id: job_chan,
+        amount_msat: 100_000_000,
+    };
+    let mut config = test_config();
+    assert!(!config.verify_balance.value);
+    assert!(own_payment_verified(&config, &task, &route, None));
+
+    // the own payment check applies even though sling-verify-balance is off
+    config.verify_own_payment.value = true;
+    assert!(!own_payment_verified(&config, &task, &route, None));
+    assert!(own_payment_verified(&config, &task, &route, Some(&arrived)));
+}
+
+#[test]
+fn test_clean_loaded_candidatelist() {
+    use crate::util::clean_loaded_candidatelist;
+    use cln_rpc::primitives::ShortChannelId;
+    use sling::Job;
+    use std::collections::{BTreeMap, HashSet};
+    use std::str::FromStr;
+
+    let scid = |s: &str| ShortChannelId::from_str(s).unwrap();
+    let own_channels: HashSet<ShortChannelId> = ["100x1x0", "101x1x0", "102x1x0"]
+        .map(scid)
+        .into_iter()
+        .collect();
+
+    // as read from jobs.json, never checked by sling-job
+    let mut jobs: BTreeMap<ShortChannelId, Job> = serde_json::from_value(serde_json::json!({
+        "100x1x0": {
+            "sat_direction": "pull",
+            "amount_msat": 100_000_000,
+            "maxppm": 100,
+            "candidatelist": ["101x1x0", "999x1x0", "101x1x0", "100x1x0", "102x1x0"],
+            "candidatelist_backup": ["998x1x0", "102x1x0"]
+        }
+    }))
+    .unwrap();
+    for (chan_id, job) in jobs.iter_mut() {
+        clean_loaded_candidatelist(chan_id, job, &own_channels);
+    }
+    let job = &jobs[&scid("100x1x0")];
+    assert_eq!(
+        job.candidatelist,
+        Some(vec![scid("101x1x0"), scid("102x1x0")])
+    );
+    assert_eq!(job.candidatelist_backup, Some(vec![scid("102x1x0")]));
+}
+
+#[test]
+fn test_peer_job_stops_only_without_usable_channels() {
+    use crate::model::JobMessage;
+    use crate::util::job_stop_reason;
+    use cln_rpc::model::responses::ListpeerchannelsChannelsState;
+    use cln_rpc::primitives::ShortChannelId;
+    use sling::Job;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    let lowest = ShortChannelId::from_str("101x1x0").unwrap();
+    let other = ShortChannelId::from_str("102x1x0").unwrap();
+    let mut peer_channels = [
+        test_peer_channel("101x1x0", TEST_PEER_A, 1_000_000_000, 500_000_000, 0),
+        test_peer_channel("102x1x0", TEST_PEER_A, 1_000_000_000, 500_000_000, 0),
+        test_peer_channel("103x1x0", TEST_PEER_B, 1_000_000_000, 500_000_000, 0),
+    ]
+    .into_iter()
+    .map(|c| (c.short_channel_id.unwrap(), c))
+    .collect::<HashMap<ShortChannelId, _>>();
+    let peer_job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 100_000_000,
+        "maxppm": 100,
+        "outppm": 50,
+        "peer": TEST_PEER_A.to_lowercase()
+    }))
+    .unwrap();
+    let channel_job = Job {
+        peer: None,
+        ..peer_job.clone()
+    };
+
+    // the channel the job is keyed by closes, the other one is still usable
+    peer_channels.get_mut(&lowest).unwrap().state =
+        ListpeerchannelsChannelsState::CHANNELD_SHUTTING_DOWN;
+    assert!(job_stop_reason(&peer_channels, &lowest, &peer_job).is_none());
+    assert!(matches!(
+        job_stop_reason(&peer_channels, &lowest, &channel_job),
+        Some(JobMessage::ChanClosing)
+    ));
+    peer_channels.remove(&lowest);
+    assert!(job_stop_reason(&peer_channels, &lowest, &peer_job).is_none());
+
+    peer_channels.get_mut(&other).unwrap().state = ListpeerchannelsChannelsState::ONCHAIN;
+    assert!(matches!(
+        job_stop_reason(&peer_channels, &lowest, &peer_job),
+        Some(JobMessage::ChanClosing)
+    ));
+    peer_channels.remove(&other);
+    assert!(matches!(
+        job_stop_reason(&peer_channels, &lowest, &peer_job),
+        Some(JobMessage::ChanNotNormal)
+    ));
+}
+
+#[test]
+fn test_attempt_amount_back_to_full_after_success() {
+    use crate::model::AttemptAmount;
+    use sling::Job;
+
+    let job: Job = serde_json::from_value(serde_json::json!({
+        "sat_direction": "pull",
+        "amount_msat": 400_000_000,
+        "min_amount_msat": 100_000_000,
+        "maxppm": 100,
+        "outppm": 50
+    }))
+    .unwrap();
+    let mut amount = AttemptAmount::new(job.amount_msat);
+    assert_eq!(amount.current(), 400_000_000);
+
+    // no route for the full amount, the split one goes through
+    assert_eq!(amount.split(&job), Some(200_000_000));
+    amount.record(false);
+    assert_eq!(amount.current(), 200_000_000);
+    amount.record(true);
+    assert_eq!(amount.current(), 400_000_000);
+
+    assert_eq!(amount.split(&job), Some(200_000_000));
+    assert_eq!(amount.split(&job), Some(100_000_000));
+    assert_eq!(amount.split(&job), None);
+    assert_eq!(amount.current(), 100_000_000);
+    amount.record(true);
+    assert_eq!(amount.current(), 400_000_000);
+}
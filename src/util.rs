@@ -1,7 +1,10 @@
 use bitcoin::secp256k1::hashes::Hash;
 use bitcoin::secp256k1::hashes::HashEngine;
+use cln_rpc::model::requests::SendpayRoute;
 use cln_rpc::model::responses::ListpeerchannelsChannels;
+use cln_rpc::model::responses::ListpeerchannelsChannelsHtlcsDirection;
 use cln_rpc::model::responses::ListpeerchannelsChannelsState;
+use cln_rpc::model::responses::{ListsendpaysPayments, ListsendpaysPaymentsStatus};
 use cln_rpc::primitives::Amount;
 use cln_rpc::primitives::PublicKey;
 use cln_rpc::primitives::Sha256;
@@ -12,20 +15,30 @@ use std::collections::BTreeMap;
 use std::collections::HashSet;
 use std::io;
 use std::path::PathBuf;
+use std::process::{Command, Stdio};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{collections::HashMap, path::Path};
 
 use crate::channel_jobstate_update;
+use crate::model::write_file;
 use crate::model::PluginState;
 use crate::model::Task;
 use crate::model::GRAPH_FILE_NAME;
 use crate::model::JOB_FILE_NAME;
 use crate::model::PLUGIN_NAME;
-use crate::model::{JobMessage, JobState, LnGraph};
+use crate::model::REDACTED;
+use crate::model::TEMPLATES_FILE_NAME;
+use crate::model::{channel_htlc_resolved, channel_htlc_stuck, STUCK_HTLC_MARGIN_SECS};
+use crate::model::{FailureReb, JobMessage, JobState, JobsDiff, LnGraph, SuccessReb};
+use crate::model::{CAPACITY_TIER_LARGE_MSAT, CAPACITY_TIER_MEDIUM_MSAT};
+use crate::model::{GRAPH_EMPTY_RETRY_MAX_SECS, GRAPH_EMPTY_RETRY_MIN_SECS};
+use crate::model::{LOW_DISK_SPACE_KB, WRITE_PROBE_FILE_NAME};
+use crate::parse::{resolve_job_template, validate_job};
 use crate::slingstop;
 use crate::DirectedChannelState;
-use sling::Job;
+use serde_json::json;
+use sling::{has_balance_fields, Job, JobStateStats, StuckHtlc};
 
 use crate::tasks::refresh_listpeerchannels;
 use anyhow::{anyhow, Error};
@@ -74,6 +87,72 @@ pub async fn refresh_joblists(p: Plugin<PluginState>) -> Result<(), Error> {
     Ok(())
 }
 
+pub fn check_candidatelist(
+    chan_id: &ShortChannelId,
+    job: &mut Job,
+    own_channels: &HashSet<ShortChannelId>,
+) -> Result<(), Error> {
+    for duplicate in job.dedup_candidatelist() {
+        warn!(
+            "{}: removed duplicate candidate {} from candidatelist",
+            chan_id, duplicate
+        );
+    }
+    if job.drop_target_candidate(chan_id) {
+        warn!(
+            "{}: removed the job's own channel from its candidates, \
+            it can't rebalance with itself",
+            chan_id
+        );
+    }
+    for candidates in [&job.candidatelist, &job.candidatelist_backup]
+        .into_iter()
+        .flatten()
+    {
+        if let Some(foreign) = candidates.iter().find(|c| !own_channels.contains(c)) {
+            return Err(anyhow!("candidate {} is not one of our channels", foreign));
+        }
+    }
+    Ok(())
+}
+
+// jobs.json can be edited by hand or outlive a channel, so on load
+// candidates that are not our channels are dropped instead of failing the job
+pub fn clean_loaded_candidatelist(
+    chan_id: &ShortChannelId,
+    job: &mut Job,
+    own_channels: &HashSet<ShortChannelId>,
+) {
+    for duplicate in job.dedup_candidatelist() {
+        warn!(
+            "{}: removed duplicate candidate {} from candidatelist",
+            chan_id, duplicate
+        );
+    }
+    if job.drop_target_candidate(chan_id) {
+        warn!(
+            "{}: removed the job's own channel from its candidates, \
+            it can't rebalance with itself",
+            chan_id
+        );
+    }
+    for candidates in [&mut job.candidatelist, &mut job.candidatelist_backup]
+        .into_iter()
+        .flatten()
+    {
+        candidates.retain(|c| {
+            let own = own_channels.contains(c);
+            if !own {
+                warn!(
+                    "{}: removed candidate {} that is not one of our channels",
+                    chan_id, c
+                );
+            }
+            own
+        });
+    }
+}
+
 pub async fn read_jobs(
     sling_dir: &PathBuf,
     plugin: &Plugin<PluginState>,
@@ -84,7 +163,11 @@ pub async fn read_jobs(
 
     create_sling_dir(sling_dir).await?;
     match jobfilecontent {
-        Ok(file) => jobs = serde_json::from_str(&file).unwrap_or(BTreeMap::new()),
+        Ok(file) => {
+            let raw_jobs: BTreeMap<ShortChannelId, serde_json::Value> =
+                serde_json::from_str(&file).unwrap_or(BTreeMap::new());
+            jobs = resolve_jobs(raw_jobs, &read_templates(sling_dir).await?)?;
+        }
         Err(e) => {
             warn!(
                 "Couldn't open {}: {}. First time using sling? Creating new file.",
@@ -95,24 +178,214 @@ pub async fn read_jobs(
             jobs = BTreeMap::new();
         }
     };
-    let peer_channels = plugin.state().peer_channels.lock();
-    let channels = get_all_normal_channels_from_listpeerchannels(&peer_channels);
-    let channels = channels.keys().collect::<Vec<&ShortChannelId>>();
+    {
+        let peer_channels = plugin.state().peer_channels.lock();
+        let channels = get_all_normal_channels_from_listpeerchannels(&peer_channels);
+        let channels = channels.keys().collect::<Vec<&ShortChannelId>>();
+        jobs.retain(|c, _j| channels.contains(&c));
+        retain_group_targets(&mut jobs);
+        let own_channels = peer_channels.keys().copied().collect();
+        for (chan_id, job) in jobs.iter_mut() {
+            clean_loaded_candidatelist(chan_id, job, &own_channels);
+        }
+    }
+    for job in jobs.values().filter(|j| j.subdir.is_some()) {
+        create_sling_dir(&job.stats_dir(sling_dir)).await?;
+    }
+    Ok(jobs)
+}
+
+pub async fn read_jobs_file(
+    jobfile: &Path,
+    own_channels: &HashSet<ShortChannelId>,
+) -> Result<BTreeMap<ShortChannelId, Job>, Error> {
+    let content = fs::read_to_string(jobfile).await?;
+    if content.trim().is_empty() {
+        return Ok(BTreeMap::new());
+    }
+    let raw_jobs: BTreeMap<ShortChannelId, serde_json::Value> = serde_json::from_str(&content)
+        .map_err(|e| anyhow!("could not parse {}: {}", jobfile.display(), e))?;
+    let templates = match jobfile.parent() {
+        Some(sling_dir) => read_templates(sling_dir).await?,
+        None => BTreeMap::new(),
+    };
+    let mut jobs = resolve_jobs(raw_jobs, &templates)?;
+    for (chan_id, job) in jobs.iter_mut() {
+        clean_loaded_candidatelist(chan_id, job, own_channels);
+        validate_job(job).map_err(|e| anyhow!("{}: {}", chan_id, e))?;
+    }
+    for (chan_id, job) in &jobs {
+        check_group_target(&jobs, chan_id, job).map_err(|e| anyhow!("{}: {}", chan_id, e))?;
+    }
+    Ok(jobs)
+}
+
+// jobs.json keeps the template references, jobs are only resolved in memory
+pub async fn read_raw_jobs(
+    sling_dir: &Path,
+) -> Result<BTreeMap<ShortChannelId, serde_json::Value>, Error> {
+    match fs::read_to_string(sling_dir.join(JOB_FILE_NAME)).await {
+        Ok(content) => Ok(serde_json::from_str(&content).unwrap_or(BTreeMap::new())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
 
-    jobs.retain(|c, _j| channels.contains(&c));
+pub fn resolve_jobs(
+    raw_jobs: BTreeMap<ShortChannelId, serde_json::Value>,
+    templates: &BTreeMap<String, serde_json::Value>,
+) -> Result<BTreeMap<ShortChannelId, Job>, Error> {
+    let mut jobs = BTreeMap::new();
+    for (chan_id, raw_job) in raw_jobs {
+        let job =
+            resolve_job_template(raw_job, templates).map_err(|e| anyhow!("{}: {}", chan_id, e))?;
+        jobs.insert(chan_id, job);
+    }
     Ok(jobs)
 }
 
+pub async fn read_templates(
+    sling_dir: &Path,
+) -> Result<BTreeMap<String, serde_json::Value>, Error> {
+    let templatefile = sling_dir.join(TEMPLATES_FILE_NAME);
+    match fs::read_to_string(&templatefile).await {
+        Ok(content) if content.trim().is_empty() => Ok(BTreeMap::new()),
+        Ok(content) => serde_json::from_str(&content)
+            .map_err(|e| anyhow!("could not parse {}: {}", templatefile.display(), e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(anyhow!("could not read {}: {}", templatefile.display(), e)),
+    }
+}
+
+// a pull and a push job on the same channel(s) whose targets can't both be
+// reached will keep undoing each other's work
+pub fn find_conflicting_jobs(
+    jobs: &BTreeMap<ShortChannelId, Job>,
+    peer_channels: &HashMap<ShortChannelId, ListpeerchannelsChannels>,
+) -> Vec<(ShortChannelId, ShortChannelId)> {
+    let covered = |chan_id: &ShortChannelId, job: &Job| -> HashSet<ShortChannelId> {
+        match job.peer {
+            Some(peer) => peer_channels
+                .iter()
+                .filter(|(_, c)| c.peer_id == peer)
+                .map(|(scid, _)| *scid)
+                .chain(std::iter::once(*chan_id))
+                .collect(),
+            None => HashSet::from([*chan_id]),
+        }
+    };
+    let mut conflicts = Vec::new();
+    for (pull_id, pull_job) in jobs
+        .iter()
+        .filter(|(_, j)| j.sat_direction == SatDirection::Pull)
+    {
+        let pull_channels = covered(pull_id, pull_job);
+        for (push_id, push_job) in jobs
+            .iter()
+            .filter(|(_, j)| j.sat_direction == SatDirection::Push)
+        {
+            if pull_job.target.unwrap_or(0.5) + push_job.target.unwrap_or(0.5) > 1.0
+                && !pull_channels.is_disjoint(&covered(push_id, push_job))
+            {
+                conflicts.push((*pull_id, *push_id));
+            }
+        }
+    }
+    conflicts
+}
+
+pub fn warn_conflicting_jobs(
+    jobs: &BTreeMap<ShortChannelId, Job>,
+    peer_channels: &HashMap<ShortChannelId, ListpeerchannelsChannels>,
+) {
+    for (pull_id, push_id) in find_conflicting_jobs(jobs, peer_channels) {
+        warn!(
+            "{}: pull job conflicts with push job {} on the same channel(s), \
+            their targets add up to more than 100% and they will work against each other",
+            pull_id, push_id
+        );
+    }
+}
+
+// all members of a group work towards the same share on our side
+pub fn check_group_target(
+    jobs: &BTreeMap<ShortChannelId, Job>,
+    chan_id: &ShortChannelId,
+    job: &Job,
+) -> Result<(), Error> {
+    let group = match &job.group {
+        Some(g) => g,
+        None => return Ok(()),
+    };
+    for (member_id, member) in jobs {
+        if member_id != chan_id
+            && member.group.as_ref() == Some(group)
+            && (member.local_target() - job.local_target()).abs() > 0.000_001
+        {
+            return Err(anyhow!(
+                "group {} keeps {}% on our side (see {}), this job would keep {}%",
+                group,
+                member.local_target() * 100.0,
+                member_id,
+                job.local_target() * 100.0
+            ));
+        }
+    }
+    Ok(())
+}
+
+// a hand edited jobs.json can disagree on a group target, the first member wins
+pub fn retain_group_targets(jobs: &mut BTreeMap<ShortChannelId, Job>) {
+    let mut kept = BTreeMap::new();
+    for (chan_id, job) in std::mem::take(jobs) {
+        match check_group_target(&kept, &chan_id, &job) {
+            Ok(()) => {
+                kept.insert(chan_id, job);
+            }
+            Err(e) => warn!("{}: not loading job: {}", chan_id, e),
+        }
+    }
+    *jobs = kept;
+}
+
+pub fn diff_jobs(
+    old: &BTreeMap<ShortChannelId, Job>,
+    new: &BTreeMap<ShortChannelId, Job>,
+) -> JobsDiff {
+    let mut diff = JobsDiff::default();
+    for (chan_id, job) in new {
+        match old.get(chan_id) {
+            None => diff.added.push(*chan_id),
+            Some(old_job) if old_job != job => diff.changed.push(*chan_id),
+            Some(_) => diff.unchanged.push(*chan_id),
+        }
+    }
+    diff.removed = old
+        .keys()
+        .filter(|chan_id| !new.contains_key(chan_id))
+        .copied()
+        .collect();
+    diff
+}
+
 pub async fn write_job(
     p: Plugin<PluginState>,
     sling_dir: PathBuf,
     chan_id: ShortChannelId,
-    job: Option<Job>,
+    job: Option<serde_json::Value>,
     remove: bool,
 ) -> Result<BTreeMap<ShortChannelId, Job>, Error> {
-    let mut jobs = read_jobs(&sling_dir, &p).await?;
+    let jobs = read_jobs(&sling_dir, &p).await?;
+    let mut raw_jobs = read_raw_jobs(&sling_dir).await?;
+    let job = match job {
+        Some(j) => {
+            let resolved = resolve_job_template(j.clone(), &read_templates(&sling_dir).await?)?;
+            check_group_target(&jobs, &chan_id, &resolved)?;
+            Some((j, resolved))
+        }
+        None => None,
+    };
     let job_change;
-    let my_job;
     let jobstates = p.state().job_state.lock().clone();
     if jobstates.contains_key(&chan_id)
         && jobstates
@@ -129,9 +402,9 @@ pub async fn write_job(
     }
     {
         let mut job_states = p.state().job_state.lock();
-        if jobs.contains_key(&chan_id) {
+        if raw_jobs.contains_key(&chan_id) {
             if remove {
-                jobs.remove(&chan_id);
+                raw_jobs.remove(&chan_id);
                 job_states.remove(&chan_id);
                 job_change = "Removing";
             } else {
@@ -145,40 +418,48 @@ pub async fn write_job(
     if remove {
         info!("{} job for {}", job_change, &chan_id);
     } else {
-        my_job = job.unwrap();
+        let (raw_job, my_job) = job.unwrap();
         info!(
-            "{} job for {} with amount: {}msat, maxppm: {}, outppm: {:?}, target: {:?},\
-            maxhops: {:?}, candidatelist: {:?},\
-            depleteuptopercent: {:?}, depleteuptoamount: {:?}, paralleljobs: {:?}",
+            "{} job for {} with amount: {}msat, min_amount: {:?}msat,\
+            amount_fraction: {:?}, maxppm: {}, outppm: {:?}, target: {:?},\
+            maxhops: {:?}, candidatelist: {:?}, candidatelist_backup: {:?},\
+            depleteuptopercent: {:?}, depleteuptoamount: {:?}, paralleljobs: {:?}, peer: {:?}, dryrun: {}",
             job_change,
             &chan_id,
             &my_job.amount_msat,
+            &my_job.min_amount_msat,
+            &my_job.amount_fraction,
             &my_job.maxppm,
             &my_job.outppm,
             &my_job.target,
             &my_job.maxhops,
             &my_job.candidatelist,
+            &my_job.candidatelist_backup,
             &my_job.depleteuptopercent,
             &my_job.depleteuptoamount,
             &my_job.paralleljobs,
+            &my_job.peer,
+            &my_job.dryrun,
         );
-        jobs.insert(chan_id, my_job);
+        raw_jobs.insert(chan_id, raw_job);
     }
     let peer_channels = p.state().peer_channels.lock().clone();
     let mut jobs_to_remove = HashSet::new();
     if !peer_channels.is_empty() {
-        for chan_id in jobs.keys() {
+        for chan_id in raw_jobs.keys() {
             if get_normal_channel_from_listpeerchannels(&peer_channels, chan_id).is_none() {
                 jobs_to_remove.insert(*chan_id);
             }
         }
     }
-    jobs.retain(|i, _j| !jobs_to_remove.contains(i));
+    raw_jobs.retain(|i, _j| !jobs_to_remove.contains(i));
     fs::write(
         sling_dir.join(JOB_FILE_NAME),
-        serde_json::to_string_pretty(&jobs)?,
+        serde_json::to_string_pretty(&raw_jobs)?,
     )
     .await?;
+    let jobs = read_jobs(&sling_dir, &p).await?;
+    p.state().loaded_jobs.lock().clone_from(&jobs);
     refresh_joblists(p.clone()).await?;
     Ok(jobs)
 }
@@ -202,6 +483,34 @@ pub async fn write_excepts<T: ToString>(
     Ok(())
 }
 
+pub fn update_excepts<T: std::hash::Hash + Eq + std::fmt::Display>(
+    excepts: &mut HashSet<T>,
+    command: &str,
+    except: T,
+) -> Result<(), Error> {
+    match command {
+        "add" => {
+            if excepts.contains(&except) {
+                return Err(anyhow!("{} is already in excepts", except));
+            }
+            excepts.insert(except);
+        }
+        "remove" => {
+            if !excepts.remove(&except) {
+                return Err(anyhow!("{} not in excepts, nothing to remove", except));
+            }
+        }
+        _ => return Err(anyhow!("Unknown command. Use `add`/`remove` or `list`")),
+    }
+    Ok(())
+}
+
+pub fn sorted_excepts<T: ToString>(excepts: &HashSet<T>) -> Vec<String> {
+    let mut result = excepts.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+    result.sort();
+    result
+}
+
 pub async fn read_graph(sling_dir: &PathBuf) -> Result<LnGraph, Error> {
     let graphfile = sling_dir.join(GRAPH_FILE_NAME);
     let graphfilecontent = fs::read_to_string(graphfile.clone()).await;
@@ -235,7 +544,14 @@ pub async fn write_graph(plugin: Plugin<PluginState>) -> Result<(), Error> {
     let graph_string = serde_json::to_string(&*plugin.state().graph.lock())?;
     let sling_dir = Path::new(&plugin.configuration().lightning_dir).join(PLUGIN_NAME);
     let now = Instant::now();
-    fs::write(sling_dir.join(GRAPH_FILE_NAME), graph_string).await?;
+    plugin.state().track_write(
+        write_file(
+            sling_dir.join(GRAPH_FILE_NAME),
+            graph_string.as_bytes(),
+            false,
+        )
+        .await,
+    )?;
     debug!(
         "Wrote graph to disk in {}ms",
         now.elapsed().as_millis().to_string()
@@ -253,6 +569,50 @@ pub async fn create_sling_dir(sling_dir: &PathBuf) -> Result<(), Error> {
     }
 }
 
+pub async fn check_sling_dir_writable(sling_dir: &PathBuf) -> Result<(), Error> {
+    create_sling_dir(sling_dir).await?;
+    let probe = sling_dir.join(WRITE_PROBE_FILE_NAME);
+    let result = match fs::write(&probe, b"sling").await {
+        Ok(()) => fs::remove_file(&probe).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        return Err(anyhow!(
+            "sling folder {} is not writable: {}",
+            sling_dir.display(),
+            e
+        ));
+    }
+    match available_disk_space_kb(sling_dir) {
+        Some(kb) if kb < LOW_DISK_SPACE_KB => warn!(
+            "Only {}MB of disk space left for the sling folder {}, \
+            writing the graph and stats may fail soon",
+            kb / 1_024,
+            sling_dir.display()
+        ),
+        Some(_) => (),
+        None => debug!(
+            "Could not determine free disk space of {}",
+            sling_dir.display()
+        ),
+    }
+    Ok(())
+}
+
+fn available_disk_space_kb(dir: &Path) -> Option<u64> {
+    let output = Command::new("df").arg("-Pk").arg(dir).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .nth(1)?
+        .split_whitespace()
+        .nth(3)?
+        .parse()
+        .ok()
+}
+
 pub fn get_preimage_paymend_hash_pair() -> (String, Sha256) {
     let mut preimage = [0u8; 32];
     thread_rng().fill(&mut preimage[..]);
@@ -272,6 +632,62 @@ pub fn get_total_htlc_count(channel: &ListpeerchannelsChannels) -> u64 {
     }
 }
 
+// a channel never takes more htlcs than it negotiated, even if the global limit allows it
+pub fn is_htlc_capped(channel: &ListpeerchannelsChannels, max_htlc_count: u64) -> bool {
+    let count = get_total_htlc_count(channel);
+    count > max_htlc_count
+        || channel
+            .max_accepted_htlcs
+            .is_some_and(|max_accepted| count >= max_accepted as u64)
+}
+
+pub fn get_peer_htlc_counts(
+    peer_channels: &HashMap<ShortChannelId, ListpeerchannelsChannels>,
+) -> HashMap<PublicKey, u64> {
+    let mut peer_htlc_counts = HashMap::new();
+    for channel in peer_channels.values() {
+        *peer_htlc_counts.entry(channel.peer_id).or_insert(0) += get_total_htlc_count(channel);
+    }
+    peer_htlc_counts
+}
+
+// share of each peer's in-flight value limit already taken by pending htlcs in
+// the direction a candidate of a job with sat_direction would add one,
+// listpeerchannels only has our limit for incoming htlcs, otherwise use the capacity
+pub fn get_peer_htlc_value_usage(
+    peer_channels: &HashMap<ShortChannelId, ListpeerchannelsChannels>,
+    sat_direction: SatDirection,
+) -> HashMap<PublicKey, f64> {
+    let mut peer_values: HashMap<PublicKey, (u64, u64)> = HashMap::new();
+    for channel in peer_channels.values() {
+        let (limit, htlc_direction) = match sat_direction {
+            SatDirection::Pull => (None, ListpeerchannelsChannelsHtlcsDirection::OUT),
+            SatDirection::Push => (
+                channel.max_total_htlc_in_msat,
+                ListpeerchannelsChannelsHtlcsDirection::IN,
+            ),
+        };
+        let limit_msat = match limit.or(channel.total_msat) {
+            Some(l) => Amount::msat(&l),
+            None => continue,
+        };
+        let pending_msat = channel
+            .htlcs
+            .iter()
+            .flatten()
+            .filter(|h| h.direction == htlc_direction)
+            .map(|h| Amount::msat(&h.amount_msat))
+            .sum::<u64>();
+        let entry = peer_values.entry(channel.peer_id).or_insert((0, 0));
+        entry.0 += pending_msat;
+        entry.1 += limit_msat;
+    }
+    peer_values
+        .into_iter()
+        .map(|(peer, (pending, limit))| (peer, pending as f64 / limit.max(1) as f64))
+        .collect()
+}
+
 pub fn edge_cost(edge: &DirectedChannelState, amount: u64) -> u64 {
     // debug!(
     //     "edge cost for {} source:{} is {}",
@@ -287,6 +703,143 @@ pub fn edge_cost(edge: &DirectedChannelState, amount: u64) -> u64 {
     )
 }
 
+pub fn reliability_penalty(failure_rate: f64, weight: f64, amount: u64) -> u64 {
+    (weight * failure_rate * amount as f64 / 1_000.0).ceil() as u64
+}
+
+// small, medium and large channels are tiers 0, 1 and 2, every tier away
+// from the preferred end costs preference_ppm of the amount
+pub fn capacity_tier_penalty(capacity_msat: u64, preference_ppm: i64, amount: u64) -> u64 {
+    if preference_ppm == 0 {
+        return 0;
+    }
+    let tier = if capacity_msat < CAPACITY_TIER_MEDIUM_MSAT {
+        0
+    } else if capacity_msat < CAPACITY_TIER_LARGE_MSAT {
+        1
+    } else {
+        2
+    };
+    let steps = if preference_ppm > 0 { 2 - tier } else { tier };
+    steps * preference_ppm.unsigned_abs() * amount / 1_000_000
+}
+
+pub fn failure_rates(
+    successes: &[SuccessReb],
+    failures: &[FailureReb],
+) -> HashMap<ShortChannelId, f64> {
+    let mut attempts: HashMap<ShortChannelId, (u64, u64)> = HashMap::new();
+    for partner in successes.iter().filter_map(|s| s.channel_partner) {
+        attempts.entry(partner).or_insert((0, 0)).0 += 1;
+    }
+    for failure in failures {
+        attempts.entry(failure.channel_partner).or_insert((0, 0)).1 += 1;
+    }
+    attempts
+        .into_iter()
+        .map(|(scid, (succ, fail))| (scid, fail as f64 / (succ + fail) as f64))
+        .collect()
+}
+
+pub fn candidate_feeppms(successes: &[SuccessReb]) -> HashMap<ShortChannelId, u64> {
+    let mut totals: HashMap<ShortChannelId, (u64, u64)> = HashMap::new();
+    for success in successes {
+        let partner = match success.channel_partner {
+            Some(p) => p,
+            None => continue,
+        };
+        let total = totals.entry(partner).or_default();
+        total.0 += success.fee_ppm as u64 * success.amount_msat;
+        total.1 += success.amount_msat;
+    }
+    totals
+        .into_iter()
+        .filter(|(_, (_, amount))| *amount > 0)
+        .map(|(scid, (weighted, amount))| (scid, weighted / amount))
+        .collect()
+}
+
+// share of the channel on our side minus 0.5, negative means most of it is on their side
+pub fn channel_imbalance(channel: &ListpeerchannelsChannels) -> f64 {
+    let total_msat = Amount::msat(&channel.total_msat.unwrap());
+    if total_msat == 0 {
+        return 0.0;
+    }
+    Amount::msat(&channel.to_us_msat.unwrap()) as f64 / total_msat as f64 - 0.5
+}
+
+// normal channels that neither have a job nor belong to the peer of a peer job,
+// most imbalanced first
+pub fn unmanaged_channels<'a>(
+    peer_channels: &'a HashMap<ShortChannelId, ListpeerchannelsChannels>,
+    pull_jobs: &HashSet<ShortChannelId>,
+    push_jobs: &HashSet<ShortChannelId>,
+    jobs: &BTreeMap<ShortChannelId, Job>,
+) -> Vec<&'a ListpeerchannelsChannels> {
+    let managed_peers = jobs
+        .iter()
+        .filter(|(scid, _)| pull_jobs.contains(scid) || push_jobs.contains(scid))
+        .filter_map(|(_, job)| job.peer)
+        .collect::<HashSet<PublicKey>>();
+    let mut unmanaged = peer_channels
+        .iter()
+        .filter(|(scid, channel)| {
+            is_channel_normal(channel)
+                && has_balance_fields(channel)
+                && !pull_jobs.contains(scid)
+                && !push_jobs.contains(scid)
+                && !managed_peers.contains(&channel.peer_id)
+        })
+        .map(|(_, channel)| channel)
+        .collect::<Vec<_>>();
+    unmanaged.sort_by(|a, b| {
+        channel_imbalance(b)
+            .abs()
+            .partial_cmp(&channel_imbalance(a).abs())
+            .unwrap()
+            .then(a.short_channel_id.cmp(&b.short_channel_id))
+    });
+    unmanaged
+}
+
+pub fn historical_flows(
+    successes: &HashMap<ShortChannelId, Vec<SuccessReb>>,
+    pull_jobs: &HashSet<ShortChannelId>,
+) -> HashMap<ShortChannelId, (u64, u64)> {
+    let mut flows: HashMap<ShortChannelId, (u64, u64)> = HashMap::new();
+    for (chan_id, rebs) in successes {
+        let is_pull = pull_jobs.contains(chan_id);
+        for reb in rebs {
+            let job_flow = flows.entry(*chan_id).or_default();
+            if is_pull {
+                job_flow.1 += reb.amount_msat;
+            } else {
+                job_flow.0 += reb.amount_msat;
+            }
+            if let Some(partner) = reb.channel_partner {
+                let partner_flow = flows.entry(partner).or_default();
+                if is_pull {
+                    partner_flow.0 += reb.amount_msat;
+                } else {
+                    partner_flow.1 += reb.amount_msat;
+                }
+            }
+        }
+    }
+    flows
+}
+
+pub fn liquidity_prior(htlc_maximum_msat: u64, flow: Option<&(u64, u64)>, outbound: bool) -> u64 {
+    match flow {
+        Some((flow_out, flow_in)) if flow_out + flow_in > 0 => {
+            let share =
+                if outbound { *flow_out } else { *flow_in } as f64 / (flow_out + flow_in) as f64;
+            (htlc_maximum_msat as f64 * share.clamp(0.1, 0.9)) as u64
+        }
+        _ => htlc_maximum_msat / 2,
+    }
+}
+
 pub fn feeppm_effective(feeppm: u32, basefee_msat: u32, amount_msat: u64) -> u64 {
     (fee_total_msat_precise(feeppm, basefee_msat, amount_msat) / amount_msat as f64 * 1_000_000.0)
         .ceil() as u64
@@ -306,6 +859,42 @@ pub fn feeppm_effective_from_amts(amount_msat_start: u64, amount_msat_end: u64)
         as u32
 }
 
+// the status of a payment over all of its parts, one complete part means it went through
+pub fn sendpay_status(payments: &[ListsendpaysPayments]) -> Option<ListsendpaysPaymentsStatus> {
+    [
+        ListsendpaysPaymentsStatus::COMPLETE,
+        ListsendpaysPaymentsStatus::PENDING,
+        ListsendpaysPaymentsStatus::FAILED,
+    ]
+    .into_iter()
+    .find(|status| payments.iter().any(|p| p.status == *status))
+}
+
+pub fn validate_circular_route(route: &[SendpayRoute], my_pubkey: &PublicKey) -> Result<(), Error> {
+    let (first, last) = match (route.first(), route.last()) {
+        (Some(first), Some(last)) if route.len() >= 2 => (first, last),
+        _ => return Err(anyhow!("route needs at least 2 hops to be circular")),
+    };
+    if last.id != *my_pubkey {
+        return Err(anyhow!("route does not end at our node"));
+    }
+    if first.channel == last.channel {
+        return Err(anyhow!(
+            "route uses {} for both the first and the last leg",
+            first.channel
+        ));
+    }
+    if route[..route.len() - 1]
+        .iter()
+        .any(|hop| hop.id == *my_pubkey)
+    {
+        return Err(anyhow!(
+            "route passes through our node as an intermediate hop"
+        ));
+    }
+    Ok(())
+}
+
 pub fn is_channel_normal(channel: &ListpeerchannelsChannels) -> bool {
     matches!(
         channel.state,
@@ -314,6 +903,96 @@ pub fn is_channel_normal(channel: &ListpeerchannelsChannels) -> bool {
     )
 }
 
+pub fn is_channel_closing(channel: &ListpeerchannelsChannels) -> bool {
+    matches!(
+        channel.state,
+        ListpeerchannelsChannelsState::CHANNELD_SHUTTING_DOWN
+            | ListpeerchannelsChannelsState::CLOSINGD_SIGEXCHANGE
+            | ListpeerchannelsChannelsState::CLOSINGD_COMPLETE
+            | ListpeerchannelsChannelsState::AWAITING_UNILATERAL
+            | ListpeerchannelsChannelsState::FUNDING_SPEND_SEEN
+            | ListpeerchannelsChannelsState::ONCHAIN
+    )
+}
+
+pub fn is_channel_opening(channel: &ListpeerchannelsChannels) -> bool {
+    matches!(
+        channel.state,
+        ListpeerchannelsChannelsState::OPENINGD
+            | ListpeerchannelsChannelsState::CHANNELD_AWAITING_LOCKIN
+            | ListpeerchannelsChannelsState::DUALOPEND_OPEN_INIT
+            | ListpeerchannelsChannelsState::DUALOPEND_OPEN_COMMITTED
+            | ListpeerchannelsChannelsState::DUALOPEND_OPEN_COMMIT_READY
+            | ListpeerchannelsChannelsState::DUALOPEND_AWAITING_LOCKIN
+    )
+}
+
+pub fn is_channel_funded(channel: &ListpeerchannelsChannels, min_funded_msat: u64) -> bool {
+    channel
+        .total_msat
+        .is_some_and(|t| t.msat() > 0 && t.msat() >= min_funded_msat)
+}
+
+pub fn channel_funding_pending(
+    peer_channels: &HashMap<ShortChannelId, ListpeerchannelsChannels>,
+    chan_id: &ShortChannelId,
+    min_funded_msat: u64,
+) -> bool {
+    match peer_channels.get(chan_id) {
+        Some(chan) => {
+            is_channel_opening(chan)
+                || is_channel_normal(chan) && !is_channel_funded(chan, min_funded_msat)
+        }
+        None => false,
+    }
+}
+
+pub fn channel_stop_reason(
+    peer_channels: &HashMap<ShortChannelId, ListpeerchannelsChannels>,
+    chan_id: &ShortChannelId,
+) -> Option<JobMessage> {
+    match peer_channels.get(chan_id) {
+        Some(chan) if is_channel_normal(chan) => None,
+        Some(chan) if is_channel_closing(chan) => Some(JobMessage::ChanClosing),
+        _ => Some(JobMessage::ChanNotNormal),
+    }
+}
+
+// a peer job is keyed by one of its channels but only stops once none
+// of the channels with the peer is usable anymore
+pub fn job_stop_reason(
+    peer_channels: &HashMap<ShortChannelId, ListpeerchannelsChannels>,
+    chan_id: &ShortChannelId,
+    job: &Job,
+) -> Option<JobMessage> {
+    let peer = match job.peer {
+        Some(p) => p,
+        None => return channel_stop_reason(peer_channels, chan_id),
+    };
+    let peer_chans = peer_channels
+        .values()
+        .filter(|c| c.peer_id == peer)
+        .collect::<Vec<_>>();
+    if peer_chans.iter().any(|c| is_channel_normal(c)) {
+        None
+    } else if peer_chans.iter().any(|c| is_channel_closing(c)) {
+        Some(JobMessage::ChanClosing)
+    } else {
+        Some(JobMessage::ChanNotNormal)
+    }
+}
+
+// our side of a public channel is disabled in gossip, e.g. after setchannel
+pub fn channel_locally_disabled(
+    graph: &LnGraph,
+    my_pubkey: &PublicKey,
+    chan_id: &ShortChannelId,
+) -> bool {
+    graph
+        .get_channel(my_pubkey, chan_id)
+        .is_ok_and(|state| !state.active)
+}
+
 pub fn get_normal_channel_from_listpeerchannels(
     peer_channels: &HashMap<ShortChannelId, ListpeerchannelsChannels>,
     chan_id: &ShortChannelId,
@@ -328,6 +1007,88 @@ pub fn get_normal_channel_from_listpeerchannels(
     }
 }
 
+pub fn get_job_channels(
+    peer_channels: &HashMap<ShortChannelId, ListpeerchannelsChannels>,
+    chan_id: &ShortChannelId,
+    job: &Job,
+    min_funded_msat: u64,
+) -> Vec<ListpeerchannelsChannels> {
+    let job_channels: Vec<ListpeerchannelsChannels> = match job.peer {
+        Some(peer) => peer_channels
+            .values()
+            .filter(|c| c.peer_id == peer && is_channel_normal(c))
+            .cloned()
+            .collect(),
+        None => get_normal_channel_from_listpeerchannels(peer_channels, chan_id)
+            .into_iter()
+            .collect(),
+    };
+    job_channels
+        .into_iter()
+        .filter(|c| {
+            let complete = has_balance_fields(c);
+            if !complete {
+                debug!(
+                    "{}: {:?} is missing balance or reserve fields, skipping",
+                    chan_id, c.short_channel_id
+                );
+            }
+            complete
+        })
+        .filter(|c| {
+            let funded = is_channel_funded(c, min_funded_msat);
+            if !funded {
+                debug!(
+                    "{}: {:?} is not funded yet, skipping",
+                    chan_id, c.short_channel_id
+                );
+            }
+            funded
+        })
+        .collect()
+}
+
+pub fn resolve_job_chan_id(
+    peer_channels: &HashMap<ShortChannelId, ListpeerchannelsChannels>,
+    chan_id: Option<ShortChannelId>,
+    peer: Option<PublicKey>,
+) -> Result<ShortChannelId, Error> {
+    match (chan_id, peer) {
+        (Some(scid), Some(peer)) => match peer_channels.get(&scid) {
+            Some(c) if c.peer_id != peer => Err(anyhow!("{} is not a channel with {}", scid, peer)),
+            _ => Ok(scid),
+        },
+        (Some(scid), None) => Ok(scid),
+        (None, Some(peer)) => peer_channels
+            .values()
+            .filter(|c| c.peer_id == peer && is_channel_normal(c))
+            .filter_map(|c| c.short_channel_id)
+            .min()
+            .ok_or(anyhow!("No channel in CHANNELD_NORMAL state with {}", peer)),
+        (None, None) => Err(anyhow!("Missing scid or peer")),
+    }
+}
+
+pub fn pick_job_channel(
+    job: &Job,
+    job_channels: &[ListpeerchannelsChannels],
+    max_htlc_count: u64,
+) -> Option<ShortChannelId> {
+    job_channels
+        .iter()
+        .filter(|c| !is_htlc_capped(c, max_htlc_count))
+        .map(|c| {
+            let room = match job.sat_direction {
+                SatDirection::Pull => Amount::msat(&c.receivable_msat.unwrap()),
+                SatDirection::Push => Amount::msat(&c.spendable_msat.unwrap()),
+            };
+            (room, job.channel_shortfall(c), c.short_channel_id.unwrap())
+        })
+        .filter(|(room, _, _)| *room >= job.amount_msat)
+        .max_by_key(|(room, shortfall, _)| (*shortfall, *room))
+        .map(|(_, _, scid)| scid)
+}
+
 pub fn get_all_normal_channels_from_listpeerchannels(
     peer_channels: &HashMap<ShortChannelId, ListpeerchannelsChannels>,
 ) -> HashMap<ShortChannelId, PublicKey> {
@@ -340,6 +1101,77 @@ pub fn get_all_normal_channels_from_listpeerchannels(
     scid_peer_map
 }
 
+// a dryrun job stops after pathfinding: no payment is sent and no success or failure is
+// written, the route only shows up in the log and as the DryRun state of the task
+pub fn dryrun_route(
+    jobstates: Arc<Mutex<HashMap<ShortChannelId, Vec<JobState>>>>,
+    task: &Task,
+    job: &Job,
+    route: &[SendpayRoute],
+    fee_ppm_effective: u32,
+) -> Result<bool, Error> {
+    if !job.dryrun {
+        return Ok(false);
+    }
+    info!(
+        "{}/{}: dryrun: would send {}msat on {}ppm route with {} hops. Sleeping...",
+        task.chan_id,
+        task.task_id,
+        Amount::msat(&route.last().unwrap().amount_msat),
+        fee_ppm_effective,
+        route.len() - 1
+    );
+    channel_jobstate_update(jobstates, task, &JobMessage::DryRun, true, false)?;
+    Ok(true)
+}
+
+// waitsendpay gives up after sling-timeoutpay but the htlc can stay in flight for much
+// longer, keep polling the payment and show the task as stuck from stuck_at until it resolves
+pub async fn track_pending_payment<F, Fut>(
+    jobstates: Arc<Mutex<HashMap<ShortChannelId, Vec<JobState>>>>,
+    task: Task,
+    stuck_htlc: StuckHtlc,
+    stuck_at: u64,
+    poll_interval: Duration,
+    mut payment_status: F,
+) where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<Option<ListsendpaysPaymentsStatus>, Error>>,
+{
+    let mut stuck = false;
+    loop {
+        match payment_status().await {
+            Ok(Some(ListsendpaysPaymentsStatus::PENDING)) => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs();
+                if !stuck && now >= stuck_at {
+                    warn!(
+                        "{}/{}: payment {} still pending {}s after sling-timeoutpay, HTLC seems stuck",
+                        task.chan_id, task.task_id, stuck_htlc.payment_hash, STUCK_HTLC_MARGIN_SECS
+                    );
+                    channel_htlc_stuck(jobstates.clone(), &task, stuck_htlc.clone());
+                    stuck = true;
+                }
+            }
+            Ok(_) => break,
+            Err(e) => warn!(
+                "{}/{}: could not look up payment {}: {}",
+                task.chan_id, task.task_id, stuck_htlc.payment_hash, e
+            ),
+        }
+        tokio::time::sleep(poll_interval).await;
+    }
+    if stuck {
+        info!(
+            "{}/{}: stuck payment {} resolved",
+            task.chan_id, task.task_id, stuck_htlc.payment_hash
+        );
+        channel_htlc_resolved(jobstates, &task);
+    }
+}
+
 pub async fn my_sleep(
     seconds: u64,
     job_state: Arc<Mutex<HashMap<ShortChannelId, Vec<JobState>>>>,
@@ -378,28 +1210,79 @@ pub async fn my_sleep(
     }
 }
 
-pub async fn wait_for_gossip(plugin: &Plugin<PluginState>, task: &Task) -> Result<(), Error> {
+// an empty graph means it was not loaded yet, not that there are no routes,
+// so wait for it with a growing interval instead of failing the task
+pub async fn wait_for_graph(
+    graph: &Arc<Mutex<LnGraph>>,
+    job_state: Arc<Mutex<HashMap<ShortChannelId, Vec<JobState>>>>,
+    task: &Task,
+    min_secs: u64,
+) -> Result<(), Error> {
+    let mut wait_secs = min_secs;
     loop {
-        {
-            let graph = plugin.state().graph.lock();
+        if !graph.lock().graph.is_empty() {
+            return Ok(());
+        }
+        let should_stop = job_state
+            .lock()
+            .get(&task.chan_id)
+            .and_then(|js| js.iter().find(|jt| jt.id() == task.task_id))
+            .is_none_or(|jt| jt.should_stop());
+        if should_stop {
+            return Ok(());
+        }
+        info!(
+            "{}/{}: graph is still empty. Sleeping {}s...",
+            task.chan_id, task.task_id, wait_secs
+        );
+        channel_jobstate_update(
+            job_state.clone(),
+            task,
+            &JobMessage::GraphEmpty,
+            true,
+            false,
+        )?;
+        my_sleep(wait_secs, job_state.clone(), task).await;
+        wait_secs = (wait_secs * 2).min(GRAPH_EMPTY_RETRY_MAX_SECS);
+    }
+}
 
-            if graph.graph.is_empty() {
-                info!(
-                    "{}/{}: graph is still empty. Sleeping...",
-                    task.chan_id, task.task_id
-                );
-                channel_jobstate_update(
-                    plugin.state().job_state.clone(),
-                    task,
-                    &JobMessage::GraphEmpty,
-                    true,
-                    false,
-                )?;
-            } else {
-                break;
-            }
+pub async fn wait_for_gossip(plugin: &Plugin<PluginState>, task: &Task) -> Result<(), Error> {
+    wait_for_graph(
+        &plugin.state().graph,
+        plugin.state().job_state.clone(),
+        task,
+        GRAPH_EMPTY_RETRY_MIN_SECS,
+    )
+    .await?;
+    loop {
+        let startup_delay_secs = plugin.state().config.lock().startup_delay_secs.value;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let should_stop = plugin
+            .state()
+            .job_state
+            .lock()
+            .get(&task.chan_id)
+            .and_then(|js| js.iter().find(|jt| jt.id() == task.task_id))
+            .is_none_or(|jt| jt.should_stop());
+        if should_stop || plugin.state().startup_ready(now, startup_delay_secs) {
+            break;
         }
-        my_sleep(600, plugin.state().job_state.clone(), task).await;
+        debug!(
+            "{}/{}: waiting for startup delay and first graph refresh...",
+            task.chan_id, task.task_id
+        );
+        channel_jobstate_update(
+            plugin.state().job_state.clone(),
+            task,
+            &JobMessage::Starting,
+            true,
+            false,
+        )?;
+        my_sleep(5, plugin.state().job_state.clone(), task).await;
     }
     Ok(())
 }
@@ -465,3 +1348,106 @@ pub fn at_or_above_version(my_version: &str, min_version: &str) -> Result<bool,
 
     Ok(my_version_parts.len() >= min_version_parts.len())
 }
+
+pub fn post_success_command(
+    command: &str,
+    chan_id: &ShortChannelId,
+    success_reb: &SuccessReb,
+) -> Option<Command> {
+    if command.is_empty() {
+        return None;
+    }
+    let mut cmd = Command::new(command);
+    cmd.arg(chan_id.to_string())
+        .arg(success_reb.amount_msat.to_string())
+        .arg(success_reb.fee_ppm.to_string())
+        .env("SLING_SCID", chan_id.to_string())
+        .env("SLING_AMOUNT_MSAT", success_reb.amount_msat.to_string())
+        .env("SLING_FEE_PPM", success_reb.fee_ppm.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    Some(cmd)
+}
+
+pub fn run_post_success_command(command: &str, chan_id: &ShortChannelId, success_reb: &SuccessReb) {
+    if let Some(mut cmd) = post_success_command(command, chan_id, success_reb) {
+        let chan_id = *chan_id;
+        let command = command.to_owned();
+        tokio::task::spawn_blocking(move || match cmd.status() {
+            Ok(status) if !status.success() => {
+                warn!("{}: {} exited with {}", chan_id, command, status)
+            }
+            Err(e) => warn!("{}: could not run {}: {}", chan_id, command, e),
+            _ => (),
+        });
+    }
+}
+
+pub fn diagnostics(
+    plugin_state: &PluginState,
+    jobs: &BTreeMap<ShortChannelId, Job>,
+    redact: bool,
+) -> serde_json::Value {
+    let config = plugin_state.config.lock().clone();
+    let (pubkey, alias) = if redact {
+        (json!(REDACTED), json!(REDACTED))
+    } else {
+        (
+            json!(config.pubkey.to_string()),
+            json!(plugin_state.alias_peer_map.lock().get(&config.pubkey)),
+        )
+    };
+    let job_states = {
+        let job_states = plugin_state.job_state.lock();
+        jobs.keys()
+            .map(|scid| {
+                (
+                    scid.to_string(),
+                    job_states
+                        .get(scid)
+                        .map(|jts| {
+                            jts.iter()
+                                .map(|jt| JobStateStats {
+                                    task_id: jt.id(),
+                                    state: jt.state().to_string(),
+                                    code: jt.state().code(),
+                                    stuck_htlc: jt.stuck_htlc().cloned(),
+                                    success_ratio: jt.success_ratio(),
+                                    too_expensive: jt.too_expensive(),
+                                })
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                )
+            })
+            .collect::<BTreeMap<String, Vec<JobStateStats>>>()
+    };
+    let (graph_nodes, graph_channels) = {
+        let graph = plugin_state.graph.lock();
+        (
+            graph.graph.len(),
+            graph.graph.values().map(|c| c.len()).sum::<usize>(),
+        )
+    };
+    json!({
+        "version": format!("v{}", env!("CARGO_PKG_VERSION")),
+        "cln_version": config.version,
+        "pubkey": pubkey,
+        "alias": alias,
+        "config": config.option_values(),
+        "maintenance": plugin_state.is_maintenance(),
+        "jobs": jobs.len(),
+        "job_states": job_states,
+        "tempbans": plugin_state.tempbans.lock().len(),
+        "graph_nodes": graph_nodes,
+        "graph_channels": graph_channels,
+        "excepts_chans": plugin_state.excepts_chans.lock().len(),
+        "tempban_exempts": plugin_state.tempban_exempts.lock().len(),
+        "excepts_peers": plugin_state.excepts_peers.lock().len(),
+        "banned_nodes": plugin_state.node_bans.lock().banned().len(),
+        "write_failures": plugin_state.write_failures.lock().total(),
+        "pending_pays": plugin_state.pays.read().len(),
+        "stats_cache_entries": plugin_state.stats_cache.lock().len(),
+    })
+}